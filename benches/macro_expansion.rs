@@ -0,0 +1,49 @@
+// Licensed   under  the   Apache  License,   Version  2.0   <LICENSE-APACHE  or
+// http://www.apache.org/licenses/LICENSE-2.0> or  the MIT  license <LICENSE-MIT
+// or http://opensource.org/licenses/MIT>, at your option.  This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// TODO revisit these unstable feature-attributes
+#![feature(test)]
+
+extern crate test;
+
+use denuocc::tu::CTranslationUnit;
+use denuocc::Session;
+use test::Bencher;
+
+/// Preprocess `source`, which invokes small macros many times
+fn bench_source(b: &mut Bencher, source: &str) {
+    let session = Session::builder()
+        .parse_cli_args_from_str(&["--pass=state_read_input", "--pass=phase1..phase4"])
+        .unwrap()
+        .build();
+
+    b.bytes = source.len() as u64;
+    b.iter(|| {
+        let mut tu = CTranslationUnit::builder(&session)
+            .source_string("<bench>", source)
+            .build();
+        tu.run().unwrap();
+        tu
+    });
+}
+
+#[bench]
+fn object_like(b: &mut Bencher) {
+    let mut source = String::from("#define ONE (1 + sizeof(int))\n");
+    for _ in 0..5000 {
+        source.push_str("ONE ONE\n");
+    }
+    bench_source(b, &source);
+}
+
+#[bench]
+fn function_like(b: &mut Bencher) {
+    let mut source =
+        String::from("#define SQ(x) ((x) * (x))\n#define MAX(a, b) ((a) > (b) ? (a) : (b))\n");
+    for i in 0..5000 {
+        source.push_str(&format!("MAX(SQ({0}), SQ(value_{0}))\n", i));
+    }
+    bench_source(b, &source);
+}
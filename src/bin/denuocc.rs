@@ -16,6 +16,7 @@ fn compile() -> denuocc::Result<bool> {
     driver.parse_cli_args_from_env()?;
     driver.run()?;
     driver.report_messages();
+    driver.report_timings();
 
     let success = driver.success();
     if success {
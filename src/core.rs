@@ -8,7 +8,12 @@
 mod error;
 mod flags;
 mod message;
+mod target;
 
 pub use error::{Error, ErrorKind, Result};
-pub use flags::{generate_clap_args, Flags};
+pub use flags::{
+    generate_clap_args, normalize_gcc_args, warning_group, CStandard, ColorChoice,
+    ColumnConvention, Flags, PathDisplay, DEFAULT_WARNINGS, OPTIONAL_WARNINGS, WARNING_GROUPS,
+};
 pub use message::{Message, Severity};
+pub use target::{Endianness, LongDoubleFormat, Target, TARGETS};
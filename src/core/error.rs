@@ -29,6 +29,13 @@ pub enum ErrorKind {
     TooManyTU,
 
     TUStateAbsent,
+    TUStateNotSaved {
+        name: String,
+    },
+    StateFileMalformed {
+        filename: String,
+        line: usize,
+    },
     TUStateTypeError {
         current_type: &'static str,
         expected_type: &'static str,
@@ -39,6 +46,24 @@ pub enum ErrorKind {
         expects: usize,
         got: usize,
     },
+    /// Like [`PassArgsArity`][ErrorKind::PassArgsArity] for passes taking a
+    /// variable number of arguments, with no upper bound if `max` is `None`
+    PassArgsArityRange {
+        pass_name: String,
+        min: usize,
+        max: Option<usize>,
+        got: usize,
+    },
+
+    MacroNotDefined {
+        name: String,
+    },
+    /// `None` stands for the absence of an argument list
+    MacroArgsArity {
+        name: String,
+        expects: Option<usize>,
+        got: Option<usize>,
+    },
     PassArgsType {
         pass_name: String,
         index: usize,
@@ -63,13 +88,19 @@ impl std::fmt::Display for ErrorKind {
             TooManyTU => write!(f, "cannot add translation unit; maximum reached"),
 
             TUStateAbsent => write!(f, "no input state for pass"),
+            TUStateNotSaved { name } => write!(f, "no state saved as `{}`", name),
+            StateFileMalformed { filename, line } => write!(
+                f,
+                "cannot read state from file `{}`: malformed line {}",
+                filename, line
+            ),
             TUStateTypeError {
                 current_type,
                 expected_type,
             } => write!(
                 f,
-                "mismatched input state for pass; got `{}`; expected `{}`",
-                current_type, expected_type
+                "mismatched translation unit state; expected `{}`, found `{}`",
+                expected_type, current_type
             ),
 
             PassArgsArity {
@@ -81,6 +112,28 @@ impl std::fmt::Display for ErrorKind {
                 "pass `{}` expected {} arguments but received {}",
                 pass_name, expects, got
             ),
+            PassArgsArityRange {
+                pass_name,
+                min,
+                max,
+                got,
+            } => match max {
+                None => write!(
+                    f,
+                    "pass `{}` expected at least {} arguments but received {}",
+                    pass_name, min, got
+                ),
+                Some(max) if *min == 0 => write!(
+                    f,
+                    "pass `{}` expected at most {} arguments but received {}",
+                    pass_name, max, got
+                ),
+                Some(max) => write!(
+                    f,
+                    "pass `{}` expected between {} and {} arguments but received {}",
+                    pass_name, min, max, got
+                ),
+            },
             PassArgsType {
                 pass_name,
                 index,
@@ -91,6 +144,17 @@ impl std::fmt::Display for ErrorKind {
                 "pass `{}` cannot parse \"{}\" as {} for argument {}",
                 pass_name, got, expects, index
             ),
+
+            MacroNotDefined { name } => write!(f, "`{}` is not a macro", name),
+            MacroArgsArity { name, expects, got } => match (expects, got) {
+                (None, _) => write!(f, "object-like macro `{}` takes no arguments", name),
+                (Some(_), None) => write!(f, "function-like macro `{}` requires arguments", name),
+                (Some(expects), Some(got)) => write!(
+                    f,
+                    "macro `{}` expected {} arguments but received {}",
+                    name, expects, got
+                ),
+            },
         }
     }
 }
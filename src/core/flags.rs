@@ -5,11 +5,15 @@
 
 //! Compiler flags
 
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
 use lazy_static::lazy_static;
 use log::{info, trace};
 use regex::Regex;
 
-use crate::core::Result;
+use crate::core::{Result, Target, TARGETS};
 use crate::passes::Pass;
 use crate::passes::PASS_CONSTRUCTORS;
 
@@ -38,11 +42,44 @@ fn lex_pass_args(specifier: &str) -> Result<(&str, Vec<&str>)> {
     Ok((name, args))
 }
 
-fn parse_pass(specifier: &std::ffi::OsStr) -> Result<Box<dyn Pass>> {
+/// The passes of the translation phases, in order
+const PHASE_PASSES: &[&str] = &["phase1", "phase2", "phase3", "phase4", "phase5", "phase6"];
+
+/// Parse one `--pass` argument into the passes it names
+///
+/// Besides a single pass, the argument may be `preprocess`, which reads the
+/// input and runs every phase, or a range of phases like `phase1..phase3`.
+fn parse_passes(specifier: &std::ffi::OsStr) -> Result<Vec<Box<dyn Pass>>> {
     let specifier = specifier
         .to_str()
         .ok_or_else(|| format!("non utf-8 argument for --pass flag: {:?}", specifier))?;
 
+    if specifier == "preprocess" {
+        let passes = default_passes::DEFAULT_PASSES_GENERIC;
+        return Ok(passes.iter().map(|&p| p.clone_pass()).collect());
+    }
+
+    if let Some((first, last)) = specifier.split_once("..") {
+        let position = |name: &str| {
+            PHASE_PASSES
+                .iter()
+                .position(|&phase| phase == name)
+                .ok_or_else(|| format!("expected a phase in pass range, found `{}`", name))
+        };
+        let (first, last) = (position(first)?, position(last)?);
+        if first > last {
+            return Err(format!("pass range `{}` is backwards", specifier).into());
+        }
+        return PHASE_PASSES[first..=last]
+            .iter()
+            .map(|name| PASS_CONSTRUCTORS[name].construct(&[]))
+            .collect();
+    }
+
+    Ok(vec![parse_pass(specifier)?])
+}
+
+fn parse_pass(specifier: &str) -> Result<Box<dyn Pass>> {
     let (name, args) = lex_pass_args(specifier)?;
     trace!("Pass::from_str() name = {:?} args = {:?}", name, args);
 
@@ -63,38 +100,334 @@ pub mod default_passes {
         &front::Phase5 {},
         &front::Phase6 {},
     ];
+
+    /// Preprocess and print dependencies instead of compiling (`-M`)
+    pub const DEFAULT_PASSES_DEPENDENCIES: &[&dyn Pass] = &[
+        &internal::StateReadInput {},
+        &front::Phase1 {},
+        &front::Phase2 {},
+        &front::Phase3 {},
+        &front::Phase4 {},
+        &front::EmitDeps {},
+    ];
+
+    /// Preprocess and print the final macro definitions instead (`-dM`)
+    pub const DEFAULT_PASSES_DEFINES: &[&dyn Pass] = &[
+        &internal::StateReadInput {},
+        &front::Phase1 {},
+        &front::Phase2 {},
+        &front::Phase3 {},
+        &front::Phase4 {},
+        &front::EmitDefines {},
+    ];
 }
 
-fn get_default_passes(_matches: &clap::ArgMatches) -> Vec<Box<dyn Pass>> {
+fn get_default_passes(matches: &clap::ArgMatches) -> Vec<Box<dyn Pass>> {
     let mut passes = Vec::new();
 
-    passes.extend(
+    let defaults = if matches.is_present("dependencies") {
+        default_passes::DEFAULT_PASSES_DEPENDENCIES
+    } else if matches.is_present("defines") {
+        default_passes::DEFAULT_PASSES_DEFINES
+    } else {
         default_passes::DEFAULT_PASSES_GENERIC
-            .iter()
-            .map(|&p| p.clone_pass()),
-    );
+    };
+    passes.extend(defaults.iter().map(|&p| p.clone_pass()));
 
     passes
 }
 
+/// Names of the optional warnings that can be enabled with `-W`
+pub const OPTIONAL_WARNINGS: &[&str] = &[
+    // an empty object-like macro used within an `#if` condition
+    "empty-macro",
+    // a file without an include guard included more than once
+    "duplicate-include",
+    // an identifier that is not a macro within an `#if` condition
+    "undef",
+    // a directive added in C2x, such as `#elifdef`
+    "c2x-extensions",
+    // a `//` that is not a comment because the standard is C89
+    "c99-extensions",
+    // a GNU directive, such as `#ident`
+    "gnu-extensions",
+    // a `$` within an identifier, allowed by `--dollars-in-identifiers`
+    "dollar-in-identifier-extension",
+];
+
+/// Names of the warnings that are enabled unless suppressed with a pragma
+pub const DEFAULT_WARNINGS: &[&str] = &[
+    // a file ending with a backslash
+    "backslash-newline-eof",
+    // an `#include_next` directive within the primary source file
+    "include-next-outside-header",
+    // tokens after `#else`, `#endif`, or the name in `#undef`
+    "extra-tokens",
+    // a comma separating macro arguments within unclosed `[` or `{`
+    "macro-argument-brackets",
+];
+
+/// Named groups of warnings that can be enabled or disabled together
+///
+/// Every warning also forms a group of its own.
+pub const WARNING_GROUPS: &[(&str, &[&str])] = &[
+    (
+        "all",
+        &[
+            "backslash-newline-eof",
+            "duplicate-include",
+            "empty-macro",
+            "extra-tokens",
+            "include-next-outside-header",
+            "macro-argument-brackets",
+            "undef",
+        ],
+    ),
+    (
+        "include",
+        &["duplicate-include", "include-next-outside-header"],
+    ),
+];
+
+/// Find the warnings belonging to a group
+pub fn warning_group(name: &str) -> Option<&'static [&'static str]> {
+    for &warnings in &[OPTIONAL_WARNINGS, DEFAULT_WARNINGS] {
+        if let Some(i) = warnings.iter().position(|&w| w == name) {
+            return Some(&warnings[i..=i]);
+        }
+    }
+    WARNING_GROUPS
+        .iter()
+        .find(|(group, _)| *group == name)
+        .map(|(_, warnings)| *warnings)
+}
+
+/// Options GCC spells with a single dash followed by several letters
+///
+/// clap would read these as a cluster of short flags, so they are rewritten to
+/// long options by [`normalize_gcc_args()`][normalize_gcc_args].
+const GCC_MULTI_LETTER_OPTIONS: &[&str] = &["-MF", "-MP", "-MT", "-dM", "-include"];
+
+/// Rewrite GCC style options such as `-MF` or `-std=c11` into the `--MF` or
+/// `--std=c11` clap expects
+pub fn normalize_gcc_args(
+    args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
+) -> Vec<OsString> {
+    args.into_iter()
+        .map(|arg| {
+            let arg = arg.into();
+            match arg.to_str() {
+                Some(option) if GCC_MULTI_LETTER_OPTIONS.contains(&option) => {
+                    format!("-{}", option).into()
+                },
+                Some(option) if option.starts_with("-std=") => format!("-{}", option).into(),
+                _ => arg,
+            }
+        })
+        .collect()
+}
+
+/// Whether diagnostics should be colorized with ANSI escape codes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Colorize only if stderr is a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn from_str(value: &str) -> Result<ColorChoice> {
+        match value {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice `{}`", value).into()),
+        }
+    }
+
+    /// Decide whether to colorize, checking stderr if necessary
+    pub fn should_colorize(&self) -> bool {
+        use std::io::IsTerminal;
+        match *self {
+            ColorChoice::Auto => std::io::stderr().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// How columns are counted in diagnostics
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnConvention {
+    /// One column per Unicode scalar value
+    Codepoint,
+    /// One column per byte of UTF-8
+    Byte,
+    /// The width a terminal displays, with wide characters taking two columns
+    /// and tabs advancing to the next tab stop
+    Display,
+}
+
+impl ColumnConvention {
+    fn from_str(value: &str) -> Result<ColumnConvention> {
+        match value {
+            "codepoint" => Ok(ColumnConvention::Codepoint),
+            "byte" => Ok(ColumnConvention::Byte),
+            "display" => Ok(ColumnConvention::Display),
+            _ => Err(format!("unknown column convention `{}`", value).into()),
+        }
+    }
+}
+
+/// The revision of the C standard being translated
+///
+/// Variants are ordered chronologically, so features introduced in a revision
+/// can be tested with e.g. `standard >= CStandard::C2x`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CStandard {
+    /// ISO/IEC 9899:1990, also known as ANSI C or C90
+    C89,
+    C99,
+    C11,
+    /// ISO/IEC 9899:2018, also known as C18
+    C17,
+    /// The upcoming revision
+    C2x,
+}
+
+impl CStandard {
+    fn from_str(value: &str) -> Result<CStandard> {
+        match value {
+            "c89" | "c90" => Ok(CStandard::C89),
+            "c99" => Ok(CStandard::C99),
+            "c11" => Ok(CStandard::C11),
+            "c17" | "c18" => Ok(CStandard::C17),
+            "c2x" => Ok(CStandard::C2x),
+            _ => Err(format!("unknown C standard `{}`", value).into()),
+        }
+    }
+
+    /// The value of `__STDC_VERSION__`, which C89 does not define
+    pub fn version(self) -> Option<&'static str> {
+        match self {
+            CStandard::C89 => None,
+            CStandard::C99 => Some("199901L"),
+            CStandard::C11 => Some("201112L"),
+            CStandard::C17 => Some("201710L"),
+            CStandard::C2x => Some("202000L"),
+        }
+    }
+
+    /// Whether phase 1 replaces trigraphs, which C2x removed
+    pub fn has_trigraphs(self) -> bool {
+        self < CStandard::C2x
+    }
+}
+
+/// How the paths of input files are displayed in diagnostics
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathDisplay {
+    /// Exactly as given on the command line or in the `#include` directive
+    Verbatim,
+    /// Canonicalized absolute paths
+    Absolute,
+    /// Relative to the given base directory
+    Relative(PathBuf),
+}
+
 /// Compiler flags
 #[derive(Clone, Debug)]
 pub struct Flags {
     pub passes: Vec<Box<dyn Pass>>,
+    pub color: ColorChoice,
+    pub warnings: HashSet<String>,
+    pub path_display: PathDisplay,
+    pub target: Target,
+    pub standard: CStandard,
+    pub verify: bool,
+    pub include_paths: Vec<PathBuf>,
+    /// Headers included before the first line of the main source, in order
+    pub forced_includes: Vec<String>,
+    /// Where `-M` writes the dependency rule instead of stdout
+    pub dependency_file: Option<PathBuf>,
+    /// Replaces the target of the dependency rule
+    pub dependency_target: Option<String>,
+    /// Whether to add an empty rule for each included file
+    pub dependency_phony: bool,
+    pub column: ColumnConvention,
+    /// Distance between tab stops when counting display columns and when
+    /// rendering source lines beneath diagnostics
+    pub tab_stop: u32,
+    /// How deeply macro invocations may nest within macro arguments
+    pub max_macro_depth: usize,
+    /// How many tokens macro replacement may produce while expanding one
+    /// stretch of text
+    pub max_expansion_tokens: usize,
+    /// Number of errors after which translation stops, if limited
+    pub max_errors: Option<usize>,
+    /// Whether the lexer produces comment tokens rather than whitespace
+    pub keep_comments: bool,
+    /// Whether identifiers may contain `$`, as a GNU extension
+    pub dollars_in_identifiers: bool,
+    /// Whether to measure how long each pass takes
+    pub time_passes: bool,
 }
 
 impl Flags {
     pub fn new() -> Flags {
-        Flags { passes: Vec::new() }
+        Flags {
+            passes: Vec::new(),
+            color: ColorChoice::Auto,
+            warnings: DEFAULT_WARNINGS.iter().map(|&w| w.to_owned()).collect(),
+            path_display: PathDisplay::Verbatim,
+            target: Target::default(),
+            standard: CStandard::C17,
+            verify: false,
+            include_paths: Vec::new(),
+            forced_includes: Vec::new(),
+            dependency_file: None,
+            dependency_target: None,
+            dependency_phony: false,
+            column: ColumnConvention::Codepoint,
+            tab_stop: 8,
+            max_macro_depth: 256,
+            max_expansion_tokens: 1 << 22,
+            max_errors: None,
+            keep_comments: false,
+            dollars_in_identifiers: false,
+            time_passes: false,
+        }
+    }
+
+    /// Whether a warning is enabled, either by default or by the user
+    pub fn warning_enabled(&self, name: &str) -> bool {
+        self.warnings.contains(name)
+    }
+
+    /// Enable every warning in the named group
+    pub fn enable_warning_group(&mut self, group: &str) -> Result<()> {
+        let warnings = warning_group(group).ok_or(format!("unknown warning `{}`", group))?;
+        self.warnings.extend(warnings.iter().map(|&w| w.to_owned()));
+        Ok(())
+    }
+
+    /// Disable every warning in the named group
+    pub fn disable_warning_group(&mut self, group: &str) -> Result<()> {
+        let warnings = warning_group(group).ok_or(format!("unknown warning `{}`", group))?;
+        for warning in warnings {
+            self.warnings.remove(*warning);
+        }
+        Ok(())
     }
 
     pub fn process_clap_matches(&mut self, matches: &clap::ArgMatches) -> Result<()> {
         // use requested passes or use defaults?
         if matches.is_present("pass") {
             for pass_arg in matches.values_of_os("pass").into_iter().flatten() {
-                let pass = parse_pass(pass_arg)
+                let passes = parse_passes(pass_arg)
                     .map_err(|e| format!("invalid argument for --pass flag: {}", e))?;
-                self.passes.push(pass);
+                self.passes.extend(passes);
             }
         } else {
             self.passes = get_default_passes(matches);
@@ -102,6 +435,199 @@ impl Flags {
         info!("Flags::process_clap_matches() passes: {:?}", &self.passes);
         assert!(!self.passes.is_empty());
 
+        if let Some(color) = matches.value_of("color") {
+            self.color = ColorChoice::from_str(color)
+                .map_err(|e| format!("invalid argument for --color flag: {}", e))?;
+        }
+        info!("Flags::process_clap_matches() color: {:?}", self.color);
+
+        // later flags override earlier ones, so `-Wall -Wno-empty-macro` works
+        for warning in matches.values_of("warning").into_iter().flatten() {
+            match warning.strip_prefix("no-") {
+                Some(group) => self.disable_warning_group(group),
+                None => self.enable_warning_group(warning),
+            }
+            .map_err(|e| format!("invalid argument for -W flag: {}", e))?;
+        }
+        info!(
+            "Flags::process_clap_matches() warnings: {:?}",
+            &self.warnings
+        );
+
+        self.path_display = match matches.value_of("path-display") {
+            None | Some("verbatim") => PathDisplay::Verbatim,
+            Some("absolute") => PathDisplay::Absolute,
+            Some("relative") => PathDisplay::Relative(
+                matches
+                    .value_of_os("path-base")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| std::env::current_dir().unwrap()),
+            ),
+            Some(_) => unreachable!(), // restricted by clap
+        };
+        info!(
+            "Flags::process_clap_matches() path_display: {:?}",
+            &self.path_display
+        );
+
+        if let Some(target) = matches.value_of("target") {
+            // restricted by clap
+            self.target = Target::from_name(target).unwrap();
+        }
+        info!("Flags::process_clap_matches() target: {:?}", &self.target);
+
+        self.verify = matches.is_present("verify");
+        info!("Flags::process_clap_matches() verify: {:?}", self.verify);
+
+        self.keep_comments = matches.is_present("keep-comments");
+        info!(
+            "Flags::process_clap_matches() keep_comments: {:?}",
+            self.keep_comments
+        );
+
+        self.dollars_in_identifiers = matches.is_present("dollars-in-identifiers");
+        info!(
+            "Flags::process_clap_matches() dollars_in_identifiers: {:?}",
+            self.dollars_in_identifiers
+        );
+
+        self.time_passes = matches.is_present("time-passes");
+        info!(
+            "Flags::process_clap_matches() time_passes: {:?}",
+            self.time_passes
+        );
+
+        self.include_paths = matches
+            .values_of_os("include")
+            .into_iter()
+            .flatten()
+            .map(PathBuf::from)
+            .collect();
+        info!(
+            "Flags::process_clap_matches() include_paths: {:?}",
+            &self.include_paths
+        );
+
+        self.forced_includes = matches
+            .values_of("force-include")
+            .into_iter()
+            .flatten()
+            .map(str::to_owned)
+            .collect();
+        // each is spelled as the header name of an `#include "..."` line,
+        // which cannot contain either
+        if let Some(file) = self
+            .forced_includes
+            .iter()
+            .find(|file| file.contains(['"', '\n']))
+        {
+            return Err(format!(
+                "invalid argument for --include flag: `{}` contains a double quote or newline",
+                file.escape_default()
+            )
+            .into());
+        }
+        info!(
+            "Flags::process_clap_matches() forced_includes: {:?}",
+            &self.forced_includes
+        );
+
+        self.dependency_file = matches.value_of_os("dependency-file").map(PathBuf::from);
+        self.dependency_target = matches.value_of("dependency-target").map(str::to_owned);
+        if let Some(standard) = matches.value_of("std") {
+            self.standard = CStandard::from_str(standard)
+                .map_err(|e| format!("invalid argument for --std flag: {}", e))?;
+        }
+        info!(
+            "Flags::process_clap_matches() standard: {:?}",
+            self.standard
+        );
+
+        if let Some(column) = matches.value_of("column") {
+            self.column = ColumnConvention::from_str(column)
+                .map_err(|e| format!("invalid argument for --column flag: {}", e))?;
+        }
+        if let Some(tab_stop) = matches.value_of("tab-stop") {
+            self.tab_stop = match tab_stop.parse::<u32>() {
+                Ok(tab_stop) if tab_stop > 0 => tab_stop,
+                _ => {
+                    return Err(format!(
+                        "invalid argument for --tab-stop flag: expected a positive integer, \
+                         found `{}`",
+                        tab_stop
+                    )
+                    .into())
+                },
+            };
+        }
+        info!(
+            "Flags::process_clap_matches() column: {:?} tab_stop: {}",
+            self.column, self.tab_stop
+        );
+
+        if let Some(depth) = matches.value_of("max-macro-depth") {
+            self.max_macro_depth = match depth.parse::<usize>() {
+                Ok(depth) if depth > 0 => depth,
+                _ => {
+                    return Err(format!(
+                        "invalid argument for --max-macro-depth flag: expected a positive \
+                         integer, found `{}`",
+                        depth
+                    )
+                    .into())
+                },
+            };
+        }
+        info!(
+            "Flags::process_clap_matches() max_macro_depth: {}",
+            self.max_macro_depth
+        );
+
+        if let Some(max) = matches.value_of("max-expansion-tokens") {
+            self.max_expansion_tokens = match max.parse::<usize>() {
+                Ok(max) if max > 0 => max,
+                _ => {
+                    return Err(format!(
+                        "invalid argument for --max-expansion-tokens flag: expected a \
+                         positive integer, found `{}`",
+                        max
+                    )
+                    .into())
+                },
+            };
+        }
+        info!(
+            "Flags::process_clap_matches() max_expansion_tokens: {}",
+            self.max_expansion_tokens
+        );
+
+        if let Some(max_errors) = matches.value_of("max-errors") {
+            // like GCC, zero means there is no limit
+            self.max_errors = match max_errors.parse::<usize>() {
+                Ok(0) => None,
+                Ok(max_errors) => Some(max_errors),
+                Err(_) => {
+                    return Err(format!(
+                        "invalid argument for --max-errors flag: expected a non-negative \
+                         integer, found `{}`",
+                        max_errors
+                    )
+                    .into())
+                },
+            };
+        }
+        info!(
+            "Flags::process_clap_matches() max_errors: {:?}",
+            self.max_errors
+        );
+
+        self.dependency_phony = matches.is_present("dependency-phony");
+        info!(
+            "Flags::process_clap_matches() dependency_file: {:?} dependency_target: {:?} \
+             dependency_phony: {:?}",
+            &self.dependency_file, &self.dependency_target, self.dependency_phony
+        );
+
         Ok(())
     }
 }
@@ -114,11 +640,90 @@ impl std::default::Default for Flags {
 }
 
 pub fn generate_clap_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
-    vec![clap::Arg::with_name("pass")
-        .long("pass")
-        .multiple(true)
-        .value_delimiter(";")
-        .takes_value(true)]
+    lazy_static! {
+        static ref TARGET_NAMES: Vec<&'static str> = TARGETS.iter().map(|t| t.name).collect();
+    }
+
+    vec![
+        clap::Arg::with_name("pass")
+            .long("pass")
+            .multiple(true)
+            .value_delimiter(";")
+            .takes_value(true),
+        clap::Arg::with_name("color")
+            .long("color")
+            .takes_value(true)
+            .possible_values(&["auto", "always", "never"]),
+        clap::Arg::with_name("warning")
+            .short("W")
+            .multiple(true)
+            .number_of_values(1)
+            .takes_value(true),
+        clap::Arg::with_name("path-display")
+            .long("path-display")
+            .takes_value(true)
+            .possible_values(&["verbatim", "absolute", "relative"]),
+        clap::Arg::with_name("path-base")
+            .long("path-base")
+            .takes_value(true)
+            .requires("path-display"),
+        clap::Arg::with_name("target")
+            .long("target")
+            .takes_value(true)
+            .possible_values(&TARGET_NAMES),
+        clap::Arg::with_name("std")
+            .long("std")
+            .takes_value(true)
+            .possible_values(&["c89", "c90", "c99", "c11", "c17", "c18", "c2x"]),
+        clap::Arg::with_name("verify").long("verify"),
+        clap::Arg::with_name("keep-comments").long("keep-comments"),
+        clap::Arg::with_name("dollars-in-identifiers").long("dollars-in-identifiers"),
+        clap::Arg::with_name("time-passes").long("time-passes"),
+        clap::Arg::with_name("include")
+            .short("I")
+            .multiple(true)
+            .number_of_values(1)
+            .takes_value(true),
+        clap::Arg::with_name("force-include")
+            .long("include")
+            .multiple(true)
+            .number_of_values(1)
+            .takes_value(true),
+        clap::Arg::with_name("dependencies")
+            .short("M")
+            .conflicts_with("pass"),
+        clap::Arg::with_name("defines")
+            .long("dM")
+            .conflicts_with("pass")
+            .conflicts_with("dependencies"),
+        clap::Arg::with_name("dependency-file")
+            .long("MF")
+            .takes_value(true)
+            .requires("dependencies"),
+        clap::Arg::with_name("dependency-target")
+            .long("MT")
+            .takes_value(true)
+            .requires("dependencies"),
+        clap::Arg::with_name("dependency-phony")
+            .long("MP")
+            .requires("dependencies"),
+        clap::Arg::with_name("column")
+            .long("column")
+            .takes_value(true)
+            .possible_values(&["codepoint", "byte", "display"]),
+        clap::Arg::with_name("tab-stop")
+            .long("tab-stop")
+            .takes_value(true),
+        clap::Arg::with_name("max-macro-depth")
+            .long("max-macro-depth")
+            .takes_value(true),
+        clap::Arg::with_name("max-expansion-tokens")
+            .long("max-expansion-tokens")
+            .takes_value(true),
+        clap::Arg::with_name("max-errors")
+            .long("max-errors")
+            .takes_value(true),
+    ]
 }
 
 #[cfg(test)]
@@ -143,6 +748,90 @@ mod test {
         pass_parsing_case("state_save(1,two)", "state_save", &["one", "2"]);
     }
 
+    #[test]
+    fn flags_warning_groups() {
+        let mut flags = Flags::new();
+        assert!(flags.warning_enabled("include-next-outside-header"));
+        assert!(!flags.warning_enabled("duplicate-include"));
+
+        flags.enable_warning_group("include").unwrap();
+        assert!(flags.warning_enabled("duplicate-include"));
+        assert!(!flags.warning_enabled("empty-macro"));
+
+        flags
+            .disable_warning_group("include-next-outside-header")
+            .unwrap();
+        assert!(flags.warning_enabled("duplicate-include"));
+        assert!(!flags.warning_enabled("include-next-outside-header"));
+
+        flags.enable_warning_group("all").unwrap();
+        assert!(flags.warning_enabled("empty-macro"));
+        flags.disable_warning_group("all").unwrap();
+        assert!(flags.warnings.is_empty());
+
+        assert!(flags.enable_warning_group("no-such-warning").is_err());
+        assert!(flags.disable_warning_group("no-such-warning").is_err());
+    }
+
+    #[test]
+    fn flags_forced_include_names() {
+        let parse = |file: &str| {
+            let app = clap::App::new("test")
+                .setting(clap::AppSettings::NoBinaryName)
+                .args(&generate_clap_args());
+            let matches = app
+                .get_matches_from_safe(&[format!("--include={}", file)])
+                .unwrap();
+            let mut flags = Flags::new();
+            flags.process_clap_matches(&matches).map(|_| flags)
+        };
+        assert_eq!(parse("config.h").unwrap().forced_includes, ["config.h"]);
+        let error = parse("we\"ird.h").unwrap_err().to_string();
+        assert!(
+            error.contains("`we\\\"ird.h` contains a double quote"),
+            "{}",
+            error
+        );
+        assert!(parse("a\nb.h").is_err());
+    }
+
+    #[test]
+    fn flags_normalize_gcc_args() {
+        let args = normalize_gcc_args(&[
+            "-M", "-MF", "-MT", "-MP", "--MF", "-MFx", "-dM", "-std=c11", "-include", "x.c",
+        ]);
+        assert_eq!(
+            args,
+            [
+                "-M",
+                "--MF",
+                "--MT",
+                "--MP",
+                "--MF",
+                "-MFx",
+                "--dM",
+                "--std=c11",
+                "--include",
+                "x.c"
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_pass_ranges() {
+        let count = |specifier: &str| parse_passes(specifier.as_ref()).map(|p| p.len());
+        assert_eq!(count("preprocess").unwrap(), 7);
+        assert_eq!(count("phase2..phase5").unwrap(), 4);
+        assert_eq!(
+            count("phase3..phase1").unwrap_err().to_string(),
+            "pass range `phase3..phase1` is backwards"
+        );
+        assert_eq!(
+            count("phase1..state_save").unwrap_err().to_string(),
+            "expected a phase in pass range, found `state_save`"
+        );
+    }
+
     #[test]
     fn flags_pass_parsing() {
         pass_parsing_case("state_save", "state_save", &[]);
@@ -34,9 +34,13 @@ pub enum Severity {
     /// the user
     Warning,
 
-    /// Additional information associated with another [`Message`][m]
+    /// Additional information attached to another [`Message`][m], rendered
+    /// beneath its parent
     ///
     /// [m]: Message
+    Note,
+
+    /// A remark that does not indicate any problem with the source code
     Info,
 }
 
@@ -46,9 +50,20 @@ impl Severity {
             Severity::Fatal => "fatal error",
             Severity::Error => "error",
             Severity::Warning => "warning",
+            Severity::Note => "note",
             Severity::Info => "info",
         }
     }
+
+    /// The ANSI escape code used to colorize this severity
+    pub fn ansi_color(&self) -> &'static str {
+        match *self {
+            Severity::Fatal | Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+            Severity::Note => "\x1b[1;36m",
+            Severity::Info => "\x1b[1m",
+        }
+    }
 }
 
 impl std::fmt::Display for Severity {
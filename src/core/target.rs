@@ -0,0 +1,132 @@
+// Licensed   under  the   Apache  License,   Version  2.0   <LICENSE-APACHE  or
+// http://www.apache.org/licenses/LICENSE-2.0> or  the MIT  license <LICENSE-MIT
+// or http://opensource.org/licenses/MIT>, at your option.  This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Properties of the machine being compiled for
+
+/// Storage format of `long double`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LongDoubleFormat {
+    /// Identical to `double` (IEEE 754 binary64)
+    Double,
+    /// The x87 80-bit extended precision format
+    X87Extended,
+    /// IEEE 754 binary128
+    Quad,
+}
+
+impl LongDoubleFormat {
+    /// Number of bytes holding the value (excluding any padding)
+    pub fn size_bytes(&self) -> usize {
+        match *self {
+            LongDoubleFormat::Double => 8,
+            LongDoubleFormat::X87Extended => 10,
+            LongDoubleFormat::Quad => 16,
+        }
+    }
+}
+
+/// Byte order of multi-byte values in memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// Description of a target machine
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub name: &'static str,
+    pub endianness: Endianness,
+    pub long_double: LongDoubleFormat,
+    /// Number of bytes in a `long`
+    pub long_size: usize,
+    /// Number of bytes in a `wchar_t`
+    pub wchar_size: usize,
+    /// Whether plain `char` is signed
+    pub char_signed: bool,
+}
+
+/// Every target that can be selected with `--target`
+pub const TARGETS: &[Target] = &[
+    Target {
+        name: "x86_64-unknown-linux-gnu",
+        endianness: Endianness::Little,
+        long_double: LongDoubleFormat::X87Extended,
+        long_size: 8,
+        wchar_size: 4,
+        char_signed: true,
+    },
+    Target {
+        name: "x86_64-pc-windows-msvc",
+        endianness: Endianness::Little,
+        long_double: LongDoubleFormat::Double,
+        long_size: 4,
+        wchar_size: 2,
+        char_signed: true,
+    },
+    Target {
+        name: "aarch64-unknown-linux-gnu",
+        endianness: Endianness::Little,
+        long_double: LongDoubleFormat::Quad,
+        long_size: 8,
+        wchar_size: 4,
+        char_signed: false,
+    },
+    Target {
+        name: "s390x-unknown-linux-gnu",
+        endianness: Endianness::Big,
+        long_double: LongDoubleFormat::Quad,
+        long_size: 8,
+        wchar_size: 4,
+        char_signed: false,
+    },
+];
+
+impl Target {
+    /// Look up a target by its name
+    pub fn from_name(name: &str) -> Option<Target> {
+        TARGETS.iter().find(|t| t.name == name).cloned()
+    }
+
+    /// Macros describing this target that compilers predefine, as pairs of
+    /// name and replacement
+    pub fn predefined_macros(&self) -> Vec<(String, String)> {
+        let arch = self.name.split('-').next().unwrap();
+        let mut macros = vec![(format!("__{}__", arch), "1".to_owned())];
+        if self.name.contains("linux") {
+            macros.push(("__linux__".to_owned(), "1".to_owned()));
+            macros.push(("__unix__".to_owned(), "1".to_owned()));
+        } else if self.name.contains("windows") {
+            macros.push(("_WIN32".to_owned(), "1".to_owned()));
+            macros.push(("_WIN64".to_owned(), "1".to_owned()));
+        }
+        if self.long_size == 8 {
+            macros.push(("_LP64".to_owned(), "1".to_owned()));
+            macros.push(("__LP64__".to_owned(), "1".to_owned()));
+        }
+
+        let byte_order = match self.endianness {
+            Endianness::Little => "__ORDER_LITTLE_ENDIAN__",
+            Endianness::Big => "__ORDER_BIG_ENDIAN__",
+        };
+        macros.push(("__ORDER_LITTLE_ENDIAN__".to_owned(), "1234".to_owned()));
+        macros.push(("__ORDER_BIG_ENDIAN__".to_owned(), "4321".to_owned()));
+        macros.push(("__BYTE_ORDER__".to_owned(), byte_order.to_owned()));
+
+        if !self.char_signed {
+            macros.push(("__CHAR_UNSIGNED__".to_owned(), "1".to_owned()));
+        }
+        macros.push(("__CHAR_BIT__".to_owned(), "8".to_owned()));
+        macros.push(("__SIZEOF_LONG__".to_owned(), self.long_size.to_string()));
+        macros.push(("__SIZEOF_WCHAR_T__".to_owned(), self.wchar_size.to_string()));
+        macros
+    }
+}
+
+impl std::default::Default for Target {
+    fn default() -> Target {
+        TARGETS[0].clone()
+    }
+}
@@ -39,7 +39,8 @@ impl Driver {
     /// The very first argument should be the binary name
     pub fn parse_cli_args_from_env(&mut self) -> Result<()> {
         let app = generate_driver_clap(true);
-        self.process_clap_matches(&app.get_matches_safe()?)
+        let args = crate::core::normalize_gcc_args(std::env::args_os());
+        self.process_clap_matches(&app.get_matches_from_safe(args)?)
     }
 
     /// Read command-line arguments from string
@@ -50,7 +51,8 @@ impl Driver {
         input: impl IntoIterator<Item = impl Into<std::ffi::OsString> + Clone>,
     ) -> Result<()> {
         let app = generate_driver_clap(false).setting(clap::AppSettings::NoBinaryName);
-        self.process_clap_matches(&app.get_matches_from_safe(input)?)
+        let args = crate::core::normalize_gcc_args(input);
+        self.process_clap_matches(&app.get_matches_from_safe(args)?)
     }
 
     fn process_clap_matches(&mut self, matches: &clap::ArgMatches) -> Result<()> {
@@ -85,17 +87,7 @@ impl Driver {
         let mut tub = CTranslationUnit::builder(self.session.as_ref().unwrap());
         if path == stdin_path {
             info!("Driver::add_input_file() reading from stdin");
-            use std::io::Read;
-
-            let mut content = String::new();
-            std::io::stdin()
-                .lock()
-                .read_to_string(&mut content)
-                .map_err(|e| ErrorKind::InputFileError {
-                    filename: "<stdin>".to_owned(),
-                    error: e,
-                })?;
-            tub = tub.source_string("<stdin>".to_owned(), content);
+            tub = tub.source_reader("<stdin>", std::io::stdin().lock())?;
         } else {
             info!("Driver::add_input_file() reading from file");
             tub = tub.source_file(path)?;
@@ -145,12 +137,26 @@ impl Driver {
     /// Write messages to stderr
     pub fn report_messages(&self) {
         for tu in &self.tus {
-            for message in tu.messages() {
+            for message in tu.messages_sorted() {
                 eprintln!("{}", message.enriched_message());
             }
         }
     }
 
+    /// Write the time each pass took to stderr, if `--time-passes` was given
+    pub fn report_timings(&self) {
+        let time_passes = self
+            .session
+            .as_ref()
+            .is_some_and(|session| session.flags().time_passes);
+        if !time_passes {
+            return;
+        }
+        for tu in &self.tus {
+            eprint!("{}", tu.timing_report());
+        }
+    }
+
     /// Return whether all translation units succeeded
     ///
     /// This will return `true` even if no translation units have even been run yet.
@@ -192,4 +198,18 @@ mod test {
             false
         });
     }
+
+    #[test]
+    pub fn test_driver_success() {
+        let mut driver = Driver::new();
+        driver.parse_cli_args_from_str(&[] as &[&str]).unwrap();
+        driver.add_input_str("<good>", "int x;\n");
+        driver.run().unwrap();
+        assert!(driver.success());
+
+        driver.add_input_str("<bad>", "#include <nonexistent.h>\n");
+        driver.run().unwrap();
+        assert!(!driver.success());
+        assert!(driver.tus[1].had_errors());
+    }
 }
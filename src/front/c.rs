@@ -5,11 +5,15 @@
 
 //! Front end for the C language
 
+pub mod include_cache;
 pub mod input;
 pub mod lexer;
 pub mod message;
 pub mod minor;
 pub mod preprocessor;
+pub mod realize;
+pub mod state_file;
 pub mod token;
 pub mod tu;
 pub mod tuctx;
+pub mod verify;
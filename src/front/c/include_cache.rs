@@ -0,0 +1,121 @@
+// Licensed   under  the   Apache  License,   Version  2.0   <LICENSE-APACHE  or
+// http://www.apache.org/licenses/LICENSE-2.0> or  the MIT  license <LICENSE-MIT
+// or http://opensource.org/licenses/MIT>, at your option.  This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Reusing the lexed lines of included files between translation units
+//!
+//! An editor re-preprocesses a unit after every change, although the headers
+//! it includes rarely change. Each [`TranslationUnit`][tu] has an
+//! [`IncludeCache`], which is empty by default. Passing the cache of a unit
+//! translated earlier to [`TranslationUnitBuilder::include_cache()`][ic] lets
+//! the new unit skip phases 1 through 3 for every header whose content is
+//! unchanged. Headers are still searched for and read, and their directives
+//! are still processed, since those depend on the macros defined around them.
+//!
+//! [tu]: crate::front::c::tu::TranslationUnit
+//! [ic]: crate::front::c::tu::TranslationUnitBuilder::include_cache
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use crate::front::c::input::Input;
+use crate::front::c::lexer::LexOptions;
+use crate::front::c::token::{PPToken, TokenOrigin};
+use crate::session::Session;
+
+/// Give lines lexed from another inclusion of the same file `input` as their
+/// origin
+pub(super) fn set_origin_input(lines: &mut [Vec<PPToken>], input: &Input) {
+    for token in lines.iter_mut().flatten() {
+        if let TokenOrigin::Source(span) = &mut token.origin {
+            span.pos.input = input.id;
+        }
+    }
+}
+
+/// Everything lexing an included file depends on
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    content_hash: u64,
+    trigraphs: bool,
+    keep_comments: bool,
+    lex_options: LexOptions,
+}
+
+impl CacheKey {
+    fn new(session: &Session, input: &Input) -> CacheKey {
+        CacheKey {
+            content_hash: input.content.get_hash(),
+            trigraphs: session.standard().has_trigraphs(),
+            keep_comments: session.flags().keep_comments,
+            lex_options: LexOptions::new(session),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    content: String,
+    lines: Vec<Vec<PPToken>>,
+}
+
+/// The lexed lines of included files, keyed by their content
+///
+/// Only files that lexed without any message are cached, since a cached file
+/// would not report them again.
+#[derive(Debug, Default)]
+pub struct IncludeCache {
+    entries: RefCell<HashMap<CacheKey, CacheEntry>>,
+    hits: Cell<usize>,
+    misses: Cell<usize>,
+}
+
+impl IncludeCache {
+    pub fn new() -> IncludeCache {
+        IncludeCache::default()
+    }
+
+    /// The lines of a file with the same content lexed earlier, if any
+    ///
+    /// The tokens are given `input` as their origin.
+    pub fn lookup(&self, session: &Session, input: &Input) -> Option<Vec<Vec<PPToken>>> {
+        let entries = self.entries.borrow();
+        let entry = entries
+            .get(&CacheKey::new(session, input))
+            .filter(|entry| entry.content == *input.content);
+        let entry = match entry {
+            Some(entry) => entry,
+            None => {
+                self.misses.set(self.misses.get() + 1);
+                return None;
+            },
+        };
+        self.hits.set(self.hits.get() + 1);
+
+        let mut lines = entry.lines.clone();
+        set_origin_input(&mut lines, input);
+        Some(lines)
+    }
+
+    /// Remember the lines `input` lexed into
+    pub fn store(&self, session: &Session, input: &Input, lines: &[Vec<PPToken>]) {
+        self.entries.borrow_mut().insert(
+            CacheKey::new(session, input),
+            CacheEntry {
+                content: input.content.to_string(),
+                lines: lines.to_vec(),
+            },
+        );
+    }
+
+    /// How many included files were found in the cache
+    pub fn hits(&self) -> usize {
+        self.hits.get()
+    }
+
+    /// How many included files had to be lexed
+    pub fn misses(&self) -> usize {
+        self.misses.get()
+    }
+}
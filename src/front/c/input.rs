@@ -5,6 +5,8 @@
 
 //! Input source code for compilation
 
+use std::cell::OnceCell;
+use std::io::Read;
 use std::path::PathBuf;
 use std::rc::Rc;
 
@@ -22,6 +24,59 @@ pub struct IncludedFrom {
     pub span: TextSpan,
 }
 
+/// A `#line` directive that changes how positions are reported
+///
+/// Every position in `input` at or after `after` has its line number (and
+/// optionally file name) presumed to be relative to the directive.
+#[derive(Clone, Debug)]
+pub struct LineOverride {
+    /// The id of the input containing the directive
+    pub input: u32,
+
+    /// The absolute position of the first character after the directive
+    pub after: u32,
+
+    /// The line number presumed for the line following the directive
+    pub line: u32,
+
+    /// The file name presumed for subsequent lines, if one was specified
+    pub name: Option<String>,
+}
+
+/// Positions of every newline within an input
+///
+/// Built the first time a position is resolved, so that each lookup is a
+/// binary search rather than a scan of the content.
+#[derive(Clone, Debug)]
+struct LineIndex {
+    /// Character offsets, as used by [`TextPosition`][tp]
+    ///
+    /// [tp]: crate::front::c::token::TextPosition
+    chars: Vec<u32>,
+    /// Byte offsets, for slicing the content
+    bytes: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(content: &str) -> LineIndex {
+        let mut chars = Vec::new();
+        let mut bytes = Vec::new();
+        for (i, (byte, c)) in content.char_indices().enumerate() {
+            if c == '\n' {
+                chars.push(i as u32);
+                bytes.push(byte);
+            }
+        }
+        LineIndex { chars, bytes }
+    }
+}
+
+/// The largest input in bytes that can be read
+///
+/// Positions within an input are `u32` character offsets, so a larger input
+/// could not be addressed.
+pub const MAX_INPUT_SIZE: u64 = u32::MAX as u64;
+
 /// An input to the compilation process
 #[derive(Clone, Debug)]
 pub struct Input {
@@ -29,42 +84,111 @@ pub struct Input {
     pub content: Hashed<String>,
     pub path: Option<PathBuf>,
     pub included_from: Option<IncludedFrom>,
+    /// Index of the `-I` directory this file was found in, if any
+    pub include_path_index: Option<usize>,
     pub depth: usize,
     pub id: u32,
-    newlines: Vec<u32>,
+    lines: OnceCell<LineIndex>,
 }
 
 impl Input {
     pub fn new(name: String, content: String, path: Option<PathBuf>) -> Self {
-        // TODO make this an Option generated on demand
-        let newlines = content
-            .chars()
-            .enumerate()
-            .filter(|&(_, c)| c == '\n')
-            .map(|(i, _)| i as u32)
-            .collect();
         let content = Hashed::new(content);
         Self {
             name,
             content,
             path,
             included_from: None,
+            include_path_index: None,
             depth: 0,
             id: 0,
-            newlines,
+            lines: OnceCell::new(),
+        }
+    }
+
+    /// Read an input from a file, stdin, or any other reader
+    ///
+    /// Fails if the content is not UTF-8 or exceeds [`MAX_INPUT_SIZE`].
+    pub fn from_reader(
+        name: String,
+        reader: impl Read,
+        path: Option<PathBuf>,
+    ) -> std::io::Result<Self> {
+        Self::from_reader_limited(name, reader, path, MAX_INPUT_SIZE)
+    }
+
+    /// Like [`from_reader()`][Input::from_reader], failing if the content
+    /// exceeds `limit` bytes
+    ///
+    /// Nothing past the limit is read.
+    pub fn from_reader_limited(
+        name: String,
+        reader: impl Read,
+        path: Option<PathBuf>,
+        limit: u64,
+    ) -> std::io::Result<Self> {
+        let mut content = String::new();
+        reader
+            .take(limit.saturating_add(1))
+            .read_to_string(&mut content)?;
+        if content.len() as u64 > limit {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("input is larger than {} bytes", limit),
+            ));
         }
+        Ok(Self::new(name, content, path))
+    }
+
+    fn lines(&self) -> &LineIndex {
+        self.lines.get_or_init(|| LineIndex::new(&self.content))
     }
 
     pub fn get_line_column(&self, absolute: u32) -> (u32, u32) {
-        let len = self.newlines.len();
-        match self.newlines.binary_search(&absolute) {
-            Ok(0) => (1, self.newlines[0] + 1),
-            Ok(i) => (i as u32 + 1, self.newlines[i] - self.newlines[i - 1]),
-            Err(0) => (1, absolute + 1),
-            Err(i) if i < len => (i as u32 + 1, absolute - self.newlines[i - 1]),
-            Err(_) => (len as u32 + 1, absolute - self.newlines.last().unwrap()),
+        let newlines = &self.lines().chars;
+        // a newline belongs to the line it ends
+        match newlines.binary_search(&absolute) {
+            Ok(i) | Err(i) if i == 0 => (1, absolute + 1),
+            Ok(i) | Err(i) => (i as u32 + 1, absolute - newlines[i - 1]),
         }
     }
+
+    /// Convert a character offset, as used by positions, into a byte offset
+    ///
+    /// Offsets past the end are clamped to the length of the content.
+    pub fn byte_offset(&self, absolute: u32) -> usize {
+        let lines = self.lines();
+        let (char_begin, byte_begin) = match lines.chars.binary_search(&absolute) {
+            Ok(i) => return lines.bytes[i],
+            Err(0) => (0, 0),
+            Err(i) => (lines.chars[i - 1] + 1, lines.bytes[i - 1] + 1),
+        };
+        self.content[byte_begin..]
+            .char_indices()
+            .nth((absolute - char_begin) as usize)
+            .map_or(self.content.len(), |(byte, _)| byte_begin + byte)
+    }
+
+    /// Get the text of a physical line, excluding the newline
+    ///
+    /// `line` starts from one, matching [`get_line_column()`][glc].
+    ///
+    /// [glc]: Input::get_line_column
+    pub fn get_line_text(&self, line: u32) -> &str {
+        let newlines = &self.lines().bytes;
+        let index = line as usize - 1;
+        if index > newlines.len() {
+            return "";
+        }
+        let begin = if index == 0 {
+            0
+        } else {
+            newlines[index - 1] + 1
+        };
+        let end = newlines.get(index).copied().unwrap_or(self.content.len());
+        let text = &self.content[begin..end];
+        text.strip_suffix('\r').unwrap_or(text)
+    }
 }
 
 #[cfg(test)]
@@ -95,4 +219,82 @@ mod test {
         assert_eq!(calc('f'), (2, 3));
         assert_eq!(calc('g'), (3, 1));
     }
+
+    /// Resolve a position by scanning from the start of the content
+    fn naive_line_column(content: &str, absolute: u32) -> (u32, u32) {
+        let (mut line, mut column) = (1, 1);
+        for c in content.chars().take(absolute as usize) {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        (line, column)
+    }
+
+    #[test]
+    fn test_input_line_column_large() {
+        let mut content = String::new();
+        for i in 0..2000 {
+            // lines of varying length, some empty and some multibyte
+            content.push_str(&"x".repeat(i % 37));
+            if i % 5 == 0 {
+                content.push_str("é∂");
+            }
+            content.push('\n');
+        }
+        content.push_str("last");
+        let input = Input::new("".to_owned(), content.clone(), None);
+
+        let lines: Vec<&str> = content.lines().collect();
+        let len = content.chars().count() as u32;
+        for absolute in (0..len).step_by(7).chain(len - 10..len) {
+            let (line, column) = input.get_line_column(absolute);
+            assert_eq!((line, column), naive_line_column(&content, absolute));
+            assert_eq!(input.get_line_text(line), lines[line as usize - 1]);
+        }
+        assert_eq!(input.get_line_text(lines.len() as u32 + 1), "");
+    }
+
+    #[test]
+    fn test_input_byte_offset() {
+        let content = "aé\n日b\n";
+        let input = Input::new("".to_owned(), content.to_owned(), None);
+        for (absolute, (byte, _)) in content.char_indices().enumerate() {
+            assert_eq!(input.byte_offset(absolute as u32), byte);
+        }
+        assert_eq!(input.byte_offset(6), content.len());
+        assert_eq!(input.byte_offset(100), content.len());
+    }
+
+    #[test]
+    fn test_input_line_text() {
+        let input = Input::new("".to_owned(), "a\r\n\nb\n".to_owned(), None);
+        assert_eq!(input.get_line_text(1), "a");
+        assert_eq!(input.get_line_text(2), "");
+        assert_eq!(input.get_line_text(3), "b");
+        assert_eq!(input.get_line_text(4), "");
+        assert_eq!(input.get_line_text(5), "");
+    }
+
+    #[test]
+    fn test_input_from_reader() {
+        let reader = std::io::Cursor::new("int x;\nint y;\n");
+        let input = Input::from_reader("<reader>".to_owned(), reader, None).unwrap();
+        assert_eq!(*input.content, "int x;\nint y;\n");
+        assert_eq!(input.get_line_text(2), "int y;");
+
+        let error = Input::from_reader("<reader>".to_owned(), &[b'a', 0xff][..], None);
+        assert_eq!(error.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+        let limited = |content: &str, limit| {
+            Input::from_reader_limited("<reader>".to_owned(), content.as_bytes(), None, limit)
+        };
+        assert_eq!(*limited("abcd", 4).unwrap().content, "abcd");
+        let error = limited("abcde", 4).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "input is larger than 4 bytes");
+    }
 }
@@ -12,10 +12,14 @@ use log::{debug, log_enabled, trace};
 use regex::{Regex, RegexSet};
 
 use super::token::TokenOrigin;
+use crate::core::CStandard;
 use crate::front::c::input::Input;
-use crate::front::c::message::MessageKind;
-use crate::front::c::token::{CharToken, PPToken, PPTokenKind};
+use crate::front::c::message::{Message, MessageKind};
+use crate::front::c::minor::{convert_trigraphs, Splice, Trigraphs};
+use crate::front::c::token::{CharToken, PPToken, PPTokenKind, TextPosition, TextSpan};
+use crate::front::c::tu::TranslationUnit;
 use crate::front::c::tuctx::TUCtx;
+use crate::session::Session;
 
 static TOKEN_PATTERNS: &[(&'static str, PPTokenKind)] = &[
     ("^.", PPTokenKind::Other),
@@ -87,13 +91,11 @@ fn find_match(input: &str, index: usize) -> &str {
     regex.find(input).unwrap().as_str()
 }
 
-/// Categorize the first token of the input string
+/// Categorize the first token of the input string using [`TOKEN_PATTERNS`]
 ///
-/// Returns the slice containing the entire token plus its kind. This slice may
-/// be less than the input string if the input lexes as more than one token.
-///
-/// The input must be non-empty.
-pub fn lex_one_token(input: &str) -> (&str, PPTokenKind) {
+/// Every pattern is tried, and the longest match wins. Ties go to the pattern
+/// listed later.
+fn lex_one_token_regex(input: &str) -> (&str, PPTokenKind) {
     // choose longest match
     let mut matches: Vec<(&str, usize)> = REGEX_SET
         .matches(input)
@@ -113,6 +115,133 @@ pub fn lex_one_token(input: &str) -> (&str, PPTokenKind) {
     (slice, kind)
 }
 
+/// Settings that change how text is split into tokens
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LexOptions {
+    /// Whether `$` may appear in identifiers, as a GNU extension
+    pub dollars_in_identifiers: bool,
+    /// Whether `//` begins a comment, which C89 does not allow
+    pub no_line_comments: bool,
+}
+
+impl LexOptions {
+    pub fn new(session: &Session) -> LexOptions {
+        LexOptions {
+            dollars_in_identifiers: session.flags().dollars_in_identifiers,
+            no_line_comments: session.standard() < CStandard::C99,
+        }
+    }
+}
+
+/// Number of leading bytes satisfying `predicate`
+fn prefix_len(input: &[u8], predicate: impl Fn(u8) -> bool) -> usize {
+    input
+        .iter()
+        .position(|&b| !predicate(b))
+        .unwrap_or(input.len())
+}
+
+/// Length of the preprocessing number at the start of `input`
+///
+/// The input must begin with a digit, or a period followed by a digit. This
+/// consumes the same text as the `PPNumber` pattern.
+fn pp_number_len(input: &[u8]) -> usize {
+    let mut i = if input[0] == b'.' { 2 } else { 1 };
+    loop {
+        match input.get(i..) {
+            Some([b'e' | b'E' | b'p' | b'P', b'+' | b'-', ..]) => i += 2,
+            Some([b, ..]) if b.is_ascii_alphanumeric() || *b == b'_' || *b == b'.' => i += 1,
+            _ => return i,
+        }
+    }
+}
+
+/// Categorize the first token of the input string
+///
+/// Returns the slice containing the entire token plus its kind. This slice may
+/// be less than the input string if the input lexes as more than one token.
+///
+/// Whitespace, identifiers, and numbers are recognized directly, since no
+/// other pattern can match more of the input than they do. Anything else falls
+/// back to trying every pattern.
+///
+/// The input must be non-empty.
+pub fn lex_one_token(input: &str) -> (&str, PPTokenKind) {
+    lex_one_token_with(input, LexOptions::default())
+}
+
+/// Categorize the first token of the input string according to `options`
+///
+/// See [`lex_one_token()`].
+pub fn lex_one_token_with(input: &str, options: LexOptions) -> (&str, PPTokenKind) {
+    let dollars = options.dollars_in_identifiers;
+    let bytes = input.as_bytes();
+    let (len, kind) = match bytes {
+        // without line comments, `//` is two punctuators, and the second may
+        // still begin a `/*` comment
+        [b'/', b'/', ..] if options.no_line_comments => (1, PPTokenKind::Punctuator),
+        [b' ' | b'\t' | b'\x0b' | b'\x0c' | b'\r', ..] => (
+            prefix_len(bytes, |b| {
+                matches!(b, b' ' | b'\t' | b'\x0b' | b'\x0c' | b'\r')
+            }),
+            PPTokenKind::Whitespace,
+        ),
+        [b'\n', ..] => (1, PPTokenKind::Whitespace),
+        // prefixes of character constants and string literals
+        [b'L' | b'u' | b'U', b'\'' | b'"', ..] | [b'u', b'8', b'"', ..] => {
+            return lex_one_token_regex(input)
+        },
+        [b'a'..=b'z' | b'A'..=b'Z' | b'_', ..] | [b'$', ..] if dollars || bytes[0] != b'$' => (
+            prefix_len(bytes, |b| {
+                b.is_ascii_alphanumeric() || b == b'_' || (dollars && b == b'$')
+            }),
+            PPTokenKind::Identifier,
+        ),
+        [b'0'..=b'9', ..] | [b'.', b'0'..=b'9', ..] => {
+            (pp_number_len(bytes), PPTokenKind::PPNumber)
+        },
+        _ => return lex_one_token_regex(input),
+    };
+
+    (&input[..len], kind)
+}
+
+/// Length of the header name at the start of `input`, if there is one
+///
+/// A header name is either `<...>` or `"..."` on a single line, with at least
+/// one character between the delimiters (C11 6.4.7).
+fn header_name_len(input: &str) -> Option<usize> {
+    let close = match input.as_bytes().first()? {
+        b'<' => '>',
+        b'"' => '"',
+        _ => return None,
+    };
+    let end = input[1..].find([close, '\n'])? + 1;
+    if end == 1 || !input[end..].starts_with(close) {
+        return None;
+    }
+    Some(end + 1)
+}
+
+/// Whether a header name may follow the given tokens at the start of a line
+///
+/// This is the case for the operand of `#include` and `#include_next`, and of
+/// `__has_include` and `__has_include_next` within `#if` and `#elif`.
+fn expects_header_name(line: &[String]) -> bool {
+    let line: Vec<&str> = line.iter().map(String::as_str).collect();
+    matches!(
+        line[..],
+        ["#" | "%:", "include" | "include_next"]
+            | [
+                "#" | "%:",
+                "if" | "elif",
+                ..,
+                "__has_include" | "__has_include_next",
+                "("
+            ]
+    )
+}
+
 /// Test if all tokens resulting from lexer have the correct input
 fn test_correct_input(tokens: &[PPToken], input: u32) -> bool {
     tokens.iter().all(|t| match t.origin {
@@ -121,24 +250,184 @@ fn test_correct_input(tokens: &[PPToken], input: u32) -> bool {
     })
 }
 
+/// Test if a token's value is the source text its span covers
+///
+/// The lexer sees text after trigraphs are replaced and lines are spliced, so
+/// those are undone before comparing.
+fn matches_source_text(tuctx: &TUCtx, token: &PPToken) -> bool {
+    let text = token.origin.as_source().text(tuctx);
+    if text == token.as_str() {
+        return true;
+    }
+    let replaced = CharToken::to_string(&convert_trigraphs(CharToken::from_str(0, text)));
+    replaced.replace("\\\n", "") == *token.value
+}
+
+/// A position in the input where [`MappedText`] stops following on from the
+/// previous character
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Jump {
+    /// Index of the character within the text
+    at: u32,
+    /// Where that character begins in the input
+    absolute: u32,
+    /// How many characters of the input it replaced
+    len: u32,
+}
+
+/// Text after phases 1 and 2, along with where each character came from
+///
+/// Storing a [`CharToken`] per character takes several times the memory of the
+/// text itself. Most characters follow directly on from the previous one, so
+/// only the exceptions are stored: each character a trigraph was replaced by,
+/// and each character following a line splice.
+#[derive(Clone, Debug)]
+pub struct MappedText {
+    text: String,
+    input: u32,
+    jumps: Vec<Jump>,
+}
+
+impl MappedText {
+    /// Collect the characters given by phases 1 and 2
+    pub fn from_chars(input: u32, chars: impl Iterator<Item = CharToken>) -> MappedText {
+        let mut text = String::with_capacity(chars.size_hint().0);
+        let mut jumps = Vec::new();
+        let mut next_absolute = 0;
+        for (at, token) in chars.enumerate() {
+            debug_assert_eq!(token.span.pos.input, input);
+            text.push(token.value);
+            let span = token.span;
+            if span.pos.absolute != next_absolute || span.len != 1 {
+                jumps.push(Jump {
+                    at: at as u32,
+                    absolute: span.pos.absolute,
+                    len: span.len,
+                });
+            }
+            next_absolute = span.end().absolute;
+        }
+        MappedText { text, input, jumps }
+    }
+
+    /// Run phases 1 and 2 on an input
+    pub fn from_input(tuctx: &mut TUCtx, input: &Input) -> MappedText {
+        let chars = CharToken::iter_str(input.id, &input.content);
+        let chars: Box<dyn Iterator<Item = CharToken>> =
+            if tuctx.session().standard().has_trigraphs() {
+                Box::new(Trigraphs::new(chars))
+            } else {
+                Box::new(chars)
+            };
+        let mut splice = Splice::new(chars);
+        let text = MappedText::from_chars(input.id, splice.by_ref());
+        splice.report(tuctx);
+        text
+    }
+
+    /// The text of an input as written, skipping phases 1 and 2
+    pub fn verbatim(input: &Input) -> MappedText {
+        MappedText::from_chars(input.id, CharToken::iter_str(input.id, &input.content))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// The span of the input the character at index `at` came from
+    pub fn span(&self, at: u32) -> TextSpan {
+        let (absolute, len) = match self.jumps.partition_point(|j| j.at <= at) {
+            0 => (at, 1),
+            i => {
+                let jump = self.jumps[i - 1];
+                if jump.at == at {
+                    (jump.absolute, jump.len)
+                } else {
+                    (jump.absolute + jump.len + (at - jump.at - 1), 1)
+                }
+            },
+        };
+        TextSpan {
+            pos: TextPosition {
+                input: self.input,
+                absolute,
+            },
+            len,
+        }
+    }
+}
+
 /// Categorize all tokens given by the input token sequence
 pub fn lex(tuctx: &mut TUCtx, tokens: Vec<CharToken>, input: &Rc<Input>) -> Vec<PPToken> {
+    let text = MappedText::from_chars(input.id, tokens.into_iter());
+    lex_text(tuctx, &text, input)
+}
+
+/// Run phases 1 through 3 on an input
+///
+/// This gives the same tokens as [`lex()`] after [`convert_trigraphs()`] and
+/// [`splice_lines()`][sl], without a [`CharToken`] for every character.
+///
+/// [sl]: crate::front::c::minor::splice_lines
+pub fn lex_input(tuctx: &mut TUCtx, input: &Rc<Input>) -> Vec<PPToken> {
+    let text = MappedText::from_input(tuctx, input);
+    lex_text(tuctx, &text, input)
+}
+
+/// Categorize all tokens of text that has been through phases 1 and 2
+pub fn lex_text(tuctx: &mut TUCtx, text: &MappedText, input: &Rc<Input>) -> Vec<PPToken> {
     debug_assert!(Rc::ptr_eq(&tuctx.inputs[input.id as usize], &input));
-    let string = CharToken::to_string(&tokens);
-    debug_assert_eq!(tokens.len(), string.len());
+    debug_assert_eq!(text.input, input.id);
+    let string = text.as_str();
 
+    // `i` indexes bytes of `string` while `t` indexes its chars
     let mut i = 0;
+    let mut t = 0;
     let mut output = Vec::new();
+    let keep_comments = tuctx.tu.session.flags().keep_comments;
+    let options = LexOptions::new(&tuctx.tu.session);
+    // the tokens of the current line if it is a directive, which decide
+    // whether a header name may appear next
+    let mut line: Option<Vec<String>> = Some(Vec::new());
 
-    while i < string.len() {
+    while i < string.len() && !tuctx.should_abort() {
         trace!("lex() i={:?} string[i..]={:?}", i, &string[i..]);
-        let (slice, kind) = lex_one_token(&string[i..]);
+        let header_name = match &line {
+            Some(line) if expects_header_name(line) => header_name_len(&string[i..]),
+            _ => None,
+        };
+        let (slice, mut kind) = match header_name {
+            Some(len) => (&string[i..i + len], PPTokenKind::HeaderName),
+            None => lex_one_token_with(&string[i..], options),
+        };
+        if keep_comments && kind == PPTokenKind::Whitespace && slice.starts_with('/') {
+            kind = PPTokenKind::Comment;
+        }
+
+        if slice == "\n" {
+            line = Some(Vec::new());
+        } else if kind != PPTokenKind::Whitespace && kind != PPTokenKind::Comment {
+            match &mut line {
+                Some(l) if !l.is_empty() || slice == "#" || slice == "%:" => {
+                    l.push(slice.to_owned())
+                },
+                _ => line = None,
+            }
+        }
         debug!("lex() slice={:?} kind={:?}", slice, kind);
 
-        let len = slice.len();
-        let first = &tokens[i];
-        let last = &tokens[i + len - 1];
-        i += len;
+        let len = slice.chars().count() as u32;
+        let first = text.span(t);
+        let span = first.merge(&text.span(t + len - 1));
+        if kind == PPTokenKind::Identifier && slice.contains('$') {
+            tuctx.emit_message(span, MessageKind::Phase3DollarInIdentifier);
+        }
+        if options.no_line_comments && string[i..].starts_with("//") && !string[..i].ends_with('/')
+        {
+            tuctx.emit_message(first, MessageKind::Phase3LineCommentBeforeC99);
+        }
+        i += slice.len();
+        t += len;
 
         if kind == PPTokenKind::Other && slice.starts_with("'") {
             // A properly terminated string would've matched the StringLiteral
@@ -146,29 +435,28 @@ pub fn lex(tuctx: &mut TUCtx, tokens: Vec<CharToken>, input: &Rc<Input>) -> Vec<
 
             // TODO move to phase7
             tuctx.emit_message(
-                first.span,
+                first,
                 MessageKind::Phase3MissingTerminator { terminator: '\'' },
             );
 
             // skip ahead
             // where should we stop? newline?
-            while i < string.len() && tokens[i].value != '\n' {
-                i += 1;
+            for c in string[i..].chars().take_while(|&c| c != '\n') {
+                i += c.len_utf8();
+                t += 1;
             }
         } else {
             // CharTokens may have length greater than one because of trigraphs
-            let mut span = first.span;
-            span.len = last.span.pos.absolute + last.span.len - first.span.pos.absolute;
-
             output.push(PPToken {
                 kind,
-                value: slice.to_owned(),
+                value: slice.into(),
                 origin: TokenOrigin::Source(span),
             })
         }
     }
 
     debug_assert!(test_correct_input(&output, input.id));
+    debug_assert!(output.iter().all(|t| matches_source_text(tuctx, t)));
 
     if log_enabled!(log::Level::Trace) {
         for (i, token) in output.iter().enumerate() {
@@ -179,10 +467,43 @@ pub fn lex(tuctx: &mut TUCtx, tokens: Vec<CharToken>, input: &Rc<Input>) -> Vec<
     output
 }
 
+/// Lex a string without running any passes
+///
+/// The text is treated as the only input of an anonymous translation unit, so
+/// trigraphs and line splices are not processed beforehand. Returns the tokens
+/// along with any messages.
+///
+/// ```
+/// use denuocc::front::c::lexer::lex_str;
+/// use denuocc::front::c::token::PPTokenKind::*;
+///
+/// let (tokens, messages) = lex_str("int x = 3;");
+/// let kinds: Vec<_> = tokens
+///     .iter()
+///     .filter(|t| !t.is_whitespace())
+///     .map(|t| t.kind)
+///     .collect();
+/// assert_eq!(kinds, [Identifier, Identifier, Punctuator, PPNumber, Punctuator]);
+/// assert!(messages.is_empty());
+/// ```
+pub fn lex_str(text: &str) -> (Vec<PPToken>, Vec<Message>) {
+    let session = Session::builder().build();
+    let mut tu = TranslationUnit::builder(&session)
+        .source_string("<string>", text)
+        .build();
+
+    let mut tuctx = TUCtx::from_tu(&mut tu);
+    let input = Rc::clone(tuctx.original_input());
+    let tokens = lex_text(&mut tuctx, &MappedText::verbatim(&input), &input);
+
+    (tokens, tu.messages)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::front::c::message::Message;
+    use crate::test_util::unit;
 
     fn phase3(input: &str) -> (Vec<PPToken>, Vec<Message>) {
         let session = crate::Session::builder()
@@ -263,10 +584,10 @@ mod test {
 
         let (tokens, _) = phase3("test /* whitespace */");
         assert_eq!(tokens.len(), 3);
-        assert_eq!(tokens[0].value, "test");
+        assert_eq!(tokens[0].as_str(), "test");
         assert_eq!(tokens[0].kind, PPTokenKind::Identifier);
         assert_eq!(tokens[1].kind, PPTokenKind::Whitespace);
-        assert_eq!(tokens[2].value, "/* whitespace */");
+        assert_eq!(tokens[2].as_str(), "/* whitespace */");
         assert_eq!(tokens[2].kind, PPTokenKind::Whitespace);
 
         let (tokens, _) = phase3("/* \n */");
@@ -276,6 +597,227 @@ mod test {
         // TODO other comment examples in 6.4.9
     }
 
+    #[test]
+    fn test_phase3_header_name() {
+        fn header_names(input: &str) -> Vec<String> {
+            let (tokens, _) = phase3(input);
+            tokens
+                .into_iter()
+                .filter(|t| t.kind == PPTokenKind::HeaderName)
+                .map(|t| t.value.to_string())
+                .collect()
+        }
+
+        assert_eq!(header_names("#include <a/b.h>\n"), ["<a/b.h>"]);
+        assert_eq!(header_names("%: include_next \"a b.h\"\n"), ["\"a b.h\""]);
+        assert_eq!(header_names("#include <a//b.h>\n"), ["<a//b.h>"]);
+        assert_eq!(
+            header_names("#if __has_include(<a.h>)\n#endif\n"),
+            ["<a.h>"]
+        );
+
+        // only directly following the directive
+        assert!(header_names("x #include <a.h>\n").is_empty());
+        assert!(header_names("#define x <a.h>\n").is_empty());
+        assert!(header_names("#include\n<a.h>\n").is_empty());
+        assert!(header_names("#include <>\n").is_empty());
+        assert!(header_names("#include <a.h\n>\n").is_empty());
+    }
+
+    /// Run phases 1 through 3 with extra arguments, returning the token
+    /// values and messages
+    fn lex_with(args: &[&str], text: &str) -> (Vec<String>, Vec<String>) {
+        let mut args = args.to_vec();
+        args.extend(&["--pass=phase1..phase3", "--pass=state_save(pptokens)"]);
+        let mut tu = unit(&args, text);
+        tu.run().unwrap();
+
+        let tokens = tu.saved_states("pptokens")[0]
+            .as_pptokens()
+            .unwrap()
+            .iter()
+            .map(|t| t.value.to_string())
+            .collect();
+        let messages = tu.messages().iter().map(|m| m.to_string()).collect();
+        (tokens, messages)
+    }
+
+    #[test]
+    fn test_phase3_dollars_in_identifiers() {
+        let (tokens, messages) = lex_with(&[], "foo$bar");
+        assert_eq!(tokens, ["foo", "$", "bar"]);
+        assert!(messages.is_empty());
+
+        let enabled = ["--dollars-in-identifiers"];
+        let (tokens, messages) = lex_with(&enabled, "foo$bar $x");
+        assert_eq!(tokens, ["foo$bar", " ", "$x"]);
+        assert!(messages.is_empty());
+
+        let pedantic = [
+            "--dollars-in-identifiers",
+            "-Wdollar-in-identifier-extension",
+        ];
+        let (_, messages) = lex_with(&pedantic, "foo$bar");
+        assert_eq!(messages, ["<unit-test>:1:1: `$` in identifier"]);
+    }
+
+    #[test]
+    fn test_phase3_line_comments_by_standard() {
+        let (tokens, messages) = lex_with(&["--std=c99"], "a // x\n");
+        assert_eq!(tokens, ["a", " ", "// x", "\n"]);
+        assert!(messages.is_empty());
+
+        let (tokens, messages) = lex_with(&["--std=c89"], "a // x\n");
+        assert_eq!(tokens, ["a", " ", "/", "/", " ", "x", "\n"]);
+        assert!(messages.is_empty());
+
+        // the second slash may still begin a block comment
+        let (tokens, _) = lex_with(&["--std=c89"], "a //**/ b\n");
+        assert_eq!(tokens, ["a", " ", "/", "/**/", " ", "b", "\n"]);
+
+        let args = ["--std=c89", "-Wc99-extensions"];
+        let (_, messages) = lex_with(&args, "a // x\n");
+        assert_eq!(
+            messages,
+            ["<unit-test>:1:3: `//` comments require C99; lexed as two `/` instead"]
+        );
+    }
+
+    #[test]
+    fn test_phase3_keep_comments() {
+        let mut tu = unit(
+            &[
+                "--keep-comments",
+                "--pass=phase1..phase3",
+                "--pass=state_save(pptokens)",
+            ],
+            "/** doc */ int x; // line\n",
+        );
+        tu.run().unwrap();
+
+        let tokens = tu.saved_states("pptokens")[0].as_pptokens().unwrap();
+        let comments: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.kind == PPTokenKind::Comment)
+            .map(PPToken::as_str)
+            .collect();
+        assert_eq!(comments, ["/** doc */", "// line"]);
+        assert!(tokens
+            .iter()
+            .all(|t| t.kind != PPTokenKind::Comment || t.is_whitespace()));
+        assert_eq!(tokens.last().unwrap().kind, PPTokenKind::Whitespace);
+    }
+
+    #[test]
+    fn test_phase3_source_text() {
+        let mut tu = unit(
+            &["--pass=phase1..phase3"],
+            "??=define a\\\nb ??( x??/\ny 日\n",
+        );
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+
+        let tokens = tuctx.get_state().unwrap().as_pptokens().unwrap();
+        let texts: Vec<_> = tokens
+            .iter()
+            .filter(|t| !t.is_whitespace())
+            .map(|t| (t.as_str(), t.origin.as_source().text(&tuctx)))
+            .collect();
+        // tokens containing trigraphs or splices differ from their source text
+        assert_eq!(
+            texts,
+            [
+                ("#", "??="),
+                ("define", "define"),
+                ("ab", "a\\\nb"),
+                ("[", "??("),
+                ("xy", "x??/\ny"),
+                ("日", "日"),
+            ]
+        );
+        assert!(tokens.iter().all(|t| matches_source_text(&tuctx, t)));
+    }
+
+    #[test]
+    fn test_lex_one_token_matches_patterns() {
+        let mut source = String::from(
+            "u8\"s\" u8'c' L'x' U\"y\" uvar _x1 Lfoo 0x1p+3 1.2.3e-4 .5e+ .. ... \
+             a.b 0.x 1_0 \t\x0b\x0c\r\n /* c */ // c\n ->* %:%: <<= 日本 @ $ \\\n",
+        );
+        for i in 0..200 {
+            source.push_str(&format!(
+                "int v{} = f(x{}, 0x{:x}u) + .{}e-{};\n",
+                i, i, i, i, i
+            ));
+        }
+
+        // the shortcuts must agree with trying every pattern, at any offset
+        for (i, _) in source.char_indices() {
+            let input = &source[i..];
+            assert_eq!(
+                lex_one_token(input),
+                lex_one_token_regex(input),
+                "input: {:?}",
+                &input[..input.len().min(10)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_phase3_form_feed() {
+        let mut tu = unit(
+            &[
+                "--pass=phase1..phase2",
+                "--pass=state_save(chartokens)",
+                "--pass=phase3",
+                "--pass=state_save(pptokens)",
+            ],
+            "a\x0c\\\n\x0bb\r\n",
+        );
+        tu.run().unwrap();
+
+        let chars = tu.saved_states("chartokens")[0]
+            .clone()
+            .into_chartokens()
+            .unwrap();
+        let whitespace: String = chars
+            .iter()
+            .filter(|t| t.is_whitespace())
+            .map(|t| t.value)
+            .collect();
+        assert_eq!(whitespace, "\x0c\x0b\r\n");
+
+        // the same characters form whitespace tokens
+        let tokens = tu.saved_states("pptokens")[0]
+            .clone()
+            .into_pptokens()
+            .unwrap();
+        let texts: Vec<_> = tokens.iter().map(|t| (t.kind, t.as_str())).collect();
+        assert_eq!(
+            texts,
+            [
+                (PPTokenKind::Identifier, "a"),
+                (PPTokenKind::Whitespace, "\x0c\x0b"),
+                (PPTokenKind::Identifier, "b"),
+                (PPTokenKind::Whitespace, "\r"),
+                (PPTokenKind::Whitespace, "\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_phase3_multibyte() {
+        let (tokens, _) = phase3("/* 日本 */ \"é\" x");
+        let tokens: Vec<_> = tokens.iter().filter(|t| !t.is_whitespace()).collect();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].kind, PPTokenKind::StringLiteral);
+        assert_eq!(tokens[0].as_str(), "\"é\"");
+        // spans count characters rather than bytes
+        assert_eq!(tokens[0].origin.as_source().pos.absolute, 9);
+        assert_eq!(tokens[0].origin.as_source().len, 3);
+        assert_eq!(tokens[1].origin.as_source().pos.absolute, 13);
+    }
+
     #[test]
     fn test_phase3_ppnumber() {
         fn case(input: &str) {
@@ -291,6 +833,17 @@ mod test {
         case(".01234abc_def56789.");
         case("0e-");
         case("0P+.");
+
+        // periods may appear anywhere after the first digit (C11 6.4.8), so
+        // these are single tokens even though they are not valid constants
+        case("1.2.3");
+        case("1..2");
+        case("0x1.8p-3.f");
+
+        // a sign only continues the number after an exponent letter
+        let (tokens, _) = phase3("1.2+3");
+        let texts: Vec<_> = tokens.iter().map(|t| t.as_str()).collect();
+        assert_eq!(texts, ["1.2", "+", "3"]);
     }
 
     #[test]
@@ -326,5 +879,104 @@ mod test {
         }
     }
 
+    /// Run phases 1 through 3 with [`lex_input()`] and with the passes, which
+    /// collect a [`CharToken`] per character
+    fn lex_both_ways(source: &str) -> (Vec<PPToken>, Vec<PPToken>, Vec<Message>, Vec<Message>) {
+        use crate::front::c::minor::splice_lines;
+
+        let mut tu = unit(&[], source);
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        let input = Rc::clone(tuctx.original_input());
+        let direct = lex_input(&mut tuctx, &input);
+        let direct_messages = std::mem::take(&mut tuctx.tu.messages);
+
+        let phase1 = convert_trigraphs(CharToken::from_input(&input));
+        let phase2 = splice_lines(&mut tuctx, phase1);
+        let collected = lex(&mut tuctx, phase2, &input);
+        let collected_messages = std::mem::take(&mut tuctx.tu.messages);
+        (direct, collected, direct_messages, collected_messages)
+    }
+
+    fn bounds(span: &TextSpan) -> (u32, u32, u32) {
+        (span.pos.input, span.pos.absolute, span.len)
+    }
+
+    #[test]
+    fn test_lex_input_matches_passes() {
+        for source in &[
+            "",
+            "a",
+            "??=define x ??/\ny\n",
+            "???= ??? ??\n",
+            "a\\\n\\\nb c\\",
+            "\"\u{e9}??<\u{1f600}\" x\\\n",
+            "'??' \\\\\n",
+            "d??/",
+        ] {
+            let (direct, collected, direct_messages, collected_messages) = lex_both_ways(source);
+            assert_eq!(direct, collected, "{:?}", source);
+            let origins = |tokens: &[PPToken]| -> Vec<(u32, u32, u32)> {
+                tokens
+                    .iter()
+                    .map(|t| bounds(t.origin.as_source()))
+                    .collect()
+            };
+            assert_eq!(origins(&direct), origins(&collected), "{:?}", source);
+            let headlines = |messages: &[Message]| -> Vec<(String, (u32, u32, u32))> {
+                messages
+                    .iter()
+                    .map(|m| (m.kind.get_headline(), bounds(m.origin.as_source())))
+                    .collect()
+            };
+            assert_eq!(
+                headlines(&direct_messages),
+                headlines(&collected_messages),
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_mapped_text_large_input() {
+        // a megabyte of source with a line splice every hundred lines
+        let mut source = String::new();
+        for i in 0..20_000 {
+            if i % 100 == 0 {
+                source.push_str("#define LONG_MACRO_NAME(x) \\\n    (x)\n");
+            } else {
+                source.push_str("int value_of_some_length = 12345;\n");
+            }
+        }
+        let input = Input::new("<unit-test>".to_owned(), source.clone(), None);
+        let chars: Vec<CharToken> = CharToken::from_input(&input);
+        let text = MappedText::from_chars(input.id, chars.iter().copied());
+
+        // one jump after each splice, rather than a span for every character
+        assert_eq!(text.jumps.len(), 0);
+        let spliced = MappedText::from_chars(input.id, Splice::new(chars.iter().copied()));
+        assert_eq!(spliced.jumps.len(), 200);
+
+        let size = spliced.text.capacity() + spliced.jumps.capacity() * std::mem::size_of::<Jump>();
+        let collected = chars.len() * std::mem::size_of::<CharToken>();
+        assert!(
+            size * 8 < collected,
+            "mapped text took {} bytes, characters {}",
+            size,
+            collected
+        );
+
+        // spans agree with collecting every character
+        let collected: Vec<CharToken> = Splice::new(chars.into_iter()).collect();
+        for (at, token) in collected.iter().enumerate().step_by(97) {
+            assert_eq!(bounds(&spliced.span(at as u32)), bounds(&token.span));
+        }
+        let last = collected.len() - 1;
+        assert_eq!(
+            bounds(&spliced.span(last as u32)),
+            bounds(&collected[last].span)
+        );
+    }
+
     // TODO test strings
 }
@@ -7,7 +7,8 @@
 
 use crate::core::{self, Severity};
 use crate::front::c::minor::Encoding;
-use crate::front::c::token::{PPTokenKind, TextPositionResolved, TokenOrigin};
+use crate::front::c::realize::{FloatType, Radix};
+use crate::front::c::token::{PPTokenKind, TextPosition, TextPositionResolved, TokenOrigin};
 use crate::front::c::tuctx::TUCtx;
 
 /// Reusable element for [`MessageKind::ExpectedFound`][MessageKind::ExpectedFound]
@@ -41,12 +42,15 @@ pub enum MessageKind {
     Phase3MissingTerminator {
         terminator: char,
     },
+    Phase3DollarInIdentifier,
+    Phase3LineCommentBeforeC99,
     Phase4UnexpectedDirective {
         directive: String,
     },
     Phase4InvalidDirective {
         directive: String,
     },
+    Phase4NonDirective,
     Phase4DefineOperator,
     Phase4MacroArity {
         name: String,
@@ -54,6 +58,11 @@ pub enum MessageKind {
         found: usize,
         vararg: bool,
     },
+    Phase4MacroDefinedParameters {
+        name: String,
+        count: usize,
+        vararg: bool,
+    },
     Phase4MacroRedefinitionDifferent {
         name: String,
     },
@@ -70,11 +79,22 @@ pub enum MessageKind {
     Phase4MacroInvocationOpening {
         name: String,
     },
+    Phase4MacroArgumentCommaInBrackets {
+        name: String,
+        bracket: String,
+    },
+    Phase4UnclosedBracketOpening {
+        bracket: String,
+    },
     Phase4RepeatedMacroParameter {
         parameter: String,
     },
     Phase4IllegalSingleHash,
     Phase4IllegalDoubleHash,
+    Phase4VaOptBeforeC2x,
+    Phase4VaOptNotVariadic,
+    Phase4VaOptNested,
+    Phase4VaOptUnterminated,
     Phase4BadConcatenation {
         lhs: String,
         rhs: String,
@@ -85,9 +105,52 @@ pub enum MessageKind {
         kind: PPTokenKind,
     },
     Phase4IncludeDepth,
+    Phase4MacroDepth,
+    Phase4ExpansionTooLarge,
     Phase4IncludeNotFound {
         desired_file: String,
     },
+    Phase4IncludeIsDirectory {
+        desired_file: String,
+    },
+    Phase4IncludeUnreadable {
+        desired_file: String,
+        error: String,
+    },
+    Phase4IncludeDuplicate {
+        name: String,
+    },
+    Phase4IncludePrevious {
+        name: String,
+    },
+    Phase4IncludeNextInPrimary,
+    Phase4LineInvalidNumber {
+        value: String,
+    },
+    Phase4IfInvalidConstant {
+        value: String,
+    },
+    Phase4IfDivisionByZero,
+    Phase4IfEmpty,
+    Phase4C2xDirective {
+        directive: String,
+    },
+    Phase4GnuDirective {
+        directive: String,
+    },
+    Phase4EmptyMacroInIf {
+        name: String,
+    },
+    Phase4IfUndefinedIdentifier {
+        name: String,
+    },
+    Phase4ExtraTokens {
+        directive: String,
+    },
+    Phase4PragmaDiagnosticInvalid,
+    Phase4PragmaUnknownWarning {
+        option: String,
+    },
     Phase5Empty,
     Phase5OutOfRange {
         prefix: &'static str,
@@ -110,6 +173,37 @@ pub enum MessageKind {
         previous: Encoding,
         current: Encoding,
     },
+    VerifyExpectationNotSeen {
+        severity: &'static str,
+        text: String,
+    },
+    TooManyErrors,
+    Phase7NumberSuffixInvalid {
+        suffix: String,
+        radix: Radix,
+    },
+    Phase7IntegerMalformed {
+        value: String,
+    },
+    Phase7IntegerOutOfRange {
+        value: String,
+        radix: Radix,
+    },
+    Phase7InvalidOctalDigit {
+        digit: char,
+    },
+    Phase7FloatMalformed {
+        value: String,
+    },
+    Phase7FloatOutOfRange {
+        value: String,
+        ty: FloatType,
+    },
+    Phase7CharacterEmpty,
+    Phase7CharacterOutOfRange {
+        value: String,
+        encoding: Encoding,
+    },
 }
 
 impl MessageKind {
@@ -121,16 +215,21 @@ impl MessageKind {
         use MessageKind::*;
         match &self {
             ExpectedFound { expected, found } => format!("expected {}; found {}", expected, found),
-            Phase1FileEndingWithBackslash => format!("file cannot end with a backslash"),
+            Phase1FileEndingWithBackslash => "file cannot end with a backslash".to_owned(),
             Phase3MissingTerminator { terminator } => {
                 format!("missing closing {} terminator", terminator)
             },
+            Phase3DollarInIdentifier => "`$` in identifier".to_owned(),
+            Phase3LineCommentBeforeC99 => {
+                "`//` comments require C99; lexed as two `/` instead".to_owned()
+            },
             Phase4UnexpectedDirective { directive } => {
                 format!("unexpected directive `{}`", &directive)
             },
             Phase4InvalidDirective { directive } => format!("invalid directive `{}`", &directive),
+            Phase4NonDirective => "expected directive name after `#`; ignoring line".to_owned(),
             Phase4DefineOperator => {
-                format!("expected identifier or left-paren after define operator")
+                "expected identifier or left-paren after define operator".to_owned()
             },
             Phase4MacroArity {
                 name,
@@ -138,16 +237,27 @@ impl MessageKind {
                 found,
                 vararg,
             } => format!(
-                "`{}` expects {} {} {}; found {}",
+                "too {} arguments to macro `{}`; expected {} {}, found {}",
+                if found < expected { "few" } else { "many" },
                 name,
                 if *vararg { "at least" } else { "exactly" },
                 expected,
-                if *expected == 1 {
-                    "argument"
+                found
+            ),
+            Phase4MacroDefinedParameters {
+                name,
+                count,
+                vararg,
+            } => format!(
+                "macro `{}` defined here with {} {}{}",
+                name,
+                count,
+                if *count == 1 {
+                    "parameter"
                 } else {
-                    "arguments"
+                    "parameters"
                 },
-                found
+                if *vararg { " and `...`" } else { "" }
             ),
             Phase4MacroRedefinitionDifferent { name } => {
                 format!("macro `{}` redefined differently", name,)
@@ -160,31 +270,91 @@ impl MessageKind {
             Phase4MacroInvocationOpening { name } => {
                 format!("macro `{}` invocation opened here", name)
             },
+            Phase4MacroArgumentCommaInBrackets { name, bracket } => format!(
+                "comma within `{}` separates arguments to macro `{}`; only parentheses group \
+                 macro arguments",
+                bracket, name
+            ),
+            Phase4UnclosedBracketOpening { bracket } => format!("`{}` opened here", bracket),
             Phase4RepeatedMacroParameter { parameter } => {
                 format!("macro parameter `{}` repeated", parameter)
             },
             Phase4IllegalSingleHash => {
-                format!("the `#` operator must be followed by a macro parameter")
+                "the `#` operator must be followed by a macro parameter".to_owned()
+            },
+            Phase4IllegalDoubleHash => "a macro cannot begin nor end with `##`".to_owned(),
+            Phase4VaOptBeforeC2x => "`__VA_OPT__` requires C2x".to_owned(),
+            Phase4VaOptNotVariadic => {
+                "`__VA_OPT__` can only appear in the replacement of a variadic macro".to_owned()
             },
-            Phase4IllegalDoubleHash => format!("a macro cannot begin nor end with `##`"),
+            Phase4VaOptNested => "`__VA_OPT__` cannot appear within `__VA_OPT__`".to_owned(),
+            Phase4VaOptUnterminated => "unterminated `__VA_OPT__`".to_owned(),
             Phase4BadConcatenation { lhs, rhs } => format!(
                 "concatenating `{}` and `{}` does not result in a valid preprocessor token",
                 lhs, rhs
             ),
-            Phase4IncludeBegin => format!(
+            Phase4IncludeBegin => {
                 r#"expected `<FILENAME>`, `"FILENAME"`, or a macro that expands to either of those"#
-            ),
+                    .to_owned()
+            },
             Phase4IncludeUnclosed => {
-                format!("expected `>` to close corresponding `<` after `#include`",)
+                "expected `>` to close corresponding `<` after `#include`".to_owned()
             },
             Phase4IncludeExtra { kind } => {
                 format!("expected newline after <FILENAME>; found {}", kind)
             },
-            Phase4IncludeDepth => format!("maximum nested include depth exceeded"),
+            Phase4IncludeDepth => "maximum nested include depth exceeded".to_owned(),
+            Phase4MacroDepth => "maximum nested macro expansion depth exceeded".to_owned(),
+            Phase4ExpansionTooLarge => {
+                "macro expansion produced too many tokens; stopping expansion".to_owned()
+            },
             Phase4IncludeNotFound { desired_file } => {
                 format!("could not include `{}`: file not found", desired_file)
             },
-            Phase5Empty => format!("expected character after escape sequence"),
+            Phase4IncludeIsDirectory { desired_file } => {
+                format!("could not include `{}`: is a directory", desired_file)
+            },
+            Phase4IncludeUnreadable {
+                desired_file,
+                error,
+            } => format!("could not include `{}`: {}", desired_file, error),
+            Phase4IncludeDuplicate { name } => {
+                format!("`{}` included again without an include guard", name)
+            },
+            Phase4IncludePrevious { name } => format!("`{}` previously included here", name),
+            Phase4IncludeNextInPrimary => "`#include_next` in primary source file".to_owned(),
+            Phase4LineInvalidNumber { value } => format!(
+                "`{}` is not a valid line number; expected a number between 1 and 2147483647",
+                value
+            ),
+            Phase4IfInvalidConstant { value } => {
+                format!("`{}` is not a valid integer constant", value)
+            },
+            Phase4IfDivisionByZero => "division by zero in `#if` condition".to_owned(),
+            Phase4IfEmpty => "expected value in `#if` condition".to_owned(),
+            Phase4C2xDirective { directive } => {
+                format!("`#{}` is a C2x extension", directive)
+            },
+            Phase4GnuDirective { directive } => {
+                format!("`#{}` is a GNU extension", directive)
+            },
+            Phase4EmptyMacroInIf { name } => {
+                format!("macro `{}` expands to nothing in `#if` condition", name)
+            },
+            Phase4IfUndefinedIdentifier { name } => {
+                format!("`{}` is not defined and evaluates to 0", name)
+            },
+            Phase4ExtraTokens { directive } => {
+                format!("extra tokens at end of `#{}` directive", directive)
+            },
+            Phase4PragmaDiagnosticInvalid => {
+                "expected `push`, `pop`, or `ignored \"-W...\"` after `#pragma GCC diagnostic`"
+                    .to_owned()
+            },
+            Phase4PragmaUnknownWarning { option } => {
+                format!("unknown warning `{}` in `#pragma GCC diagnostic`", option)
+            },
+            Phase5Empty => "expected character after escape sequence".to_owned(),
             Phase5Incomplete {
                 expected,
                 found,
@@ -212,22 +382,137 @@ impl MessageKind {
                 previous.to_str(),
                 current.to_str()
             ),
+            VerifyExpectationNotSeen { severity, text } => {
+                format!(
+                    "expected {} containing `{}` was not reported",
+                    severity, text
+                )
+            },
+            TooManyErrors => "too many errors emitted, stopping now".to_owned(),
+            Phase7NumberSuffixInvalid { suffix, radix } => {
+                format!("invalid suffix `{}` on {} constant", suffix, radix.to_str())
+            },
+            Phase7IntegerMalformed { value } => {
+                format!("`{}` is not a valid integer constant", value)
+            },
+            Phase7IntegerOutOfRange { value, radix } => format!(
+                "{} constant `{}` is too large for any integer type",
+                radix.to_str(),
+                value
+            ),
+            Phase7InvalidOctalDigit { digit } => {
+                format!("invalid digit `{}` in octal constant", digit)
+            },
+            Phase7FloatMalformed { value } => {
+                format!("`{}` is not a valid floating constant", value)
+            },
+            Phase7FloatOutOfRange { value, ty } => {
+                format!("`{}` is out of range for type `{}`", value, ty.to_str())
+            },
+            Phase7CharacterEmpty => "empty character constant".to_owned(),
+            Phase7CharacterOutOfRange { value, encoding } => format!(
+                "character constant `{}` does not fit in a single `{}`",
+                value,
+                encoding.type_str()
+            ),
         }
     }
 
     pub fn severity(&self) -> Severity {
         use MessageKind::*;
         match self {
-            Phase4MacroInvocationOpening { .. } | Phase4MacroFirstDefined { .. } => Severity::Info,
+            Phase4MacroInvocationOpening { .. }
+            | Phase4UnclosedBracketOpening { .. }
+            | Phase4MacroDefinedParameters { .. }
+            | Phase4MacroFirstDefined { .. }
+            | Phase4IncludePrevious { .. } => Severity::Note,
+            Phase1FileEndingWithBackslash
+            | Phase3DollarInIdentifier
+            | Phase3LineCommentBeforeC99
+            | Phase4EmptyMacroInIf { .. }
+            | Phase4IncludeNextInPrimary
+            | Phase4IncludeDuplicate { .. }
+            | Phase4IfUndefinedIdentifier { .. }
+            | Phase4ExtraTokens { .. }
+            | Phase4MacroArgumentCommaInBrackets { .. }
+            | Phase4NonDirective
+            | Phase4C2xDirective { .. }
+            | Phase4GnuDirective { .. }
+            | Phase4PragmaDiagnosticInvalid
+            | Phase4PragmaUnknownWarning { .. } => Severity::Warning,
             _ => Severity::Fatal, // TODO message severities
         }
     }
+
+    /// The name used with `-W` and `#pragma GCC diagnostic` for this warning
+    ///
+    /// Warnings without a name cannot be suppressed.
+    pub fn warning_name(&self) -> Option<&'static str> {
+        use MessageKind::*;
+        match self {
+            Phase1FileEndingWithBackslash => Some("backslash-newline-eof"),
+            Phase3DollarInIdentifier => Some("dollar-in-identifier-extension"),
+            Phase3LineCommentBeforeC99 => Some("c99-extensions"),
+            Phase4EmptyMacroInIf { .. } => Some("empty-macro"),
+            Phase4IfUndefinedIdentifier { .. } => Some("undef"),
+            Phase4ExtraTokens { .. } => Some("extra-tokens"),
+            Phase4MacroArgumentCommaInBrackets { .. } => Some("macro-argument-brackets"),
+            Phase4C2xDirective { .. } => Some("c2x-extensions"),
+            Phase4GnuDirective { .. } => Some("gnu-extensions"),
+            Phase4IncludeNextInPrimary => Some("include-next-outside-header"),
+            Phase4IncludeDuplicate { .. } => Some("duplicate-include"),
+            _ => None,
+        }
+    }
+}
+
+/// Expand tabs within a source line and draw an underline beneath it
+///
+/// The underline begins with `^` at `column` and continues with `~` for the
+/// rest of the `len` characters, stopping at the end of the line. Tabs advance
+/// to the next multiple of `tab_stop`.
+fn underline_source(line: &str, column: u32, len: u32, tab_stop: usize) -> (String, String) {
+    let start = column as usize - 1;
+    let end = start + std::cmp::max(len, 1) as usize;
+
+    let mut expanded = String::new();
+    let mut underline = String::new();
+    for (i, c) in line.chars().enumerate() {
+        let width = if c == '\t' {
+            tab_stop - expanded.chars().count() % tab_stop
+        } else {
+            1
+        };
+        if c == '\t' {
+            expanded.push_str(&" ".repeat(width));
+        } else {
+            expanded.push(c);
+        }
+
+        if i < start {
+            underline.push_str(&" ".repeat(width));
+        } else if i == start {
+            underline.push('^');
+            underline.push_str(&"~".repeat(width - 1));
+        } else if i < end {
+            underline.push_str(&"~".repeat(width));
+        }
+    }
+
+    // spans such as a newline begin after the visible text of the line
+    if start >= line.chars().count() {
+        underline.push('^');
+    }
+
+    (expanded, underline)
 }
 
 #[derive(Clone, Debug)]
 pub struct Extra {
     pub enriched: String,
     pub position: TextPositionResolved,
+    /// Where the message is anchored, outside of any macro expansion
+    pub root: TextPosition,
 }
 
 /// A message about the source code being processed
@@ -240,31 +525,90 @@ pub struct Message {
 }
 
 impl Message {
+    /// Resolve the location of this message and its children into text
+    ///
+    /// See [`render_pretty()`][Message::render_pretty] for the layout. The
+    /// session decides whether the result is colorized.
     pub fn enrich(&mut self, tuctx: &TUCtx) {
+        let color = tuctx.tu.session.use_color();
+        self.enrich_with_color(tuctx, color);
+    }
+
+    fn enrich_with_color(&mut self, tuctx: &TUCtx, color: bool) {
+        let span = self.origin.macro_root_textspan(tuctx);
+        let position = span.pos.resolve(tuctx).own_string();
+        self.extra = Some(Extra {
+            enriched: self.render_pretty(tuctx, color),
+            position,
+            root: span.pos,
+        });
+
+        if let Some(children) = &mut self.children {
+            children
+                .iter_mut()
+                .for_each(|t| t.enrich_with_color(tuctx, color));
+        }
+    }
+
+    /// Format this message for the end user
+    ///
+    /// The headline is followed by the position and the line of source code
+    /// responsible, with a `^~~~` underline beneath the exact span. Children are
+    /// rendered in the same manner beneath their parent, indented one level
+    /// further.
+    ///
+    /// If `color` is true, ANSI escape codes are used to highlight the
+    /// severity, headline, and carets. Otherwise the result is plain text.
+    pub fn render_pretty(&self, tuctx: &TUCtx, color: bool) -> String {
+        let mut output = String::new();
+        self.render_pretty_indented(tuctx, color, 0, &mut output);
+        output
+    }
+
+    fn render_pretty_indented(
+        &self,
+        tuctx: &TUCtx,
+        color: bool,
+        depth: usize,
+        output: &mut String,
+    ) {
         use std::fmt::Write;
 
-        let mut string = String::new();
+        const BOLD: &str = "\x1b[1m";
+        const RESET: &str = "\x1b[0m";
+        let (severity_color, bold, reset) = if color {
+            (self.kind.severity().ansi_color(), BOLD, RESET)
+        } else {
+            ("", "", "")
+        };
+
+        let indent = "  ".repeat(depth);
         let span = self.origin.macro_root_textspan(tuctx);
-        // let (name, lno, cno) = span.alias_line_column(tuctx);
         let textpos = span.pos.resolve(tuctx);
+        let (line_text, column) = span.pos.source_line(tuctx);
+        let tab_stop = tuctx.session().flags().tab_stop as usize;
+        let (line_text, underline) = underline_source(line_text, column, span.len, tab_stop);
 
         writeln!(
-            &mut string,
-            "{}: {}",
+            output,
+            "{}{}{}:{} {}{}{}",
+            indent,
+            severity_color,
             self.kind.severity(),
-            self.kind.get_headline()
+            reset,
+            bold,
+            self.kind.get_headline(),
+            reset,
         )
         .unwrap();
-        writeln!(&mut string, "  {}", textpos).unwrap();
-        writeln!(&mut string, "  {}", span.text(tuctx)).unwrap();
-
-        self.extra = Some(Extra {
-            enriched: string,
-            position: textpos.own_string(),
-        });
+        writeln!(output, "{}  {}", indent, textpos).unwrap();
+        writeln!(output, "{}  {}", indent, line_text).unwrap();
+        writeln!(output, "{}  {}{}{}", indent, bold, underline, reset).unwrap();
 
-        if let Some(children) = &mut self.children {
-            children.iter_mut().for_each(|t| t.enrich(tuctx));
+        if let Some(children) = &self.children {
+            for child in children.iter() {
+                child.render_pretty_indented(tuctx, color, depth + 1, output);
+            }
         }
     }
 
@@ -302,3 +646,187 @@ impl std::convert::From<(TokenOrigin, MessageKind)> for Message {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::front::c::token::{TextPosition, TextSpan};
+    use crate::test_util::unit;
+
+    fn span(absolute: u32, len: u32) -> TokenOrigin {
+        TokenOrigin::Source(TextSpan {
+            pos: TextPosition { input: 0, absolute },
+            len,
+        })
+    }
+
+    #[test]
+    fn test_enrich_error_with_two_notes() {
+        let mut tu = unit(&[], "#define a 1\n#define a 2\nf(3\n");
+        let tuctx = TUCtx::from_tu(&mut tu);
+
+        let mut message = Message {
+            kind: MessageKind::Phase4MacroRedefinitionDifferent {
+                name: "a".to_owned(),
+            },
+            origin: span(20, 1),
+            children: Some(
+                vec![
+                    Message::from((
+                        span(8, 1),
+                        MessageKind::Phase4MacroFirstDefined {
+                            name: "a".to_owned(),
+                        },
+                    )),
+                    Message::from((
+                        span(25, 1),
+                        MessageKind::Phase4MacroInvocationOpening {
+                            name: "f".to_owned(),
+                        },
+                    )),
+                ]
+                .into_boxed_slice(),
+            ),
+            extra: None,
+        };
+        message.enrich_with_color(&tuctx, false);
+
+        assert_eq!(
+            message.enriched_message(),
+            "\
+fatal error: macro `a` redefined differently
+  <unit-test>:2:9
+  #define a 2
+          ^
+  note: macro `a` first defined here
+    <unit-test>:1:9
+    #define a 1
+            ^
+  note: macro `f` invocation opened here
+    <unit-test>:3:2
+    f(3
+     ^
+"
+        );
+    }
+
+    #[test]
+    fn test_render_pretty_macro_redefinition() {
+        let mut tu = unit(
+            &["--pass=phase1..phase4", "--color=never"],
+            "#define a 1\n#define a 2\n",
+        );
+        tu.run().unwrap();
+
+        assert_eq!(tu.messages().len(), 1);
+        assert_eq!(
+            tu.messages()[0].enriched_message(),
+            "\
+fatal error: macro `a` redefined differently
+  <unit-test>:2:9
+  #define a 2
+          ^
+  note: macro `a` first defined here
+    <unit-test>:1:9
+    #define a 1
+            ^
+"
+        );
+    }
+
+    #[test]
+    fn test_enrich_warning() {
+        let mut tu = unit(&[], "a\\");
+        let tuctx = TUCtx::from_tu(&mut tu);
+
+        let mut message = Message::from((span(1, 1), MessageKind::Phase1FileEndingWithBackslash));
+        message.enrich_with_color(&tuctx, false);
+
+        assert_eq!(
+            message.enriched_message(),
+            "\
+warning: file cannot end with a backslash
+  <unit-test>:1:2
+  a\\
+   ^
+"
+        );
+    }
+
+    #[test]
+    fn test_render_pretty_color_always() {
+        let mut tu = unit(
+            &["--pass=phase1..phase4", "--color=always"],
+            "#define abc 1\n#define abc 2\n",
+        );
+        tu.run().unwrap();
+
+        assert_eq!(tu.messages().len(), 1);
+        assert_eq!(
+            tu.messages()[0].enriched_message(),
+            "\
+\x1b[1;31mfatal error:\x1b[0m \x1b[1mmacro `abc` redefined differently\x1b[0m
+  <unit-test>:2:9
+  #define abc 2
+  \x1b[1m        ^~~\x1b[0m
+  \x1b[1;36mnote:\x1b[0m \x1b[1mmacro `abc` first defined here\x1b[0m
+    <unit-test>:1:9
+    #define abc 1
+    \x1b[1m        ^~~\x1b[0m
+"
+        );
+    }
+
+    #[test]
+    fn test_render_pretty_underline_mid_line() {
+        let mut tu = unit(&[], "#define a 1\n\t#define a 22 + 3\n");
+        let tuctx = TUCtx::from_tu(&mut tu);
+
+        let message = Message::from((
+            span(23, 2),
+            MessageKind::Phase4MacroRedefinitionDifferent {
+                name: "a".to_owned(),
+            },
+        ));
+
+        assert_eq!(
+            message.render_pretty(&tuctx, false),
+            "\
+fatal error: macro `a` redefined differently
+  <unit-test>:2:12
+          #define a 22 + 3
+                    ^~
+"
+        );
+    }
+
+    #[test]
+    fn test_render_pretty_tab_stop() {
+        let mut tu = unit(
+            &["--column=display", "--tab-stop=4"],
+            "#define a 1\n\t#define a 22 + 3\n",
+        );
+        let tuctx = TUCtx::from_tu(&mut tu);
+
+        let mut message = Message::from((
+            span(23, 2),
+            MessageKind::Phase4MacroRedefinitionDifferent {
+                name: "a".to_owned(),
+            },
+        ));
+        message.enrich(&tuctx);
+        assert_eq!(
+            message.to_string(),
+            "<unit-test>:2:15: macro `a` redefined differently"
+        );
+        assert_eq!(
+            message.render_pretty(&tuctx, false),
+            "\
+fatal error: macro `a` redefined differently
+  <unit-test>:2:15
+      #define a 22 + 3
+                ^~
+"
+        );
+    }
+}
@@ -5,57 +5,18 @@
 
 //! Minor phases: 1, 2, 5, 6
 
-use std::convert::TryFrom;
+use std::collections::VecDeque;
 
 use log::{log_enabled, trace};
 
 use crate::front::c::message::MessageKind;
-use crate::front::c::token::{CharToken, PPToken, PPTokenKind, TokenOrigin};
+use crate::front::c::token::{CharToken, PPToken, PPTokenKind, TextSpan, TokenOrigin};
 use crate::front::c::tuctx::TUCtx;
+use crate::util::escape::{c_unescape_with, EscapeError};
 
 /// Phase 1: Convert trigraphs
 pub fn convert_trigraphs<'a>(tokens: Vec<CharToken>) -> Vec<CharToken> {
-    static REPLACEMENTS: &[(char, char)] = &[
-        ('=', '#'),
-        (')', ']'),
-        ('!', '|'),
-        ('(', '['),
-        ('\'', '^'),
-        ('>', '}'),
-        ('/', '\\'),
-        ('<', '{'),
-        ('-', '~'),
-    ];
-
-    let mut output = Vec::new();
-    let mut iter = tokens.into_iter();
-
-    while iter.as_slice().len() > 2 {
-        // advance iter by one
-        let first = iter.next().unwrap();
-
-        // peek ahead two extra tokens (after next)
-        let second = &iter.as_slice()[0];
-        let third = &iter.as_slice()[1];
-
-        if first.value == '?' && second.value == '?' {
-            if let Some((_, to)) = REPLACEMENTS.iter().find(|(from, _)| *from == third.value) {
-                let mut span = first.span;
-                span.len = 3;
-                output.push(CharToken { value: *to, span });
-                iter.next();
-                iter.next();
-                continue;
-            }
-        }
-
-        // did not find any trigraphs
-        output.push(first);
-    }
-
-    while let Some(token) = iter.next() {
-        output.push(token);
-    }
+    let output: Vec<CharToken> = Trigraphs::new(tokens.into_iter()).collect();
 
     if log_enabled!(log::Level::Trace) {
         for (i, token) in output.iter().enumerate() {
@@ -66,44 +27,132 @@ pub fn convert_trigraphs<'a>(tokens: Vec<CharToken>) -> Vec<CharToken> {
     output
 }
 
+/// Replaces trigraphs within a stream of characters
+///
+/// This implements [`convert_trigraphs()`] without collecting the characters,
+/// so that the lexer can read an input directly.
+pub struct Trigraphs<I: Iterator<Item = CharToken>> {
+    iter: I,
+    /// Characters read ahead, at most two
+    pending: VecDeque<CharToken>,
+}
+
+impl<I: Iterator<Item = CharToken>> Trigraphs<I> {
+    pub fn new(iter: I) -> Self {
+        Trigraphs {
+            iter,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<I: Iterator<Item = CharToken>> Iterator for Trigraphs<I> {
+    type Item = CharToken;
+
+    fn next(&mut self) -> Option<CharToken> {
+        static REPLACEMENTS: &[(char, char)] = &[
+            ('=', '#'),
+            (')', ']'),
+            ('!', '|'),
+            ('(', '['),
+            ('\'', '^'),
+            ('>', '}'),
+            ('/', '\\'),
+            ('<', '{'),
+            ('-', '~'),
+        ];
+
+        while self.pending.len() < 3 {
+            match self.iter.next() {
+                Some(token) => self.pending.push_back(token),
+                None => break,
+            }
+        }
+        let first = self.pending.pop_front()?;
+
+        if let (Some(second), Some(third)) = (self.pending.front(), self.pending.get(1)) {
+            if first.value == '?' && second.value == '?' {
+                if let Some((_, to)) = REPLACEMENTS.iter().find(|(from, _)| *from == third.value) {
+                    let mut span = first.span;
+                    span.len = 3;
+                    self.pending.clear();
+                    return Some(CharToken { value: *to, span });
+                }
+            }
+        }
+
+        // did not find any trigraphs
+        Some(first)
+    }
+}
+
 /// Phase 2: Splice together physical lines into logical lines
 ///
 /// A line ending in `\` will be spliced together with the next line. Thus both
 /// the back slash and newline characters will be removed. This allows multiline
 /// comments and strings
 pub fn splice_lines(tuctx: &mut TUCtx, input: Vec<CharToken>) -> Vec<CharToken> {
-    let mut output = Vec::new();
-    let mut iter = input.into_iter();
-
-    while iter.as_slice().len() > 1 {
-        let first = iter.next().unwrap();
-        let second = &iter.as_slice()[0];
-
-        if first.value == '\\' && second.value == '\n' {
-            iter.next(); // consume second
+    let mut splice = Splice::new(input.into_iter());
+    let output = splice.by_ref().collect();
+    splice.report(tuctx);
+    output
+}
 
-            // do not emit either to output, in effect splicing physical lines
-            // into one logical line
+/// Splices lines within a stream of characters
+///
+/// This implements [`splice_lines()`] without collecting the characters. Once
+/// the stream is exhausted, [`report()`][Splice::report] emits the warning
+/// for a file ending with a backslash.
+pub struct Splice<I: Iterator<Item = CharToken>> {
+    iter: std::iter::Peekable<I>,
+    /// The backslash the input ended with, if any
+    ending_backslash: Option<TextSpan>,
+}
 
-            // are these the last two characters of input?
-            if iter.as_slice().len() == 0 {
-                tuctx.emit_message(first.span, MessageKind::Phase1FileEndingWithBackslash);
-            }
-        } else {
-            output.push(first);
+impl<I: Iterator<Item = CharToken>> Splice<I> {
+    pub fn new(iter: I) -> Self {
+        Splice {
+            iter: iter.peekable(),
+            ending_backslash: None,
         }
     }
 
-    if let Some(last) = iter.next() {
-        if last.value == '\\' {
-            tuctx.emit_message(last.span, MessageKind::Phase1FileEndingWithBackslash);
-        } else {
-            output.push(last);
+    /// Emit any message about the characters read so far
+    pub fn report(&self, tuctx: &mut TUCtx) {
+        if let Some(span) = self.ending_backslash {
+            tuctx.emit_message(span, MessageKind::Phase1FileEndingWithBackslash);
         }
     }
-    assert!(iter.next().is_none());
+}
 
-    output
+impl<I: Iterator<Item = CharToken>> Iterator for Splice<I> {
+    type Item = CharToken;
+
+    fn next(&mut self) -> Option<CharToken> {
+        loop {
+            let first = self.iter.next()?;
+            if first.value != '\\' {
+                return Some(first);
+            }
+            match self.iter.peek() {
+                Some(second) if second.value == '\n' => {
+                    // do not emit either to output, in effect splicing
+                    // physical lines into one logical line
+                    self.iter.next();
+
+                    // are these the last two characters of input?
+                    if self.iter.peek().is_none() {
+                        self.ending_backslash = Some(first.span);
+                    }
+                },
+                Some(_) => return Some(first),
+                None => {
+                    self.ending_backslash = Some(first.span);
+                    return None;
+                },
+            }
+        }
+    }
 }
 
 /// Represents what type prefix was applied to a given string/character constant
@@ -187,235 +236,48 @@ impl Encoding {
     }
 }
 
-/// Represents what character came after the backslash in a numeric escape sequence
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum DigitEscapePrefix {
-    Universal16,
-    Universal32,
-    Hexadecimal,
-    Octal,
-}
-
-impl DigitEscapePrefix {
-    fn skip(&self) -> bool {
-        use DigitEscapePrefix::*;
-        match *self {
-            Hexadecimal | Universal16 | Universal32 => true,
-            Octal => false,
-        }
-    }
-
-    fn radix(&self) -> u32 {
-        use DigitEscapePrefix::*;
-        match *self {
-            Hexadecimal | Universal16 | Universal32 => 16,
-            Octal => 8,
-        }
-    }
-
-    fn max_len(&self) -> Option<usize> {
-        use DigitEscapePrefix::*;
-        match *self {
-            Hexadecimal => None,
-            Octal => Some(3),
-            Universal16 => Some(4),
-            Universal32 => Some(8),
-        }
-    }
-
-    fn exact_len(&self) -> Option<usize> {
-        use DigitEscapePrefix::*;
-        match *self {
-            Hexadecimal | Octal => None,
-            Universal16 => Some(4),
-            Universal32 => Some(8),
-        }
-    }
-
-    fn as_str(&self) -> &'static str {
-        use DigitEscapePrefix::*;
-        match *self {
-            Hexadecimal => "x",
-            Octal => "",
-            Universal16 => "u",
-            Universal32 => "U",
-        }
-    }
-}
-
-/// Parse a numeric escape digit like `\x1234` or `\040`.
-fn parse_digits(
-    tuctx: &mut TUCtx,
-    iter: &mut std::iter::Peekable<std::str::Chars>,
-    buffer: &mut String,
-    origin: &TokenOrigin,
-    encoding: Encoding,
-    prefix: DigitEscapePrefix,
-) -> Option<char> {
-    if prefix.skip() {
-        // throw away the x, u, or U prefix for \x, \u, and \U escapes respectively
-        iter.next();
-    }
-
-    while iter.peek().map(|c| c.is_digit(prefix.radix())) == Some(true) {
-        buffer.push(iter.next().unwrap());
-
-        // don't parse more than necessary for universal-character-name or octal-escape-sequence
-        if let Some(max) = prefix.max_len() {
-            if buffer.len() >= max {
-                break;
-            }
-        }
-    }
-
-    if buffer.is_empty() {
-        // TODO FIXME error reporting within escape sequences
-        tuctx.emit_message(origin.clone(), MessageKind::Phase5Empty);
-        return None;
-    }
-
-    if prefix
-        .exact_len()
-        .map(|desired| buffer.len() < desired)
-        .unwrap_or(false)
-    {
-        // detect an incomplete universal-character-name
-        tuctx.emit_message(
-            origin.clone(), // TODO FIXME error reporting within escape sequences
-            MessageKind::Phase5Incomplete {
-                expected: prefix.exact_len().unwrap(),
-                found: buffer.len(),
-                prefix: if prefix == DigitEscapePrefix::Universal16 {
-                    'u'
-                } else {
-                    'U'
-                },
-            },
-        );
-        return None;
-    } else if prefix == DigitEscapePrefix::Hexadecimal && buffer.len() > encoding.size_bytes() * 2 {
-        // detect a hexadecimal-escape-sequence that doesn't fit.
-        // size is measured in bytes; there are 2 hexadecimal digits in a byte
-        tuctx.emit_message(
-            origin.clone(), // TODO FIXME error reporting within escape sequences
-            MessageKind::Phase5OutOfRange {
-                prefix: prefix.as_str(),
-                value: std::mem::take(buffer),
-                encoding,
-            },
-        );
-        return None;
-    }
-
-    let value = char::try_from(buffer.chars().fold(0u32, |current, next| {
-        current * prefix.radix() + next.to_digit(prefix.radix()).unwrap()
-    }));
-
-    if let Ok(value) = value {
-        Some(value)
-    } else {
-        tuctx.emit_message(
-            origin.clone(), // TODO FIXME error reporting within escape sequences
-            MessageKind::Phase5Invalid {
-                prefix: prefix.as_str(),
-                value: std::mem::take(buffer),
-            },
-        );
-        None
-    }
-}
-
 /// Translate an input string/character constant
 ///
 /// This will return `None` if the token has no escape codes. It will not
-/// allocate in that case.
+/// allocate in that case. A backslash with nothing after it is reported and
+/// dropped, so the result never ends in a dangling backslash.
 fn translate_escapes(
     tuctx: &mut TUCtx,
     text: &str,
     origin: &TokenOrigin,
     encoding: Encoding,
 ) -> Option<String> {
-    // avoid allocating until we encounter first escape code
-    // then we must rematerialize the `output` variable for all the previously
-    // encountered characters
-    let mut escaped = false;
-    let mut output = String::new();
-
-    let mut iter = text.chars().peekable();
-    let mut i = 0;
-    let mut buffer = String::new();
-
-    while let Some(c) = iter.next() {
-        i += 1;
-        if c != '\\' {
-            // not the beginning of an escape, ignore.
-
-            if escaped {
-                // we've encountered an escape already, therefore add this to output
-                output.push(c);
-            }
-            continue;
-        }
+    if !text.contains('\\') {
+        return None;
+    }
 
-        // rematerialize what we've processed before into the output string
-        if !escaped && output.is_empty() {
-            // `!escaped` clause is to make sure this is only run for the very
-            // first escape encountered (which may not result in a character if
-            // it was erroneous )
-            output = text.chars().take(i - 1).collect();
-        }
-        escaped = true;
-
-        // just saw a backslash, beginning an escape
-        let prefix = match iter.peek() {
-            Some('x') => Some(DigitEscapePrefix::Hexadecimal), // hexadecimal-escape-sequence
-            Some('u') => Some(DigitEscapePrefix::Universal16), // universal-character-name
-            Some('U') => Some(DigitEscapePrefix::Universal32), // universal-character-name
-            Some(c) if c.is_digit(8) => Some(DigitEscapePrefix::Octal), // octal-escape-sequence
-            _ => None,
+    let mut errors = Vec::new();
+    let output = c_unescape_with(text, encoding.size_bytes(), |error| errors.push(error));
+    for error in errors {
+        // TODO FIXME error reporting within escape sequences
+        let kind = match error {
+            EscapeError::Empty => MessageKind::Phase5Empty,
+            EscapeError::Incomplete {
+                prefix,
+                expected,
+                found,
+            } => MessageKind::Phase5Incomplete {
+                expected,
+                found,
+                prefix,
+            },
+            EscapeError::OutOfRange { prefix, value } => MessageKind::Phase5OutOfRange {
+                prefix,
+                value,
+                encoding,
+            },
+            EscapeError::Invalid { prefix, value } => MessageKind::Phase5Invalid { prefix, value },
+            EscapeError::Unrecognized(escape) => MessageKind::Phase5Unrecognized { escape },
         };
-
-        if let Some(prefix) = prefix {
-            // the escape is made of some sequence of digits
-            buffer.clear();
-            if let Some(c) = parse_digits(tuctx, &mut iter, &mut buffer, origin, encoding, prefix) {
-                output.push(c);
-            }
-        } else {
-            match iter.next() {
-                // simple-escape-sequence
-                Some('\\') => output.push('\\'),
-                Some('?') => output.push('?'),
-                Some('\'') => output.push('\''),
-                Some('"') => output.push('"'),
-                Some('a') => output.push('\x07'),
-                Some('b') => output.push('\x08'),
-                Some('f') => output.push('\x0c'),
-                Some('n') => output.push('\x0a'),
-                Some('r') => output.push('\x0d'),
-                Some('t') => output.push('\x09'),
-                Some('v') => output.push('\x0b'),
-
-                Some(c) => {
-                    // TODO FIXME error reporting within escape sequences
-                    tuctx.emit_message(
-                        origin.clone(),
-                        MessageKind::Phase5Unrecognized { escape: c },
-                    );
-                },
-                None => {
-                    // TODO FIXME error reporting within escape sequences
-                    tuctx.emit_message(origin.clone(), MessageKind::Phase5Empty);
-                },
-            }
-        }
+        tuctx.emit_message(origin.clone(), kind);
     }
 
-    if output.is_empty() {
-        None
-    } else {
-        Some(output)
-    }
+    Some(output)
 }
 
 /// Mutate a [`PPToken`] to process escape sequences
@@ -435,7 +297,7 @@ fn unescape_token(tuctx: &mut TUCtx, token: &mut PPToken, delim: &str) {
     let text = &token.value[start..end];
 
     if let Some(value) = translate_escapes(tuctx, text, &token.origin, Encoding::from_str(prefix)) {
-        token.value = format!("{}{}{}{}", prefix, delim, value, delim);
+        token.value = format!("{}{}{}{}", prefix, delim, value, delim).into();
     }
 }
 
@@ -482,8 +344,15 @@ pub fn concatenate(tuctx: &mut TUCtx, input: Vec<PPToken>) -> Vec<PPToken> {
                 let new_encoding = get_string_encoding(&new_token.value, "\"");
 
                 if encoding.compatible(new_encoding) {
-                    // TODO FIXME expand token locations???
                     string.push_str(get_string_content(&new_token.value, "\""));
+                    // a string spanning several inputs keeps its first location
+                    if let (TokenOrigin::Source(first), TokenOrigin::Source(next)) =
+                        (&token.origin, &new_token.origin)
+                    {
+                        if first.input == next.input {
+                            token.origin = TokenOrigin::Source(first.merge(next));
+                        }
+                    }
                 } else {
                     tuctx.emit_message(
                         new_token.origin,
@@ -495,7 +364,7 @@ pub fn concatenate(tuctx: &mut TUCtx, input: Vec<PPToken>) -> Vec<PPToken> {
                 }
             }
 
-            token.value = format!("{}\"{}\"", encoding.prefix(), string);
+            token.value = format!("{}\"{}\"", encoding.prefix(), string).into();
 
             output.push(token);
         }
@@ -4,19 +4,26 @@
 // copied, modified, or distributed except according to those terms.
 
 //! Phase 4: Execute preprocessor directives
-use std::collections::HashMap;
+
+mod if_expr;
+
+use std::collections::{HashMap, VecDeque};
 use std::rc::Rc;
 use std::vec::IntoIter;
 
 use log::{debug, trace};
 
-use crate::front::c::input::{IncludedFrom, Input};
-use crate::front::c::lexer::lex_one_token;
-use crate::front::c::message::{ExpectedFoundPart, MessageKind};
+use crate::core::{warning_group, CStandard, Error, ErrorKind, Result};
+use crate::front::c::input::{IncludedFrom, Input, LineOverride};
+use crate::front::c::lexer::{lex_one_token_with, LexOptions};
+use crate::front::c::message::{ExpectedFoundPart, Message, MessageKind};
+use crate::front::c::minor::get_string_content;
 use crate::front::c::token::{
     MacroInvocation, MacroResult, PPToken, PPTokenKind, TextPosition, TextSpan, TokenOrigin,
 };
-use crate::front::c::tuctx::TUCtx;
+use crate::front::c::tuctx::{DiagnosticPragma, TUCtx};
+use crate::session::{IncludeError, Session};
+use crate::util::c_escape;
 
 type Line = Vec<PPToken>;
 
@@ -29,7 +36,7 @@ type Line = Vec<PPToken>;
 #[derive(Clone, Debug)]
 pub struct MacroObject {
     name: String,
-    replacement: Vec<PPToken>,
+    replacement: Rc<[PPToken]>,
     origin: TokenOrigin,
 }
 
@@ -44,7 +51,7 @@ pub struct MacroObject {
 #[derive(Clone, Debug)]
 pub struct MacroFunction {
     pub name: String,
-    pub replacement: Vec<PPToken>,
+    pub replacement: Rc<[PPToken]>,
     pub params: Vec<String>,
     pub vararg: bool,
     pub origin: TokenOrigin,
@@ -79,6 +86,37 @@ impl MacroDef {
         }
     }
 
+    /// The parameter list as written, e.g. `(a, b, ...)`
+    ///
+    /// Object-like macros have no parameter list.
+    pub fn parameter_text(&self) -> Option<String> {
+        let func = match self {
+            MacroDef::Object(..) => return None,
+            MacroDef::Function(func) => func,
+        };
+        let mut params = func.params.clone();
+        if func.vararg {
+            params.push("...".to_owned());
+        }
+        Some(format!("({})", params.join(", ")))
+    }
+
+    /// The replacement list as text
+    ///
+    /// Whitespace around the replacement is dropped, and any other whitespace
+    /// becomes a single space.
+    pub fn replacement_text(&self) -> String {
+        let mut output = String::new();
+        for token in tokens_trim_whitespace(self.replacement()) {
+            if !token.is_whitespace() {
+                output.push_str(&token.value);
+            } else if !output.ends_with(' ') {
+                output.push(' ');
+            }
+        }
+        output
+    }
+
     pub fn as_function(&self) -> &MacroFunction {
         match self {
             MacroDef::Function(func) => &func,
@@ -96,8 +134,19 @@ impl MacroDef {
             let mut lhs = tokens_trim_whitespace(lhs).iter().peekable();
             let mut rhs = tokens_trim_whitespace(rhs).iter().peekable();
 
-            while let (Some(left), Some(right)) = (lhs.next(), rhs.next()) {
-                if left.kind != right.kind {
+            loop {
+                let (left, right) = match (lhs.next(), rhs.next()) {
+                    (Some(left), Some(right)) => (left, right),
+                    // sequences are equal if we have consumed both in their
+                    // entirety at the same time
+                    (None, None) => return true,
+                    _ => return false,
+                };
+
+                // a kept comment is just whitespace
+                if left.is_whitespace() != right.is_whitespace()
+                    || (!left.is_whitespace() && left.kind != right.kind)
+                {
                     return false;
                 }
                 if left.is_whitespace() {
@@ -114,9 +163,6 @@ impl MacroDef {
                     return false;
                 }
             }
-
-            // sequences are equal if we have consumed both in their entirety
-            lhs.next().is_none() && rhs.next().is_none()
         }
 
         match (self, other) {
@@ -162,14 +208,14 @@ enum IfCondition {
 }
 
 impl IfCondition {
-    pub fn evaluate(&self, defines: &HashMap<String, Rc<MacroDef>>) -> bool {
+    pub fn evaluate(&self, tuctx: &mut TUCtx, defines: &mut HashMap<String, Rc<MacroDef>>) -> bool {
         debug!("IfCondition::evaluate() self = {:?}", self);
         trace!("IfCondition::evaluate() defines = {:?}", defines);
 
         match self {
-            IfCondition::Plain(_line) => unimplemented!(),
-            IfCondition::Defined(token) => defines.contains_key(&token.value),
-            IfCondition::Undefined(token) => !defines.contains_key(&token.value),
+            IfCondition::Plain(line) => if_expr::evaluate(tuctx, defines, line.clone()),
+            IfCondition::Defined(token) => if_expr::is_defined(defines, &token.value),
+            IfCondition::Undefined(token) => !if_expr::is_defined(defines, &token.value),
 
             // only ever used when discarding the output in order to better recover from parsing
             // errors
@@ -178,6 +224,18 @@ impl IfCondition {
     }
 }
 
+/// A GNU assertion such as `machine(i386)`
+///
+/// Assertions form a namespace separate from macros. Each predicate holds a
+/// set of answers, which `#assert` adds to and `#unassert` removes from.
+#[derive(Clone, Debug)]
+struct Assertion {
+    predicate: PPToken,
+    /// The answer with whitespace between tokens normalized, or `None` to
+    /// refer to every answer of the predicate
+    answer: Option<String>,
+}
+
 #[derive(Debug)]
 enum Directive {
     IfSection {
@@ -194,6 +252,21 @@ enum Directive {
         span: TextSpan,
         // span: TextSpan,
     },
+    IncludeNext {
+        content: Vec<PPToken>,
+        span: TextSpan,
+    },
+    Line {
+        content: Vec<PPToken>,
+        after: TextPosition,
+    },
+    Pragma {
+        content: Vec<PPToken>,
+    },
+    Assert(Assertion),
+    Unassert(Assertion),
+    /// The string of an `#ident` or `#sccs` directive
+    Ident(String),
 }
 
 /// Checks whether this is the last line of the file
@@ -222,10 +295,22 @@ fn line_is_directive(line: &[PPToken]) -> Option<&str> {
     second.map(|t| t.as_str())
 }
 
+/// Returns the token following `#` if the line begins with `#` but is not a
+/// named directive
+///
+/// This is a newline for the null directive, a number for a GNU linemarker, and
+/// anything else for a non-directive.
+fn line_is_unnamed_directive(line: &[PPToken]) -> Option<&PPToken> {
+    let mut iter = line.iter().filter(|t| !t.is_whitespace_not_newline());
+    if iter.next().map(|t| t.as_str()) != Some("#") {
+        return None;
+    }
+    iter.next().filter(|t| !t.is_ident())
+}
+
 /// Returns the token of the name of the directive
 fn line_get_directive_name(line: &[PPToken]) -> &PPToken {
     debug_assert!(line_is_directive(&line).is_some());
-    dbg!(line);
 
     line.iter().filter(|t| !t.is_whitespace()).nth(1).unwrap()
 }
@@ -234,7 +319,10 @@ fn line_get_directive_name(line: &[PPToken]) -> &PPToken {
 fn collect_lines_until_directive(line_iter: &mut IntoIter<Line>, output: &mut Vec<PPToken>) {
     while line_iter.as_slice().len() > 0 {
         let line = &line_iter.as_slice()[0];
-        if line_is_eof(line) || line_is_directive(line).is_some() {
+        if line_is_eof(line)
+            || line_is_directive(line).is_some()
+            || line_is_unnamed_directive(line).is_some()
+        {
             break;
         }
 
@@ -261,9 +349,11 @@ fn collect_lines_until_ify_directive(line_iter: &mut IntoIter<Line>) -> Vec<Line
             // nest
             (0, Some("else")) => break,
             (0, Some("elif")) => break,
+            (0, Some("elifdef")) => break,
+            (0, Some("elifndef")) => break,
             (0, Some("endif")) => break,
 
-            (_, Some("if")) => depth += 1,
+            (_, Some("if")) | (_, Some("ifdef")) | (_, Some("ifndef")) => depth += 1,
             (_, Some("endif")) => depth -= 1,
 
             (_, _) => {},
@@ -302,10 +392,10 @@ fn tokens_trim_whitespace(tokens: &[PPToken]) -> &[PPToken] {
 
     let mut first = 0;
     let mut last = tokens.len() - 1;
-    while first < tokens.len() && tokens[first].kind == PPTokenKind::Whitespace {
+    while first < tokens.len() && tokens[first].is_whitespace() {
         first += 1;
     }
-    while last >= first && tokens[last].kind == PPTokenKind::Whitespace {
+    while last >= first && tokens[last].is_whitespace() {
         last -= 1;
     }
     &tokens[first..last + 1]
@@ -373,7 +463,7 @@ fn parse_directive_define(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Option<Dir
         token_iter.next().unwrap();
 
         let mut vararg = false;
-        let mut params = Vec::new();
+        let mut params: Vec<String> = Vec::new();
 
         #[derive(Copy, Clone, Debug, PartialEq, Eq)]
         enum State {
@@ -388,18 +478,18 @@ fn parse_directive_define(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Option<Dir
             match (state, token.kind, token.as_str()) {
                 (State::LParen, _, ")") | (State::Ident, _, ")") | (State::Vararg, _, ")") => break,
 
-                (_, PPTokenKind::Whitespace, _) => continue,
+                (_, PPTokenKind::Whitespace, _) | (_, PPTokenKind::Comment, _) => continue,
 
                 (State::LParen, PPTokenKind::Identifier, ..)
                 | (State::Comma, PPTokenKind::Identifier, ..) => {
                     state = State::Ident;
-                    if !params.contains(&token.value) {
-                        params.push(token.value);
+                    if !params.iter().any(|p| *p == token.as_str()) {
+                        params.push(token.value.to_string());
                     } else {
                         tuctx.emit_message(
                             token.origin,
                             MessageKind::Phase4RepeatedMacroParameter {
-                                parameter: token.value,
+                                parameter: token.value.to_string(),
                             },
                         );
                     }
@@ -463,7 +553,9 @@ fn parse_directive_define(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Option<Dir
             .filter(|t| !t.is_whitespace_not_newline())
         {
             if let Some(location) = singlehash {
-                if !(params.contains(&token.value) || (vararg && token.value == "__VA_ARGS__")) {
+                if !(params.iter().any(|p| *p == token.as_str())
+                    || (vararg && token.as_str() == "__VA_ARGS__"))
+                {
                     tuctx.emit_message(location.clone(), MessageKind::Phase4IllegalSingleHash);
                     return None;
                 }
@@ -476,7 +568,7 @@ fn parse_directive_define(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Option<Dir
         // Now remove whitespace at beginning/end of replacement because it
         // simplifies testing for `##` (but it's also how macros are supposed to
         // expand)
-        let replacement = tokens_trim_whitespace(replacement).to_vec();
+        let replacement: Rc<[PPToken]> = tokens_trim_whitespace(replacement).into();
 
         // Test for ## at begin/end of macro
         if replacement.len() > 0 {
@@ -493,9 +585,13 @@ fn parse_directive_define(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Option<Dir
             }
         }
 
+        if !check_va_opt(tuctx, &replacement, vararg) {
+            return None;
+        }
+
         Some(Directive::Define(Rc::new(MacroDef::Function(
             MacroFunction {
-                name: name_token.value,
+                name: name_token.value.to_string(),
                 params,
                 vararg,
                 replacement,
@@ -504,13 +600,112 @@ fn parse_directive_define(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Option<Dir
         ))))
     } else {
         Some(Directive::Define(Rc::new(MacroDef::Object(MacroObject {
-            name: name_token.value,
-            replacement: tokens_trim_whitespace(token_iter.as_slice()).to_vec(),
+            name: name_token.value.to_string(),
+            replacement: tokens_trim_whitespace(token_iter.as_slice()).into(),
             origin: name_token.origin,
         }))))
     }
 }
 
+/// Check each `__VA_OPT__` in a function macro's replacement
+///
+/// Before C2x `__VA_OPT__` is rejected outright. Otherwise it may only appear
+/// in variadic macros, must be followed by a parenthesized group, and cannot
+/// nest, which lets [`Expander::replace()`] assume a well formed group.
+fn check_va_opt(tuctx: &mut TUCtx, replacement: &[PPToken], vararg: bool) -> bool {
+    let mut iter = replacement.iter().filter(|t| !t.is_whitespace());
+    while let Some(token) = iter.next() {
+        if token.as_str() != "__VA_OPT__" {
+            continue;
+        }
+
+        let mut origin = &token.origin;
+        let kind = if tuctx.session().standard() < CStandard::C2x {
+            Some(MessageKind::Phase4VaOptBeforeC2x)
+        } else if !vararg {
+            Some(MessageKind::Phase4VaOptNotVariadic)
+        } else {
+            match iter.next() {
+                Some(open) if open.as_str() == "(" => {
+                    let mut depth = 1;
+                    let mut nested = false;
+                    for token in iter.by_ref() {
+                        match token.as_str() {
+                            "(" => depth += 1,
+                            ")" => depth -= 1,
+                            "__VA_OPT__" => nested = true,
+                            _ => {},
+                        }
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    if nested {
+                        Some(MessageKind::Phase4VaOptNested)
+                    } else if depth > 0 {
+                        Some(MessageKind::Phase4VaOptUnterminated)
+                    } else {
+                        None
+                    }
+                },
+                Some(found) => {
+                    origin = &found.origin;
+                    Some(MessageKind::ExpectedFound {
+                        expected: ExpectedFoundPart::Plain("`(`".to_owned()),
+                        found: ExpectedFoundPart::Plain(format!("`{}`", found.value)),
+                    })
+                },
+                None => Some(MessageKind::ExpectedFound {
+                    expected: ExpectedFoundPart::Plain("`(`".to_owned()),
+                    found: ExpectedFoundPart::Plain("end of line".to_owned()),
+                }),
+            }
+        };
+
+        if let Some(kind) = kind {
+            tuctx.emit_message(origin.clone(), kind);
+            return false;
+        }
+    }
+    true
+}
+
+/// Take the group following `__VA_OPT__`, without its parentheses
+///
+/// The group was checked by [`check_va_opt()`] when the macro was defined.
+fn take_va_opt_group(input: &mut IntoIter<PPToken>) -> Vec<PPToken> {
+    line_skip_whitespace_until_newline(input);
+    input.next(); // consume `(`
+    let mut depth = 1;
+    let mut group = Vec::new();
+    for token in input {
+        match token.as_str() {
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            _ => {},
+        }
+        if depth == 0 {
+            break;
+        }
+        group.push(token);
+    }
+    group
+}
+
+/// Warn about any tokens remaining on the line after a directive's operands
+///
+/// Comments are whitespace by now, so `#endif // FOO` is fine.
+fn line_check_extra_tokens(tuctx: &mut TUCtx, directive: &str, rest: &[PPToken]) {
+    if let Some(extra) = rest.iter().find(|t| !t.is_whitespace()) {
+        tuctx.emit_message(
+            extra.origin.clone(),
+            MessageKind::Phase4ExtraTokens {
+                directive: directive.to_owned(),
+            },
+        );
+    }
+}
+
 fn parse_directive_undefine(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Option<Directive> {
     let mut token_iter = tokens.into_iter();
     line_skip_until_directive_content(&mut token_iter);
@@ -529,20 +724,8 @@ fn parse_directive_undefine(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Option<D
         return None;
     }
 
-    line_skip_whitespace_until_newline(&mut token_iter);
-
-    if line_peek(&mut token_iter).unwrap().is_newline() {
-        return Some(Directive::Undefine(name_token));
-    } else {
-        tuctx.emit_message(
-            name_token.origin,
-            MessageKind::ExpectedFound {
-                expected: ExpectedFoundPart::Plain("newline".to_owned()),
-                found: ExpectedFoundPart::PPToken(name_token.kind),
-            },
-        );
-        return None;
-    }
+    line_check_extra_tokens(tuctx, "undef", token_iter.as_slice());
+    Some(Directive::Undefine(name_token))
 }
 
 fn parse_directive_if_generic(
@@ -554,6 +737,8 @@ fn parse_directive_if_generic(
     let mut main_body = None;
     let mut elifs = Vec::new();
     let mut else_body = None;
+    // an invalid `#elifdef` discards the whole section, as `#ifdef` would
+    let mut failed = false;
 
     #[derive(Debug)]
     enum State {
@@ -586,7 +771,12 @@ fn parse_directive_if_generic(
 
         // update state
         match (state, line_is_directive(&line)) {
-            (_, Some("endif")) => break,
+            (_, Some("endif")) => {
+                let mut iter = line.into_iter();
+                line_skip_until_directive_content(&mut iter);
+                line_check_extra_tokens(tuctx, "endif", iter.as_slice());
+                break;
+            },
 
             // next directive is elif
             (State::Main, Some("elif")) | (State::Elif(..), Some("elif")) => {
@@ -598,23 +788,39 @@ fn parse_directive_if_generic(
                 state = State::Elif(condition);
             },
 
-            // next directive is `else`
-            (State::Main, Some("else")) | (State::Elif(..), Some("else")) => {
-                // skip hash and directive name
-                let mut iter = line.into_iter();
-                line_skip_until_directive_content(&mut iter);
-
-                let should_be_newline = iter.next().unwrap();
-                if !should_be_newline.is_newline() {
+            // next directive is `elifdef` or `elifndef`
+            (State::Main, Some(directive @ "elifdef"))
+            | (State::Main, Some(directive @ "elifndef"))
+            | (State::Elif(..), Some(directive @ "elifdef"))
+            | (State::Elif(..), Some(directive @ "elifndef")) => {
+                if tuctx.session().standard() < CStandard::C2x {
                     tuctx.emit_message(
-                        should_be_newline.origin,
-                        MessageKind::ExpectedFound {
-                            expected: ExpectedFoundPart::Plain("newline".to_owned()),
-                            found: ExpectedFoundPart::PPToken(should_be_newline.kind),
+                        line_get_directive_name(&line).origin.clone(),
+                        MessageKind::Phase4C2xDirective {
+                            directive: directive.to_owned(),
                         },
                     );
                 }
 
+                let defined = directive == "elifdef";
+                let mut iter = line.into_iter();
+                line_skip_until_directive_content(&mut iter);
+                let condition = match line_get_identifier_and_newline(tuctx, &mut iter) {
+                    Some(identifier) if defined => IfCondition::Defined(identifier),
+                    Some(identifier) => IfCondition::Undefined(identifier),
+                    None => {
+                        failed = true;
+                        IfCondition::Empty
+                    },
+                };
+                state = State::Elif(condition);
+            },
+
+            // next directive is `else`
+            (State::Main, Some("else")) | (State::Elif(..), Some("else")) => {
+                let mut iter = line.into_iter();
+                line_skip_until_directive_content(&mut iter);
+                line_check_extra_tokens(tuctx, "else", iter.as_slice());
                 state = State::Else;
             },
 
@@ -637,6 +843,9 @@ fn parse_directive_if_generic(
         }
     }
 
+    if failed {
+        return;
+    }
     output.push(Directive::IfSection {
         condition,
         main_body: main_body.unwrap(),
@@ -710,8 +919,9 @@ fn parse_directive_ifndef(
 /// Breaks stream into separate lines
 ///
 /// Will append a newline token to the input before splitting if the last token
-/// is not a newline already
-fn parse_lines(mut tokens: Vec<PPToken>, input: &Input) -> Vec<Line> {
+/// is not a newline already. Every line ends with its newline token. See
+/// [`join_lines()`][join_lines] for the inverse.
+pub fn parse_lines(mut tokens: Vec<PPToken>, input: &Input) -> Vec<Vec<PPToken>> {
     // Create newline if missing
     {
         let mut newline_pos = None;
@@ -723,9 +933,7 @@ fn parse_lines(mut tokens: Vec<PPToken>, input: &Input) -> Vec<Line> {
         } else {
             let last_token = tokens.last().unwrap();
             if !last_token.is_newline() {
-                let mut span = *last_token.origin.as_source();
-                span.pos.absolute += span.len;
-                newline_pos = Some(span.pos);
+                newline_pos = Some(last_token.origin.as_source().end());
             }
         }
 
@@ -736,7 +944,7 @@ fn parse_lines(mut tokens: Vec<PPToken>, input: &Input) -> Vec<Line> {
             // trying to read the source text
             tokens.push(PPToken {
                 kind: PPTokenKind::Whitespace,
-                value: "\n".to_owned(),
+                value: "\n".into(),
                 origin: TokenOrigin::Source(TextSpan { pos, len: 0 }),
             });
         }
@@ -770,12 +978,35 @@ fn parse_lines(mut tokens: Vec<PPToken>, input: &Input) -> Vec<Line> {
     lines
 }
 
-fn parse_include(tuctx: &mut TUCtx, line: Line) -> Option<Directive> {
+/// Concatenates lines back into a single stream
+///
+/// This is the inverse of [`parse_lines()`][parse_lines], so the newline
+/// appended to the final line is removed if it did not come from the input.
+pub fn join_lines(lines: Vec<Vec<PPToken>>) -> Vec<PPToken> {
+    let mut tokens: Vec<PPToken> = lines.into_iter().flatten().collect();
+
+    // the appended newline has zero width; see `parse_lines()`
+    let appended = tokens.last().map(|last| {
+        last.is_newline()
+            && match &last.origin {
+                TokenOrigin::Source(span) => span.len == 0,
+                _ => false,
+            }
+    });
+    if appended == Some(true) {
+        tokens.pop();
+    }
+
+    tokens
+}
+
+/// Parse either an `#include` or an `#include_next` directive
+fn parse_include(tuctx: &mut TUCtx, line: Line, next: bool) -> Option<Directive> {
     let mut line_iter = line.into_iter();
 
     // get location of `#`
     line_skip_whitespace_until_newline(&mut line_iter);
-    let begin = line_iter.as_slice()[0].origin.as_source_span().begin();
+    let hash = line_iter.as_slice()[0].origin.as_source_span();
     line_skip_until_directive_content(&mut line_iter);
     line_skip_whitespace_until_newline(&mut line_iter);
 
@@ -785,20 +1016,195 @@ fn parse_include(tuctx: &mut TUCtx, line: Line) -> Option<Directive> {
         return None;
     }
     // do not include newline in span
-    let end = content[content.len() - 2].origin.as_source_span().end();
+    let span = hash.merge(&content[content.len() - 2].origin.as_source_span());
+    if next {
+        Some(Directive::IncludeNext { content, span })
+    } else {
+        Some(Directive::Include { content, span })
+    }
+}
+
+fn parse_directive_line(line: Line) -> Directive {
+    let after = line.last().unwrap().origin.as_source_span().end();
+
+    let mut line_iter = line.into_iter();
+    line_skip_until_directive_content(&mut line_iter);
+
+    Directive::Line {
+        content: line_iter.collect(),
+        after,
+    }
+}
+
+/// Parse a GNU linemarker such as `# 1 "file.h" 1 3` into a `#line` directive
+///
+/// The flags after the file name, which describe entering and leaving
+/// included files, are ignored.
+fn parse_linemarker(line: Line) -> Directive {
+    let after = line.last().unwrap().origin.as_source_span().end();
+
+    let mut content: Vec<PPToken> = line.into_iter().skip_while(|t| t.as_str() != "#").collect();
+    content.remove(0);
+    let name = content
+        .iter()
+        .position(|t| t.kind == PPTokenKind::StringLiteral);
+    if let Some(name) = name {
+        let flags = &content[name + 1..content.len() - 1];
+        if flags
+            .iter()
+            .all(|t| t.is_whitespace() || t.kind == PPTokenKind::PPNumber)
+        {
+            content.drain(name + 1..content.len() - 1);
+        }
+    }
+
+    Directive::Line { content, after }
+}
+
+fn parse_directive_pragma(line: Line) -> Directive {
+    let mut line_iter = line.into_iter();
+    line_skip_until_directive_content(&mut line_iter);
+
+    Directive::Pragma {
+        content: line_iter.collect(),
+    }
+}
+
+/// Parse a predicate with an optional parenthesized answer
+///
+/// Used by `#assert`, `#unassert`, and `#if #predicate(answer)`.
+fn parse_assertion(tuctx: &mut TUCtx, iter: &mut IntoIter<PPToken>) -> Option<Assertion> {
+    line_skip_whitespace_until_newline(iter);
+    let predicate = iter.next().unwrap();
+    if !predicate.is_ident() {
+        tuctx.emit_message(
+            predicate.origin.clone(),
+            MessageKind::ExpectedFound {
+                expected: ExpectedFoundPart::Plain("predicate".to_owned()),
+                found: if_expr::found_part(&predicate),
+            },
+        );
+        return None;
+    }
+
+    line_skip_whitespace_until_newline(iter);
+    match line_peek(iter) {
+        Some(open) if open.kind == PPTokenKind::Punctuator && open.as_str() == "(" => {
+            iter.next();
+        },
+        _ => {
+            return Some(Assertion {
+                predicate,
+                answer: None,
+            })
+        },
+    }
+
+    let mut answer = Vec::new();
+    let mut depth = 0;
+    loop {
+        let token = iter.next().unwrap();
+        if token.is_newline() {
+            tuctx.emit_message(
+                token.origin.clone(),
+                MessageKind::ExpectedFound {
+                    expected: ExpectedFoundPart::Plain("`)`".to_owned()),
+                    found: if_expr::found_part(&token),
+                },
+            );
+            return None;
+        } else if token.kind == PPTokenKind::Punctuator && token.as_str() == "(" {
+            depth += 1;
+        } else if token.kind == PPTokenKind::Punctuator && token.as_str() == ")" {
+            if depth == 0 {
+                if answer.is_empty() {
+                    tuctx.emit_message(
+                        token.origin.clone(),
+                        MessageKind::ExpectedFound {
+                            expected: ExpectedFoundPart::Plain("answer".to_owned()),
+                            found: if_expr::found_part(&token),
+                        },
+                    );
+                    return None;
+                }
+                break;
+            }
+            depth -= 1;
+        }
+        if !token.is_whitespace() {
+            answer.push(token.value);
+        }
+    }
 
-    Some(Directive::Include {
-        content,
-        span: TextSpan::between(&begin, &end),
+    Some(Assertion {
+        predicate,
+        answer: Some(answer.join(" ")),
     })
 }
 
+/// Parse either an `#assert` or an `#unassert` directive
+///
+/// Only `#unassert` may omit the answer.
+fn parse_directive_assert(tuctx: &mut TUCtx, line: Line, unassert: bool) -> Option<Directive> {
+    let mut line_iter = line.into_iter();
+    line_skip_until_directive_content(&mut line_iter);
+    let assertion = parse_assertion(tuctx, &mut line_iter)?;
+
+    if unassert {
+        line_check_extra_tokens(tuctx, "unassert", line_iter.as_slice());
+        Some(Directive::Unassert(assertion))
+    } else if assertion.answer.is_none() {
+        let next = line_iter.find(|t| !t.is_whitespace_not_newline()).unwrap();
+        tuctx.emit_message(
+            next.origin.clone(),
+            MessageKind::ExpectedFound {
+                expected: ExpectedFoundPart::Plain("`(`".to_owned()),
+                found: if_expr::found_part(&next),
+            },
+        );
+        None
+    } else {
+        line_check_extra_tokens(tuctx, "assert", line_iter.as_slice());
+        Some(Directive::Assert(assertion))
+    }
+}
+
+/// Parse an `#ident` or `#sccs` directive, whose operand is a string literal
+fn parse_directive_ident(tuctx: &mut TUCtx, line: Line, directive: &str) -> Option<Directive> {
+    tuctx.emit_message(
+        line_get_directive_name(&line).origin.clone(),
+        MessageKind::Phase4GnuDirective {
+            directive: directive.to_owned(),
+        },
+    );
+
+    let mut line_iter = line.into_iter();
+    line_skip_until_directive_content(&mut line_iter);
+    line_skip_whitespace_until_newline(&mut line_iter);
+    let string = line_iter.next().unwrap();
+    if string.kind != PPTokenKind::StringLiteral || !string.value.starts_with('"') {
+        tuctx.emit_message(
+            string.origin.clone(),
+            MessageKind::ExpectedFound {
+                expected: ExpectedFoundPart::Plain("string literal".to_owned()),
+                found: if_expr::found_part(&string),
+            },
+        );
+        return None;
+    }
+
+    line_check_extra_tokens(tuctx, directive, line_iter.as_slice());
+    Some(Directive::Ident(
+        get_string_content(&string.value, "\"").to_owned(),
+    ))
+}
+
 /// Collates lines into directives
 fn parse_directives(tuctx: &mut TUCtx, lines: Vec<Line>) -> Vec<Directive> {
     let mut directives = Vec::<Directive>::new();
     let mut line_iter = lines.into_iter();
     while let Some(line) = line_iter.next() {
-        if line_is_eof(&line) {
+        if line_is_eof(&line) || tuctx.should_abort() {
             break;
         }
 
@@ -814,7 +1220,34 @@ fn parse_directives(tuctx: &mut TUCtx, lines: Vec<Line>) -> Vec<Directive> {
                 }
             },
             Some("include") => {
-                if let Some(directive) = parse_include(tuctx, line) {
+                if let Some(directive) = parse_include(tuctx, line, false) {
+                    directives.push(directive);
+                }
+            },
+            Some("include_next") => {
+                if let Some(directive) = parse_include(tuctx, line, true) {
+                    directives.push(directive);
+                }
+            },
+            Some("line") => directives.push(parse_directive_line(line)),
+            Some("pragma") => directives.push(parse_directive_pragma(line)),
+            Some("assert") => {
+                if let Some(directive) = parse_directive_assert(tuctx, line, false) {
+                    directives.push(directive);
+                }
+            },
+            Some("unassert") => {
+                if let Some(directive) = parse_directive_assert(tuctx, line, true) {
+                    directives.push(directive);
+                }
+            },
+            Some("ident") => {
+                if let Some(directive) = parse_directive_ident(tuctx, line, "ident") {
+                    directives.push(directive);
+                }
+            },
+            Some("sccs") => {
+                if let Some(directive) = parse_directive_ident(tuctx, line, "sccs") {
                     directives.push(directive);
                 }
             },
@@ -832,12 +1265,23 @@ fn parse_directives(tuctx: &mut TUCtx, lines: Vec<Line>) -> Vec<Directive> {
                 );
             },
 
-            // No directive means it's text
-            None => {
-                let mut text = line;
-                collect_lines_until_directive(&mut line_iter, &mut text);
+            None => match line_is_unnamed_directive(&line) {
+                // the null directive has no effect
+                Some(next) if next.is_newline() => {},
+                Some(next) if next.kind == PPTokenKind::PPNumber => {
+                    directives.push(parse_linemarker(line))
+                },
+                Some(next) => {
+                    tuctx.emit_message(next.origin.clone(), MessageKind::Phase4NonDirective);
+                },
+
+                // No directive means it's text
+                None => {
+                    let mut text = line;
+                    collect_lines_until_directive(&mut line_iter, &mut text);
 
-                directives.push(Directive::Text(text))
+                    directives.push(Directive::Text(text))
+                },
             },
         }
     }
@@ -845,20 +1289,78 @@ fn parse_directives(tuctx: &mut TUCtx, lines: Vec<Line>) -> Vec<Directive> {
     directives
 }
 
-/// Used when we #include a file
-fn process_file_inclusion(
+/// Handle a `#pragma` directive
+///
+/// Only `#pragma once` and `#pragma GCC diagnostic` with `push`, `pop`, or
+/// `ignored "-W..."` are understood, where the option names a warning or a
+/// group of warnings. Unknown pragmas are ignored, as the standard permits.
+fn process_pragma_directive(tuctx: &mut TUCtx, content: Vec<PPToken>) {
+    let newline = content.last().unwrap().origin.clone();
+    let mut iter = content.iter().filter(|t| !t.is_whitespace());
+    let first = iter.next();
+    if first.map(|t| t.as_str()) == Some("once") {
+        let input = newline.macro_root_textspan(tuctx).input(tuctx).clone();
+        tuctx.add_once_input(input);
+        return;
+    }
+    if first.map(|t| t.as_str()) != Some("GCC")
+        || iter.next().map(|t| t.as_str()) != Some("diagnostic")
+    {
+        return;
+    }
+
+    // the directive applies to everything after its line
+    let at = *newline.as_source();
+    let kind = iter.next();
+    match kind.map(|t| t.as_str()) {
+        Some("push") => tuctx.add_diagnostic_pragma(&at, DiagnosticPragma::Push),
+        Some("pop") => tuctx.add_diagnostic_pragma(&at, DiagnosticPragma::Pop),
+        Some("ignored") => match iter.next() {
+            Some(option)
+                if option.kind == PPTokenKind::StringLiteral && option.value.starts_with('"') =>
+            {
+                let text = &option.value[1..option.value.len() - 1];
+                match text.strip_prefix("-W").and_then(warning_group) {
+                    Some(warnings) => {
+                        tuctx.add_diagnostic_pragma(&at, DiagnosticPragma::Ignored(warnings))
+                    },
+                    None => tuctx.emit_message(
+                        option.origin.clone(),
+                        MessageKind::Phase4PragmaUnknownWarning {
+                            option: text.to_owned(),
+                        },
+                    ),
+                }
+            },
+            other => tuctx.emit_message(
+                other.map_or(newline, |t| t.origin.clone()),
+                MessageKind::Phase4PragmaDiagnosticInvalid,
+            ),
+        },
+        _ => tuctx.emit_message(
+            kind.map_or(newline, |t| t.origin.clone()),
+            MessageKind::Phase4PragmaDiagnosticInvalid,
+        ),
+    }
+}
+
+/// Find the header named by an `#include` line or a `__has_include` operand
+///
+/// `tokens` must end with a newline. Returns the name of the header, whether
+/// it was written in the `<...>` form, and the token beginning it.
+fn parse_header_name(
     tuctx: &mut TUCtx,
     mut tokens: Vec<PPToken>,
-    span: TextSpan,
     defines: &mut HashMap<String, Rc<MacroDef>>,
-) -> Vec<Line> {
-    use crate::front::c::lexer::lex;
-    use crate::front::c::minor::{convert_trigraphs, splice_lines};
-    use crate::front::c::token::CharToken;
-
+) -> Option<(String, bool, PPToken)> {
     debug_assert!(!tokens.is_empty()); // should always be a newline
     debug_assert!(tokens.last().unwrap().is_newline());
-    if tokens[0].kind == PPTokenKind::Identifier {
+    // C11 6.10.2p4: only a line not already in one of the two header forms is
+    // macro replaced, so `#include <a>` looks for `a` even if it is a macro
+    let header_form = tokens[0].kind == PPTokenKind::HeaderName
+        || tokens[0].kind == PPTokenKind::StringLiteral
+        || (tokens[0].kind == PPTokenKind::Punctuator && tokens[0].as_str() == "<");
+    if !header_form {
         let expander = Expander::from_tokens(tuctx, defines, tokens);
         tokens = expander.expand();
         // should still have newline after expansion
@@ -869,15 +1371,21 @@ fn process_file_inclusion(
     let system;
     let mut file = String::new();
     let mut iter = tokens.into_iter();
+    // a macro expanding to nothing may leave whitespace before the header name
+    line_skip_whitespace_until_newline(&mut iter);
     let first = iter.next().unwrap();
-    match (first.kind, first.value.as_str()) {
+    match (first.kind, first.as_str()) {
+        (PPTokenKind::HeaderName, value) => {
+            system = value.starts_with('<');
+            file = value[1..value.len() - 1].to_owned();
+        },
         (PPTokenKind::Punctuator, "<") => {
             system = true;
             while let Some(token) = iter.next() {
                 if token.is_newline() {
                     tuctx.emit_message(token.origin, MessageKind::Phase4IncludeUnclosed);
-                    return Vec::new();
-                } else if token.kind == PPTokenKind::Punctuator && token.value == ">" {
+                    return None;
+                } else if token.kind == PPTokenKind::Punctuator && token.as_str() == ">" {
                     break;
                 }
                 file.push_str(&token.value);
@@ -885,11 +1393,11 @@ fn process_file_inclusion(
         },
         (PPTokenKind::StringLiteral, _) => {
             system = false;
-            file = first.value;
+            file = get_string_content(&first.value, "\"").to_owned();
         },
         (_, _) => {
             tuctx.emit_message(first.origin, MessageKind::Phase4IncludeBegin);
-            return Vec::new();
+            return None;
         },
     }
 
@@ -906,44 +1414,269 @@ fn process_file_inclusion(
         // unlike the other errors, this one is innocuous enough to continue past
     }
 
+    Some((file, system, first))
+}
+
+/// Used when we #include a file
+///
+/// If `next` is true, this is an `#include_next` directive.
+fn process_file_inclusion(
+    tuctx: &mut TUCtx,
+    tokens: Vec<PPToken>,
+    span: TextSpan,
+    next: bool,
+    defines: &mut HashMap<String, Rc<MacroDef>>,
+) -> Vec<Line> {
+    let (file, system, first) = match parse_header_name(tuctx, tokens, defines) {
+        Some(header) => header,
+        None => return Vec::new(),
+    };
+
     let input = first.origin.macro_root_textspan(tuctx).input(tuctx).clone();
     if input.depth > 32 {
         tuctx.emit_message(first.origin, MessageKind::Phase4IncludeDepth);
         return Vec::new();
     }
 
-    let included_input: Option<_> = tuctx.add_include(&file, system, IncludedFrom { input, span });
-    if included_input.is_none() {
-        tuctx.emit_message(
-            first.origin,
-            MessageKind::Phase4IncludeNotFound { desired_file: file },
-        );
-        return Vec::new();
+    if next && input.included_from.is_none() {
+        // there is no search path entry to continue after, so this behaves
+        // like a plain `#include`
+        tuctx.emit_message(span, MessageKind::Phase4IncludeNextInPrimary);
+    }
+
+    let included_from = IncludedFrom {
+        input: Rc::clone(&input),
+        span,
+    };
+    let included_input = match (next, input.include_path_index) {
+        (true, Some(index)) => tuctx.add_include_next(&file, index, included_from),
+        _ => tuctx.add_include(&file, system, included_from),
+    };
+    let included_input = match included_input {
+        Ok(input) => Rc::clone(input),
+        Err(error) => {
+            let kind = match error {
+                IncludeError::NotFound => MessageKind::Phase4IncludeNotFound { desired_file: file },
+                IncludeError::IsDirectory(_) => {
+                    MessageKind::Phase4IncludeIsDirectory { desired_file: file }
+                },
+                IncludeError::Io(_, error) => MessageKind::Phase4IncludeUnreadable {
+                    desired_file: file,
+                    error: error.to_string(),
+                },
+            };
+            tuctx.emit_message(first.origin, kind);
+            return Vec::new();
+        },
+    };
+
+    debug!(
+        "process_file_inclusion() included_input = {:?}",
+        included_input
+    );
+    // a file with `#pragma once` is guarded just as if it had an include
+    // guard, so it is neither included nor warned about again
+    if tuctx.is_once_input(&included_input) {
+        return Vec::new();
+    }
+    let lines = lex_included_file(tuctx, &included_input);
+
+    if tuctx
+        .tu
+        .session
+        .flags()
+        .warning_enabled("duplicate-include")
+    {
+        warn_duplicate_include(tuctx, &included_input, span, &lines, defines);
     }
-    let included_input = Rc::clone(included_input.unwrap());
 
-    debug!(
-        "process_file_inclusion() included_input = {:?}",
-        included_input
-    );
-    let tokens = CharToken::from_input(&included_input);
-    let phase1 = convert_trigraphs(tokens);
-    let phase2 = splice_lines(tuctx, phase1);
-    let phase3 = lex(tuctx, phase2, &included_input);
-    let lines = parse_lines(phase3, &included_input);
     lines
 }
 
+/// Find the macro name of an include guard wrapping these lines
+///
+/// An include guard is an `#ifndef` directive whose matching `#endif` is the
+/// last line, with nothing but whitespace before or after.
+fn find_include_guard(lines: &[Line]) -> Option<&str> {
+    let is_blank = |line: &Line| line.iter().all(PPToken::is_whitespace);
+    let first = lines.iter().position(|line| !is_blank(line))?;
+    let last = lines.iter().rposition(|line| !is_blank(line))?;
+
+    if line_is_directive(&lines[first]) != Some("ifndef") {
+        return None;
+    }
+    let mut iter = lines[first].iter();
+    let name = iter
+        .by_ref()
+        .skip_while(|t| t.as_str() != "ifndef")
+        .skip(1)
+        .find(|t| !t.is_whitespace_not_newline())?;
+    if !name.is_ident() {
+        return None;
+    }
+
+    // the `#endif` matching the `#ifndef` must be the last line
+    let mut depth = 0;
+    for (i, line) in lines.iter().enumerate().take(last + 1).skip(first) {
+        match line_is_directive(line) {
+            Some("if") | Some("ifdef") | Some("ifndef") => depth += 1,
+            Some("endif") => {
+                depth -= 1;
+                if depth == 0 {
+                    return if i == last { Some(&name.value) } else { None };
+                }
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+/// Warn if a file without an include guard was already included
+fn warn_duplicate_include(
+    tuctx: &mut TUCtx,
+    input: &Input,
+    span: TextSpan,
+    lines: &[Line],
+    defines: &HashMap<String, Rc<MacroDef>>,
+) {
+    let previous = match tuctx.find_previous_inclusion(input) {
+        Some(previous) => previous,
+        None => return,
+    };
+    // the original input of the TU was not included by any directive
+    let previous_span = match &previous.included_from {
+        Some(included_from) => included_from.span,
+        None => return,
+    };
+
+    let guarded = find_include_guard(lines)
+        .map(|name| defines.contains_key(name))
+        .unwrap_or(false);
+    if guarded {
+        return;
+    }
+
+    tuctx.emit_message_with_children(
+        span,
+        MessageKind::Phase4IncludeDuplicate {
+            name: input.name.clone(),
+        },
+        vec![Message::from((
+            TokenOrigin::from(previous_span),
+            MessageKind::Phase4IncludePrevious {
+                name: input.name.clone(),
+            },
+        ))],
+    );
+}
+
+/// Used when we encounter a `#line` directive
+///
+/// The operands are macro expanded before being interpreted, so both the line
+/// number and the file name may come from macros.
+fn process_line_directive(
+    tuctx: &mut TUCtx,
+    tokens: Vec<PPToken>,
+    after: TextPosition,
+    defines: &mut HashMap<String, Rc<MacroDef>>,
+) {
+    debug_assert!(tokens.last().unwrap().is_newline());
+    let expander = Expander::from_tokens(tuctx, defines, tokens);
+    let tokens = expander.expand();
+    // should still have newline after expansion
+    debug_assert!(tokens.last().unwrap().is_newline());
+
+    let mut iter = tokens.into_iter();
+    line_skip_whitespace_until_newline(&mut iter);
+    let number = iter.next().unwrap();
+    if number.kind != PPTokenKind::PPNumber {
+        tuctx.emit_message(
+            number.origin,
+            MessageKind::ExpectedFound {
+                expected: ExpectedFoundPart::Plain("line number".to_owned()),
+                found: ExpectedFoundPart::PPToken(number.kind),
+            },
+        );
+        return;
+    }
+
+    // must be a digit sequence, which is interpreted as decimal even with
+    // leading zeros (ISO 9899:2018 6.10.4)
+    let line = Some(&number.value)
+        .filter(|v| v.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|l| (1..=2147483647).contains(l));
+    let line = match line {
+        Some(line) => line,
+        None => {
+            tuctx.emit_message(
+                number.origin,
+                MessageKind::Phase4LineInvalidNumber {
+                    value: number.value.to_string(),
+                },
+            );
+            return;
+        },
+    };
+
+    line_skip_whitespace_until_newline(&mut iter);
+    let mut name = None;
+    let mut next = iter.next().unwrap();
+    if next.kind == PPTokenKind::StringLiteral && next.value.starts_with('"') {
+        name = Some(get_string_content(&next.value, "\"").to_owned());
+        line_skip_whitespace_until_newline(&mut iter);
+        next = iter.next().unwrap();
+    }
+
+    if !next.is_newline() {
+        let expected = if name.is_some() {
+            "newline"
+        } else {
+            "string literal or newline"
+        };
+        tuctx.emit_message(
+            next.origin,
+            MessageKind::ExpectedFound {
+                expected: ExpectedFoundPart::Plain(expected.to_owned()),
+                found: ExpectedFoundPart::PPToken(next.kind),
+            },
+        );
+        return;
+    }
+
+    tuctx.add_line_override(LineOverride {
+        input: after.input,
+        after: after.absolute,
+        line,
+        name,
+    });
+}
+
+/// Process conditional inclusion and source file inclusion
+///
+/// The bodies of taken groups and the lines of included files are processed
+/// in place of the directive holding them. They are kept on an explicit stack
+/// rather than handled recursively, so deeply nested groups cannot exhaust
+/// the call stack.
 fn process_include_directives(
     tuctx: &mut TUCtx,
     lines: Vec<Line>,
     defines: &mut HashMap<String, Rc<MacroDef>>,
 ) -> Vec<Directive> {
-    let input_directives = parse_directives(tuctx, lines);
-
     let mut output_directives = Vec::new();
 
-    'outer: for directive in input_directives {
+    // the innermost group or file being processed is last
+    let mut pending = vec![parse_directives(tuctx, lines).into_iter()];
+    while let Some(directives) = pending.last_mut() {
+        let directive = match directives.next() {
+            Some(directive) => directive,
+            None => {
+                pending.pop();
+                continue;
+            },
+        };
+
         match directive {
             Directive::IfSection {
                 condition,
@@ -951,27 +1684,17 @@ fn process_include_directives(
                 elifs,
                 else_body,
             } => {
-                if condition.evaluate(defines) {
-                    output_directives.append(&mut process_include_directives(
-                        tuctx,
-                        main_body.clone(),
-                        defines,
-                    ));
-                    continue 'outer;
-                }
-                for (condition, body) in elifs {
-                    if condition.evaluate(defines) {
-                        output_directives.append(&mut process_include_directives(
-                            tuctx,
-                            body.clone(),
-                            defines,
-                        ));
-                        continue 'outer;
-                    }
-                }
-                if let Some(else_body) = else_body.clone() {
-                    output_directives
-                        .append(&mut process_include_directives(tuctx, else_body, defines));
+                let body = if condition.evaluate(tuctx, defines) {
+                    Some(main_body)
+                } else {
+                    elifs
+                        .into_iter()
+                        .find(|(condition, _)| condition.evaluate(tuctx, defines))
+                        .map(|(_, body)| body)
+                        .or(else_body)
+                };
+                if let Some(body) = body {
+                    pending.push(parse_directives(tuctx, body).into_iter());
                 }
             },
 
@@ -981,19 +1704,44 @@ fn process_include_directives(
                 output_directives.push(Directive::Define(macrodef));
             },
             Directive::Undefine(name) => {
-                defines.remove(&name.value);
+                defines.remove(name.as_str());
                 output_directives.push(Directive::Undefine(name));
             },
             directive @ Directive::Text(..) => {
                 output_directives.push(directive);
             },
+            Directive::Line { content, after } => {
+                process_line_directive(tuctx, content, after, defines);
+            },
+            Directive::Pragma { content } => process_pragma_directive(tuctx, content),
+            Directive::Assert(assertion) => {
+                let answers = tuctx
+                    .assertions
+                    .entry(assertion.predicate.value.to_string())
+                    .or_default();
+                let answer = assertion.answer.unwrap();
+                if !answers.contains(&answer) {
+                    answers.push(answer);
+                }
+            },
+            Directive::Ident(string) => tuctx.idents.push(string),
+            Directive::Unassert(assertion) => match assertion.answer {
+                Some(answer) => {
+                    if let Some(answers) = tuctx.assertions.get_mut(assertion.predicate.as_str()) {
+                        answers.retain(|a| *a != answer);
+                    }
+                },
+                None => {
+                    tuctx.assertions.remove(assertion.predicate.as_str());
+                },
+            },
             Directive::Include { content, span } => {
-                let included_directives = process_file_inclusion(tuctx, content, span, defines);
-                output_directives.append(&mut process_include_directives(
-                    tuctx,
-                    included_directives,
-                    defines,
-                ))
+                let included_lines = process_file_inclusion(tuctx, content, span, false, defines);
+                pending.push(parse_directives(tuctx, included_lines).into_iter());
+            },
+            Directive::IncludeNext { content, span } => {
+                let included_lines = process_file_inclusion(tuctx, content, span, true, defines);
+                pending.push(parse_directives(tuctx, included_lines).into_iter());
             },
         }
     }
@@ -1001,37 +1749,6 @@ fn process_include_directives(
     output_directives
 }
 
-fn disable_macro_recursion(tokens: &mut Vec<PPToken>, name: &PPToken) {
-    for token in tokens {
-        if token.value == name.value {
-            // The contents of the macro expansion included the macro's own
-            // name. Mark that this macro cannot be expanded
-            token.kind = PPTokenKind::IdentifierNonExpandable;
-        }
-    }
-}
-
-/// Escape string literals and character constants
-///
-/// We wish to place these tokens within a string literal, so we only care about
-/// escaping backslashes and double quotes. In either case, we prepend those
-/// characters with another backslash.
-fn escape(output: &mut String, token: &PPToken) {
-    for c in token.value.chars() {
-        match c {
-            '\\' => {
-                output.push('\\');
-                output.push('\\');
-            },
-            '"' => {
-                output.push('\\');
-                output.push('"');
-            },
-            c => output.push(c),
-        }
-    }
-}
-
 fn stringize(input: &[PPToken], origin: TokenOrigin) -> PPToken {
     use PPTokenKind::*;
 
@@ -1044,14 +1761,16 @@ fn stringize(input: &[PPToken], origin: TokenOrigin) -> PPToken {
     for token in inner {
         trace!("stringize() token={:?}", &token);
         match token.kind {
-            Whitespace => {
+            Whitespace | Comment => {
                 // sequences of multiple whitespace tokens should be replaced
                 // with only one space character.
                 if output.chars().next_back() != Some(' ') {
                     output.push(' ');
                 }
             },
-            StringLiteral | CharacterConstant => escape(&mut output, &token),
+            // we place these tokens within a string literal, so their
+            // backslashes and double quotes must be escaped
+            StringLiteral | CharacterConstant => output.push_str(&c_escape(&token.value)),
             _ => output.push_str(&token.value),
         }
     }
@@ -1062,23 +1781,40 @@ fn stringize(input: &[PPToken], origin: TokenOrigin) -> PPToken {
 
     PPToken {
         kind: PPTokenKind::StringLiteral,
-        value: output,
+        value: output.into(),
         origin, // TODO verify origin of stringizing macros
     }
 }
 
-fn pre_update_macro_arg_tokens(tokens: &mut [PPToken], invocation: u32, mut start: u16) -> u16 {
-    for token in tokens {
-        token.origin = TokenOrigin::Macro(MacroResult::new_param(invocation, start));
-        start += 1;
-    }
-    start
+/// Copy a macro argument, marking its tokens as coming from the invocation
+///
+/// The tokens of all arguments are numbered consecutively, so this argument
+/// begins at `start`. Only the origins are new; the text is shared.
+fn materialize_macro_arg(tokens: &[PPToken], invocation: u32, start: u16) -> Vec<PPToken> {
+    tokens
+        .iter()
+        .zip(start..)
+        .map(|(token, index)| PPToken {
+            value: token.value.clone(),
+            kind: token.kind,
+            origin: TokenOrigin::Macro(MacroResult::new_param(invocation, index)),
+        })
+        .collect()
 }
 
-fn pre_update_macro_body_tokens(tokens: &mut [PPToken], invocation: u32) {
-    for (index, token) in tokens.iter_mut().enumerate() {
-        token.origin = TokenOrigin::Macro(MacroResult::new_body(invocation, index as u16));
-    }
+/// Copy a replacement list, marking its tokens as coming from the invocation
+///
+/// Only the origins are new; the text is shared with the definition.
+fn materialize_macro_body(tokens: &[PPToken], invocation: u32) -> Vec<PPToken> {
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(index, token)| PPToken {
+            value: token.value.clone(),
+            kind: token.kind,
+            origin: TokenOrigin::Macro(MacroResult::new_body(invocation, index as u16)),
+        })
+        .collect()
 }
 
 fn post_update_macro_result(tokens: &mut [PPToken], invocation: u32) {
@@ -1093,6 +1829,56 @@ fn post_update_macro_result(tokens: &mut [PPToken], invocation: u32) {
     }
 }
 
+/// What replacement of a function-like macro invocation has learned about its
+/// arguments
+#[derive(Debug, Default)]
+struct ExpandedArguments {
+    /// Fully expanded arguments, by parameter name
+    ///
+    /// Each argument is expanded at most once, no matter how many times its
+    /// parameter appears, and only if it appears outside of `#` and `##`.
+    tokens: HashMap<String, Vec<PPToken>>,
+
+    /// Parameters whose arguments turned out to need expanding, in order of
+    /// first appearance
+    ///
+    /// If any are found, the result of replacement is discarded and it is run
+    /// again once they have been expanded.
+    needed: Vec<String>,
+
+    /// Messages from replacement, emitted once it need not be run again
+    messages: Vec<(TokenOrigin, MessageKind)>,
+}
+
+/// A function-like macro invocation waiting for its arguments to be expanded
+#[derive(Debug)]
+struct PendingInvocation {
+    /// The name of the macro, as it appeared in the invocation
+    name: PPToken,
+    definition: Rc<MacroDef>,
+    invocation: u32,
+    /// The arguments, marked as coming from the invocation
+    parameters: HashMap<String, Vec<PPToken>>,
+    expanded: ExpandedArguments,
+    /// Parameters whose arguments are still to be expanded, last first
+    prescan: Vec<String>,
+}
+
+/// The state of an [`Expander`] while it prescans a macro argument
+///
+/// Everything here is restored once the argument is fully expanded.
+#[derive(Debug)]
+struct Suspended {
+    pending: PendingInvocation,
+    /// The parameter whose argument is being prescanned
+    parameter: String,
+    output: Vec<PPToken>,
+    rescan: VecDeque<PPToken>,
+    active: Vec<(Rc<str>, Option<usize>)>,
+    line: Option<IntoIter<PPToken>>,
+    directives: IntoIter<Directive>,
+}
+
 /// Struct for managing complex expansion logic
 ///
 /// This largely follows the algorithm proposed in X3J11/86-196, an ancient
@@ -1103,9 +1889,6 @@ struct Expander<'tu, 'drv, 'def> {
     tuctx: &'tu mut TUCtx<'drv>,
 
     /// Macro definitions
-    ///
-    /// Stored as a pointer so we can easily recurse with a second Expander to
-    /// handle small subsets of the token stream.
     defines: &'def mut HashMap<String, Rc<MacroDef>>,
 
     /// Output of expansion
@@ -1115,12 +1898,41 @@ struct Expander<'tu, 'drv, 'def> {
 
     /// Tokens to be rescanned after expansion
     ///
-    /// These tokens are examined before any of the remaining tokens that
-    /// actually derive from the input file. This vector is stored in reverse
-    /// order. We want a collection that we can easily append to and pop from
-    /// the front of. This is easily achieved by calling `.reverse()` on data
-    /// before appending it to this.
-    rescan: Vec<PPToken>,
+    /// These tokens are examined, front first, before any of the remaining
+    /// tokens that actually derive from the input file.
+    rescan: VecDeque<PPToken>,
+
+    /// Macros currently being rescanned
+    ///
+    /// Any identifier naming one of these macros that is read while the macro
+    /// is active is painted as `IdentifierNonExpandable`, which stops both
+    /// direct and mutual recursion. Each entry records the length of
+    /// `self.rescan` before the macro's replacement list was pushed, so the
+    /// macro stops being active once all of its tokens have been consumed. An
+    /// entry of `None` was inherited from the expansion suspended to prescan a
+    /// macro argument, and stays active for the whole argument.
+    active: Vec<(Rc<str>, Option<usize>)>,
+
+    /// Expansions suspended while prescanning macro arguments, innermost last
+    ///
+    /// Keeping these on an explicit stack rather than recursing means deeply
+    /// nested invocations cannot overflow the call stack. Once there are as
+    /// many as the `--max-macro-depth` limit, arguments are substituted without
+    /// being expanded first.
+    suspended: Vec<Suspended>,
+
+    /// Number of tokens produced by macro replacement so far
+    ///
+    /// Once it exceeds the `--max-expansion-tokens` limit, expansion stops so
+    /// that a pathological macro cannot exhaust memory.
+    generated: usize,
+
+    /// Whether expansion stopped because macro replacement produced too many
+    /// tokens
+    ///
+    /// Besides exceeding the limit, a single invocation may have more tokens
+    /// than a [`MacroResult`] can number.
+    stopped: bool,
 
     /// Tokens of the current text line of the input file
     ///
@@ -1145,7 +1957,11 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
             defines,
             output: Vec::new(),
 
-            rescan: Vec::new(),
+            rescan: VecDeque::new(),
+            active: Vec::new(),
+            suspended: Vec::new(),
+            generated: 0,
+            stopped: false,
             line: None,
             directives: directives.into_iter(),
         }
@@ -1162,7 +1978,11 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
             defines,
             output: Vec::new(),
 
-            rescan: Vec::new(),
+            rescan: VecDeque::new(),
+            active: Vec::new(),
+            suspended: Vec::new(),
+            generated: 0,
+            stopped: false,
             line: if line.is_empty() {
                 None
             } else {
@@ -1176,11 +1996,109 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
     ///
     /// The following call to `next_token()` will return the first element of
     /// this vector
-    fn rescan(&mut self, mut tokens: Vec<PPToken>) {
-        // self.rescan is stored in reverse order so that we can easily append
-        // to and pop from it.
-        tokens.reverse();
-        self.rescan.append(&mut tokens);
+    fn rescan(&mut self, tokens: Vec<PPToken>) {
+        for token in tokens.into_iter().rev() {
+            self.rescan.push_front(token);
+        }
+    }
+
+    /// Rescan the replacement list of the macro `name`
+    ///
+    /// The macro is marked active until all of these tokens are consumed, so
+    /// that it cannot be expanded again from within its own replacement. The
+    /// tokens must already have been counted by `count_expansion()`.
+    fn rescan_expansion(&mut self, name: &PPToken, tokens: Vec<PPToken>) {
+        self.tuctx.macro_expansions += 1;
+
+        self.active
+            .push((name.value.clone(), Some(self.rescan.len())));
+        self.rescan(tokens);
+    }
+
+    /// Count the tokens produced by replacing the macro `name`
+    ///
+    /// Returns false if expansion has stopped, which happens once macro
+    /// replacement has produced more tokens than allowed, or this replacement
+    /// more than a [`MacroResult`] can number.
+    fn count_expansion(&mut self, name: &PPToken, count: usize) -> bool {
+        if self.stopped {
+            return false;
+        }
+        self.generated += count;
+        if self.generated > self.tuctx.tu.session.flags().max_expansion_tokens
+            || count > MacroResult::MAX_OUTPUT_TOKENS
+        {
+            self.stop(name);
+            return false;
+        }
+        true
+    }
+
+    /// The most tokens replacement can produce before `count_expansion()`
+    /// stops expansion
+    fn replacement_limit(&self) -> usize {
+        let max = self.tuctx.tu.session.flags().max_expansion_tokens;
+        max.saturating_sub(self.generated)
+            .min(MacroResult::MAX_OUTPUT_TOKENS)
+    }
+
+    /// Stop expansion because the invocation of `name` is too large
+    fn stop(&mut self, name: &PPToken) {
+        self.stopped = true;
+        self.tuctx
+            .emit_message(name.origin.clone(), MessageKind::Phase4ExpansionTooLarge);
+    }
+
+    /// Suspend expansion to prescan `tokens`, the argument for `parameter`
+    ///
+    /// Macros which are active now remain active while prescanning. Once the
+    /// argument is exhausted, `expand()` calls `resume()`.
+    fn suspend(&mut self, pending: PendingInvocation, parameter: String, tokens: Vec<PPToken>) {
+        debug_assert!(!tokens.is_empty());
+        let active = self
+            .active
+            .iter()
+            .map(|(name, _)| (name.clone(), None))
+            .collect();
+        self.suspended.push(Suspended {
+            pending,
+            parameter,
+            output: std::mem::take(&mut self.output),
+            rescan: std::mem::take(&mut self.rescan),
+            active: std::mem::replace(&mut self.active, active),
+            line: self.line.replace(tokens.into_iter()),
+            directives: std::mem::replace(&mut self.directives, Vec::new().into_iter()),
+        });
+    }
+
+    /// Return to the expansion suspended by `suspend()`, carrying on with the
+    /// invocation now that the argument is fully expanded
+    fn resume(&mut self, suspended: Suspended) {
+        let Suspended {
+            mut pending,
+            parameter,
+            output,
+            rescan,
+            active,
+            line,
+            directives,
+        } = suspended;
+        let tokens = std::mem::replace(&mut self.output, output);
+        self.rescan = rescan;
+        self.active = active;
+        self.line = line;
+        self.directives = directives;
+
+        pending.expanded.tokens.insert(parameter, tokens);
+        self.replace_invocation(pending);
+    }
+
+    /// Whether prescanning `tokens` would leave them unchanged, because none
+    /// of them names a macro
+    fn names_no_macro(&self, tokens: &[PPToken]) -> bool {
+        !tokens
+            .iter()
+            .any(|t| t.kind == PPTokenKind::Identifier && self.defines.contains_key(t.as_str()))
     }
 
     /// Retrieve next token from `self.line` and cleanup when we've exhausted it
@@ -1220,11 +2138,13 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
 
     /// Remove a macro definition
     fn remove_define(&mut self, name: PPToken) {
-        let macrodef = self.defines.remove(&name.value);
+        let macrodef = self.defines.remove(name.as_str());
         if macrodef.is_none() {
             self.tuctx.emit_message(
                 name.origin,
-                MessageKind::Phase4UndefineInvalidMacro { name: name.value },
+                MessageKind::Phase4UndefineInvalidMacro {
+                    name: name.value.to_string(),
+                },
             )
         }
     }
@@ -1243,7 +2163,14 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
                     self.line = Some(tokens.into_iter());
                     return self.next_token();
                 },
-                Directive::IfSection { .. } | Directive::Include { .. } => unreachable!(),
+                Directive::IfSection { .. }
+                | Directive::Include { .. }
+                | Directive::IncludeNext { .. }
+                | Directive::Line { .. }
+                | Directive::Pragma { .. }
+                | Directive::Assert(..)
+                | Directive::Unassert(..)
+                | Directive::Ident(..) => unreachable!(),
             }
         }
         None
@@ -1251,10 +2178,15 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
 
     /// Returns next token to be processed
     ///
-    /// The priority is `self.rescan` (in reverse order), then `self.line`, then
+    /// The priority is `self.rescan`, then `self.line`, then
     /// extracting a new value for `self.line` from `self.directives`.
     fn next_token(&mut self) -> Option<PPToken> {
-        if self.rescan.is_empty() {
+        // forget macros whose replacement lists have been entirely consumed
+        let depth = self.rescan.len();
+        self.active
+            .retain(|(_, start)| start.map(|start| start < depth).unwrap_or(true));
+
+        let mut token = if self.rescan.is_empty() {
             if self.line.is_some() {
                 Some(self.next_token_from_line())
             } else {
@@ -1262,8 +2194,19 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
             }
         } else {
             // should always return Option::Some
-            self.rescan.pop()
+            self.rescan.pop_front()
+        };
+
+        if let Some(token) = &mut token {
+            if token.kind == PPTokenKind::Identifier
+                && self.active.iter().any(|(name, _)| *name == token.value)
+            {
+                // The token names a macro whose expansion we are still inside
+                // of. Mark that this macro cannot be expanded
+                token.kind = PPTokenKind::IdentifierNonExpandable;
+            }
         }
+        token
     }
 
     /// Parse arguments to a function-like macro
@@ -1286,6 +2229,10 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
         let mut depth = 0;
         let mut arguments = Vec::new();
         let mut current_arg = Vec::new();
+        // Only parentheses group macro arguments, but a comma within brackets
+        // or braces was likely not meant to separate them
+        let mut brackets: Vec<PPToken> = Vec::new();
+        let mut warned_brackets = false;
         while let Some(token) = self.next_token() {
             trace!(
                 "Expander::parse_arguments() token={} depth={} current_arg={:?} arguments={:?}",
@@ -1303,6 +2250,23 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
                     // the commas)
                     current_arg.push(token);
                 } else {
+                    if let (Some(bracket), false) = (brackets.last(), warned_brackets) {
+                        warned_brackets = true;
+                        let bracket = bracket.clone();
+                        self.tuctx.emit_message_with_children(
+                            token.origin.clone(),
+                            MessageKind::Phase4MacroArgumentCommaInBrackets {
+                                name: func.name.clone(),
+                                bracket: bracket.value.to_string(),
+                            },
+                            vec![(
+                                bracket.origin,
+                                MessageKind::Phase4UnclosedBracketOpening {
+                                    bracket: bracket.value.to_string(),
+                                },
+                            )],
+                        );
+                    }
                     arguments.push(std::mem::replace(&mut current_arg, Vec::new()));
                 }
             } else if token.as_str() == "(" {
@@ -1312,16 +2276,20 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
                 current_arg.push(token);
                 depth -= 1;
             } else if token.as_str() == ")" && depth == 0 {
-                if !tokens_trim_whitespace(&current_arg).is_empty() || func.params.len() > 0 {
+                if !tokens_trim_whitespace(&current_arg).is_empty()
+                    || !func.params.is_empty()
+                    || !arguments.is_empty()
+                {
                     // an argument can be empty, but if the function expects 0
-                    // arguments, don't push one
+                    // arguments, `f()` passes none. Once a comma has been
+                    // seen, every argument counts even if it is empty
                     arguments.push(current_arg);
                 }
 
                 // we want caller to handle closing paren so it can find an
                 // accurate span of the entire macro invocation
                 // TODO do we still need to do ^^^
-                self.rescan.push(token);
+                self.rescan.push_front(token);
                 break;
             } else if token.kind == PPTokenKind::EndOfFile {
                 // error
@@ -1339,6 +2307,13 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
                 );
                 return None;
             } else {
+                match token.spelling_normalized() {
+                    "[" | "{" => brackets.push(token.clone()),
+                    "]" | "}" => {
+                        brackets.pop();
+                    },
+                    _ => {},
+                }
                 current_arg.push(token);
             }
         }
@@ -1365,7 +2340,7 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
         trace!("Expander::parse_arguments() vararg={:?}", &vararg);
 
         if arguments.len() != func.params.len() {
-            self.tuctx.emit_message(
+            self.tuctx.emit_message_with_children(
                 open.clone(),
                 MessageKind::Phase4MacroArity {
                     name: func.name.clone(),
@@ -1373,6 +2348,14 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
                     found: arguments.len(),
                     vararg: func.vararg,
                 },
+                vec![(
+                    func.origin.clone(),
+                    MessageKind::Phase4MacroDefinedParameters {
+                        name: func.name.clone(),
+                        count: func.params.len(),
+                        vararg: func.vararg,
+                    },
+                )],
             );
             return None;
         }
@@ -1389,26 +2372,48 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
         Some(parameters)
     }
 
+    /// Whether `token` begins a `__VA_OPT__` group
+    ///
+    /// This is only ever true under C2x because earlier standards reject such
+    /// macros when they are defined.
+    fn is_va_opt(
+        &self,
+        function: bool,
+        token: &PPToken,
+        parameters: &HashMap<String, Vec<PPToken>>,
+    ) -> bool {
+        function && token.as_str() == "__VA_OPT__" && parameters.contains_key("__VA_ARGS__")
+    }
+
     /// Perform macro replacement
     ///
     /// This includes function macro arguments as well as token stringifying and
-    /// concatenation
+    /// concatenation. Arguments which need expanding but are not yet in
+    /// `expanded` are substituted unexpanded and added to `expanded.needed`, in
+    /// which case the result must be discarded.
     fn replace(
         &mut self,
         function: bool,
         mut input: IntoIter<PPToken>,
-        parameters: HashMap<String, Vec<PPToken>>,
+        parameters: &HashMap<String, Vec<PPToken>>,
+        expanded: &mut ExpandedArguments,
     ) -> Vec<PPToken> {
         trace!(
             "Expander::replace(function: {}, input: {:?}, parameters: {:?})",
             function,
             PPToken::to_strings(input.as_slice()),
-            &parameters
+            parameters
         );
 
         let mut output = Vec::new();
         let mut skip_rhs_of_concat = false;
+        // stop as soon as the result is too large to be used, rather than
+        // building all of it
+        let limit = self.replacement_limit();
         while let Some(token) = input.next() {
+            if output.len() > limit {
+                break;
+            }
             trace!("Expander::replace() loop token={}", &token);
             trace!(
                 "Expander::replace() loop input={:?}",
@@ -1419,7 +2424,7 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
                 PPToken::to_strings(output.as_slice())
             );
 
-            if let Some(replacement) = parameters.get(token.as_str()) {
+            if let Some((name, replacement)) = parameters.get_key_value(token.as_str()) {
                 // We have a macro parameter. It can either be the left-hand
                 // side of a `##` operator or it could be just a plain
                 // substitution of the macro argument.
@@ -1459,13 +2464,28 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
                 } else if skip_rhs_of_concat {
                     skip_rhs_of_concat = false;
                     output.extend_from_slice(replacement);
+                    output.append(&mut whitespace);
                 } else {
                     // Plain parameter substitution, so take the parameter value and expand it
-                    let expander =
-                        Expander::from_tokens(self.tuctx, self.defines, replacement.clone());
-                    output.append(&mut expander.expand());
+                    if let Some(tokens) = expanded.tokens.get(name) {
+                        output.extend_from_slice(tokens);
+                    } else {
+                        if !self.names_no_macro(replacement) && !expanded.needed.contains(name) {
+                            expanded.needed.push(name.clone());
+                        }
+                        output.extend_from_slice(replacement);
+                    }
                     output.append(&mut whitespace);
                 }
+            } else if self.is_va_opt(function, &token, parameters) {
+                // `__VA_OPT__(group)` is replaced by the group only if the
+                // variable arguments are not empty
+                let group = take_va_opt_group(&mut input);
+                if !parameters["__VA_ARGS__"].is_empty() {
+                    let mut replaced =
+                        self.replace(function, group.into_iter(), parameters, expanded);
+                    output.append(&mut replaced);
+                }
             } else if token.as_str() == "#" && function {
                 // we only stringize `#` tokens that occur within function macros
 
@@ -1500,9 +2520,22 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
                 // tokens, in which case only the first will be considered the
                 // rhs to be concatenated, and the others will be appended to
                 // output after the result of concatenation.
+                let va_opt;
+                let next_replacement = if self.is_va_opt(function, &next, parameters) {
+                    let group = take_va_opt_group(&mut input);
+                    va_opt = if parameters["__VA_ARGS__"].is_empty() {
+                        Vec::new()
+                    } else {
+                        self.replace(function, group.into_iter(), parameters, expanded)
+                    };
+                    Some(&va_opt)
+                } else {
+                    parameters.get(next.as_str())
+                };
+
                 let rhs;
                 let mut additional_output = None;
-                if let Some(replacement) = parameters.get(next.as_str()) {
+                if let Some(replacement) = next_replacement {
                     if replacement.is_empty() {
                         // parameter expanded to nothing, so nothing to concatenate with.
                         // thus we push lhs back on to output and discard the `##`
@@ -1519,11 +2552,12 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
                 }
 
                 let value = format!("{}{}", lhs.value, rhs.value);
-                let (slice, kind) = lex_one_token(&value);
+                let options = LexOptions::new(self.tuctx.session());
+                let (slice, kind) = lex_one_token_with(&value, options);
 
                 if value.len() == slice.len() {
                     output.push(PPToken {
-                        value,
+                        value: value.into(),
                         kind,
                         origin: token.origin,
                     });
@@ -1534,13 +2568,13 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
                     // the token we lexed does not contain the entire
                     // concatenated string, thus indicating the concatenation
                     // did not result in a (single) valid token.
-                    self.tuctx.emit_message(
+                    expanded.messages.push((
                         token.origin,
                         MessageKind::Phase4BadConcatenation {
-                            lhs: lhs.value,
-                            rhs: rhs.value,
+                            lhs: lhs.value.to_string(),
+                            rhs: rhs.value.to_string(),
                         },
-                    );
+                    ));
                 }
             } else {
                 output.push(token);
@@ -1554,14 +2588,74 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
         output
     }
 
+    /// Replace a function-like macro invocation, expanding its arguments first
+    ///
+    /// Rather than recursing, this suspends expansion to prescan the next
+    /// argument that needs it, and is called again by `resume()` once that
+    /// argument is fully expanded.
+    fn replace_invocation(&mut self, mut pending: PendingInvocation) {
+        let max_depth = self.tuctx.tu.session.flags().max_macro_depth;
+        loop {
+            if self.stopped {
+                return;
+            }
+            if let Some(parameter) = pending.prescan.pop() {
+                let argument = &pending.parameters[&parameter];
+                if self.suspended.len() < max_depth {
+                    let tokens = argument.clone();
+                    self.suspend(pending, parameter, tokens);
+                    return;
+                }
+                // give up rather than exhausting memory
+                self.tuctx
+                    .emit_message(argument[0].origin.clone(), MessageKind::Phase4MacroDepth);
+                let tokens = argument.clone();
+                pending.expanded.tokens.insert(parameter, tokens);
+                continue;
+            }
+
+            // copy the text of the macro as coming from the span of the
+            // entire macro invocation
+            let func = pending.definition.as_function();
+            let replacement = materialize_macro_body(&func.replacement, pending.invocation);
+            let mut replaced = self.replace(
+                true,
+                replacement.into_iter(),
+                &pending.parameters,
+                &mut pending.expanded,
+            );
+
+            let expanded = &mut pending.expanded;
+            if expanded.needed.is_empty() {
+                for (origin, kind) in expanded.messages.drain(..) {
+                    self.tuctx.emit_message(origin, kind);
+                }
+                if self.count_expansion(&pending.name, replaced.len()) {
+                    post_update_macro_result(&mut replaced, pending.invocation);
+                    self.rescan_expansion(&pending.name, replaced);
+                }
+                return;
+            }
+
+            // expand the arguments replacement found, then run it again
+            expanded.messages.clear();
+            pending.prescan = std::mem::take(&mut expanded.needed);
+            pending.prescan.reverse();
+        }
+    }
+
     /// Inspect a single identifier and determine if it needs expanding
     fn expand_ident(&mut self, token: PPToken) {
         trace!("Expander::expand_ident(token: {})", &token);
 
-        let macrodef = self.defines.get(&token.value);
+        let macrodef = self.defines.get(token.as_str());
         match macrodef.map(|d| &**d) {
             Some(MacroDef::Object(obj)) => {
                 trace!("Expander::expand_ident() {:?}", &obj);
+                if obj.replacement.len() > MacroResult::MAX_INPUT_TOKENS {
+                    self.stop(&token);
+                    return;
+                }
 
                 let invocation: u32 = self.tuctx.add_macro_invocation(MacroInvocation {
                     definition: Rc::clone(macrodef.unwrap()),
@@ -1569,19 +2663,24 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
                     arguments: HashMap::new(),
                 });
 
-                // copy replacement list and modify locations to show these
-                // tokens were used in a macro
-                let mut replacement = obj.replacement.clone();
-                pre_update_macro_body_tokens(&mut replacement, invocation);
+                // copy replacement list with locations showing these tokens
+                // were used in a macro
+                let replacement = materialize_macro_body(&obj.replacement, invocation);
 
-                let mut replaced = self.replace(
+                let mut expanded = ExpandedArguments::default();
+                let replaced = self.replace(
                     false, // function-like?
                     replacement.into_iter(),
-                    HashMap::new(),
+                    &HashMap::new(),
+                    &mut expanded,
                 );
+                for (origin, kind) in expanded.messages {
+                    self.tuctx.emit_message(origin, kind);
+                }
 
-                disable_macro_recursion(&mut replaced, &token);
-                self.rescan(replaced);
+                if self.count_expansion(&token, replaced.len()) {
+                    self.rescan_expansion(&token, replaced);
+                }
             },
             Some(MacroDef::Function(_)) => {
                 // This nonsense with the Rc is a hack to work around borrow
@@ -1628,55 +2727,63 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
                             // we want to continue parsing as much as possible,
                             // so eat the closing parent if it exists. pop() will return None if
                             // error was unexpected EOF
-                            let _closing_paren = self.rescan.pop();
+                            let _closing_paren = self.rescan.pop_front();
                             return;
                         }
 
-                        let closing_paren = self.rescan.pop().unwrap();
-                        let mut arguments = arguments.unwrap();
+                        let closing_paren = self.rescan.pop_front().unwrap();
+                        let arguments: HashMap<String, Rc<[PPToken]>> = arguments
+                            .unwrap()
+                            .into_iter()
+                            .map(|(name, tokens)| (name, tokens.into()))
+                            .collect();
                         debug_assert_eq!(closing_paren.kind, PPTokenKind::Punctuator);
-                        debug_assert_eq!(closing_paren.value, ")");
+                        debug_assert_eq!(closing_paren.as_str(), ")");
+
+                        let argument_len: usize = arguments.values().map(|a| a.len()).sum();
+                        if argument_len > MacroResult::MAX_INPUT_TOKENS
+                            || func.replacement.len() > MacroResult::MAX_INPUT_TOKENS
+                        {
+                            self.stop(&token);
+                            return;
+                        }
 
-                        // update the parameters of the macro as coming from the
-                        // correct argument of the invocation
                         let invocation: u32 = self.tuctx.add_macro_invocation(MacroInvocation {
                             definition: Rc::clone(&macrodef),
                             name: token.clone(),
                             arguments: arguments.clone(),
                         });
 
+                        // copy the parameters of the macro as coming from the
+                        // correct argument of the invocation
+                        let vararg = if func.vararg {
+                            Some("__VA_ARGS__")
+                        } else {
+                            None
+                        };
+                        let mut parameters = HashMap::new();
                         let mut in_index: u16 = 0;
-                        for param_name in &func.params {
-                            in_index = pre_update_macro_arg_tokens(
-                                arguments.get_mut(param_name).unwrap(),
-                                invocation,
-                                in_index,
-                            );
-                        }
-                        if func.vararg {
-                            pre_update_macro_arg_tokens(
-                                arguments.get_mut("__VA_ARGS__").unwrap(),
-                                invocation,
-                                in_index,
-                            );
+                        for param_name in func.params.iter().map(String::as_str).chain(vararg) {
+                            let argument = &arguments[param_name];
+                            let tokens = materialize_macro_arg(argument, invocation, in_index);
+                            in_index += argument.len() as u16;
+                            parameters.insert(param_name.to_owned(), tokens);
                         }
 
-                        // update location of the text of the macro as coming
-                        // from the span of the entire macro invocation
-                        let mut replacement = func.replacement.clone();
-                        pre_update_macro_body_tokens(&mut replacement, invocation);
-
-                        let mut replaced = self.replace(true, replacement.into_iter(), arguments);
-                        post_update_macro_result(&mut replaced, invocation);
-                        disable_macro_recursion(&mut replaced, &token);
-
-                        self.rescan(replaced);
+                        self.replace_invocation(PendingInvocation {
+                            name: token,
+                            definition: macrodef,
+                            invocation,
+                            parameters,
+                            expanded: ExpandedArguments::default(),
+                            prescan: Vec::new(),
+                        });
                     } else if next.kind == PPTokenKind::Identifier {
                         // this ident is not being used as a function macro, so output it
                         self.output.push(token);
                         self.output.append(&mut whitespace);
                         // the next ident should be rescanned
-                        self.rescan.push(next);
+                        self.rescan.push_front(next);
                         return;
                     } else {
                         // this ident is not being used as a function macro, so output it
@@ -1697,7 +2804,19 @@ impl<'tu, 'drv, 'def> Expander<'tu, 'drv, 'def> {
 
     fn expand(mut self) -> Vec<PPToken> {
         trace!("Expander::expand()");
-        while let Some(token) = self.next_token() {
+        loop {
+            let token = match self.next_token() {
+                Some(token) if !self.tuctx.should_abort() && !self.stopped => token,
+                // a macro argument being prescanned is finished, or else the
+                // whole input is
+                _ => match self.suspended.pop() {
+                    Some(suspended) => {
+                        self.resume(suspended);
+                        continue;
+                    },
+                    None => break,
+                },
+            };
             trace!("Expander::expand() token={}", &token);
             match token.kind {
                 PPTokenKind::Identifier => {
@@ -1727,7 +2846,7 @@ pub fn preprocess(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Vec<PPToken> {
     let last_span = *lines.last().unwrap().last().unwrap().origin.as_source();
     let eof = PPToken {
         kind: PPTokenKind::EndOfFile,
-        value: "".to_owned(),
+        value: "".into(),
         origin: TokenOrigin::Source(last_span),
     };
 
@@ -1758,7 +2877,21 @@ pub fn preprocess(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Vec<PPToken> {
     // directives as well as evaluate macro definitions and undefinitions, so we
     // wish to ignore the resulting map of definitions. They will only be used
     // when evaluating macros in #if-like or #include directives
-    let mut directives = process_include_directives(tuctx, lines, &mut HashMap::new());
+    let builtin = predefined_lines(tuctx);
+    let mut if_defines = HashMap::new();
+    let mut directives = process_include_directives(tuctx, builtin, &mut if_defines);
+    if let Some(forced) = forced_include_lines(tuctx) {
+        directives.append(&mut process_include_directives(
+            tuctx,
+            forced,
+            &mut if_defines,
+        ));
+    }
+    directives.append(&mut process_include_directives(
+        tuctx,
+        lines,
+        &mut if_defines,
+    ));
 
     // Ensure the last thing Expander::from_directives().expand() sees is an EOF token,
     // which is necessary to know that there is absolutely nothing left to
@@ -1783,5 +2916,398 @@ pub fn preprocess(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Vec<PPToken> {
     // Now that we have the the entire text of input, we will expand macros
     let mut defines = HashMap::new();
     let expander = Expander::from_directives(tuctx, &mut defines, directives);
-    expander.expand()
+    let output = expander.expand();
+
+    // keep the final definitions around for tooling
+    tuctx.defines = defines;
+    tuctx.remove_suppressed_warnings();
+    output
+}
+
+/// Run phases 1 through 3 on an included file and split it into lines
+///
+/// A file included earlier by this unit, or with the same content as one
+/// lexed by a unit sharing its [`IncludeCache`][ic], is not lexed again.
+///
+/// [ic]: crate::front::c::include_cache::IncludeCache
+fn lex_included_file(tuctx: &mut TUCtx, input: &Rc<Input>) -> Vec<Line> {
+    use crate::front::c::lexer::lex_input;
+
+    if let Some(lines) = tuctx.included_file_lines(input) {
+        return lines;
+    }
+    let session = Rc::clone(&tuctx.tu.session);
+    let cache = Rc::clone(&tuctx.tu.include_cache);
+    if let Some(lines) = cache.lookup(&session, input) {
+        tuctx.set_included_file_lines(input, &lines);
+        return lines;
+    }
+
+    let message_count = tuctx.tu.messages.len();
+    let tokens = lex_input(tuctx, input);
+    let lines = parse_lines(tokens, input);
+    if tuctx.tu.messages.len() == message_count {
+        cache.store(&session, input, &lines);
+        tuctx.set_included_file_lines(input, &lines);
+    }
+    lines
+}
+
+/// The text of the `<built-in>` pseudo-file, which defines the macros the
+/// selected standard and target predefine
+fn predefined_macros(session: &Session) -> String {
+    let mut text = String::from("#define __STDC__ 1\n");
+    if let Some(version) = session.standard().version() {
+        text.push_str(&format!("#define __STDC_VERSION__ {}\n", version));
+    }
+    text.push_str("#define __STDC_HOSTED__ 1\n");
+    for (name, replacement) in session.flags().target.predefined_macros() {
+        text.push_str(&format!("#define {} {}\n", name, replacement));
+    }
+    text
+}
+
+/// Lex the predefined macros so they precede the main file's directives
+///
+/// Giving them a pseudo-file means their definitions have an origin like any
+/// other, so redefining one reports where it was first defined.
+fn predefined_lines(tuctx: &mut TUCtx) -> Vec<Line> {
+    use crate::front::c::lexer::{lex_text, MappedText};
+
+    let content = predefined_macros(tuctx.session());
+    let input = tuctx.add_pseudo_input("<built-in>", content);
+    let tokens = lex_text(tuctx, &MappedText::verbatim(&input), &input);
+    parse_lines(tokens, &input)
+}
+
+/// Lex an `#include` of each header given to `--include`, if any
+///
+/// As with GCC's `-include`, these are found as if included by a file in the
+/// working directory, falling back to the include paths.
+fn forced_include_lines(tuctx: &mut TUCtx) -> Option<Vec<Line>> {
+    use crate::front::c::lexer::{lex_text, MappedText};
+
+    let forced = &tuctx.session().flags().forced_includes;
+    if forced.is_empty() {
+        return None;
+    }
+    let content: String = forced
+        .iter()
+        .map(|file| format!("#include \"{}\"\n", file))
+        .collect();
+    let input = tuctx.add_pseudo_input("<command-line>", content);
+    let tokens = lex_text(tuctx, &MappedText::verbatim(&input), &input);
+    Some(parse_lines(tokens, &input))
+}
+
+/// Replace a single macro invocation without rescanning
+///
+/// See [`TUCtx::expand_macro_once()`][emo].
+///
+/// [emo]: crate::front::c::tuctx::TUCtx::expand_macro_once
+pub(super) fn expand_macro_once(
+    tuctx: &mut TUCtx,
+    name: &str,
+    args: Option<Vec<Vec<PPToken>>>,
+) -> Result<Vec<PPToken>> {
+    let macrodef = match tuctx.defines.get(name) {
+        Some(macrodef) => Rc::clone(macrodef),
+        None => {
+            return Err(ErrorKind::MacroNotDefined {
+                name: name.to_owned(),
+            }
+            .into())
+        },
+    };
+    let arity_error = |expects, got| -> Error {
+        ErrorKind::MacroArgsArity {
+            name: name.to_owned(),
+            expects,
+            got,
+        }
+        .into()
+    };
+
+    let (function, parameters) = match (&*macrodef, args) {
+        (MacroDef::Object(..), None) => (false, HashMap::new()),
+        (MacroDef::Object(..), Some(args)) => return Err(arity_error(None, Some(args.len()))),
+        (MacroDef::Function(func), None) => {
+            let expected = func.params.len() + func.vararg as usize;
+            return Err(arity_error(Some(expected), None));
+        },
+        (MacroDef::Function(func), Some(args)) => {
+            let expected = func.params.len() + func.vararg as usize;
+            if args.len() != expected {
+                return Err(arity_error(Some(expected), Some(args.len())));
+            }
+
+            // `__VA_ARGS__` is only reached if the macro is variadic
+            let names = func.params.iter().map(String::as_str);
+            let names = names.chain(Some("__VA_ARGS__")).map(str::to_owned);
+            (true, names.zip(args).collect())
+        },
+    };
+
+    // With no definitions, the arguments are substituted without being
+    // expanded themselves
+    let mut no_defines = HashMap::new();
+    let mut expander = Expander::from_tokens(tuctx, &mut no_defines, Vec::new());
+    let replacement = macrodef.replacement().to_vec();
+    let mut expanded = ExpandedArguments::default();
+    let replaced = expander.replace(
+        function,
+        replacement.into_iter(),
+        &parameters,
+        &mut expanded,
+    );
+    for (origin, kind) in expanded.messages {
+        expander.tuctx.emit_message(origin, kind);
+    }
+    Ok(replaced)
+}
+
+/// Fully expand a sequence of tokens
+///
+/// See [`TUCtx::expand_macro_fully()`][emf].
+///
+/// [emf]: crate::front::c::tuctx::TUCtx::expand_macro_fully
+pub(super) fn expand_macro_fully(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Vec<PPToken> {
+    // Expansion cannot define or undefine macros, but the expander wants its
+    // own table to borrow alongside `tuctx`
+    let mut defines = tuctx.defines.clone();
+    Expander::from_tokens(tuctx, &mut defines, tokens).expand()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{session, unit, unit_in};
+
+    #[test]
+    fn test_parse_lines_synthesized_newline() {
+        fn last_newline(content: &str, tokens: Vec<PPToken>) -> (u32, u32) {
+            let input = Input::new("<unit-test>".to_owned(), content.to_owned(), None);
+            let lines = parse_lines(tokens, &input);
+            let newline = lines.last().unwrap().last().unwrap();
+            let span = newline.origin.as_source();
+            assert_eq!(span.len, 0);
+            input.get_line_column(span.pos.absolute)
+        }
+        fn token(kind: PPTokenKind, value: &str, absolute: u32) -> PPToken {
+            PPToken {
+                kind,
+                value: value.into(),
+                origin: TokenOrigin::Source(TextSpan {
+                    pos: TextPosition { input: 0, absolute },
+                    len: value.chars().count() as u32,
+                }),
+            }
+        }
+
+        assert_eq!(last_newline("", Vec::new()), (1, 1));
+        let tokens = vec![
+            token(PPTokenKind::Identifier, "a", 0),
+            token(PPTokenKind::Whitespace, "\n", 1),
+            token(PPTokenKind::Identifier, "bc", 2),
+        ];
+        assert_eq!(last_newline("a\nbc", tokens), (2, 3));
+    }
+
+    #[test]
+    fn test_deeply_nested_groups() {
+        let depth = 5000;
+        let source = format!(
+            "{}x\n{}{}",
+            "#if 1\n".repeat(depth),
+            "#elif 1\ny\n#else\nz\n#endif\n".repeat(depth / 2),
+            "#endif\n".repeat(depth - depth / 2)
+        );
+
+        let mut tu = unit(&["--pass=phase1..phase4"], &source);
+        tu.run().unwrap();
+        assert!(tu.messages().is_empty());
+
+        let tokens = tu.saved_states("<final>")[0].as_pptokens().unwrap();
+        let words: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.is_ident())
+            .map(PPToken::as_str)
+            .collect();
+        assert_eq!(words, ["x"]);
+    }
+
+    #[test]
+    fn test_deeply_nested_parentheses() {
+        let depth = 20000;
+        // each level is spliced onto a line of its own, since checking the
+        // tokens of a long physical line is slow in debug builds
+        let source = format!(
+            "#if {}1{} == 1\nx\n#endif\n",
+            "(-\\\n".repeat(depth),
+            ")\\\n".repeat(depth)
+        );
+
+        let mut tu = unit(&["--pass=phase1..phase4"], &source);
+        tu.run().unwrap();
+        assert!(tu.messages().is_empty());
+
+        let tokens = tu.saved_states("<final>")[0].as_pptokens().unwrap();
+        assert!(tokens.iter().any(|t| t.as_str() == "x"));
+    }
+
+    #[test]
+    fn test_nested_macro_arguments() {
+        // within the default limit, however deep that is for the call stack
+        let depth = 250;
+        let source = format!(
+            "#define f(a) a\n{}1{}\n",
+            "f(".repeat(depth),
+            ")".repeat(depth)
+        );
+
+        let mut tu = unit(&["--pass=phase1..phase4"], &source);
+        tu.run().unwrap();
+        assert!(tu.messages().is_empty());
+
+        let tokens = tu.saved_states("<final>")[0].as_pptokens().unwrap();
+        let values: Vec<_> = tokens
+            .iter()
+            .filter(|t| !t.is_whitespace())
+            .map(PPToken::as_str)
+            .collect();
+        assert_eq!(values, ["1", ""]);
+    }
+
+    #[test]
+    fn test_macro_depth_limit() {
+        let depth = 10000;
+        let source = format!(
+            "#define f(a) a\n{}1{}\n",
+            "f(".repeat(depth),
+            ")".repeat(depth)
+        );
+
+        let mut tu = unit(&["--max-macro-depth=32", "--pass=phase1..phase4"], &source);
+        tu.run().unwrap();
+
+        let headlines: Vec<_> = tu
+            .messages()
+            .iter()
+            .map(|m| m.kind.get_headline())
+            .collect();
+        assert_eq!(headlines, ["maximum nested macro expansion depth exceeded"]);
+    }
+
+    #[test]
+    fn test_invocation_too_large() {
+        // spliced, since checking the tokens of a long physical line is slow in
+        // debug builds
+        let wide = "x \\\n".repeat(20000);
+        let sources = [
+            // arguments
+            format!("#define f(a) a\nf({})\n", wide),
+            // replacement list
+            format!("#define B {}\nB\n", wide),
+            // result
+            format!(
+                "#define f(a) a a a a a a a a\nf({})\n",
+                "x \\\n".repeat(5000)
+            ),
+        ];
+
+        for source in &sources {
+            let mut tu = unit(&["--pass=phase1..phase4"], source);
+            tu.run().unwrap();
+
+            let headlines: Vec<_> = tu
+                .messages()
+                .iter()
+                .map(|m| m.kind.get_headline())
+                .collect();
+            assert_eq!(
+                headlines,
+                ["macro expansion produced too many tokens; stopping expansion"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_va_opt_reuses_expanded_arguments() {
+        let source = "#define G 1\n#define F(x, ...) x __VA_OPT__(x x)\nF(G, 2)\n";
+        let args = ["--std=c2x", "--pass=phase1..phase4", "--pass=count_tokens"];
+        let mut tu = unit(&args, source);
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+
+        // `F` once, and its argument `G` only once despite appearing three times
+        let stats = tuctx.token_stats().unwrap();
+        assert_eq!(stats.macro_expansions, 2);
+    }
+
+    #[test]
+    fn test_expansion_shares_token_text() {
+        let mut source = String::from("#define SQ(x) ((x) * (x))\n");
+        let mut expected = String::new();
+        for i in 0..5000 {
+            source.push_str(&format!("SQ({})\n", i));
+            expected.push_str(&format!("(({0}) * ({0}))\n", i));
+        }
+        let mut tu = unit(&["--pass=phase1..phase4"], &source);
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+
+        let tokens = tuctx.get_state().unwrap().as_pptokens().unwrap();
+        assert_eq!(PPToken::to_string(tokens), expected);
+
+        // every expansion refers to the text of the definition rather than a
+        // copy of it
+        let star = match &*tuctx.defines["SQ"] {
+            MacroDef::Function(func) => func.replacement[5].clone(),
+            MacroDef::Object(..) => unreachable!(),
+        };
+        assert_eq!(star.as_str(), "*");
+        let shared = tokens
+            .iter()
+            .filter(|t| Rc::ptr_eq(&t.value, &star.value))
+            .count();
+        assert_eq!(shared, 5000);
+    }
+
+    #[test]
+    fn test_feature_queries() {
+        let source = "\
+#if __has_builtin(__builtin_expect) && !__has_builtin(__builtin_trap)
+builtin
+#endif
+#if __has_attribute(__packed__) && !__has_feature(c_atomic)
+attribute
+#endif
+#if defined __has_feature && defined(__has_attribute)
+defined
+#endif
+#ifdef __has_builtin
+ifdef
+#endif
+#ifndef __has_attribute
+ifndef
+#endif
+";
+
+        let session = session(&["--pass=phase1..phase4"])
+            .add_feature("__has_builtin", "__builtin_expect".to_owned())
+            .add_feature("__has_attribute", "packed".to_owned())
+            .add_feature("__has_attribute", "c_atomic".to_owned())
+            .build();
+        let mut tu = unit_in(&session, source);
+        tu.run().unwrap();
+        assert!(tu.messages().is_empty());
+
+        let tokens = tu.saved_states("<final>")[0].as_pptokens().unwrap();
+        let words: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.is_ident())
+            .map(PPToken::as_str)
+            .collect();
+        assert_eq!(words, ["builtin", "attribute", "defined", "ifdef"]);
+    }
 }
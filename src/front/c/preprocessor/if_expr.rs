@@ -0,0 +1,835 @@
+// Licensed   under  the   Apache  License,   Version  2.0   <LICENSE-APACHE  or
+// http://www.apache.org/licenses/LICENSE-2.0> or  the MIT  license <LICENSE-MIT
+// or http://opensource.org/licenses/MIT>, at your option.  This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Evaluation of `#if` and `#elif` conditions
+
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::vec::IntoIter;
+
+use log::debug;
+
+use super::{parse_assertion, parse_header_name, Expander, MacroDef};
+use crate::front::c::message::{ExpectedFoundPart, Message, MessageKind};
+use crate::front::c::minor::Encoding;
+use crate::front::c::realize::parse_character_constant;
+use crate::front::c::token::{PPToken, PPTokenKind, TokenOrigin};
+use crate::front::c::tuctx::TUCtx;
+use crate::session::FEATURE_QUERIES;
+
+/// The value of a (sub)expression
+///
+/// Every integer in a condition acts as if it has type `intmax_t` or
+/// `uintmax_t` (ISO 9899:2018 6.10.1p4), so we store the bits along with the
+/// signedness.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Value {
+    bits: u64,
+    unsigned: bool,
+}
+
+impl Value {
+    fn signed(value: i64) -> Value {
+        Value {
+            bits: value as u64,
+            unsigned: false,
+        }
+    }
+
+    fn boolean(value: bool) -> Value {
+        Value::signed(value as i64)
+    }
+
+    fn is_true(&self) -> bool {
+        self.bits != 0
+    }
+}
+
+/// Convert a token into the `found` part of an error message
+pub(super) fn found_part(token: &PPToken) -> ExpectedFoundPart {
+    match token.kind {
+        _ if token.is_newline() => ExpectedFoundPart::Plain("newline".to_owned()),
+        PPTokenKind::Punctuator
+        | PPTokenKind::Identifier
+        | PPTokenKind::IdentifierNonExpandable => {
+            ExpectedFoundPart::Plain(format!("`{}`", token.value))
+        },
+        kind => ExpectedFoundPart::PPToken(kind),
+    }
+}
+
+/// Whether `name` is one of the operators testing for a header
+fn is_has_include(name: &str) -> bool {
+    name == "__has_include" || name == "__has_include_next"
+}
+
+/// Whether `name` counts as defined for `defined`, `#ifdef` and `#ifndef`
+///
+/// Besides macros, the `__has_include` and feature query operators are
+/// reported as defined so that code can test for them before using them.
+pub(super) fn is_defined(defines: &HashMap<String, Rc<MacroDef>>, name: &str) -> bool {
+    defines.contains_key(name) || is_has_include(name) || is_feature_query(name)
+}
+
+/// Whether `name` is one of the operators querying the
+/// [features][crate::Session::has_feature] of the implementation
+fn is_feature_query(name: &str) -> bool {
+    FEATURE_QUERIES.contains(&name)
+}
+
+/// Replace every query operator such as `__has_include(header)` or
+/// `__has_builtin(name)` with `1` or `0`
+///
+/// For `__has_include` and `__has_include_next`, the header is searched for
+/// just as `#include` would, but it is not read. An operand not already in one
+/// of the header forms is macro expanded. The other operators take a single
+/// identifier, which is looked up in the session.
+fn replace_queries(
+    tuctx: &mut TUCtx,
+    defines: &mut HashMap<String, Rc<MacroDef>>,
+    tokens: Vec<PPToken>,
+) -> Option<Vec<PPToken>> {
+    let mut output = Vec::new();
+    let mut iter = tokens.into_iter();
+    // `defined __has_include` etc. are left for `replace_defined()`
+    let mut after_defined = false;
+    while let Some(token) = iter.next() {
+        let operand_of_defined = after_defined;
+        let is_open = token.kind == PPTokenKind::Punctuator && token.as_str() == "(";
+        if !token.is_whitespace_not_newline() && !is_open {
+            after_defined = token.is_ident() && token.as_str() == "defined";
+        }
+
+        let is_query =
+            token.is_ident() && (is_has_include(&token.value) || is_feature_query(&token.value));
+        if !is_query || operand_of_defined {
+            output.push(token);
+            continue;
+        }
+
+        let open = iter
+            .by_ref()
+            .find(|t| !t.is_whitespace_not_newline())
+            .unwrap();
+        if open.kind != PPTokenKind::Punctuator || open.as_str() != "(" {
+            tuctx.emit_message(
+                open.origin.clone(),
+                MessageKind::ExpectedFound {
+                    expected: ExpectedFoundPart::Plain("`(`".to_owned()),
+                    found: found_part(&open),
+                },
+            );
+            return None;
+        }
+
+        let mut operand = Vec::new();
+        let mut depth = 0;
+        loop {
+            let next = iter.next().unwrap();
+            if next.is_newline() {
+                tuctx.emit_message(
+                    next.origin.clone(),
+                    MessageKind::ExpectedFound {
+                        expected: ExpectedFoundPart::Plain("`)`".to_owned()),
+                        found: found_part(&next),
+                    },
+                );
+                return None;
+            } else if next.kind == PPTokenKind::Punctuator && next.as_str() == "(" {
+                depth += 1;
+            } else if next.kind == PPTokenKind::Punctuator && next.as_str() == ")" {
+                if depth == 0 {
+                    // the operand must be followed by a newline
+                    operand.push(PPToken {
+                        kind: PPTokenKind::Whitespace,
+                        value: "\n".into(),
+                        origin: next.origin,
+                    });
+                    break;
+                }
+                depth -= 1;
+            }
+            operand.push(next);
+        }
+        // leading whitespace would hide the header form from the check
+        // against macro expansion
+        let start = operand
+            .iter()
+            .position(|t| !t.is_whitespace_not_newline())
+            .unwrap();
+        operand.drain(..start);
+
+        let found = if is_has_include(&token.value) {
+            let (file, system, first) = parse_header_name(tuctx, operand, defines)?;
+            let input = first.origin.macro_root_textspan(tuctx).input(tuctx).clone();
+            let session = &tuctx.tu.session;
+            let found = match (token.as_str(), input.include_path_index) {
+                ("__has_include_next", Some(index)) => {
+                    session.search_for_include_next(&file, index)
+                },
+                _ => session.search_for_include(&file, input.path.as_deref(), system),
+            };
+            found.is_ok()
+        } else {
+            let mut operand = operand
+                .into_iter()
+                .filter(|t| !t.is_whitespace_not_newline());
+            let name = operand.next().unwrap();
+            if !name.is_ident() {
+                tuctx.emit_message(
+                    name.origin.clone(),
+                    MessageKind::ExpectedFound {
+                        expected: ExpectedFoundPart::Plain("identifier".to_owned()),
+                        found: found_part(&name),
+                    },
+                );
+                return None;
+            }
+            let newline = operand.next().unwrap();
+            if !newline.is_newline() {
+                tuctx.emit_message(
+                    newline.origin.clone(),
+                    MessageKind::ExpectedFound {
+                        expected: ExpectedFoundPart::Plain("`)`".to_owned()),
+                        found: found_part(&newline),
+                    },
+                );
+                return None;
+            }
+            tuctx.tu.session.has_feature(&token.value, &name.value)
+        };
+        output.push(PPToken {
+            kind: PPTokenKind::PPNumber,
+            value: if found { "1" } else { "0" }.into(),
+            origin: token.origin,
+        });
+    }
+
+    Some(output)
+}
+
+/// Replace every GNU assertion query `#predicate(answer)` with `1` or `0`
+///
+/// Without an answer, the query is true if the predicate has any answer.
+fn replace_assertions(tuctx: &mut TUCtx, tokens: Vec<PPToken>) -> Option<Vec<PPToken>> {
+    let mut output = Vec::new();
+    let mut iter = tokens.into_iter();
+    while let Some(token) = iter.next() {
+        if token.kind != PPTokenKind::Punctuator
+            || (token.as_str() != "#" && token.as_str() != "%:")
+        {
+            output.push(token);
+            continue;
+        }
+
+        let assertion = parse_assertion(tuctx, &mut iter)?;
+        let answers = tuctx.assertions.get(assertion.predicate.as_str());
+        let value = match (answers, &assertion.answer) {
+            (Some(answers), Some(answer)) => answers.contains(answer),
+            (Some(answers), None) => !answers.is_empty(),
+            (None, _) => false,
+        };
+        output.push(PPToken {
+            kind: PPTokenKind::PPNumber,
+            value: if value { "1" } else { "0" }.into(),
+            origin: token.origin,
+        });
+    }
+
+    Some(output)
+}
+
+/// Replace every `defined X` and `defined ( X )` with `1` or `0`
+///
+/// This must happen before macro expansion so that the operands are not
+/// expanded. Also warns about empty macros if requested.
+fn replace_defined(
+    tuctx: &mut TUCtx,
+    defines: &HashMap<String, Rc<MacroDef>>,
+    tokens: Vec<PPToken>,
+) -> Option<Vec<PPToken>> {
+    let warn_empty = tuctx.tu.session.flags().warning_enabled("empty-macro");
+
+    let mut output = Vec::new();
+    let mut iter = tokens.into_iter();
+    while let Some(token) = iter.next() {
+        if !token.is_ident() || token.as_str() != "defined" {
+            if warn_empty && token.is_ident() {
+                warn_empty_macro(tuctx, defines, &token);
+            }
+            output.push(token);
+            continue;
+        }
+
+        let mut next = iter
+            .by_ref()
+            .find(|t| !t.is_whitespace_not_newline())
+            .unwrap();
+        let parenthesized = next.kind == PPTokenKind::Punctuator && next.as_str() == "(";
+        if parenthesized {
+            next = iter
+                .by_ref()
+                .find(|t| !t.is_whitespace_not_newline())
+                .unwrap();
+        }
+
+        if !next.is_ident() {
+            tuctx.emit_message(
+                next.origin.clone(),
+                MessageKind::ExpectedFound {
+                    expected: ExpectedFoundPart::Plain("identifier".to_owned()),
+                    found: found_part(&next),
+                },
+            );
+            return None;
+        }
+
+        if parenthesized {
+            let closing = iter
+                .by_ref()
+                .find(|t| !t.is_whitespace_not_newline())
+                .unwrap();
+            if closing.kind != PPTokenKind::Punctuator || closing.as_str() != ")" {
+                tuctx.emit_message(
+                    closing.origin.clone(),
+                    MessageKind::ExpectedFound {
+                        expected: ExpectedFoundPart::Plain("`)`".to_owned()),
+                        found: found_part(&closing),
+                    },
+                );
+                return None;
+            }
+        }
+
+        let value = if is_defined(defines, &next.value) {
+            "1"
+        } else {
+            "0"
+        };
+        output.push(PPToken {
+            kind: PPTokenKind::PPNumber,
+            value: value.into(),
+            origin: token.origin,
+        });
+    }
+
+    Some(output)
+}
+
+/// Warn if `token` names an object-like macro whose replacement is empty
+///
+/// Such a macro silently disappears from the condition, e.g. `#if FLAG`
+/// becomes `#if`.
+fn warn_empty_macro(tuctx: &mut TUCtx, defines: &HashMap<String, Rc<MacroDef>>, token: &PPToken) {
+    let macrodef = match defines.get(token.as_str()) {
+        Some(macrodef) => macrodef,
+        None => return,
+    };
+    if let MacroDef::Object(object) = &**macrodef {
+        if object.replacement.iter().all(PPToken::is_whitespace) {
+            tuctx.emit_message_with_children(
+                token.origin.clone(),
+                MessageKind::Phase4EmptyMacroInIf {
+                    name: token.value.to_string(),
+                },
+                vec![Message::from((
+                    object.origin.clone(),
+                    MessageKind::Phase4MacroFirstDefined {
+                        name: token.value.to_string(),
+                    },
+                ))],
+            );
+        }
+    }
+}
+
+/// Parse the value of an integer constant, including any suffixes
+fn parse_integer(value: &str) -> Option<Value> {
+    let lower = value.to_ascii_lowercase();
+    let digits = lower.trim_end_matches(&['u', 'l'][..]);
+    let suffix = &lower[digits.len()..];
+    let unsigned_suffix = match suffix {
+        "" | "l" | "ll" => false,
+        "u" | "ul" | "lu" | "ull" | "llu" => true,
+        _ => return None,
+    };
+    // `lL` and `Ll` are not valid suffixes
+    if suffix.contains("ll") && !value.contains("ll") && !value.contains("LL") {
+        return None;
+    }
+
+    let (digits, radix) = if let Some(hex) = digits.strip_prefix("0x") {
+        (hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        (&digits[1..], 8)
+    } else {
+        (digits, 10)
+    };
+    if digits.is_empty() {
+        return None;
+    }
+
+    let bits = u64::from_str_radix(digits, radix).ok()?;
+    Some(Value {
+        bits,
+        // a constant too large for `intmax_t` must be `uintmax_t`
+        unsigned: unsigned_suffix || bits > i64::MAX as u64,
+    })
+}
+
+/// An operator whose operands are still being parsed
+enum Pending {
+    /// A unary operator, applied to the next unary-expression
+    Unary(String),
+    /// An opening parenthesis, closed after the next conditional-expression
+    Paren,
+    /// A binary operator and its left operand
+    Binary {
+        lhs: Value,
+        operator: String,
+        precedence: u8,
+        origin: TokenOrigin,
+        live: bool,
+    },
+    /// The condition of a conditional operator, waiting for its second operand
+    Condition { condition: Value, live: bool },
+    /// The condition and second operand of a conditional operator, waiting for
+    /// its third operand
+    Alternative {
+        condition: Value,
+        lhs: Value,
+        live: bool,
+    },
+}
+
+/// Operator precedence evaluator of the expanded condition
+///
+/// Operators waiting for their operands are kept on an explicit stack rather
+/// than the call stack, so that deeply nested parentheses cannot overflow it.
+struct Parser<'a, 'drv> {
+    tuctx: &'a mut TUCtx<'drv>,
+    tokens: Peekable<IntoIter<PPToken>>,
+}
+
+impl<'a, 'drv> Parser<'a, 'drv> {
+    fn peek(&mut self) -> &PPToken {
+        self.tokens.peek().unwrap()
+    }
+
+    fn peek_punctuator(&mut self) -> Option<&str> {
+        let token = self.peek();
+        if token.kind == PPTokenKind::Punctuator {
+            Some(token.as_str())
+        } else {
+            None
+        }
+    }
+
+    fn expect_punctuator(&mut self, punctuator: &str) -> Option<()> {
+        if self.peek_punctuator() == Some(punctuator) {
+            self.tokens.next();
+            Some(())
+        } else {
+            let token = self.tokens.next().unwrap();
+            self.tuctx.emit_message(
+                token.origin.clone(),
+                MessageKind::ExpectedFound {
+                    expected: ExpectedFoundPart::Plain(format!("`{}`", punctuator)),
+                    found: found_part(&token),
+                },
+            );
+            None
+        }
+    }
+
+    /// Parse a whole condition, which must be followed by a newline
+    fn parse(&mut self) -> Option<Value> {
+        let value = self.conditional()?;
+        let token = self.tokens.next().unwrap();
+        if !token.is_newline() {
+            self.tuctx.emit_message(
+                token.origin.clone(),
+                MessageKind::ExpectedFound {
+                    expected: ExpectedFoundPart::Plain("newline".to_owned()),
+                    found: found_part(&token),
+                },
+            );
+            return None;
+        }
+        Some(value)
+    }
+
+    /// Parse a conditional-expression
+    ///
+    /// `live` is false while parsing an operand whose result is discarded due
+    /// to short-circuiting, in which case errors such as division by zero are
+    /// not reported.
+    fn conditional(&mut self) -> Option<Value> {
+        let mut pending = Vec::new();
+        let mut live = true;
+
+        'operand: loop {
+            let mut value = match self.peek_punctuator() {
+                Some("+") | Some("-") | Some("~") | Some("!") => {
+                    let operator = self.tokens.next().unwrap().value.to_string();
+                    pending.push(Pending::Unary(operator));
+                    continue 'operand;
+                },
+                Some("(") => {
+                    self.tokens.next();
+                    pending.push(Pending::Paren);
+                    continue 'operand;
+                },
+                _ => self.primary(live)?,
+            };
+
+            // `value` is a whole unary-expression
+            'unary: loop {
+                while let Some(Pending::Unary(operator)) = pending.last() {
+                    value = apply_unary(operator, value);
+                    pending.pop();
+                }
+
+                // apply the binary operators binding at least as tightly as
+                // the next one
+                let operator = self.peek_punctuator().map(str::to_owned);
+                let precedence = operator.as_deref().and_then(binary_precedence);
+                while let Some(Pending::Binary { precedence: p, .. }) = pending.last() {
+                    if precedence.is_some_and(|precedence| *p < precedence) {
+                        break;
+                    }
+                    let (lhs, operator, origin, outer) = match pending.pop() {
+                        Some(Pending::Binary {
+                            lhs,
+                            operator,
+                            origin,
+                            live,
+                            ..
+                        }) => (lhs, operator, origin, live),
+                        _ => unreachable!(),
+                    };
+                    value = match apply_binary(&operator, lhs, value) {
+                        Some(value) => value,
+                        None if outer => {
+                            self.tuctx
+                                .emit_message(origin, MessageKind::Phase4IfDivisionByZero);
+                            return None;
+                        },
+                        None => Value::signed(0),
+                    };
+                    live = outer;
+                }
+
+                if let (Some(operator), Some(precedence)) = (operator, precedence) {
+                    let origin = self.tokens.next().unwrap().origin;
+                    let rhs_live = match &*operator {
+                        "&&" => live && value.is_true(),
+                        "||" => live && !value.is_true(),
+                        _ => live,
+                    };
+                    pending.push(Pending::Binary {
+                        lhs: value,
+                        operator,
+                        precedence,
+                        origin,
+                        live,
+                    });
+                    live = rhs_live;
+                    continue 'operand;
+                }
+
+                if self.peek_punctuator() == Some("?") {
+                    self.tokens.next();
+                    pending.push(Pending::Condition {
+                        condition: value,
+                        live,
+                    });
+                    live = live && value.is_true();
+                    continue 'operand;
+                }
+
+                // `value` is a whole conditional-expression
+                loop {
+                    match pending.pop() {
+                        None => return Some(value),
+                        Some(Pending::Paren) => {
+                            self.expect_punctuator(")")?;
+                            continue 'unary;
+                        },
+                        Some(Pending::Condition {
+                            condition,
+                            live: outer,
+                        }) => {
+                            self.expect_punctuator(":")?;
+                            pending.push(Pending::Alternative {
+                                condition,
+                                lhs: value,
+                                live: outer,
+                            });
+                            live = outer && !condition.is_true();
+                            continue 'operand;
+                        },
+                        Some(Pending::Alternative {
+                            condition,
+                            lhs,
+                            live: outer,
+                        }) => {
+                            let unsigned = lhs.unsigned || value.unsigned;
+                            if condition.is_true() {
+                                value = lhs;
+                            }
+                            value.unsigned = unsigned;
+                            live = outer;
+                        },
+                        Some(Pending::Unary(_)) | Some(Pending::Binary { .. }) => unreachable!(),
+                    }
+                }
+            }
+        }
+    }
+
+    fn primary(&mut self, live: bool) -> Option<Value> {
+        let token = self.tokens.next().unwrap();
+        let value = match token.kind {
+            // identifiers remaining after expansion are replaced with zero
+            PPTokenKind::Identifier => {
+                if live {
+                    self.tuctx.emit_message(
+                        token.origin.clone(),
+                        MessageKind::Phase4IfUndefinedIdentifier {
+                            name: token.value.to_string(),
+                        },
+                    );
+                }
+                Some(Value::signed(0))
+            },
+            // a macro that expanded to its own name is not undefined
+            PPTokenKind::IdentifierNonExpandable => Some(Value::signed(0)),
+            PPTokenKind::PPNumber => parse_integer(&token.value),
+            PPTokenKind::CharacterConstant => self.character_constant(&token),
+            _ => {
+                self.tuctx.emit_message(
+                    token.origin.clone(),
+                    MessageKind::ExpectedFound {
+                        expected: ExpectedFoundPart::Plain("expression".to_owned()),
+                        found: found_part(&token),
+                    },
+                );
+                return None;
+            },
+        };
+
+        if value.is_none() && token.kind == PPTokenKind::PPNumber {
+            self.tuctx.emit_message(
+                token.origin,
+                MessageKind::Phase4IfInvalidConstant {
+                    value: token.value.to_string(),
+                },
+            );
+        }
+        value
+    }
+
+    /// Compute the value of a character constant
+    ///
+    /// The constant takes the value phase 7 gives it, see
+    /// [`parse_character_constant()`]. An unprefixed constant has type `int`,
+    /// so `'\xff'` is -1 where plain `char` is signed.
+    fn character_constant(&mut self, token: &PPToken) -> Option<Value> {
+        let target = &self.tuctx.tu.session.flags().target;
+        match parse_character_constant(target, &token.value) {
+            Ok(data) if data.encoding == Encoding::Default => {
+                Some(Value::signed(data.value as i32 as i64))
+            },
+            Ok(data) => Some(Value::signed(data.value as i64)),
+            Err(kind) => {
+                self.tuctx.emit_message(token.origin.clone(), kind);
+                None
+            },
+        }
+    }
+}
+
+fn apply_unary(operator: &str, value: Value) -> Value {
+    match operator {
+        "+" => value,
+        "-" => Value {
+            bits: value.bits.wrapping_neg(),
+            ..value
+        },
+        "~" => Value {
+            bits: !value.bits,
+            ..value
+        },
+        "!" => Value::boolean(!value.is_true()),
+        _ => unreachable!(),
+    }
+}
+
+fn binary_precedence(operator: &str) -> Option<u8> {
+    Some(match operator {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        _ => return None,
+    })
+}
+
+/// Apply a binary operator, returning `None` for division by zero
+fn apply_binary(operator: &str, lhs: Value, rhs: Value) -> Option<Value> {
+    // the usual arithmetic conversions
+    let unsigned = lhs.unsigned || rhs.unsigned;
+    let (a, b) = (lhs.bits, rhs.bits);
+    let (sa, sb) = (a as i64, b as i64);
+
+    let bits = match operator {
+        "||" => return Some(Value::boolean(lhs.is_true() || rhs.is_true())),
+        "&&" => return Some(Value::boolean(lhs.is_true() && rhs.is_true())),
+        "==" => return Some(Value::boolean(a == b)),
+        "!=" => return Some(Value::boolean(a != b)),
+        "<" | ">" | "<=" | ">=" => {
+            let ordering = if unsigned { a.cmp(&b) } else { sa.cmp(&sb) };
+            let result = match operator {
+                "<" => ordering.is_lt(),
+                ">" => ordering.is_gt(),
+                "<=" => ordering.is_le(),
+                _ => ordering.is_ge(),
+            };
+            return Some(Value::boolean(result));
+        },
+
+        // shifts take the type of the left operand. Shifting by a negative
+        // amount or by at least the width of the type is undefined, so treat
+        // that as shifting out every bit.
+        "<<" | ">>" => {
+            let amount = if !rhs.unsigned && sb < 0 { 64 } else { b };
+            let bits = match operator {
+                "<<" if amount >= 64 => 0,
+                "<<" => a << amount,
+                _ if !lhs.unsigned => (sa >> amount.min(63)) as u64,
+                _ if amount >= 64 => 0,
+                _ => a >> amount,
+            };
+            return Some(Value {
+                bits,
+                unsigned: lhs.unsigned,
+            });
+        },
+
+        "|" => a | b,
+        "^" => a ^ b,
+        "&" => a & b,
+        "+" => a.wrapping_add(b),
+        "-" => a.wrapping_sub(b),
+        "*" => a.wrapping_mul(b),
+        "/" | "%" if b == 0 => return None,
+        "/" if unsigned => a / b,
+        "/" => sa.wrapping_div(sb) as u64,
+        "%" if unsigned => a % b,
+        "%" => sa.wrapping_rem(sb) as u64,
+        _ => unreachable!(),
+    };
+    Some(Value { bits, unsigned })
+}
+
+/// Evaluate the condition of an `#if` or `#elif` directive
+///
+/// `tokens` contains everything after the directive name, including the
+/// trailing newline. Any errors are reported and treated as false.
+pub(super) fn evaluate(
+    tuctx: &mut TUCtx,
+    defines: &mut HashMap<String, Rc<MacroDef>>,
+    tokens: Vec<PPToken>,
+) -> bool {
+    debug_assert!(tokens.last().unwrap().is_newline());
+
+    // comments are whitespace too, so `#if /* x */` is also empty
+    if tokens.iter().all(PPToken::is_whitespace) {
+        let newline = tokens.last().unwrap();
+        tuctx.emit_message(newline.origin.clone(), MessageKind::Phase4IfEmpty);
+        return false;
+    }
+
+    let tokens = match replace_queries(tuctx, defines, tokens) {
+        Some(tokens) => tokens,
+        None => return false,
+    };
+    let tokens = match replace_assertions(tuctx, tokens) {
+        Some(tokens) => tokens,
+        None => return false,
+    };
+    let tokens = match replace_defined(tuctx, defines, tokens) {
+        Some(tokens) => tokens,
+        None => return false,
+    };
+    let tokens = Expander::from_tokens(tuctx, defines, tokens).expand();
+    let tokens: Vec<PPToken> = tokens
+        .into_iter()
+        .filter(|t| !t.is_whitespace_not_newline())
+        .collect();
+
+    let mut parser = Parser {
+        tuctx,
+        tokens: tokens.into_iter().peekable(),
+    };
+    let value = parser.parse();
+    debug!("if_expr::evaluate() value = {:?}", value);
+
+    value.map(|v| v.is_true()).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn int(value: &str) -> Option<(u64, bool)> {
+        parse_integer(value).map(|v| (v.bits, v.unsigned))
+    }
+
+    #[test]
+    fn test_parse_integer() {
+        assert_eq!(int("0"), Some((0, false)));
+        assert_eq!(int("42"), Some((42, false)));
+        assert_eq!(int("052"), Some((42, false)));
+        assert_eq!(int("0x2A"), Some((42, false)));
+        assert_eq!(int("42u"), Some((42, true)));
+        assert_eq!(int("42ULL"), Some((42, true)));
+        assert_eq!(int("42lu"), Some((42, true)));
+        assert_eq!(int("18446744073709551615"), Some((u64::MAX, true)));
+        assert_eq!(int("42lL"), None);
+        assert_eq!(int("08"), None);
+        assert_eq!(int("0x"), None);
+        assert_eq!(int("1.0"), None);
+        assert_eq!(int("42uu"), None);
+    }
+
+    #[test]
+    fn test_apply_binary() {
+        let apply = |op, a: i64, b: i64| apply_binary(op, Value::signed(a), Value::signed(b));
+        assert_eq!(apply("-", 1, 2), Some(Value::signed(-1)));
+        assert_eq!(apply("<", -1, 0), Some(Value::boolean(true)));
+        assert_eq!(apply(">>", -8, 1), Some(Value::signed(-4)));
+        assert_eq!(apply("/", -7, 2), Some(Value::signed(-3)));
+        assert_eq!(apply("%", 1, 0), None);
+
+        // comparison with an unsigned operand is unsigned
+        let unsigned_zero = Value {
+            bits: 0,
+            unsigned: true,
+        };
+        assert_eq!(
+            apply_binary("<", Value::signed(-1), unsigned_zero),
+            Some(Value::boolean(false))
+        );
+    }
+}
@@ -0,0 +1,1576 @@
+// Licensed   under  the   Apache  License,   Version  2.0   <LICENSE-APACHE  or
+// http://www.apache.org/licenses/LICENSE-2.0> or  the MIT  license <LICENSE-MIT
+// or http://opensource.org/licenses/MIT>, at your option.  This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Phase 7 of translation: computing the values of constants
+//!
+//! Preprocessing numbers are very loosely defined, so these functions check
+//! that a token actually forms a valid constant and compute the bytes the
+//! target machine will see.
+
+use crate::core::{Endianness, LongDoubleFormat, Target};
+use crate::front::c::message::MessageKind;
+use crate::front::c::minor::{get_string_content, get_string_encoding, Encoding};
+
+/// Base in which a constant is written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Radix {
+    Binary,
+    Octal,
+    Decimal,
+    Hexadecimal,
+}
+
+impl Radix {
+    pub fn value(&self) -> u32 {
+        match *self {
+            Radix::Binary => 2,
+            Radix::Octal => 8,
+            Radix::Decimal => 10,
+            Radix::Hexadecimal => 16,
+        }
+    }
+
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            Radix::Binary => "binary",
+            Radix::Octal => "octal",
+            Radix::Decimal => "decimal",
+            Radix::Hexadecimal => "hexadecimal",
+        }
+    }
+}
+
+/// Type of an integer constant, as deduced from its value and suffix
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntegerType {
+    Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+}
+
+impl IntegerType {
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            IntegerType::Int => "int",
+            IntegerType::UnsignedInt => "unsigned int",
+            IntegerType::Long => "long",
+            IntegerType::UnsignedLong => "unsigned long",
+            IntegerType::LongLong => "long long",
+            IntegerType::UnsignedLongLong => "unsigned long long",
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        match *self {
+            IntegerType::Int | IntegerType::Long | IntegerType::LongLong => true,
+            IntegerType::UnsignedInt
+            | IntegerType::UnsignedLong
+            | IntegerType::UnsignedLongLong => false,
+        }
+    }
+
+    fn length(&self) -> IntegerLength {
+        match *self {
+            IntegerType::Int | IntegerType::UnsignedInt => IntegerLength::Int,
+            IntegerType::Long | IntegerType::UnsignedLong => IntegerLength::Long,
+            IntegerType::LongLong | IntegerType::UnsignedLongLong => IntegerLength::LongLong,
+        }
+    }
+
+    pub fn size_bytes(&self, target: &Target) -> usize {
+        match self.length() {
+            IntegerLength::Int => 4,
+            IntegerLength::Long => target.long_size,
+            IntegerLength::LongLong => 8,
+        }
+    }
+
+    /// The shortest suffix that gives a constant this type
+    pub fn suffix(&self) -> &'static str {
+        match *self {
+            IntegerType::Int => "",
+            IntegerType::UnsignedInt => "u",
+            IntegerType::Long => "l",
+            IntegerType::UnsignedLong => "ul",
+            IntegerType::LongLong => "ll",
+            IntegerType::UnsignedLongLong => "ull",
+        }
+    }
+
+    /// Largest value of this type on the target
+    pub fn max_value(&self, target: &Target) -> u64 {
+        let bits = 8 * self.size_bytes(target) as u32 - self.is_signed() as u32;
+        u64::MAX >> (64 - bits)
+    }
+}
+
+/// Length requested by the `l` or `ll` in an integer suffix
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum IntegerLength {
+    Int,
+    Long,
+    LongLong,
+}
+
+/// Type of a floating constant, as selected by its suffix
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloatType {
+    Float,
+    Double,
+    LongDouble,
+}
+
+impl FloatType {
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            FloatType::Float => "float",
+            FloatType::Double => "double",
+            FloatType::LongDouble => "long double",
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match *self {
+            FloatType::Float => "f",
+            FloatType::Double => "",
+            FloatType::LongDouble => "l",
+        }
+    }
+}
+
+/// Layout of a binary floating point format
+struct FloatFormat {
+    /// Number of significand bits, excluding the integer bit
+    significand_bits: u32,
+    exponent_bits: u32,
+    /// Whether the integer bit of the significand is stored rather than
+    /// implied (only the x87 format does this)
+    explicit_integer_bit: bool,
+}
+
+const BINARY32: FloatFormat = FloatFormat {
+    significand_bits: 23,
+    exponent_bits: 8,
+    explicit_integer_bit: false,
+};
+
+const BINARY64: FloatFormat = FloatFormat {
+    significand_bits: 52,
+    exponent_bits: 11,
+    explicit_integer_bit: false,
+};
+
+const X87_EXTENDED: FloatFormat = FloatFormat {
+    significand_bits: 63,
+    exponent_bits: 15,
+    explicit_integer_bit: true,
+};
+
+const BINARY128: FloatFormat = FloatFormat {
+    significand_bits: 112,
+    exponent_bits: 15,
+    explicit_integer_bit: false,
+};
+
+impl FloatFormat {
+    fn for_type(target: &Target, ty: FloatType) -> &'static FloatFormat {
+        match ty {
+            FloatType::Float => &BINARY32,
+            FloatType::Double => &BINARY64,
+            FloatType::LongDouble => match target.long_double {
+                LongDoubleFormat::Double => &BINARY64,
+                LongDoubleFormat::X87Extended => &X87_EXTENDED,
+                LongDoubleFormat::Quad => &BINARY128,
+            },
+        }
+    }
+
+    /// Number of significand bits in the encoding, including any integer bit
+    fn stored_bits(&self) -> u32 {
+        self.significand_bits + self.explicit_integer_bit as u32
+    }
+
+    fn bias(&self) -> i32 {
+        (1 << (self.exponent_bits - 1)) - 1
+    }
+
+    /// Encode the value `significand * 2^exponent`
+    ///
+    /// Values that cannot be represented exactly are rounded to nearest, with
+    /// ties to even. Returns `None` if the value is too large.
+    fn encode(&self, negative: bool, significand: u128, exponent: i32) -> Option<u128> {
+        let stored_bits = self.stored_bits();
+        let sign = (negative as u128) << (stored_bits + self.exponent_bits);
+        if significand == 0 {
+            return Some(sign);
+        }
+
+        // exponent of the leading one bit
+        let msb = 127 - significand.leading_zeros() as i32;
+        let leading = msb + exponent;
+        if leading > self.bias() {
+            return None;
+        }
+
+        // Exponent of the lowest significand bit. Values below the normal
+        // range are stored as subnormals, which have a fixed exponent.
+        let min_exponent = 1 - self.bias();
+        let mut lowest = leading.max(min_exponent) - self.significand_bits as i32;
+
+        let shift = exponent - lowest;
+        let mut significand = if shift >= 0 {
+            significand << shift
+        } else {
+            shift_right_rounded(significand, (-shift) as u32)
+        };
+
+        // rounding up may carry into the next power of two
+        if significand >> (self.significand_bits + 1) != 0 {
+            significand >>= 1;
+            lowest += 1;
+        }
+
+        // exponent of the integer bit, which is only set for normal values
+        let integer = lowest + self.significand_bits as i32;
+        let integer_bit = significand >> self.significand_bits;
+        let biased = if integer_bit == 0 {
+            0
+        } else if integer > self.bias() {
+            return None;
+        } else {
+            (integer + self.bias()) as u128
+        };
+
+        let mut bits = significand & ((1 << self.significand_bits) - 1);
+        if self.explicit_integer_bit {
+            bits |= integer_bit << self.significand_bits;
+        }
+        Some(sign | biased << stored_bits | bits)
+    }
+
+    /// Split an encoding into its sign, integer significand, and exponent
+    ///
+    /// Returns `None` for infinities and NaNs.
+    fn decode(&self, bits: u128) -> Option<(bool, u128, i32)> {
+        let stored_bits = self.stored_bits();
+        let negative = bits >> (stored_bits + self.exponent_bits) & 1 != 0;
+        let biased = (bits >> stored_bits) as i32 & ((1 << self.exponent_bits) - 1);
+        let mut significand = bits & ((1 << stored_bits) - 1);
+
+        if biased == (1 << self.exponent_bits) - 1 {
+            return None;
+        }
+        let exponent = if biased == 0 {
+            1 - self.bias()
+        } else {
+            if !self.explicit_integer_bit {
+                significand |= 1 << self.significand_bits;
+            }
+            biased - self.bias()
+        };
+        Some((
+            negative,
+            significand,
+            exponent - self.significand_bits as i32,
+        ))
+    }
+}
+
+/// Shift `value` right, rounding to nearest with ties to even
+fn shift_right_rounded(value: u128, shift: u32) -> u128 {
+    if shift > 128 {
+        return 0;
+    }
+    let (kept, rest) = if shift == 128 {
+        (0, value)
+    } else {
+        (value >> shift, value & ((1 << shift) - 1))
+    };
+
+    let half = 1 << (shift - 1);
+    if rest > half || (rest == half && kept & 1 == 1) {
+        kept + 1
+    } else {
+        kept
+    }
+}
+
+/// Split an `f64` into its sign, integer significand, and exponent
+fn decompose_f64(value: f64) -> (bool, u128, i32) {
+    let bits = value.to_bits();
+    let negative = bits >> 63 != 0;
+    let biased = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = (bits & ((1 << 52) - 1)) as u128;
+    if biased == 0 {
+        (negative, fraction, -1074)
+    } else {
+        (negative, fraction | 1 << 52, biased - 1075)
+    }
+}
+
+/// A realized floating constant as the bytes stored on the target
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FloatData {
+    Float([u8; 4]),
+    Double([u8; 8]),
+    /// As many bytes as the target's [`LongDoubleFormat`] occupies
+    LongDouble(Vec<u8>),
+}
+
+impl FloatData {
+    /// Lay out the encoding of a value of type `ty` in memory
+    pub fn from_bits(target: &Target, ty: FloatType, bits: u128) -> FloatData {
+        let size = match ty {
+            FloatType::Float => 4,
+            FloatType::Double => 8,
+            FloatType::LongDouble => target.long_double.size_bytes(),
+        };
+
+        // the value occupies the low `size` bytes of `bits`
+        let bytes = match target.endianness {
+            Endianness::Little => bits.to_le_bytes()[..size].to_vec(),
+            Endianness::Big => bits.to_be_bytes()[16 - size..].to_vec(),
+        };
+
+        match ty {
+            FloatType::Float => {
+                let mut data = [0; 4];
+                data.copy_from_slice(&bytes);
+                FloatData::Float(data)
+            },
+            FloatType::Double => {
+                let mut data = [0; 8];
+                data.copy_from_slice(&bytes);
+                FloatData::Double(data)
+            },
+            FloatType::LongDouble => FloatData::LongDouble(bytes),
+        }
+    }
+
+    pub fn ty(&self) -> FloatType {
+        match self {
+            FloatData::Float(..) => FloatType::Float,
+            FloatData::Double(..) => FloatType::Double,
+            FloatData::LongDouble(..) => FloatType::LongDouble,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            FloatData::Float(data) => data,
+            FloatData::Double(data) => data,
+            FloatData::LongDouble(data) => data,
+        }
+    }
+
+    /// Recover the encoding from its layout in memory
+    pub fn to_bits(&self, target: &Target) -> u128 {
+        let bytes = self.as_bytes();
+        let mut data = [0; 16];
+        match target.endianness {
+            Endianness::Little => {
+                data[..bytes.len()].copy_from_slice(bytes);
+                u128::from_le_bytes(data)
+            },
+            Endianness::Big => {
+                data[16 - bytes.len()..].copy_from_slice(bytes);
+                u128::from_be_bytes(data)
+            },
+        }
+    }
+
+    /// Format the value as a hexadecimal floating constant
+    ///
+    /// The bytes alone do not determine the value, so this needs the target
+    /// the constant was realized for.
+    pub fn display<'a>(&'a self, target: &'a Target) -> FloatDisplay<'a> {
+        FloatDisplay { data: self, target }
+    }
+}
+
+/// Helper for printing a [`FloatData`], returned by [`FloatData::display`]
+///
+/// Finite values are printed exactly, so parsing the output again for the
+/// same target gives back the same data.
+pub struct FloatDisplay<'a> {
+    data: &'a FloatData,
+    target: &'a Target,
+}
+
+impl std::fmt::Display for FloatDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let ty = self.data.ty();
+        let format = FloatFormat::for_type(self.target, ty);
+        let bits = self.data.to_bits(self.target);
+
+        let (negative, mut significand, mut exponent) = match format.decode(bits) {
+            Some(parts) => parts,
+            None => {
+                // not expressible as a constant, so use the GCC builtins
+                let is_nan = bits & ((1 << format.significand_bits) - 1) != 0;
+                let negative = bits >> (format.stored_bits() + format.exponent_bits) & 1 != 0;
+                let name = if is_nan { "nan" } else { "inf" };
+                let sign = if negative && !is_nan { "-" } else { "" };
+                let argument = if is_nan { "\"\"" } else { "" };
+                return write!(f, "{}__builtin_{}{}({})", sign, name, ty.suffix(), argument);
+            },
+        };
+        if negative {
+            write!(f, "-")?;
+        }
+        if significand == 0 {
+            return write!(f, "0x0p+0{}", ty.suffix());
+        }
+
+        // normalize to a single leading one before the point
+        let zeros = significand.trailing_zeros();
+        significand >>= zeros;
+        exponent += zeros as i32;
+        let fraction_bits = 127 - significand.leading_zeros();
+        exponent += fraction_bits as i32;
+
+        write!(f, "0x1")?;
+        if fraction_bits > 0 {
+            let digits = fraction_bits.div_ceil(4);
+            let fraction =
+                (significand & ((1 << fraction_bits) - 1)) << (digits * 4 - fraction_bits);
+            write!(f, ".{:0width$x}", fraction, width = digits as usize)?;
+        }
+        write!(f, "p{:+}{}", exponent, ty.suffix())
+    }
+}
+
+/// Consume the leading digits of the given radix
+fn take_digits<'a>(input: &mut &'a str, radix: Radix) -> &'a str {
+    let end = input
+        .find(|c: char| !c.is_digit(radix.value()))
+        .unwrap_or(input.len());
+    let (digits, rest) = input.split_at(end);
+    *input = rest;
+    digits
+}
+
+/// Consume an exponent part, starting after the `e` or `p`
+///
+/// Returns `None` if there are no digits. Huge exponents are clamped, since
+/// they are out of range for every format anyway.
+fn take_exponent(input: &mut &str) -> Option<i32> {
+    let negative = if let Some(rest) = input.strip_prefix('-') {
+        *input = rest;
+        true
+    } else {
+        if let Some(rest) = input.strip_prefix('+') {
+            *input = rest;
+        }
+        false
+    };
+
+    let digits = take_digits(input, Radix::Decimal);
+    if digits.is_empty() {
+        return None;
+    }
+    let magnitude = digits.parse::<i32>().unwrap_or(i32::MAX).min(1 << 20);
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+fn parse_float_suffix(suffix: &str, radix: Radix) -> Result<FloatType, MessageKind> {
+    match suffix {
+        "" => Ok(FloatType::Double),
+        "f" | "F" => Ok(FloatType::Float),
+        "l" | "L" => Ok(FloatType::LongDouble),
+        _ => Err(MessageKind::Phase7NumberSuffixInvalid {
+            suffix: suffix.to_owned(),
+            radix,
+        }),
+    }
+}
+
+fn parse_decimal_float_constant(target: &Target, input: &str) -> Result<FloatData, MessageKind> {
+    let malformed = || MessageKind::Phase7FloatMalformed {
+        value: input.to_owned(),
+    };
+
+    let mut remaining = input;
+    let integer = take_digits(&mut remaining, Radix::Decimal);
+    let mut fraction = "";
+    if let Some(rest) = remaining.strip_prefix('.') {
+        remaining = rest;
+        fraction = take_digits(&mut remaining, Radix::Decimal);
+    }
+    if integer.is_empty() && fraction.is_empty() {
+        return Err(malformed());
+    }
+    if let Some(rest) = remaining.strip_prefix(|c| c == 'e' || c == 'E') {
+        remaining = rest;
+        take_exponent(&mut remaining).ok_or_else(malformed)?;
+    }
+
+    let (body, suffix) = input.split_at(input.len() - remaining.len());
+    let ty = parse_float_suffix(suffix, Radix::Decimal)?;
+    let out_of_range = || MessageKind::Phase7FloatOutOfRange {
+        value: input.to_owned(),
+        ty,
+    };
+
+    let bits = if ty == FloatType::Float {
+        let value = body.parse::<f32>().map_err(|_| malformed())?;
+        if value.is_infinite() {
+            return Err(out_of_range());
+        }
+        value.to_bits() as u128
+    } else {
+        let value = body.parse::<f64>().map_err(|_| malformed())?;
+        if value.is_infinite() {
+            return Err(out_of_range());
+        }
+
+        let format = FloatFormat::for_type(target, ty);
+        if format.significand_bits == BINARY64.significand_bits {
+            value.to_bits() as u128
+        } else {
+            // TODO: decimal long double constants are only as precise as a
+            // double, since we rely on the standard library to parse them
+            let (negative, significand, exponent) = decompose_f64(value);
+            // every double fits in the wider formats
+            format.encode(negative, significand, exponent).unwrap()
+        }
+    };
+
+    Ok(FloatData::from_bits(target, ty, bits))
+}
+
+fn parse_hex_float_constant(target: &Target, input: &str) -> Result<FloatData, MessageKind> {
+    let malformed = || MessageKind::Phase7FloatMalformed {
+        value: input.to_owned(),
+    };
+
+    // caller verified the `0x` prefix
+    let mut remaining = &input[2..];
+    let integer = take_digits(&mut remaining, Radix::Hexadecimal);
+    let mut fraction = "";
+    if let Some(rest) = remaining.strip_prefix('.') {
+        remaining = rest;
+        fraction = take_digits(&mut remaining, Radix::Hexadecimal);
+    }
+    if integer.is_empty() && fraction.is_empty() {
+        return Err(malformed());
+    }
+
+    // unlike decimal constants, the exponent is mandatory
+    remaining = remaining
+        .strip_prefix(|c| c == 'p' || c == 'P')
+        .ok_or_else(malformed)?;
+    let mut exponent = take_exponent(&mut remaining).ok_or_else(malformed)?;
+
+    let suffix = remaining;
+    let ty = parse_float_suffix(suffix, Radix::Hexadecimal)?;
+
+    let mut significand: u128 = 0;
+    let mut sticky = false;
+    for (i, c) in integer.chars().chain(fraction.chars()).enumerate() {
+        let digit = c.to_digit(16).unwrap() as u128;
+        let is_fraction = i >= integer.len();
+        if significand >> 124 == 0 {
+            significand = significand << 4 | digit;
+            if is_fraction {
+                exponent -= 4;
+            }
+        } else {
+            // Too many digits to track. These lie far below the precision of
+            // any format, so they only matter for breaking a tie in rounding
+            sticky |= digit != 0;
+            if !is_fraction {
+                exponent += 4;
+            }
+        }
+    }
+    if sticky {
+        significand |= 1;
+    }
+
+    let format = FloatFormat::for_type(target, ty);
+    match format.encode(false, significand, exponent) {
+        Some(bits) => Ok(FloatData::from_bits(target, ty, bits)),
+        None => Err(MessageKind::Phase7FloatOutOfRange {
+            value: input.to_owned(),
+            ty,
+        }),
+    }
+}
+
+/// Compute the value of a floating constant
+///
+/// The `input` is the text of a preprocessing number, including its suffix.
+pub fn parse_float_constant(target: &Target, input: &str) -> Result<FloatData, MessageKind> {
+    if input.starts_with("0x") || input.starts_with("0X") {
+        parse_hex_float_constant(target, input)
+    } else {
+        parse_decimal_float_constant(target, input)
+    }
+}
+
+/// A realized integer constant
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IntegerData {
+    pub value: u64,
+    pub ty: IntegerType,
+}
+
+impl std::fmt::Display for IntegerData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}{}", self.value, self.ty.suffix())
+    }
+}
+
+impl IntegerData {
+    /// Lay out the value in memory
+    pub fn to_bytes(&self, target: &Target) -> Vec<u8> {
+        let size = self.ty.size_bytes(target);
+        match target.endianness {
+            Endianness::Little => self.value.to_le_bytes()[..size].to_vec(),
+            Endianness::Big => self.value.to_be_bytes()[8 - size..].to_vec(),
+        }
+    }
+}
+
+/// Consume a `u` or `U` if present
+fn take_unsigned_suffix(input: &mut &str) -> bool {
+    match input.strip_prefix(|c| c == 'u' || c == 'U') {
+        Some(rest) => {
+            *input = rest;
+            true
+        },
+        None => false,
+    }
+}
+
+/// Parse an integer suffix into whether it is unsigned and its length
+///
+/// The `u` may come before or after the length, but `l` and `L` cannot be
+/// mixed within `ll`.
+fn parse_integer_suffix(suffix: &str, radix: Radix) -> Result<(bool, IntegerLength), MessageKind> {
+    let mut remaining = suffix;
+    let mut unsigned = take_unsigned_suffix(&mut remaining);
+
+    let length = if let Some(rest) = remaining
+        .strip_prefix("ll")
+        .or_else(|| remaining.strip_prefix("LL"))
+    {
+        remaining = rest;
+        IntegerLength::LongLong
+    } else if let Some(rest) = remaining.strip_prefix(|c| c == 'l' || c == 'L') {
+        remaining = rest;
+        IntegerLength::Long
+    } else {
+        IntegerLength::Int
+    };
+
+    if !unsigned {
+        unsigned = take_unsigned_suffix(&mut remaining);
+    }
+    if !remaining.is_empty() {
+        return Err(MessageKind::Phase7NumberSuffixInvalid {
+            suffix: suffix.to_owned(),
+            radix,
+        });
+    }
+    Ok((unsigned, length))
+}
+
+/// Choose the first candidate at least as long as the suffix asks that can
+/// hold the value
+fn first_fitting_type(
+    target: &Target,
+    candidates: &[IntegerType],
+    value: u64,
+    length: IntegerLength,
+) -> Option<IntegerType> {
+    candidates
+        .iter()
+        .copied()
+        .filter(|ty| ty.length() >= length)
+        .find(|ty| value <= ty.max_value(target))
+}
+
+/// Deduce the type of a decimal constant (C11 6.4.4.1p5)
+///
+/// Without a `u` suffix, decimal constants are always signed.
+fn deduce_type_dec(
+    target: &Target,
+    value: u64,
+    unsigned: bool,
+    length: IntegerLength,
+) -> Option<IntegerType> {
+    use IntegerType::*;
+    let candidates: &[IntegerType] = if unsigned {
+        &[UnsignedInt, UnsignedLong, UnsignedLongLong]
+    } else {
+        &[Int, Long, LongLong]
+    };
+    first_fitting_type(target, candidates, value, length)
+}
+
+/// Deduce the type of a binary, octal, or hexadecimal constant (C11 6.4.4.1p5)
+///
+/// Unlike decimal constants, these may become unsigned to fit their value.
+fn deduce_type_nondec(
+    target: &Target,
+    value: u64,
+    unsigned: bool,
+    length: IntegerLength,
+) -> Option<IntegerType> {
+    use IntegerType::*;
+    let candidates: &[IntegerType] = if unsigned {
+        &[UnsignedInt, UnsignedLong, UnsignedLongLong]
+    } else {
+        &[
+            Int,
+            UnsignedInt,
+            Long,
+            UnsignedLong,
+            LongLong,
+            UnsignedLongLong,
+        ]
+    };
+    first_fitting_type(target, candidates, value, length)
+}
+
+/// Compute the value and type of an integer constant
+///
+/// The `input` is the text of a preprocessing number, including its suffix.
+/// Binary constants with a `0b` prefix are accepted as an extension.
+pub fn parse_integer_constant(target: &Target, input: &str) -> Result<IntegerData, MessageKind> {
+    let (radix, mut remaining) = if let Some(rest) = input
+        .strip_prefix("0x")
+        .or_else(|| input.strip_prefix("0X"))
+    {
+        (Radix::Hexadecimal, rest)
+    } else if let Some(rest) = input
+        .strip_prefix("0b")
+        .or_else(|| input.strip_prefix("0B"))
+    {
+        (Radix::Binary, rest)
+    } else if input.starts_with('0') {
+        (Radix::Octal, input)
+    } else {
+        (Radix::Decimal, input)
+    };
+
+    let digits = take_digits(&mut remaining, radix);
+    if digits.is_empty() {
+        return Err(MessageKind::Phase7IntegerMalformed {
+            value: input.to_owned(),
+        });
+    }
+    if radix == Radix::Octal {
+        if let Some(digit) = remaining.chars().next().filter(|c| c.is_ascii_digit()) {
+            return Err(MessageKind::Phase7InvalidOctalDigit { digit });
+        }
+    }
+    let (unsigned, length) = parse_integer_suffix(remaining, radix)?;
+
+    let out_of_range = || MessageKind::Phase7IntegerOutOfRange {
+        value: input.to_owned(),
+        radix,
+    };
+    let value = digits
+        .chars()
+        .try_fold(0u64, |value, c| {
+            value
+                .checked_mul(u64::from(radix.value()))?
+                .checked_add(u64::from(c.to_digit(radix.value()).unwrap()))
+        })
+        .ok_or_else(out_of_range)?;
+
+    let ty = if radix == Radix::Decimal {
+        deduce_type_dec(target, value, unsigned, length)
+    } else {
+        deduce_type_nondec(target, value, unsigned, length)
+    };
+    let ty = ty.ok_or_else(out_of_range)?;
+
+    Ok(IntegerData { value, ty })
+}
+
+/// The code units of a realized string literal, excluding the null terminator
+///
+/// Numeric escapes denote code units rather than characters, so the contents
+/// need not be valid UTF-8, UTF-16, or UTF-32.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StringData {
+    /// Bytes of a `""` or `u8""` literal
+    Bytes(Vec<u8>),
+    /// Code units of a `u""` literal, or of `L""` when `wchar_t` is 2 bytes
+    Units16(Vec<u16>),
+    /// Code units of a `U""` literal, or of `L""` when `wchar_t` is 4 bytes
+    Units32(Vec<u32>),
+}
+
+impl StringData {
+    /// Number of code units
+    pub fn len(&self) -> usize {
+        match self {
+            StringData::Bytes(data) => data.len(),
+            StringData::Units16(data) => data.len(),
+            StringData::Units32(data) => data.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lay out the code units in memory
+    pub fn to_bytes(&self, target: &Target) -> Vec<u8> {
+        match (self, target.endianness) {
+            (StringData::Bytes(data), _) => data.clone(),
+            (StringData::Units16(data), Endianness::Little) => {
+                data.iter().flat_map(|u| u.to_le_bytes()).collect()
+            },
+            (StringData::Units16(data), Endianness::Big) => {
+                data.iter().flat_map(|u| u.to_be_bytes()).collect()
+            },
+            (StringData::Units32(data), Endianness::Little) => {
+                data.iter().flat_map(|u| u.to_le_bytes()).collect()
+            },
+            (StringData::Units32(data), Endianness::Big) => {
+                data.iter().flat_map(|u| u.to_be_bytes()).collect()
+            },
+        }
+    }
+}
+
+/// Append the code units encoding `c` in units of `size` bytes
+fn push_char(units: &mut Vec<u32>, size: usize, c: char) {
+    match size {
+        1 => units.extend(c.encode_utf8(&mut [0; 4]).bytes().map(u32::from)),
+        2 => units.extend(c.encode_utf16(&mut [0; 2]).iter().map(|&u| u32::from(u))),
+        _ => units.push(u32::from(c)),
+    }
+}
+
+/// Consume up to `max_len` digits of the given radix
+fn take_escape_digits(
+    iter: &mut std::iter::Peekable<std::str::Chars>,
+    radix: u32,
+    max_len: usize,
+) -> String {
+    let mut digits = String::new();
+    while digits.len() < max_len && iter.peek().map(|c| c.is_digit(radix)) == Some(true) {
+        digits.push(iter.next().unwrap());
+    }
+    digits
+}
+
+/// Compute the code unit denoted by a hexadecimal or octal escape
+fn parse_escape_unit(
+    digits: String,
+    radix: u32,
+    prefix: &'static str,
+    encoding: Encoding,
+    size: usize,
+) -> Result<u32, MessageKind> {
+    if digits.is_empty() {
+        return Err(MessageKind::Phase5Empty);
+    }
+
+    let max = u64::MAX >> (64 - 8 * size);
+    let value = digits.chars().try_fold(0u64, |current, next| {
+        current
+            .checked_mul(u64::from(radix))
+            .map(|current| current + u64::from(next.to_digit(radix).unwrap()))
+            .filter(|&value| value <= max)
+    });
+    match value {
+        Some(value) => Ok(value as u32),
+        None => Err(MessageKind::Phase5OutOfRange {
+            prefix,
+            value: digits,
+            encoding,
+        }),
+    }
+}
+
+/// Compute the character named by a `\u` or `\U` escape
+fn parse_universal_char(
+    iter: &mut std::iter::Peekable<std::str::Chars>,
+    prefix: char,
+) -> Result<char, MessageKind> {
+    let expected = if prefix == 'u' { 4 } else { 8 };
+    let digits = take_escape_digits(iter, 16, expected);
+    if digits.is_empty() {
+        return Err(MessageKind::Phase5Empty);
+    } else if digits.len() < expected {
+        return Err(MessageKind::Phase5Incomplete {
+            expected,
+            found: digits.len(),
+            prefix,
+        });
+    }
+
+    let value = u32::from_str_radix(&digits, 16).unwrap();
+    std::char::from_u32(value).ok_or(MessageKind::Phase5Invalid {
+        prefix: if prefix == 'u' { "u" } else { "U" },
+        value: digits,
+    })
+}
+
+/// Number of bytes in a code unit of the given encoding
+fn unit_size(target: &Target, encoding: Encoding) -> usize {
+    match encoding {
+        Encoding::WChar => target.wchar_size,
+        _ => encoding.size_bytes(),
+    }
+}
+
+/// Compute the code units of the content of a string literal or character
+/// constant, interpreting its escape sequences
+fn parse_code_units(
+    content: &str,
+    encoding: Encoding,
+    size: usize,
+) -> Result<Vec<u32>, MessageKind> {
+    let mut units = Vec::new();
+    let mut iter = content.chars().peekable();
+    while let Some(c) = iter.next() {
+        if c != '\\' {
+            push_char(&mut units, size, c);
+            continue;
+        }
+
+        match iter.next() {
+            Some('x') => {
+                let digits = take_escape_digits(&mut iter, 16, usize::MAX);
+                units.push(parse_escape_unit(digits, 16, "x", encoding, size)?);
+            },
+            Some(c) if c.is_digit(8) => {
+                let mut digits = c.to_string();
+                digits.push_str(&take_escape_digits(&mut iter, 8, 2));
+                units.push(parse_escape_unit(digits, 8, "", encoding, size)?);
+            },
+            Some(prefix @ 'u') | Some(prefix @ 'U') => {
+                let c = parse_universal_char(&mut iter, prefix)?;
+                push_char(&mut units, size, c);
+            },
+            Some('\\') => units.push(u32::from('\\')),
+            Some('?') => units.push(u32::from('?')),
+            Some('\'') => units.push(u32::from('\'')),
+            Some('"') => units.push(u32::from('"')),
+            Some('a') => units.push(0x07),
+            Some('b') => units.push(0x08),
+            Some('f') => units.push(0x0c),
+            Some('n') => units.push(0x0a),
+            Some('r') => units.push(0x0d),
+            Some('t') => units.push(0x09),
+            Some('v') => units.push(0x0b),
+            Some(escape) => return Err(MessageKind::Phase5Unrecognized { escape }),
+            None => return Err(MessageKind::Phase5Empty),
+        }
+    }
+    Ok(units)
+}
+
+/// Compute the code units of a string literal
+///
+/// The `input` is the text of a single string literal token, including its
+/// encoding prefix and quotes, with escape sequences still present. Numeric
+/// escapes such as `\xD800` are stored as the code unit they denote, even if
+/// it is not a valid character on its own.
+pub fn parse_string_constant(target: &Target, input: &str) -> Result<StringData, MessageKind> {
+    let encoding = get_string_encoding(input, "\"");
+    let size = unit_size(target, encoding);
+    let units = parse_code_units(get_string_content(input, "\""), encoding, size)?;
+
+    Ok(match size {
+        1 => StringData::Bytes(units.into_iter().map(|u| u as u8).collect()),
+        2 => StringData::Units16(units.into_iter().map(|u| u as u16).collect()),
+        _ => StringData::Units32(units),
+    })
+}
+
+/// A realized character constant
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CharacterData {
+    /// Value of the constant, as stored in its type
+    pub value: u32,
+    pub encoding: Encoding,
+}
+
+impl CharacterData {
+    /// Name of the type of the constant
+    ///
+    /// Unprefixed character constants have type `int`.
+    pub fn type_str(&self) -> &'static str {
+        match self.encoding {
+            Encoding::Default => "int",
+            encoding => encoding.type_str(),
+        }
+    }
+
+    /// Number of bytes in the type of the constant
+    pub fn size_bytes(&self, target: &Target) -> usize {
+        match self.encoding {
+            Encoding::Default => 4,
+            encoding => unit_size(target, encoding),
+        }
+    }
+
+    /// Lay out the value in memory
+    pub fn to_bytes(&self, target: &Target) -> Vec<u8> {
+        let size = self.size_bytes(target);
+        match target.endianness {
+            Endianness::Little => self.value.to_le_bytes()[..size].to_vec(),
+            Endianness::Big => self.value.to_be_bytes()[4 - size..].to_vec(),
+        }
+    }
+}
+
+/// Compute the value of a character constant
+///
+/// The `input` is the text of a single character constant token, including
+/// its encoding prefix and quotes, with escape sequences still present.
+///
+/// A prefixed constant must be a single code unit of its type, so `u'😀'` is
+/// rejected because it needs a surrogate pair. An unprefixed constant with
+/// several bytes, such as `'ab'` or `'é'`, has an implementation-defined
+/// value; like GCC, the bytes are combined big-endian and truncated to an
+/// `int`. A single byte is converted from plain `char`, so `'\xff'` is -1 where
+/// `char` is signed and 255 where it is unsigned.
+pub fn parse_character_constant(
+    target: &Target,
+    input: &str,
+) -> Result<CharacterData, MessageKind> {
+    let encoding = get_string_encoding(input, "'");
+    let size = unit_size(target, encoding);
+    let units = parse_code_units(get_string_content(input, "'"), encoding, size)?;
+
+    let value = match (encoding, units.as_slice()) {
+        (_, []) => return Err(MessageKind::Phase7CharacterEmpty),
+        (Encoding::Default, [unit]) if target.char_signed => *unit as u8 as i8 as u32,
+        (Encoding::Default, units) => units.iter().fold(0u32, |value, &unit| (value << 8) | unit),
+        (_, [unit]) => *unit,
+        (_, _) => {
+            return Err(MessageKind::Phase7CharacterOutOfRange {
+                value: input.to_owned(),
+                encoding,
+            })
+        },
+    };
+    Ok(CharacterData { value, encoding })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn target(name: &str) -> Target {
+        Target::from_name(name).unwrap()
+    }
+
+    fn bytes(target: &Target, input: &str) -> Vec<u8> {
+        parse_float_constant(target, input)
+            .unwrap()
+            .as_bytes()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_parse_float_constant() {
+        let target = Target::default();
+        assert_eq!(bytes(&target, "1.5f"), 1.5f32.to_le_bytes());
+        assert_eq!(bytes(&target, "2.5e-3"), 2.5e-3f64.to_le_bytes());
+        assert_eq!(bytes(&target, ".5"), 0.5f64.to_le_bytes());
+        assert_eq!(bytes(&target, "1.e2F"), 100f32.to_le_bytes());
+        assert_eq!(bytes(&target, "0x1.8p1"), 3f64.to_le_bytes());
+        assert_eq!(bytes(&target, "0X.8P-1f"), 0.25f32.to_le_bytes());
+        assert_eq!(bytes(&target, "0x1p-1074"), 1u64.to_le_bytes());
+        assert_eq!(
+            parse_float_constant(&target, "1.0").unwrap().ty(),
+            FloatType::Double
+        );
+    }
+
+    #[test]
+    fn test_hex_float_rounding() {
+        let target = Target::default();
+        let float = |bits: u32| bits.to_le_bytes().to_vec();
+        let double = |bits: u64| bits.to_le_bytes().to_vec();
+
+        // ties go to the even significand
+        assert_eq!(bytes(&target, "0x1.000001p0f"), float(0x3f80_0000));
+        assert_eq!(bytes(&target, "0x1.000003p0f"), float(0x3f80_0002));
+        assert_eq!(bytes(&target, "0x1.0000011p0f"), float(0x3f80_0001));
+        // digits beyond what can be tracked still break ties
+        assert_eq!(
+            bytes(&target, "0x1.00000100000000000000000000000001p0f"),
+            float(0x3f80_0001)
+        );
+
+        // subnormals round too, possibly up to the smallest normal value
+        assert_eq!(bytes(&target, "0x3p-1075"), double(2));
+        assert_eq!(bytes(&target, "0x1p-1075"), double(0));
+        assert_eq!(
+            bytes(&target, "0x1.fffffffffffff8p-1023"),
+            double(0x0010_0000_0000_0000)
+        );
+        assert_eq!(bytes(&target, "0x1p-2000"), double(0));
+
+        // rounding up may overflow
+        assert_eq!(
+            bytes(&target, "0x1.fffffffffffff7p1023"),
+            double(0x7fef_ffff_ffff_ffff)
+        );
+        assert!(matches!(
+            parse_float_constant(&target, "0x1.fffffffffffff8p1023"),
+            Err(MessageKind::Phase7FloatOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_float_constant_errors() {
+        let target = Target::default();
+        let error = |input| parse_float_constant(&target, input).unwrap_err();
+
+        assert!(matches!(
+            error("1.0x"),
+            MessageKind::Phase7NumberSuffixInvalid {
+                radix: Radix::Decimal,
+                ..
+            }
+        ));
+        assert!(matches!(
+            error("0x1p0lf"),
+            MessageKind::Phase7NumberSuffixInvalid {
+                radix: Radix::Hexadecimal,
+                ..
+            }
+        ));
+        assert!(matches!(
+            error("0x1.0"),
+            MessageKind::Phase7FloatMalformed { .. }
+        ));
+        assert!(matches!(
+            error("1e+"),
+            MessageKind::Phase7FloatMalformed { .. }
+        ));
+        assert!(matches!(
+            error("1e39f"),
+            MessageKind::Phase7FloatOutOfRange {
+                ty: FloatType::Float,
+                ..
+            }
+        ));
+        assert!(matches!(
+            error("0x1p1024"),
+            MessageKind::Phase7FloatOutOfRange {
+                ty: FloatType::Double,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_long_double_targets() {
+        let x86 = target("x86_64-unknown-linux-gnu");
+        let msvc = target("x86_64-pc-windows-msvc");
+        let aarch64 = target("aarch64-unknown-linux-gnu");
+
+        let extended = vec![0, 0, 0, 0, 0, 0, 0, 0x80, 0xff, 0x3f];
+        let mut quad = vec![0; 14];
+        quad.extend_from_slice(&[0xff, 0x3f]);
+
+        for input in &["1.0L", "0x1p0l"] {
+            assert_eq!(bytes(&x86, input), extended);
+            assert_eq!(bytes(&msvc, input), 1f64.to_le_bytes());
+            assert_eq!(bytes(&aarch64, input), quad);
+        }
+
+        // beyond the range of double, but not of the wider formats
+        assert!(parse_float_constant(&msvc, "0x1p1024L").is_err());
+        assert!(parse_float_constant(&x86, "0x1p1024L").is_ok());
+
+        // the smallest subnormal of the x87 format
+        let mut smallest = vec![0; 10];
+        smallest[0] = 1;
+        assert_eq!(bytes(&x86, "0x1p-16445L"), smallest);
+    }
+
+    #[test]
+    fn test_big_endian_target() {
+        let s390x = target("s390x-unknown-linux-gnu");
+
+        assert_eq!(bytes(&s390x, "1.5f"), 1.5f32.to_be_bytes());
+        assert_eq!(bytes(&s390x, "0x1.8p1"), 3f64.to_be_bytes());
+        assert_eq!(bytes(&s390x, "0x1p-1074"), 1u64.to_be_bytes());
+
+        let mut quad = vec![0x3f, 0xff];
+        quad.extend_from_slice(&[0; 14]);
+        assert_eq!(bytes(&s390x, "1.0L"), quad);
+        assert_eq!(bytes(&s390x, "0x1p0L"), quad);
+    }
+
+    #[test]
+    fn test_parse_integer_constant() {
+        let linux = Target::default();
+        let parse = |input| parse_integer_constant(&linux, input).unwrap();
+        let data = |value, ty| IntegerData { value, ty };
+
+        assert_eq!(parse("0"), data(0, IntegerType::Int));
+        assert_eq!(parse("017"), data(15, IntegerType::Int));
+        assert_eq!(parse("0x1fU"), data(31, IntegerType::UnsignedInt));
+        assert_eq!(parse("1ul"), data(1, IntegerType::UnsignedLong));
+        assert_eq!(parse("1LLu"), data(1, IntegerType::UnsignedLongLong));
+        assert_eq!(parse("1Ull"), data(1, IntegerType::UnsignedLongLong));
+
+        // decimal constants stay signed, but others may become unsigned
+        assert_eq!(parse("2147483648"), data(1 << 31, IntegerType::Long));
+        assert_eq!(parse("0x80000000"), data(1 << 31, IntegerType::UnsignedInt));
+        assert_eq!(
+            parse("0xffffffffffffffff"),
+            data(u64::MAX, IntegerType::UnsignedLong)
+        );
+        assert_eq!(
+            parse("18446744073709551615u"),
+            data(u64::MAX, IntegerType::UnsignedLong)
+        );
+
+        // `long` is narrower on some targets
+        let msvc = target("x86_64-pc-windows-msvc");
+        let data = parse_integer_constant(&msvc, "2147483648").unwrap();
+        assert_eq!(data.ty, IntegerType::LongLong);
+        assert_eq!(data.to_bytes(&msvc), (1u64 << 31).to_le_bytes());
+    }
+
+    #[test]
+    fn test_integer_display() {
+        let linux = Target::default();
+        let msvc = target("x86_64-pc-windows-msvc");
+        let cases = [
+            ("0", "0"),
+            ("017", "15"),
+            ("0x80000000", "2147483648u"),
+            ("2147483648", "2147483648l"),
+            ("0b101LL", "5ll"),
+            ("0xffffffffffffffff", "18446744073709551615ul"),
+            ("1uLL", "1ull"),
+        ];
+        for &(input, expected) in &cases {
+            let data = parse_integer_constant(&linux, input).unwrap();
+            assert_eq!(data.to_string(), expected);
+            assert_eq!(parse_integer_constant(&linux, expected).unwrap(), data);
+        }
+
+        let data = parse_integer_constant(&msvc, "2147483648").unwrap();
+        assert_eq!(data.to_string(), "2147483648ll");
+    }
+
+    #[test]
+    fn test_float_display() {
+        let cases = [
+            ("1.0", "0x1p+0"),
+            ("0.5f", "0x1p-1f"),
+            ("1.5L", "0x1.8p+0l"),
+            ("0.1", "0x1.999999999999ap-4"),
+            ("0.0", "0x0p+0"),
+            ("0x1p-1074", "0x1p-1074"),
+            ("0x1.fffffep127f", "0x1.fffffep+127f"),
+        ];
+        for name in &[
+            "x86_64-unknown-linux-gnu",
+            "x86_64-pc-windows-msvc",
+            "s390x-unknown-linux-gnu",
+        ] {
+            let target = target(name);
+            for &(input, expected) in &cases {
+                let data = parse_float_constant(&target, input).unwrap();
+                let displayed = data.display(&target).to_string();
+                assert_eq!(parse_float_constant(&target, &displayed).unwrap(), data);
+                if data.ty() != FloatType::LongDouble {
+                    assert_eq!(displayed, expected);
+                }
+            }
+        }
+
+        let linux = Target::default();
+        let data = parse_float_constant(&linux, "0.1L").unwrap();
+        assert_eq!(data.display(&linux).to_string(), "0x1.999999999999ap-4l");
+        let infinity = FloatData::from_bits(&linux, FloatType::Float, 0xff80_0000);
+        assert_eq!(infinity.display(&linux).to_string(), "-__builtin_inff()");
+        let nan = FloatData::from_bits(&linux, FloatType::Double, 0x7ff8 << 48);
+        assert_eq!(nan.display(&linux).to_string(), "__builtin_nan(\"\")");
+
+        // infinities and NaNs of each long double format
+        for &(name, sign, infinity, quiet) in &[
+            (
+                "x86_64-unknown-linux-gnu",
+                1 << 79,
+                0x7fff << 64 | 1 << 63,
+                1 << 62,
+            ),
+            ("x86_64-pc-windows-msvc", 1 << 63, 0x7ff << 52, 1 << 51),
+            (
+                "aarch64-unknown-linux-gnu",
+                1 << 127,
+                0x7fff << 112,
+                1 << 111,
+            ),
+        ] {
+            let target = target(name);
+            let display = |bits| {
+                let data = FloatData::from_bits(&target, FloatType::LongDouble, bits);
+                data.display(&target).to_string()
+            };
+            assert_eq!(display(infinity), "__builtin_infl()");
+            assert_eq!(display(sign | infinity), "-__builtin_infl()");
+            assert_eq!(display(infinity | quiet), "__builtin_nanl(\"\")");
+            assert_eq!(display(sign | infinity | quiet), "__builtin_nanl(\"\")");
+        }
+    }
+
+    #[test]
+    fn test_binary_integer_constant() {
+        let target = Target::default();
+        assert_eq!(Radix::Binary.value(), 2);
+        assert_eq!(Radix::Binary.to_str(), "binary");
+
+        let data = parse_integer_constant(&target, "0b11u").unwrap();
+        assert_eq!(data.value, 3);
+        assert_eq!(data.ty, IntegerType::UnsignedInt);
+        let data = parse_integer_constant(&target, "0B10000000000000000000000000000000").unwrap();
+        assert_eq!(data.ty, IntegerType::UnsignedInt);
+
+        let too_large = format!("0b1{}", "0".repeat(64));
+        assert!(matches!(
+            parse_integer_constant(&target, &too_large),
+            Err(MessageKind::Phase7IntegerOutOfRange {
+                radix: Radix::Binary,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_integer_constant(&target, "0b1x"),
+            Err(MessageKind::Phase7NumberSuffixInvalid {
+                radix: Radix::Binary,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_integer_constant_errors() {
+        let target = Target::default();
+        let error = |input| parse_integer_constant(&target, input).unwrap_err();
+
+        assert!(matches!(
+            error("1lL"),
+            MessageKind::Phase7NumberSuffixInvalid {
+                radix: Radix::Decimal,
+                ..
+            }
+        ));
+        assert!(matches!(
+            error("1uu"),
+            MessageKind::Phase7NumberSuffixInvalid { .. }
+        ));
+        assert!(matches!(
+            error("0x1g"),
+            MessageKind::Phase7NumberSuffixInvalid {
+                radix: Radix::Hexadecimal,
+                ..
+            }
+        ));
+        assert!(matches!(
+            error("0x"),
+            MessageKind::Phase7IntegerMalformed { .. }
+        ));
+        assert!(matches!(
+            error("078"),
+            MessageKind::Phase7InvalidOctalDigit { digit: '8' }
+        ));
+        assert!(matches!(
+            error("09"),
+            MessageKind::Phase7InvalidOctalDigit { digit: '9' }
+        ));
+        assert!(matches!(
+            error("0779u"),
+            MessageKind::Phase7InvalidOctalDigit { digit: '9' }
+        ));
+        assert!(matches!(
+            error("9223372036854775808"),
+            MessageKind::Phase7IntegerOutOfRange {
+                radix: Radix::Decimal,
+                ..
+            }
+        ));
+        assert!(matches!(
+            error("0x10000000000000000"),
+            MessageKind::Phase7IntegerOutOfRange {
+                radix: Radix::Hexadecimal,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_parse_string_constant() {
+        let target = Target::default();
+        let parse = |input| parse_string_constant(&target, input).unwrap();
+
+        assert_eq!(
+            parse(r#""aé\xff""#),
+            StringData::Bytes(vec![b'a', 0xc3, 0xa9, 0xff])
+        );
+        assert_eq!(parse(r#"u8"\101""#), StringData::Bytes(vec![b'A']));
+        assert_eq!(
+            parse("u\"a\u{e9}\u{1f600}\""),
+            StringData::Units16(vec![0x61, 0xe9, 0xd83d, 0xde00])
+        );
+        assert_eq!(
+            parse("U\"a\u{1f600}\\n\""),
+            StringData::Units32(vec![0x61, 0x1f600, 0x0a])
+        );
+        assert_eq!(
+            parse(r#"L"\U0001F600""#),
+            StringData::Units32(vec![0x1f600])
+        );
+
+        // a lone surrogate is a valid code unit, though not a character
+        assert_eq!(parse(r#"u"\xD800""#), StringData::Units16(vec![0xd800]));
+        assert_eq!(parse(r#"U"\xD800""#), StringData::Units32(vec![0xd800]));
+    }
+
+    #[test]
+    fn test_parse_string_constant_errors() {
+        let target = Target::default();
+        let error = |input| parse_string_constant(&target, input).unwrap_err();
+
+        assert!(matches!(
+            error(r#""\x100""#),
+            MessageKind::Phase5OutOfRange { prefix: "x", .. }
+        ));
+        assert!(matches!(
+            error(r#""\777""#),
+            MessageKind::Phase5OutOfRange { prefix: "", .. }
+        ));
+        assert!(matches!(
+            error(r#"u"\x10000""#),
+            MessageKind::Phase5OutOfRange {
+                encoding: Encoding::Char16,
+                ..
+            }
+        ));
+        assert!(matches!(
+            error(r#"u"\uD800""#),
+            MessageKind::Phase5Invalid { prefix: "u", .. }
+        ));
+        assert!(matches!(
+            error(r#"U"\u12""#),
+            MessageKind::Phase5Incomplete {
+                expected: 4,
+                found: 2,
+                prefix: 'u',
+            }
+        ));
+        assert!(matches!(error(r#""\x""#), MessageKind::Phase5Empty));
+        assert!(matches!(
+            error(r#""\q""#),
+            MessageKind::Phase5Unrecognized { escape: 'q' }
+        ));
+    }
+
+    #[test]
+    fn test_wide_string_target() {
+        let msvc = target("x86_64-pc-windows-msvc");
+        let s390x = target("s390x-unknown-linux-gnu");
+
+        let data = parse_string_constant(&msvc, r#"L"a\xD800""#).unwrap();
+        assert_eq!(data, StringData::Units16(vec![0x61, 0xd800]));
+        assert_eq!(data.to_bytes(&msvc), vec![0x61, 0, 0x00, 0xd8]);
+
+        let data = parse_string_constant(&s390x, r#"L"a""#).unwrap();
+        assert_eq!(data, StringData::Units32(vec![0x61]));
+        assert_eq!(data.to_bytes(&s390x), vec![0, 0, 0, 0x61]);
+    }
+
+    #[test]
+    fn test_parse_character_constant() {
+        let target = Target::default();
+        let value = |input| parse_character_constant(&target, input).unwrap().value;
+
+        assert_eq!(value("'a'"), 0x61);
+        assert_eq!(value(r"'\xff'"), 0xffff_ffff);
+        assert_eq!(value(r"'\x7f'"), 0x7f);
+        assert_eq!(value(r"'\xff\xff'"), 0xffff);
+        assert_eq!(value("'ab'"), 0x6162);
+        assert_eq!(value("'abcde'"), 0x62636465);
+        assert_eq!(value("'é'"), 0xc3a9);
+        assert_eq!(value("L'字'"), 0x5b57);
+        assert_eq!(value("u'字'"), 0x5b57);
+        assert_eq!(value("U'字'"), 0x5b57);
+        assert_eq!(value("U'😀'"), 0x1f600);
+        assert_eq!(value(r"U'\U0001F600'"), 0x1f600);
+        assert_eq!(value(r"u'\xD800'"), 0xd800);
+
+        let data = parse_character_constant(&target, "'a'").unwrap();
+        assert_eq!(data.type_str(), "int");
+        assert_eq!(data.to_bytes(&target), vec![0x61, 0, 0, 0]);
+        let data = parse_character_constant(&target, "u'a'").unwrap();
+        assert_eq!(data.type_str(), "char16_t");
+        assert_eq!(data.to_bytes(&target), vec![0x61, 0]);
+        let data = parse_character_constant(&target, r"'\xff'").unwrap();
+        assert_eq!(data.to_bytes(&target), vec![0xff, 0xff, 0xff, 0xff]);
+
+        // plain `char` is unsigned here
+        let aarch64 = Target::from_name("aarch64-unknown-linux-gnu").unwrap();
+        let data = parse_character_constant(&aarch64, r"'\xff'").unwrap();
+        assert_eq!(data.value, 0xff);
+        assert_eq!(data.to_bytes(&aarch64), vec![0xff, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_parse_character_constant_errors() {
+        let default = Target::default();
+        let error = |input| parse_character_constant(&default, input).unwrap_err();
+
+        assert!(matches!(error("''"), MessageKind::Phase7CharacterEmpty));
+        assert!(matches!(
+            error("u'😀'"),
+            MessageKind::Phase7CharacterOutOfRange {
+                encoding: Encoding::Char16,
+                ..
+            }
+        ));
+        assert!(matches!(
+            error(r"u'\U0001F600'"),
+            MessageKind::Phase7CharacterOutOfRange { .. }
+        ));
+        assert!(matches!(
+            error(r"u'\x10000'"),
+            MessageKind::Phase5OutOfRange {
+                encoding: Encoding::Char16,
+                ..
+            }
+        ));
+        assert!(matches!(
+            error("U'ab'"),
+            MessageKind::Phase7CharacterOutOfRange { .. }
+        ));
+        assert_eq!(
+            error("u'😀'").get_headline(),
+            "character constant `u'😀'` does not fit in a single `char16_t`"
+        );
+
+        // `wchar_t` is only 2 bytes on Windows
+        let msvc = target("x86_64-pc-windows-msvc");
+        assert_eq!(
+            parse_character_constant(&msvc, "L'字'").unwrap().value,
+            0x5b57
+        );
+        assert!(parse_character_constant(&msvc, "L'😀'").is_err());
+    }
+}
@@ -0,0 +1,456 @@
+// Licensed   under  the   Apache  License,   Version  2.0   <LICENSE-APACHE  or
+// http://www.apache.org/licenses/LICENSE-2.0> or  the MIT  license <LICENSE-MIT
+// or http://opensource.org/licenses/MIT>, at your option.  This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Storing a [`TUState`] in a file
+//!
+//! The format is plain text, one record per line, so snapshots of
+//! intermediate stages can be compared with ordinary tools. The first line
+//! names the kind of state. Each following line holds one token: its kind (for
+//! preprocessing tokens), its origin, and then its escaped text, which runs to
+//! the end of the line. Every field is kept, so reading a written state gives
+//! back exactly the same tokens.
+//!
+//! Origins within macro expansions refer to invocations recorded in the
+//! [`TUCtx`][crate::front::c::tuctx::TUCtx], so a state should only be read
+//! back while translating the same unit.
+
+use crate::core::{ErrorKind, Result};
+use crate::front::c::token::{
+    CharToken, MacroResult, PPToken, PPTokenKind, TextPosition, TextSpan, TokenOrigin,
+};
+use crate::front::c::tuctx::TUState;
+
+const HEADER: &str = "denuocc-state 1";
+
+fn escape(text: &str, output: &mut String) {
+    for c in text.chars() {
+        match c {
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if c.is_control() => output.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => output.push(c),
+        }
+    }
+}
+
+fn unescape(text: &str) -> Option<String> {
+    let mut output = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => output.push('\\'),
+            'n' => output.push('\n'),
+            'r' => output.push('\r'),
+            't' => output.push('\t'),
+            'u' => {
+                let rest = chars.as_str().strip_prefix('{')?;
+                let end = rest.find('}')?;
+                let value = u32::from_str_radix(&rest[..end], 16).ok()?;
+                output.push(std::char::from_u32(value)?);
+                chars = rest[end + 1..].chars();
+            },
+            _ => return None,
+        }
+    }
+    Some(output)
+}
+
+fn kind_name(kind: PPTokenKind) -> &'static str {
+    use PPTokenKind::*;
+    match kind {
+        EndOfFile => "eof",
+        Whitespace => "ws",
+        Comment => "comment",
+        Identifier => "ident",
+        IdentifierNonExpandable => "ident-noexpand",
+        HeaderName => "header",
+        PPNumber => "number",
+        CharacterConstant => "char",
+        StringLiteral => "string",
+        Punctuator => "punct",
+        Other => "other",
+    }
+}
+
+fn kind_from_name(name: &str) -> Option<PPTokenKind> {
+    use PPTokenKind::*;
+    Some(match name {
+        "eof" => EndOfFile,
+        "ws" => Whitespace,
+        "comment" => Comment,
+        "ident" => Identifier,
+        "ident-noexpand" => IdentifierNonExpandable,
+        "header" => HeaderName,
+        "number" => PPNumber,
+        "char" => CharacterConstant,
+        "string" => StringLiteral,
+        "punct" => Punctuator,
+        "other" => Other,
+        _ => return None,
+    })
+}
+
+fn write_span(span: &TextSpan, output: &mut String) {
+    output.push_str(&format!(
+        "{}:{}:{}",
+        span.pos.input, span.pos.absolute, span.len
+    ));
+}
+
+fn read_span(text: &str) -> Option<TextSpan> {
+    let mut fields = text.split(':').map(|f| f.parse::<u32>().ok());
+    let span = TextSpan {
+        pos: TextPosition {
+            input: fields.next()??,
+            absolute: fields.next()??,
+        },
+        len: fields.next()??,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(span)
+}
+
+fn write_pptoken(token: &PPToken, output: &mut String) {
+    output.push_str(kind_name(token.kind));
+    output.push(' ');
+    match &token.origin {
+        TokenOrigin::Source(span) => {
+            output.push_str("source:");
+            write_span(span, output);
+        },
+        TokenOrigin::Macro(result) => {
+            let (invocation, in_index, out_index) = result.to_raw();
+            output.push_str(&format!("macro:{}:{}:{}", invocation, in_index, out_index));
+        },
+    }
+    output.push(' ');
+    escape(&token.value, output);
+    output.push('\n');
+}
+
+fn read_pptoken(line: &str) -> Option<PPToken> {
+    let mut fields = line.splitn(3, ' ');
+    let kind = kind_from_name(fields.next()?)?;
+    let origin = fields.next()?;
+    let value = unescape(fields.next()?)?;
+
+    let origin = if let Some(span) = origin.strip_prefix("source:") {
+        TokenOrigin::Source(read_span(span)?)
+    } else {
+        let mut fields = origin.strip_prefix("macro:")?.split(':');
+        let invocation = fields.next()?.parse().ok()?;
+        let in_index = fields.next()?.parse().ok()?;
+        let out_index = fields.next()?.parse().ok()?;
+        if fields.next().is_some() {
+            return None;
+        }
+        TokenOrigin::Macro(MacroResult::from_raw(invocation, in_index, out_index))
+    };
+
+    Some(PPToken {
+        kind,
+        value: value.into(),
+        origin,
+    })
+}
+
+/// Convert a state into the text stored in a file
+pub fn serialize(state: &TUState) -> String {
+    let mut output = String::new();
+    output.push_str(HEADER);
+    output.push('\n');
+    output.push_str(state.kind());
+    output.push('\n');
+
+    match state {
+        TUState::CharTokens(tokens) => {
+            for token in tokens {
+                write_span(&token.span, &mut output);
+                output.push(' ');
+                escape(&token.value.to_string(), &mut output);
+                output.push('\n');
+            }
+        },
+        TUState::PPTokens(tokens) => {
+            for token in tokens {
+                write_pptoken(token, &mut output);
+            }
+        },
+        TUState::Lines(lines) => {
+            // each line begins with a marker, so empty lines survive
+            for line in lines {
+                output.push_str("line\n");
+                for token in line {
+                    write_pptoken(token, &mut output);
+                }
+            }
+        },
+        TUState::Text(text) => {
+            escape(text, &mut output);
+            output.push('\n');
+        },
+    }
+
+    output
+}
+
+/// Recover a state from text produced by [`serialize()`]
+///
+/// Returns the number of the first line that could not be understood on
+/// failure.
+pub fn deserialize(text: &str) -> Result<TUState, usize> {
+    let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line));
+    match lines.next() {
+        Some((_, HEADER)) => {},
+        _ => return Err(1),
+    }
+    let kind = lines.next().ok_or(2usize)?.1;
+
+    match kind {
+        "CharTokens" => {
+            let mut tokens = Vec::new();
+            for (number, line) in lines {
+                let token = line.split_once(' ').and_then(|(span, value)| {
+                    let value = unescape(value)?;
+                    let mut chars = value.chars();
+                    let value = chars.next()?;
+                    if chars.next().is_some() {
+                        return None;
+                    }
+                    Some(CharToken {
+                        value,
+                        span: read_span(span)?,
+                    })
+                });
+                tokens.push(token.ok_or(number)?);
+            }
+            Ok(TUState::CharTokens(tokens))
+        },
+        "PPTokens" => {
+            let mut tokens = Vec::new();
+            for (number, line) in lines {
+                tokens.push(read_pptoken(line).ok_or(number)?);
+            }
+            Ok(TUState::PPTokens(tokens))
+        },
+        "Lines" => {
+            let mut output: Vec<Vec<PPToken>> = Vec::new();
+            for (number, line) in lines {
+                if line == "line" {
+                    output.push(Vec::new());
+                } else {
+                    let token = read_pptoken(line).ok_or(number)?;
+                    output.last_mut().ok_or(number)?.push(token);
+                }
+            }
+            Ok(TUState::Lines(output))
+        },
+        "Text" => {
+            let (number, line) = lines.next().ok_or(3usize)?;
+            let text = unescape(line).ok_or(number)?;
+            match lines.next() {
+                Some((number, _)) => Err(number),
+                None => Ok(TUState::Text(text)),
+            }
+        },
+        _ => Err(2),
+    }
+}
+
+fn write_json_string(text: &str, output: &mut String) {
+    output.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if c.is_control() => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+fn write_json_span(span: &TextSpan, output: &mut String) {
+    output.push_str(&format!(
+        "[{}, {}, {}]",
+        span.pos.input, span.pos.absolute, span.len
+    ));
+}
+
+fn write_json_pptokens(tokens: &[PPToken], output: &mut String) {
+    output.push('[');
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 {
+            output.push_str(", ");
+        }
+        output.push_str("{\"kind\": ");
+        write_json_string(kind_name(token.kind), output);
+        match &token.origin {
+            TokenOrigin::Source(span) => {
+                output.push_str(", \"source\": ");
+                write_json_span(span, output);
+            },
+            TokenOrigin::Macro(result) => {
+                let (invocation, in_index, out_index) = result.to_raw();
+                output.push_str(&format!(
+                    ", \"macro\": [{}, {}, {}]",
+                    invocation, in_index, out_index
+                ));
+            },
+        }
+        output.push_str(", \"value\": ");
+        write_json_string(&token.value, output);
+        output.push('}');
+    }
+    output.push(']');
+}
+
+/// Convert a state into JSON, for tools that cannot read [`serialize()`]'s
+/// format
+///
+/// The object has the state's `kind` and, depending on it, a list of `tokens`,
+/// a list of `lines` each holding a list of tokens, or the `text`. Tokens have
+/// their text as `value` and their origin as either `source`, a span, or
+/// `macro`, a macro result, each given as an array of numbers. Preprocessing
+/// tokens also have a `kind`. There is no reading JSON back.
+pub fn to_json(state: &TUState) -> String {
+    let mut output = String::new();
+    output.push_str("{\"kind\": ");
+    write_json_string(state.kind(), &mut output);
+    match state {
+        TUState::CharTokens(tokens) => {
+            output.push_str(", \"tokens\": [");
+            for (i, token) in tokens.iter().enumerate() {
+                if i > 0 {
+                    output.push_str(", ");
+                }
+                output.push_str("{\"source\": ");
+                write_json_span(&token.span, &mut output);
+                output.push_str(", \"value\": ");
+                write_json_string(&token.value.to_string(), &mut output);
+                output.push('}');
+            }
+            output.push(']');
+        },
+        TUState::PPTokens(tokens) => {
+            output.push_str(", \"tokens\": ");
+            write_json_pptokens(tokens, &mut output);
+        },
+        TUState::Lines(lines) => {
+            output.push_str(", \"lines\": [");
+            for (i, line) in lines.iter().enumerate() {
+                if i > 0 {
+                    output.push_str(", ");
+                }
+                write_json_pptokens(line, &mut output);
+            }
+            output.push(']');
+        },
+        TUState::Text(text) => {
+            output.push_str(", \"text\": ");
+            write_json_string(text, &mut output);
+        },
+    }
+    output.push('}');
+    output
+}
+
+/// Write a state to the named file
+pub fn write_file(state: &TUState, filename: &str) -> Result<()> {
+    std::fs::write(filename, serialize(state)).map_err(|error| {
+        ErrorKind::OutputFileError {
+            filename: filename.to_owned(),
+            error,
+        }
+        .into()
+    })
+}
+
+/// Read a state from the named file
+pub fn read_file(filename: &str) -> Result<TUState> {
+    let text = std::fs::read_to_string(filename).map_err(|error| ErrorKind::InputFileError {
+        filename: filename.to_owned(),
+        error,
+    })?;
+    deserialize(&text).map_err(|line| {
+        ErrorKind::StateFileMalformed {
+            filename: filename.to_owned(),
+            line,
+        }
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{unit, TempDir};
+
+    #[test]
+    fn test_state_file_round_trip() {
+        let dir = TempDir::new("state");
+        let path = dir.join("pptokens");
+        let path = path.to_str().unwrap();
+        let write = format!("--pass=state_write_file(pptokens,{})", path);
+        let read = format!("--pass=state_read_file({})", path);
+        let mut tu = unit(
+            &[
+                "--pass=phase1..phase4",
+                "--pass=state_save(pptokens)",
+                &write,
+                &read,
+                "--pass=state_save(reread)",
+            ],
+            "#define F(x) x + \"\\\\\\t\"\nF(a\\\n\tb) 'c'\n",
+        );
+        tu.run().unwrap();
+
+        let original = tu.saved_states("pptokens")[0].as_pptokens().unwrap();
+        let reread = tu.saved_states("reread")[0].as_pptokens().unwrap();
+        assert!(original
+            .iter()
+            .any(|t| matches!(t.origin, TokenOrigin::Macro(..))));
+        assert_eq!(format!("{:?}", original), format!("{:?}", reread));
+    }
+
+    #[test]
+    fn test_state_serialize() {
+        let tokens = CharToken::from_str(0, "a\u{1}\\\n");
+        let text = serialize(&TUState::CharTokens(tokens.clone()));
+        assert_eq!(
+            text,
+            "denuocc-state 1\nCharTokens\n0:0:1 a\n0:1:1 \\u{1}\n0:2:1 \\\\\n0:3:1 \\n\n"
+        );
+        let state = deserialize(&text).unwrap();
+        CharToken::assert_equal(state.as_chartokens().unwrap(), &tokens);
+
+        let lines = TUState::Lines(vec![Vec::new(), Vec::new()]);
+        let state = deserialize(&serialize(&lines)).unwrap();
+        assert_eq!(state.as_lines().unwrap().len(), 2);
+
+        let text = TUState::Text("one\ntwo\\".to_owned());
+        let state = deserialize(&serialize(&text)).unwrap();
+        assert_eq!(state.as_text().unwrap(), "one\ntwo\\");
+
+        assert_eq!(deserialize("").unwrap_err(), 1);
+        assert_eq!(deserialize("denuocc-state 1\nBogus\n").unwrap_err(), 2);
+        assert_eq!(
+            deserialize("denuocc-state 1\nPPTokens\nident source:0:0:1 a\nident x a\n")
+                .unwrap_err(),
+            4
+        );
+    }
+}
@@ -14,6 +14,8 @@ mod preprocessor_token;
 mod text;
 
 pub use self::char_token::CharToken;
-pub use self::origin::{MacroInvocation, MacroResult, TokenOrigin};
+pub use self::origin::{
+    MacroInvocation, MacroResult, ProvenanceChain, ProvenanceSource, ProvenanceStep, TokenOrigin,
+};
 pub use self::preprocessor_token::{PPToken, PPTokenKind};
 pub use self::text::{TextPosition, TextPositionResolved, TextSpan};
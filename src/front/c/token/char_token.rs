@@ -21,26 +21,29 @@ impl CharToken {
     }
 
     pub fn from_str(input: u32, content: &str) -> Vec<CharToken> {
+        Self::iter_str(input, content).collect()
+    }
+
+    /// Like [`from_str()`][CharToken::from_str], without collecting the
+    /// characters
+    pub fn iter_str(input: u32, content: &str) -> impl Iterator<Item = CharToken> + '_ {
         assert!((content.len() as u32) < u32::MAX);
 
-        content
-            .chars()
-            .enumerate()
-            .map(|(i, c)| CharToken {
-                value: c,
-                span: TextSpan {
-                    pos: TextPosition {
-                        input,
-                        absolute: i as u32,
-                    },
-                    len: 1,
+        content.chars().enumerate().map(move |(i, c)| CharToken {
+            value: c,
+            span: TextSpan {
+                pos: TextPosition {
+                    input,
+                    absolute: i as u32,
                 },
-            })
-            .collect()
+                len: 1,
+            },
+        })
     }
 
+    /// Whether this is a character the lexer treats as whitespace
     pub fn is_whitespace(&self) -> bool {
-        [' ', '\n', '\t'].contains(&self.value)
+        [' ', '\n', '\t', '\x0c', '\r', '\x0b'].contains(&self.value)
     }
 
     /// Converts the given list of [`CharTokens`](CharToken) into a string.
@@ -17,7 +17,7 @@ use crate::front::c::tuctx::TUCtx;
 pub struct MacroInvocation {
     pub definition: Rc<MacroDef>,
     pub name: PPToken,
-    pub arguments: HashMap<String, Vec<PPToken>>,
+    pub arguments: HashMap<String, Rc<[PPToken]>>,
 }
 
 #[derive(Clone, Debug)]
@@ -32,7 +32,46 @@ pub enum MacroTokenOrigin<'a> {
     Argument(&'a PPToken),
 }
 
+/// Which part of a macro invocation produced a token
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProvenanceSource {
+    /// The token at `index` of the macro's replacement list
+    Body { index: usize },
+    /// The token at `index` of the argument given for `parameter`
+    Argument { parameter: String, index: usize },
+}
+
+/// One macro expansion in the history of a token
+#[derive(Clone, Debug)]
+pub struct ProvenanceStep<'a> {
+    /// The name of the macro, as it appeared in the invocation
+    pub name: &'a PPToken,
+    pub source: ProvenanceSource,
+}
+
+/// The complete expansion history of a token
+///
+/// See [`TUCtx::resolve_provenance()`][rp].
+///
+/// [rp]: crate::front::c::tuctx::TUCtx::resolve_provenance
+#[derive(Clone, Debug)]
+pub struct ProvenanceChain<'a> {
+    /// Every expansion the token passed through, beginning with the one that
+    /// directly produced it. Empty if the token came straight from the source.
+    pub steps: Vec<ProvenanceStep<'a>>,
+
+    /// The text in the source code ultimately responsible for the token
+    pub span: TextSpan,
+}
+
 impl MacroResult {
+    /// The most tokens the arguments of one invocation may have altogether,
+    /// and likewise its replacement list
+    pub const MAX_INPUT_TOKENS: usize = 0x8000;
+
+    /// The most tokens one invocation may be replaced with
+    pub const MAX_OUTPUT_TOKENS: usize = 0xffff;
+
     pub fn new_param(invocation: u32, in_index: u16) -> MacroResult {
         assert!(in_index < 0x8000, "Macro arguments too long");
         MacroResult {
@@ -52,11 +91,40 @@ impl MacroResult {
         }
     }
 
+    /// Reassemble a result from the parts given by
+    /// [`to_raw()`][MacroResult::to_raw]
+    pub fn from_raw(invocation: u32, in_index: u16, out_index: u16) -> MacroResult {
+        MacroResult {
+            invocation,
+            in_index,
+            out_index,
+        }
+    }
+
+    /// The invocation, input index, and output index making up this result
+    pub fn to_raw(&self) -> (u32, u16, u16) {
+        (self.invocation, self.in_index, self.out_index)
+    }
+
+    /// Record the index of this token in the output of its invocation
+    ///
+    /// This may only be called once, after the replacement of the invocation
+    /// is complete.
     pub fn update_out_index(&mut self, out_index: u16) {
-        debug_assert!(out_index < 0xffff);
+        debug_assert!(out_index < 0xffff, "Macro output too long");
+        debug_assert_eq!(self.out_index, 0xffff, "Macro output index updated twice");
         self.out_index = out_index;
     }
 
+    /// The index of this token in the output of its invocation
+    pub fn out_index(&self) -> Option<usize> {
+        if self.out_index == 0xffff {
+            None
+        } else {
+            Some(self.out_index as usize)
+        }
+    }
+
     pub fn textspan(&self) -> &TextSpan {
         todo!()
     }
@@ -93,24 +161,38 @@ impl MacroResult {
         &tuctx.macro_invocations[self.invocation as usize]
     }
 
-    pub fn origin<'a>(&self, tuctx: &'a TUCtx) -> MacroTokenOrigin<'a> {
+    /// Find the parameter whose argument this token came from
+    ///
+    /// Returns the name of the parameter and the index within its argument.
+    pub fn parameter<'a>(&self, tuctx: &'a TUCtx) -> Option<(&'a str, usize)> {
+        let mut arg_index = self.arg_index()?;
         let invocation = self.invocation(tuctx);
-        if let Some(mut param_index) = self.arg_index() {
-            debug_assert!(matches!(*invocation.definition, MacroDef::Function(..)));
-            let function = invocation.definition.as_function();
-            for param_name in &function.params {
-                let param = &invocation.arguments[param_name];
-                if param.len() >= param_index {
-                    param_index -= param.len();
-                } else {
-                    return MacroTokenOrigin::Argument(&param[param_index]);
-                }
+        debug_assert!(matches!(*invocation.definition, MacroDef::Function(..)));
+        let function = invocation.definition.as_function();
+
+        // arguments are numbered consecutively in order of the parameters
+        let vararg = if function.vararg {
+            Some("__VA_ARGS__")
+        } else {
+            None
+        };
+        for param_name in function.params.iter().map(|p| p.as_str()).chain(vararg) {
+            let (param_name, param) = invocation.arguments.get_key_value(param_name).unwrap();
+            if arg_index >= param.len() {
+                arg_index -= param.len();
+            } else {
+                return Some((param_name, arg_index));
             }
-            unreachable!();
+        }
+        unreachable!();
+    }
+
+    pub fn origin<'a>(&self, tuctx: &'a TUCtx) -> MacroTokenOrigin<'a> {
+        let invocation = self.invocation(tuctx);
+        if let Some((param_name, index)) = self.parameter(tuctx) {
+            MacroTokenOrigin::Argument(&invocation.arguments[param_name][index])
         } else if let Some(body_index) = self.body_index() {
-            return MacroTokenOrigin::Body(
-                &invocation.definition.replacement()[body_index as usize],
-            );
+            MacroTokenOrigin::Body(&invocation.definition.replacement()[body_index as usize])
         } else {
             unreachable!();
         }
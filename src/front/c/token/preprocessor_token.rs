@@ -5,16 +5,26 @@
 
 //! Tokens encompassing strings of text used during preprocessing
 
+use std::rc::Rc;
+
 use crate::front::c::token::TokenOrigin;
 
 /// The different kinds of [`PPToken`]
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum PPTokenKind {
     EndOfFile,
 
     Whitespace,
+    /// A comment, kept only if [`keep_comments`][kc] is set
+    ///
+    /// Everywhere else, this is treated as whitespace.
+    ///
+    /// [kc]: crate::core::Flags::keep_comments
+    Comment,
     Identifier,
     IdentifierNonExpandable,
+    /// `<file>` or `"file"`, only lexed where a directive expects one
+    HeaderName,
     PPNumber,
     CharacterConstant,
     StringLiteral,
@@ -28,7 +38,9 @@ impl PPTokenKind {
         match self {
             EndOfFile => "end-of-file",
             Whitespace => "whitespace",
+            Comment => "comment",
             Identifier | IdentifierNonExpandable => "identifier",
+            HeaderName => "header-name",
             PPNumber => "number",
             CharacterConstant => "character-constant",
             StringLiteral => "string-literal",
@@ -44,7 +56,7 @@ impl PPTokenKind {
 #[derive(Clone, Debug)]
 pub struct PPToken {
     pub kind: PPTokenKind,
-    pub value: String,
+    pub value: Rc<str>,
     pub origin: TokenOrigin,
 }
 
@@ -58,7 +70,7 @@ impl PPToken {
     }
 
     pub fn is_whitespace(&self) -> bool {
-        self.kind == PPTokenKind::Whitespace
+        self.kind == PPTokenKind::Whitespace || self.kind == PPTokenKind::Comment
     }
 
     pub fn is_whitespace_not_newline(&self) -> bool {
@@ -68,6 +80,39 @@ impl PPToken {
     pub fn is_newline(&self) -> bool {
         self.is_whitespace() && self.as_str() == "\n"
     }
+
+    /// The primary spelling of this token, with digraphs replaced
+    ///
+    /// For example, `<:` is spelled `[` and `%:%:` is spelled `##`. Other
+    /// tokens are spelled as written.
+    pub fn spelling_normalized(&self) -> &str {
+        if self.kind != PPTokenKind::Punctuator {
+            return self.as_str();
+        }
+        match self.as_str() {
+            "<:" => "[",
+            ":>" => "]",
+            "<%" => "{",
+            "%>" => "}",
+            "%:" => "#",
+            "%:%:" => "##",
+            spelling => spelling,
+        }
+    }
+
+    /// Compare two tokens as the same token regardless of how they are
+    /// spelled, so `<:` equals `[`
+    ///
+    /// The [`PartialEq`] implementation compares exact spellings instead, which
+    /// matters when the text must be reproduced.
+    pub fn eq_normalized(&self, other: &PPToken) -> bool {
+        match (self.kind, other.kind) {
+            (PPTokenKind::Punctuator, PPTokenKind::Punctuator) => {
+                self.spelling_normalized() == other.spelling_normalized()
+            },
+            _ => self == other,
+        }
+    }
 }
 
 // Static methods
@@ -173,6 +218,7 @@ impl std::cmp::PartialEq for PPToken {
         match (self.kind, rhs.kind) {
             (EndOfFile, EndOfFile) => self.value == rhs.value,
             (Whitespace, Whitespace) => self.value == rhs.value,
+            (Comment, Comment) => self.value == rhs.value,
             (Identifier, Identifier) => self.value == rhs.value,
             (IdentifierNonExpandable, IdentifierNonExpandable) => self.value == rhs.value,
 
@@ -180,6 +226,7 @@ impl std::cmp::PartialEq for PPToken {
             (IdentifierNonExpandable, Identifier) => self.value == rhs.value,
             (Identifier, IdentifierNonExpandable) => self.value == rhs.value,
 
+            (HeaderName, HeaderName) => self.value == rhs.value,
             (PPNumber, PPNumber) => self.value == rhs.value,
             (CharacterConstant, CharacterConstant) => self.value == rhs.value,
             (StringLiteral, StringLiteral) => self.value == rhs.value,
@@ -189,3 +236,43 @@ impl std::cmp::PartialEq for PPToken {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::front::c::token::{TextPosition, TextSpan};
+
+    fn punctuator(value: &str) -> PPToken {
+        PPToken {
+            kind: PPTokenKind::Punctuator,
+            value: value.into(),
+            origin: TokenOrigin::Source(TextSpan {
+                pos: TextPosition {
+                    input: 0,
+                    absolute: 0,
+                },
+                len: value.len() as u32,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_spelling_normalized() {
+        let digraph = punctuator("<:");
+        let bracket = punctuator("[");
+        assert_eq!(digraph.spelling_normalized(), "[");
+        assert_eq!(punctuator("%:%:").spelling_normalized(), "##");
+        assert_eq!(punctuator("+").spelling_normalized(), "+");
+
+        assert!(digraph.eq_normalized(&bracket));
+        assert!(bracket.eq_normalized(&digraph));
+        assert!(!digraph.eq_normalized(&punctuator("]")));
+        assert_ne!(digraph, bracket);
+
+        // only punctuators have digraph spellings
+        let mut other = punctuator("<:");
+        other.kind = PPTokenKind::Other;
+        assert_eq!(other.spelling_normalized(), "<:");
+        assert!(!other.eq_normalized(&bracket));
+    }
+}
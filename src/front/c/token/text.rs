@@ -7,6 +7,9 @@
 
 use std::rc::Rc;
 
+use unicode_width::UnicodeWidthChar;
+
+use crate::core::ColumnConvention;
 use crate::front::c::input::Input;
 use crate::front::c::tuctx::TUCtx;
 
@@ -38,6 +41,27 @@ impl TextPositionResolved<&str> {
     }
 }
 
+/// Convert a column counted in characters into the given convention
+///
+/// `line` is the text of the physical line containing the column.
+fn convert_column(line: &str, column: u32, convention: ColumnConvention, tab_stop: u32) -> u32 {
+    let before = line.chars().take(column as usize - 1);
+    match convention {
+        ColumnConvention::Codepoint => column,
+        ColumnConvention::Byte => before.map(char::len_utf8).sum::<usize>() as u32 + 1,
+        ColumnConvention::Display => {
+            let mut width = 0;
+            for c in before {
+                width += match c {
+                    '\t' => tab_stop - width % tab_stop,
+                    _ => c.width().unwrap_or(0) as u32,
+                };
+            }
+            width + 1
+        },
+    }
+}
+
 /// An exact position in the source code
 #[derive(Clone, Copy, Debug)]
 pub struct TextPosition {
@@ -50,11 +74,45 @@ impl TextPosition {
         &tuctx.inputs[self.input as usize]
     }
 
-    pub fn resolve<'a>(&self, tuctx: &'a TUCtx) -> TextPositionResolved<&'a str> {
+    /// Find the text of the physical line containing this position
+    ///
+    /// Also returns the column of this position within that line. Unlike
+    /// [`resolve()`][TextPosition::resolve], this ignores `#line` directives.
+    pub fn source_line<'a>(&self, tuctx: &'a TUCtx) -> (&'a str, u32) {
         let input = self.input(tuctx);
         let (line, column) = input.get_line_column(self.absolute);
+        (input.get_line_text(line), column)
+    }
+
+    /// Find the line, column, and name of the input
+    ///
+    /// This respects any `#line` directives preceding this position. Columns
+    /// are counted as selected by `--column`.
+    pub fn resolve<'a>(&self, tuctx: &'a TUCtx) -> TextPositionResolved<&'a str> {
+        let input = self.input(tuctx);
+        let (mut line, mut column) = input.get_line_column(self.absolute);
+        let flags = tuctx.tu.session.flags();
+        if flags.column != ColumnConvention::Codepoint {
+            let text = input.get_line_text(line);
+            column = convert_column(text, column, flags.column, flags.tab_stop);
+        }
+        let mut name = input.name.as_str();
+
+        let line_override = tuctx
+            .line_overrides
+            .iter()
+            .rev()
+            .find(|o| o.input == self.input && o.after <= self.absolute);
+        if let Some(line_override) = line_override {
+            let (first_line, _) = input.get_line_column(line_override.after);
+            line = line_override.line + (line - first_line);
+            if let Some(override_name) = &line_override.name {
+                name = override_name;
+            }
+        }
+
         TextPositionResolved {
-            input: &input.name,
+            input: name,
             line,
             column,
         }
@@ -89,9 +147,10 @@ impl TextSpan {
     }
 
     pub fn text<'a>(&self, tuctx: &'a TUCtx) -> &'a str {
-        let beg = self.pos.absolute as usize;
-        let end = beg + (self.len as usize);
-        &self.pos.input(tuctx).content[beg..end]
+        let input = self.pos.input(tuctx);
+        let beg = input.byte_offset(self.pos.absolute);
+        let end = input.byte_offset(self.pos.absolute + self.len);
+        &input.content[beg..end]
     }
 
     pub fn begin(&self) -> TextPosition {
@@ -103,4 +162,94 @@ impl TextSpan {
         pos.absolute += self.len;
         pos
     }
+
+    /// The smallest span covering both spans, and anything between them
+    ///
+    /// Both spans must be within the same input.
+    pub fn merge(&self, other: &TextSpan) -> TextSpan {
+        assert_eq!(
+            self.pos.input, other.pos.input,
+            "cannot merge spans of different inputs"
+        );
+        let begin = std::cmp::min(self.pos.absolute, other.pos.absolute);
+        let end = std::cmp::max(self.end().absolute, other.end().absolute);
+        TextSpan {
+            pos: TextPosition {
+                input: self.pos.input,
+                absolute: begin,
+            },
+            len: end - begin,
+        }
+    }
+
+    /// The smallest span covering every span given
+    ///
+    /// Returns `None` if there are no spans.
+    pub fn enclosing<'a>(spans: impl IntoIterator<Item = &'a TextSpan>) -> Option<TextSpan> {
+        let mut iter = spans.into_iter();
+        let first = *iter.next()?;
+        Some(iter.fold(first, |span, next| span.merge(next)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn span(absolute: u32, len: u32) -> TextSpan {
+        TextSpan {
+            pos: TextPosition { input: 0, absolute },
+            len,
+        }
+    }
+
+    fn bounds(span: TextSpan) -> (u32, u32) {
+        (span.begin().absolute, span.end().absolute)
+    }
+
+    #[test]
+    fn test_convert_column() {
+        use ColumnConvention::*;
+
+        let line = "aé日\tb";
+        let columns = |convention, tab_stop| {
+            (1..=6)
+                .map(|c| convert_column(line, c, convention, tab_stop))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(columns(Codepoint, 8), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(columns(Byte, 8), [1, 2, 4, 7, 8, 9]);
+        assert_eq!(columns(Display, 8), [1, 2, 3, 5, 9, 10]);
+        assert_eq!(columns(Display, 5), [1, 2, 3, 5, 6, 7]);
+        assert_eq!(columns(Display, 3), [1, 2, 3, 5, 7, 8]);
+    }
+
+    #[test]
+    fn test_textspan_merge() {
+        // overlapping, in either order
+        assert_eq!(bounds(span(2, 4).merge(&span(4, 5))), (2, 9));
+        assert_eq!(bounds(span(4, 5).merge(&span(2, 4))), (2, 9));
+        // one within the other
+        assert_eq!(bounds(span(2, 8).merge(&span(4, 1))), (2, 10));
+        // disjoint, covering the gap between
+        assert_eq!(bounds(span(0, 1).merge(&span(7, 2))), (0, 9));
+        // empty spans
+        assert_eq!(bounds(span(3, 0).merge(&span(3, 0))), (3, 3));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_textspan_merge_different_inputs() {
+        let mut other = span(0, 1);
+        other.pos.input = 1;
+        span(0, 1).merge(&other);
+    }
+
+    #[test]
+    fn test_textspan_enclosing() {
+        assert!(TextSpan::enclosing(&[]).is_none());
+        assert_eq!(bounds(TextSpan::enclosing(&[span(5, 1)]).unwrap()), (5, 6));
+        let spans = [span(5, 1), span(1, 2), span(8, 0)];
+        assert_eq!(bounds(TextSpan::enclosing(&spans).unwrap()), (1, 8));
+    }
 }
@@ -8,10 +8,12 @@
 use std::collections::HashMap;
 use std::path::Path;
 use std::rc::Rc;
+use std::time::Duration;
 
-use crate::core::{ErrorKind, Result};
+use crate::core::{ErrorKind, Result, Severity};
+use crate::front::c::include_cache::IncludeCache;
 use crate::front::c::input::Input;
-use crate::front::c::message::Message;
+use crate::front::c::message::{Message, MessageKind};
 use crate::front::c::tuctx::{TUCtx, TUState};
 use crate::session::Session;
 
@@ -23,6 +25,8 @@ pub struct TranslationUnit {
     pub(super) messages: Vec<Message>,
     pub(super) saved_states: HashMap<String, Vec<TUState>>,
     pub(super) success: bool,
+    pub(super) include_cache: Rc<IncludeCache>,
+    pub(super) pass_timings: Vec<(String, Duration)>,
 }
 
 impl TranslationUnit {
@@ -31,6 +35,7 @@ impl TranslationUnit {
         TranslationUnitBuilder {
             session: Rc::clone(session),
             input: None,
+            include_cache: None,
         }
     }
 
@@ -39,33 +44,135 @@ impl TranslationUnit {
         &self.input
     }
 
+    /// The lexed included files this unit keeps for units translated later
+    ///
+    /// See [`TranslationUnitBuilder::include_cache()`].
+    pub fn include_cache(&self) -> &Rc<IncludeCache> {
+        &self.include_cache
+    }
+
     /// Messages generated during processing
     pub fn messages(&self) -> &[Message] {
         &self.messages
     }
 
+    /// Messages ordered by where they occur, for reading top to bottom
+    ///
+    /// Messages are ordered by input, in the order the inputs were first
+    /// read, and then by position within it. Messages at the same position
+    /// keep the order they were generated in, as does the note that there were
+    /// too many errors, which always comes last.
+    pub fn messages_sorted(&self) -> Vec<&Message> {
+        let mut messages: Vec<&Message> = self.messages.iter().collect();
+        messages.sort_by_key(|m| {
+            let position = m.extra.as_ref().map(|e| (e.root.input, e.root.absolute));
+            (matches!(m.kind, MessageKind::TooManyErrors), position)
+        });
+        messages
+    }
+
     /// States saved by the [`state_save`][ss] pass
     ///
+    /// States saved under the same name are kept in the order the passes
+    /// saving them ran.
+    ///
     /// [ss]: crate::passes::internal::StateSave
     pub fn saved_states(&self, name: &str) -> &[TUState] {
         &self.saved_states[name]
     }
 
+    /// The state most recently saved under this name, if any
+    pub fn saved_state_last(&self, name: &str) -> Option<&TUState> {
+        self.saved_states.get(name).and_then(|states| states.last())
+    }
+
+    /// Every name states were saved under, with how many were saved
+    ///
+    /// Sorted by name.
+    pub fn saved_state_counts(&self) -> Vec<(&str, usize)> {
+        let mut counts: Vec<_> = self
+            .saved_states
+            .iter()
+            .map(|(name, states)| (name.as_str(), states.len()))
+            .collect();
+        counts.sort();
+        counts
+    }
+
+    /// How long each pass took, in the order they ran
+    ///
+    /// Passes are only timed with `--time-passes`.
+    pub fn pass_timings(&self) -> &[(String, Duration)] {
+        &self.pass_timings
+    }
+
+    /// A table of [`pass_timings()`][TranslationUnit::pass_timings] with the
+    /// share of the total each pass took
+    pub fn timing_report(&self) -> String {
+        let total: Duration = self.pass_timings.iter().map(|(_, time)| *time).sum();
+        let width = self
+            .pass_timings
+            .iter()
+            .map(|(pass, _)| pass.len())
+            .max()
+            .unwrap_or(0)
+            .max("total".len());
+
+        let mut report = format!("pass timings for {}:\n", self.input.name);
+        for (pass, time) in &self.pass_timings {
+            let share = if total.is_zero() {
+                0.0
+            } else {
+                100.0 * time.as_secs_f64() / total.as_secs_f64()
+            };
+            report.push_str(&format!(
+                "  {:width$}  {:>10.3} ms  {:5.1}%\n",
+                pass,
+                time.as_secs_f64() * 1000.0,
+                share,
+                width = width
+            ));
+        }
+        report.push_str(&format!(
+            "  {:width$}  {:>10.3} ms\n",
+            "total",
+            total.as_secs_f64() * 1000.0,
+            width = width
+        ));
+        report
+    }
+
     /// Whether translation succeeded
+    ///
+    /// This is `false` until the unit has been [run][TranslationUnit::run].
     pub fn success(&self) -> bool {
         self.success
     }
 
-    pub fn run(&mut self) -> Result<()> {
+    /// Whether any message is an error
+    pub fn had_errors(&self) -> bool {
+        self.messages.iter().any(|m| {
+            let severity = m.kind.severity();
+            severity == Severity::Fatal || severity == Severity::Error
+        })
+    }
+
+    /// Translate this unit
+    ///
+    /// Returns whether translation succeeded. Errors in the source are
+    /// reported through [`messages()`][TranslationUnit::messages]; only
+    /// failures of the compiler itself are returned as `Err`.
+    pub fn run(&mut self) -> Result<bool> {
         let mut ctx = TUCtx::from_tu(self);
         self.success = ctx.run()?;
-        Ok(())
+        Ok(self.success)
     }
 }
 
 pub struct TranslationUnitBuilder {
     session: Rc<Session>,
     input: Option<Rc<Input>>,
+    include_cache: Option<Rc<IncludeCache>>,
 }
 
 impl TranslationUnitBuilder {
@@ -77,9 +184,20 @@ impl TranslationUnitBuilder {
             saved_states: HashMap::new(),
 
             success: false,
+            include_cache: self.include_cache.unwrap_or_default(),
+            pass_timings: Vec::new(),
         }
     }
 
+    /// Reuse the included files lexed by an earlier unit
+    ///
+    /// Typically this is the [cache][TranslationUnit::include_cache] of the
+    /// previous translation of the same source, as it was before an edit.
+    pub fn include_cache(mut self, cache: &Rc<IncludeCache>) -> Self {
+        self.include_cache = Some(Rc::clone(cache));
+        self
+    }
+
     fn assert_no_input(&self) {
         assert!(
             self.input.is_none(),
@@ -91,15 +209,44 @@ impl TranslationUnitBuilder {
         self.assert_no_input();
 
         let name = path.to_string_lossy().into_owned();
-        let content = std::fs::read_to_string(path).map_err(|e| ErrorKind::InputFileError {
-            filename: name.to_owned(),
-            error: e,
-        })?;
-
         // make sure path we store is rooted
         let mut pathbuf = std::env::current_dir().unwrap();
         pathbuf.push(path);
-        let input = Input::new(name.clone(), content, Some(pathbuf));
+        let display_name = self.session.display_name(&name, &pathbuf);
+
+        let input = std::fs::File::open(path)
+            .and_then(|file| Input::from_reader(display_name, file, Some(pathbuf)))
+            .map_err(|e| ErrorKind::InputFileError {
+                filename: name,
+                error: e,
+            })?;
+        self.input = Some(Rc::new(input));
+
+        Ok(self)
+    }
+
+    /// Read the source from a reader, such as stdin
+    ///
+    /// As with [`source_string()`][TranslationUnitBuilder::source_string],
+    /// `alias` must be wrapped in angle brackets.
+    pub fn source_reader(
+        mut self,
+        alias: impl Into<String>,
+        reader: impl std::io::Read,
+    ) -> Result<Self> {
+        let alias = alias.into();
+        assert!(
+            alias.starts_with("<") && alias.ends_with(">"),
+            "alias must be enclosed in <> brackets"
+        );
+        self.assert_no_input();
+
+        let input = Input::from_reader(alias.clone(), reader, None).map_err(|e| {
+            ErrorKind::InputFileError {
+                filename: alias,
+                error: e,
+            }
+        })?;
         self.input = Some(Rc::new(input));
 
         Ok(self)
@@ -117,3 +264,207 @@ impl TranslationUnitBuilder {
         self
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{session, unit, unit_in};
+
+    #[test]
+    fn test_saved_states_order() {
+        let mut tu = unit(
+            &[
+                "--pass=phase1",
+                "--pass=state_save(state)",
+                "--pass=phase2..phase3",
+                "--pass=state_save(state)",
+                "--pass=state_save(other)",
+            ],
+            "a\\\nb",
+        );
+        tu.run().unwrap();
+
+        assert_eq!(
+            tu.saved_state_counts(),
+            [("<final>", 1), ("other", 1), ("state", 2)]
+        );
+        let states = tu.saved_states("state");
+        assert_eq!(states[0].kind(), "CharTokens");
+        assert_eq!(states[1].kind(), "PPTokens");
+        assert_eq!(states[0].as_chartokens().unwrap().len(), 4);
+        assert_eq!(states[1].as_pptokens().unwrap().len(), 1);
+
+        let last = tu.saved_state_last("state").unwrap();
+        assert_eq!(last.kind(), "PPTokens");
+        assert!(tu.saved_state_last("missing").is_none());
+    }
+
+    #[test]
+    fn test_saved_state_formats() {
+        let mut tu = unit(
+            &[
+                "--pass=phase1..phase3",
+                "--pass=state_save(out)",
+                "--pass=state_save(out, json)",
+                "--pass=state_save(out, text)",
+            ],
+            "a \"b\"",
+        );
+        tu.run().unwrap();
+
+        let states = tu.saved_states("out");
+        assert_eq!(states.len(), 3);
+        assert_eq!(states[0].as_pptokens().unwrap().len(), 3);
+        assert_eq!(
+            states[1].as_text().unwrap(),
+            r#"{"kind": "PPTokens", "tokens": [{"kind": "ident", "source": [0, 0, 1], "value": "a"}, {"kind": "ws", "source": [0, 1, 1], "value": " "}, {"kind": "string", "source": [0, 2, 3], "value": "\"b\""}]}"#
+        );
+        assert_eq!(states[2].as_text().unwrap(), "a \"b\"");
+
+        let error = Session::builder()
+            .parse_cli_args_from_str(&["--pass=state_save(out, xml)"])
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "invalid argument for --pass flag: pass `state_save` cannot parse \"xml\" as `tokens`, `text`, or `json` for argument 1"
+        );
+    }
+
+    #[test]
+    fn test_embedded_nul_string() {
+        use crate::front::c::realize::{parse_string_constant, StringData};
+
+        let session = session(&["--pass=phase1..phase6", "--pass=state_save(out)"]).build();
+        let mut tu = unit_in(&session, r#""a\0b" "\0" "\0""#);
+        tu.run().unwrap();
+        assert!(tu.messages().is_empty());
+
+        let tokens = tu.saved_states("out")[0].as_pptokens().unwrap();
+        assert_eq!(tokens.len(), 2); // with end of file
+        assert_eq!(tokens[0].as_str(), "\"a\0b\0\0\"");
+
+        let data = parse_string_constant(&session.flags().target, &tokens[0].value).unwrap();
+        assert_eq!(data, StringData::Bytes(vec![b'a', 0, b'b', 0, 0]));
+
+        let mut tu = unit_in(&session, r#""a\0b""#);
+        tu.run().unwrap();
+        let tokens = tu.saved_states("out")[0].as_pptokens().unwrap();
+        let data = parse_string_constant(&session.flags().target, &tokens[0].value).unwrap();
+        // the terminator is implicit
+        assert_eq!(data.len(), 3);
+    }
+
+    #[test]
+    fn test_include_cache() {
+        fn session_with_header(header: &str) -> Rc<Session> {
+            session(&["--pass=phase1..phase4", "--pass=state_save(out)"])
+                .add_extra_file("a.h".to_owned(), header.to_owned())
+                .build()
+        }
+        fn output(tu: &TranslationUnit) -> String {
+            tu.saved_states("out")[0].to_string().trim().to_owned()
+        }
+
+        let session = session_with_header("#define A a\n");
+        let mut first = unit_in(&session, "#include \"a.h\"\nA 1\n");
+        first.run().unwrap();
+        assert_eq!(output(&first), "a 1");
+        let cache = first.include_cache();
+        assert_eq!((cache.hits(), cache.misses()), (0, 1));
+
+        // the source changed but the header did not
+        let mut second = TranslationUnit::builder(&session)
+            .source_string("<unit-test>", "#define B b\n#include \"a.h\"\nA B 2\n")
+            .include_cache(cache)
+            .build();
+        second.run().unwrap();
+        assert_eq!(output(&second), "a b 2");
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+        // the header changed
+        let session = session_with_header("#define A z\n");
+        let mut third = TranslationUnit::builder(&session)
+            .source_string("<unit-test>", "#include \"a.h\"\nA 3\n")
+            .include_cache(cache)
+            .build();
+        third.run().unwrap();
+        assert_eq!(output(&third), "z 3");
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+    }
+
+    #[test]
+    fn test_time_passes() {
+        fn run(time_passes: bool) -> TranslationUnit {
+            let mut args = vec!["--pass=phase1..phase2"];
+            if time_passes {
+                args.push("--time-passes");
+            }
+            let mut tu = unit(&args, "a\n");
+            tu.run().unwrap();
+            tu
+        }
+
+        assert!(run(false).pass_timings().is_empty());
+
+        let tu = run(true);
+        let passes: Vec<_> = tu.pass_timings().iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(passes, ["StateReadInput", "Phase1", "Phase2"]);
+
+        let report = tu.timing_report();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "pass timings for <unit-test>:");
+        for (line, pass) in lines[1..]
+            .iter()
+            .zip(&["StateReadInput", "Phase1", "Phase2", "total"])
+        {
+            assert!(line.trim_start().starts_with(pass));
+            assert!(line.contains(" ms"));
+        }
+    }
+
+    #[test]
+    fn test_messages_sorted() {
+        // directives within a group are processed after those following it
+        let mut tu = unit(
+            &["--pass=phase1..phase4"],
+            "#if 1\n#undef 1\n#endif\n#undef 2\n",
+        );
+        tu.run().unwrap();
+
+        let positions = |messages: Vec<&Message>| -> Vec<String> {
+            messages
+                .iter()
+                .map(|m| m.extra.as_ref().unwrap().position.to_string())
+                .collect()
+        };
+        assert_eq!(
+            positions(tu.messages().iter().collect()),
+            ["<unit-test>:4:8", "<unit-test>:2:8"]
+        );
+        assert_eq!(
+            positions(tu.messages_sorted()),
+            ["<unit-test>:2:8", "<unit-test>:4:8"]
+        );
+    }
+
+    #[test]
+    fn test_had_errors() {
+        let session = Session::builder()
+            .parse_cli_args_from_str(&[] as &[&str])
+            .unwrap()
+            .build();
+        let mut tu = unit_in(&session, "#include \"nonexistent.h\"\n");
+        assert!(!tu.run().unwrap());
+        assert!(tu.had_errors());
+        assert!(!tu.success());
+        assert_eq!(tu.messages().len(), 1);
+
+        let mut tu = unit_in(&session, "#define FOO\n#undef FOO FOO\nint x;\n");
+        assert!(tu.run().unwrap());
+        assert!(!tu.had_errors());
+        assert!(tu.success());
+        assert_eq!(tu.messages().len(), 1);
+    }
+}
@@ -5,15 +5,26 @@
 
 //! Translation Unit Context
 
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::Instant;
 
 use log::{debug, info};
 
 use crate::core::{ErrorKind, Result, Severity};
-use crate::front::c::input::{IncludedFrom, Input};
+use crate::front::c::include_cache::set_origin_input;
+use crate::front::c::input::{IncludedFrom, Input, LineOverride};
 use crate::front::c::message::{Message, MessageKind};
-use crate::front::c::token::{CharToken, MacroInvocation, PPToken, TokenOrigin};
+use crate::front::c::preprocessor::{self, MacroDef};
+use crate::front::c::state_file;
+use crate::front::c::token::{
+    CharToken, MacroInvocation, PPToken, PPTokenKind, ProvenanceChain, ProvenanceSource,
+    ProvenanceStep, TextSpan, TokenOrigin,
+};
 use crate::front::c::tu::TranslationUnit;
+use crate::front::c::verify;
+use crate::session::{read_include, IncludeError, Session};
 
 /// Translation Unit State
 ///
@@ -25,6 +36,35 @@ use crate::front::c::tu::TranslationUnit;
 pub enum TUState {
     CharTokens(Vec<CharToken>),
     PPTokens(Vec<PPToken>),
+    /// [`PPTokens`][TUState::PPTokens] grouped into lines, each ending with a
+    /// newline token
+    Lines(Vec<Vec<PPToken>>),
+    /// Source text reconstructed from tokens
+    Text(String),
+}
+
+/// How [`TUCtx::save_state_as()`] materializes the state it saves
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveFormat {
+    /// The state itself, as kept in memory
+    Tokens,
+    /// The state printed as text, saved as a [`TUState::Text`]
+    Text,
+    /// The state as [JSON][state_file::to_json], saved as a [`TUState::Text`]
+    Json,
+}
+
+impl std::str::FromStr for SaveFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<SaveFormat, ()> {
+        match value {
+            "tokens" => Ok(SaveFormat::Tokens),
+            "text" => Ok(SaveFormat::Text),
+            "json" => Ok(SaveFormat::Json),
+            _ => Err(()),
+        }
+    }
 }
 
 macro_rules! into_methods {
@@ -57,12 +97,16 @@ impl TUState {
         match self {
             CharTokens(..) => "CharTokens",
             PPTokens(..) => "PPTokens",
+            Lines(..) => "Lines",
+            Text(..) => "Text",
         }
     }
 
     into_methods! {
         (into_chartokens, as_chartokens, CharTokens, Vec<CharToken>),
-        (into_pptokens, as_pptokens, PPTokens, Vec<PPToken>)
+        (into_pptokens, as_pptokens, PPTokens, Vec<PPToken>),
+        (into_lines, as_lines, Lines, Vec<Vec<PPToken>>),
+        (into_text, as_text, Text, String)
     }
 }
 
@@ -72,10 +116,69 @@ impl std::fmt::Display for TUState {
         match self {
             CharTokens(tokens) => write!(f, "{}", CharToken::to_string(tokens)),
             PPTokens(tokens) => write!(f, "{}", PPToken::to_string(tokens)),
+            Lines(lines) => {
+                for line in lines {
+                    write!(f, "{}", PPToken::to_string(line))?;
+                }
+                Ok(())
+            },
+            Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// Statistics gathered by the [`count_tokens`][ct] pass
+///
+/// [ct]: crate::passes::internal::CountTokens
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TokenStats {
+    /// Number of tokens of each kind in the state
+    pub kinds: BTreeMap<PPTokenKind, usize>,
+    /// Number of macro invocations replaced, including those within `#if`
+    /// and `#include` directives
+    pub macro_expansions: usize,
+    /// Number of times a file was included
+    pub includes: usize,
+}
+
+impl std::fmt::Display for TokenStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (kind, count) in &self.kinds {
+            writeln!(f, "{:?} tokens: {}", kind, count)?;
         }
+        writeln!(f, "macro expansions: {}", self.macro_expansions)?;
+        writeln!(f, "includes: {}", self.includes)
     }
 }
 
+/// A `#pragma GCC diagnostic` directive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiagnosticPragma {
+    /// Save the set of suppressed warnings
+    Push,
+    /// Restore the set of suppressed warnings saved by the last push
+    Pop,
+    /// Suppress these warnings until the set is next popped
+    Ignored(&'static [&'static str]),
+}
+
+/// Whether two inputs were read from the same file
+fn same_file(a: &Input, b: &Input) -> bool {
+    match (&a.path, &b.path) {
+        (Some(a), Some(b)) => a == b,
+        (None, None) => a.name == b.name,
+        _ => false,
+    }
+}
+
+/// A file read for an `#include`, kept for later inclusions of it
+#[derive(Debug)]
+struct IncludedFile {
+    content: String,
+    /// The result of phases 1 through 3, once lexed without any message
+    lines: Option<Vec<Vec<PPToken>>>,
+}
+
 /// Intermediate data kept while processing this translation unit
 #[derive(Debug)]
 pub struct TUCtx<'a> {
@@ -83,8 +186,36 @@ pub struct TUCtx<'a> {
     pub(super) inputs: Vec<Rc<Input>>,
     pub(super) state: Option<TUState>,
     pub(super) macro_invocations: Vec<MacroInvocation>,
+    /// Number of macro invocations the preprocessor has replaced
+    pub(super) macro_expansions: usize,
+    /// Set by the [`count_tokens`][ct] pass
+    ///
+    /// [ct]: crate::passes::internal::CountTokens
+    token_stats: Option<TokenStats>,
+    pub(super) line_overrides: Vec<LineOverride>,
+    pub(super) defines: HashMap<String, Rc<MacroDef>>,
+    /// Answers of each GNU assertion predicate, in the order asserted
+    pub(super) assertions: HashMap<String, Vec<String>>,
+    /// Strings of `#ident` and `#sccs` directives, in order
+    pub(super) idents: Vec<String>,
+    /// `#pragma GCC diagnostic` directives in the order they were processed,
+    /// each with the position it takes effect from
+    ///
+    /// See [`translation_order()`][TUCtx::translation_order].
+    diagnostic_pragmas: Vec<(Vec<u32>, DiagnosticPragma)>,
+    /// Number of errors emitted, counted against `--max-errors`
+    error_count: usize,
+    /// Files already included, by canonical path
+    ///
+    /// A header without guards may be included many times, but is read from
+    /// disk and lexed only once.
+    included_files: HashMap<PathBuf, IncludedFile>,
+    /// Files containing `#pragma once`, which are not included again
+    once_inputs: Vec<Rc<Input>>,
+    /// Reads included files that are not yet in `included_files`
+    pub(super) read_file: fn(&Path) -> std::result::Result<String, IncludeError>,
 
-    fatal_error: bool,
+    pub(super) fatal_error: bool,
 }
 
 impl<'a> TUCtx<'a> {
@@ -97,6 +228,17 @@ impl<'a> TUCtx<'a> {
             inputs,
             state: None,
             macro_invocations: Vec::new(),
+            macro_expansions: 0,
+            token_stats: None,
+            line_overrides: Vec::new(),
+            defines: HashMap::new(),
+            assertions: HashMap::new(),
+            idents: Vec::new(),
+            diagnostic_pragmas: Vec::new(),
+            error_count: 0,
+            included_files: HashMap::new(),
+            once_inputs: Vec::new(),
+            read_file: read_include,
 
             fatal_error: false,
         }
@@ -107,13 +249,35 @@ impl<'a> TUCtx<'a> {
         &self.inputs[0]
     }
 
+    /// The session this unit is translated under
+    pub fn session(&self) -> &Session {
+        &self.tu.session
+    }
+
     /// Saves the current state, associating it with the given name
     ///
+    /// Saving under a name used before appends to the states already saved
+    /// under it, rather than replacing them.
+    ///
     /// Implicitly used in the [`state_save`][ss] pass.
     ///
     /// [ss]: crate::passes::internal::StateSave
     pub fn save_state(&mut self, name: &str) -> Result<()> {
-        let state = self.get_state()?.clone();
+        self.save_state_as(name, SaveFormat::Tokens)
+    }
+
+    /// Saves the current state in the given format, associating it with the
+    /// given name
+    ///
+    /// Formats other than [`SaveFormat::Tokens`] save a [`TUState::Text`], so
+    /// the same state may be saved in several forms, under one name or many.
+    pub fn save_state_as(&mut self, name: &str, format: SaveFormat) -> Result<()> {
+        let state = self.get_state()?;
+        let state = match format {
+            SaveFormat::Tokens => state.clone(),
+            SaveFormat::Text => TUState::Text(state.to_string()),
+            SaveFormat::Json => TUState::Text(state_file::to_json(state)),
+        };
         let entry = self
             .tu
             .saved_states
@@ -123,6 +287,17 @@ impl<'a> TUCtx<'a> {
         Ok(())
     }
 
+    /// Every name states have been saved under so far, with how many were
+    /// saved
+    pub fn saved_state_counts(&self) -> Vec<(&str, usize)> {
+        self.tu.saved_state_counts()
+    }
+
+    /// The state most recently saved under this name, if any
+    pub fn saved_state_last(&self, name: &str) -> Option<&TUState> {
+        self.tu.saved_state_last(name)
+    }
+
     /// Takes the existing primary state out of this object
     pub fn take_state(&mut self) -> Result<TUState> {
         self.state.take().ok_or(ErrorKind::TUStateAbsent.into())
@@ -143,6 +318,124 @@ impl<'a> TUCtx<'a> {
         self.state = Some(state);
     }
 
+    /// Record a `#pragma GCC diagnostic` directive, which applies to messages
+    /// positioned after `at`
+    pub fn add_diagnostic_pragma(&mut self, at: &TextSpan, pragma: DiagnosticPragma) {
+        let order = self.translation_order(at);
+        self.diagnostic_pragmas.push((order, pragma));
+    }
+
+    /// A key sorting positions in the order they are translated
+    ///
+    /// A position in an included file comes after the `#include` line and
+    /// before anything following it. Positions in inputs other than the
+    /// original one and the files it includes, such as the predefined macros,
+    /// come first.
+    fn translation_order(&self, span: &TextSpan) -> Vec<u32> {
+        let mut order = vec![span.pos.absolute];
+        let mut input = span.pos.input(self);
+        while let Some(included_from) = &input.included_from {
+            order.push(included_from.span.pos.absolute);
+            input = &included_from.input;
+        }
+        order.push(input.id);
+        order.push((input.id == 0) as u32);
+        order.reverse();
+        order
+    }
+
+    /// The warnings suppressed at the position of `origin`
+    fn suppressed_warnings(&self, origin: &TokenOrigin) -> HashSet<&'static str> {
+        let order = self.translation_order(origin.macro_root_textspan(self));
+        let mut stack = vec![HashSet::new()];
+        for (_, pragma) in self
+            .diagnostic_pragmas
+            .iter()
+            .take_while(|(at, _)| *at < order)
+        {
+            match pragma {
+                DiagnosticPragma::Push => {
+                    let top = stack.last().unwrap().clone();
+                    stack.push(top);
+                },
+                // a `pop` without a matching `push` does nothing
+                DiagnosticPragma::Pop if stack.len() > 1 => {
+                    stack.pop();
+                },
+                DiagnosticPragma::Pop => {},
+                DiagnosticPragma::Ignored(names) => stack.last_mut().unwrap().extend(*names),
+            }
+        }
+        stack.pop().unwrap()
+    }
+
+    /// Whether this message is a warning that is disabled or suppressed
+    fn is_ignored(&self, message: &Message) -> bool {
+        if message.kind.severity() != Severity::Warning {
+            return false;
+        }
+        match message.kind.warning_name() {
+            Some(name) => {
+                !self.tu.session.flags().warning_enabled(name)
+                    || (!self.diagnostic_pragmas.is_empty()
+                        && self.suppressed_warnings(&message.origin).contains(name))
+            },
+            None => false,
+        }
+    }
+
+    /// Drop the warnings suppressed by `#pragma GCC diagnostic` directives
+    /// processed after the warnings were emitted
+    ///
+    /// Directives are only processed once the lines holding them are reached,
+    /// but some warnings about later lines are emitted before that.
+    pub(super) fn remove_suppressed_warnings(&mut self) {
+        let messages = std::mem::take(&mut self.tu.messages);
+        let messages = messages
+            .into_iter()
+            .filter(|message| !self.is_ignored(message))
+            .collect();
+        self.tu.messages = messages;
+    }
+
+    /// Whether the `--max-errors` limit has been reached
+    ///
+    /// Passes should check this between tokens and stop early once it is set,
+    /// since any further messages are discarded.
+    pub fn should_abort(&self) -> bool {
+        match self.tu.session.flags().max_errors {
+            Some(limit) => self.error_count >= limit,
+            None => false,
+        }
+    }
+
+    fn push_message(&mut self, message: Message) {
+        if self.is_ignored(&message) || self.should_abort() {
+            return;
+        }
+
+        let severity = message.kind.severity();
+        if severity == Severity::Fatal {
+            self.fatal_error = true;
+        }
+        let origin = message.origin.clone();
+        self.tu.messages.push(message);
+
+        if severity == Severity::Fatal || severity == Severity::Error {
+            self.error_count += 1;
+            if self.should_abort() {
+                info!("TUCtx::push_message() reached error limit");
+                self.fatal_error = true;
+                self.tu.messages.push(Message {
+                    kind: MessageKind::TooManyErrors,
+                    origin,
+                    children: None,
+                    extra: None,
+                });
+            }
+        }
+    }
+
     /// Emit an error to this translation unit's list
     pub fn emit_message(&mut self, origin: impl Into<TokenOrigin>, kind: MessageKind) {
         let origin = origin.into();
@@ -150,10 +443,7 @@ impl<'a> TUCtx<'a> {
             "TUCTx::emit_message() kind {:?} origin {:?}",
             &kind, &origin
         );
-        if kind.severity() == Severity::Fatal {
-            self.fatal_error = true;
-        }
-        self.tu.messages.push(Message {
+        self.push_message(Message {
             kind,
             origin,
             children: None,
@@ -177,10 +467,7 @@ impl<'a> TUCtx<'a> {
             "TUCtx::emit_message_with_children() kind {:?} origin {:?} children {:?}",
             &kind, &origin, &children
         );
-        if kind.severity() == Severity::Fatal {
-            self.fatal_error = true;
-        }
-        self.tu.messages.push(Message {
+        self.push_message(Message {
             kind,
             origin,
             children: Some(children),
@@ -194,45 +481,376 @@ impl<'a> TUCtx<'a> {
         id as u32
     }
 
+    /// Record a `#line` directive
+    ///
+    /// Directives must be added in the order they appear within each input.
+    pub fn add_line_override(&mut self, line_override: LineOverride) {
+        self.line_overrides.push(line_override);
+    }
+
+    /// Find an earlier inclusion of the same file as `input`
+    ///
+    /// Files are compared by path. Files without a path, such as
+    /// [extra files][ef], are compared by name instead.
+    ///
+    /// [ef]: crate::Session::extra_files
+    pub fn find_previous_inclusion(&self, input: &Input) -> Option<&Rc<Input>> {
+        self.inputs[..input.id as usize]
+            .iter()
+            .find(|other| same_file(other, input))
+    }
+
+    /// Record that `input` contains `#pragma once`
+    pub fn add_once_input(&mut self, input: Rc<Input>) {
+        self.once_inputs.push(input);
+    }
+
+    /// Whether an earlier inclusion of the same file as `input` contained
+    /// `#pragma once`
+    ///
+    /// Files are compared as in
+    /// [`find_previous_inclusion()`][TUCtx::find_previous_inclusion].
+    pub fn is_once_input(&self, input: &Input) -> bool {
+        self.once_inputs.iter().any(|other| same_file(other, input))
+    }
+
+    /// Tally the tokens of the current state along with the work the
+    /// preprocessor did to produce them
+    pub fn count_tokens(&mut self) -> Result<&TokenStats> {
+        let mut kinds = BTreeMap::new();
+        for token in self.get_state()?.as_pptokens()? {
+            *kinds.entry(token.kind).or_insert(0) += 1;
+        }
+        let includes = self
+            .inputs
+            .iter()
+            .filter(|input| input.included_from.is_some())
+            .count();
+        Ok(self.token_stats.insert(TokenStats {
+            kinds,
+            macro_expansions: self.macro_expansions,
+            includes,
+        }))
+    }
+
+    /// The statistics most recently gathered by [`count_tokens()`][ct]
+    ///
+    /// [ct]: TUCtx::count_tokens
+    pub fn token_stats(&self) -> Option<&TokenStats> {
+        self.token_stats.as_ref()
+    }
+
+    /// The strings of every `#ident` and `#sccs` directive, in order
+    ///
+    /// A backend may place these in the object file, as GCC does in its
+    /// `.comment` section.
+    pub fn idents(&self) -> &[String] {
+        &self.idents
+    }
+
+    /// Describe every macro defined at the end of preprocessing
+    ///
+    /// There is one line per macro, sorted by name, giving the name, the kind
+    /// of macro with any parameters, and the replacement list.
+    pub fn define_table(&self) -> String {
+        let mut names: Vec<&String> = self.defines.keys().collect();
+        names.sort();
+
+        let mut output = String::new();
+        for name in names {
+            let macrodef = &self.defines[name];
+            let kind = match macrodef.parameter_text() {
+                Some(params) => format!("function-like {}", params),
+                None => "object-like".to_owned(),
+            };
+            output.push_str(&format!(
+                "{} {} = {}\n",
+                name,
+                kind,
+                macrodef.replacement_text()
+            ));
+        }
+        output
+    }
+
+    /// Write a `#define` directive for every macro defined at the end of
+    /// preprocessing, as `gcc -dM` does
+    ///
+    /// The directives are sorted by name and would recreate the same
+    /// definitions if preprocessed again.
+    pub fn define_directives(&self) -> String {
+        let mut names: Vec<&String> = self.defines.keys().collect();
+        names.sort();
+
+        let mut output = String::new();
+        for name in names {
+            let macrodef = &self.defines[name];
+            output.push_str("#define ");
+            output.push_str(name);
+            if let Some(params) = macrodef.parameter_text() {
+                output.push_str(&params);
+            }
+            let replacement = macrodef.replacement_text();
+            if !replacement.is_empty() {
+                output.push(' ');
+                output.push_str(&replacement);
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    /// Perform one step of expansion of the named macro
+    ///
+    /// The definitions in effect at the end of preprocessing are used. `args`
+    /// must be `None` for object-like macros and hold one list of tokens per
+    /// parameter for function-like macros; for a variadic macro, the last list
+    /// holds the entire variable argument, commas included. Neither the
+    /// arguments nor the result are rescanned for further macros.
+    ///
+    /// Fails if there is no such macro or the arguments do not match.
+    pub fn expand_macro_once(
+        &mut self,
+        name: &str,
+        args: Option<Vec<Vec<PPToken>>>,
+    ) -> Result<Vec<PPToken>> {
+        preprocessor::expand_macro_once(self, name, args)
+    }
+
+    /// Expand every macro in `tokens`, rescanning until none remain
+    ///
+    /// The definitions in effect at the end of preprocessing are used, so the
+    /// result matches what an invocation would produce at the end of the
+    /// translation unit.
+    pub fn expand_macro_fully(&mut self, tokens: Vec<PPToken>) -> Vec<PPToken> {
+        preprocessor::expand_macro_fully(self, tokens)
+    }
+
+    /// Trace the macro expansions that produced a token
+    ///
+    /// Each step records which invocation the token passed through and
+    /// whether it came from the body of the macro or one of its arguments.
+    pub fn resolve_provenance(&self, origin: &TokenOrigin) -> ProvenanceChain<'_> {
+        let mut steps = Vec::new();
+        let mut origin = origin;
+        while let TokenOrigin::Macro(mresult) = origin {
+            let invocation = mresult.invocation(self);
+            let source = match mresult.parameter(self) {
+                Some((parameter, index)) => ProvenanceSource::Argument {
+                    parameter: parameter.to_owned(),
+                    index,
+                },
+                None => ProvenanceSource::Body {
+                    index: mresult.body_index().unwrap(),
+                },
+            };
+            steps.push(ProvenanceStep {
+                name: &invocation.name,
+                source,
+            });
+            origin = &mresult.input_token(self).origin;
+        }
+
+        ProvenanceChain {
+            steps,
+            span: *origin.as_source(),
+        }
+    }
+
     /// Search for a file and include it in this translation unit's context
     pub fn add_include(
         &mut self,
         desired_file: &str,
         system: bool,
         included_from: IncludedFrom,
-    ) -> Option<&Rc<Input>> {
+    ) -> std::result::Result<&Rc<Input>, IncludeError> {
         let including_file = included_from
             .input
             .path
             .as_ref()
             .map(|p| p.as_path())
             .clone();
-        let input = self
-            .tu
-            .session
-            .search_for_include(desired_file, including_file, system);
+        let session = Rc::clone(&self.tu.session);
+        let input =
+            session.search_for_include_with(desired_file, including_file, system, &mut |path| {
+                self.read_included_file(path)
+            });
+        self.push_include(input, included_from)
+    }
+
+    /// Search for a file with `#include_next` semantics and include it
+    ///
+    /// The search resumes after the include path at index `after`, where the
+    /// including file was found.
+    pub fn add_include_next(
+        &mut self,
+        desired_file: &str,
+        after: usize,
+        included_from: IncludedFrom,
+    ) -> std::result::Result<&Rc<Input>, IncludeError> {
+        let session = Rc::clone(&self.tu.session);
+        let input = session.search_for_include_next_with(desired_file, after, &mut |path| {
+            self.read_included_file(path)
+        });
+        self.push_include(input, included_from)
+    }
+
+    /// Read a file for an `#include`, unless it was already read
+    fn read_included_file(&mut self, path: &Path) -> std::result::Result<String, IncludeError> {
+        let canonical = match std::fs::canonicalize(path) {
+            Ok(canonical) => canonical,
+            Err(_) => return (self.read_file)(path),
+        };
+        if let Some(file) = self.included_files.get(&canonical) {
+            return Ok(file.content.clone());
+        }
+        let content = (self.read_file)(path)?;
+        self.included_files.insert(
+            canonical,
+            IncludedFile {
+                content: content.clone(),
+                lines: None,
+            },
+        );
+        Ok(content)
+    }
+
+    fn included_file_mut(&mut self, input: &Input) -> Option<&mut IncludedFile> {
+        let canonical = std::fs::canonicalize(input.path.as_ref()?).ok()?;
+        self.included_files.get_mut(&canonical)
+    }
+
+    /// The lines an earlier inclusion of the same file lexed into, if any
+    ///
+    /// The tokens are given `input` as their origin.
+    pub(super) fn included_file_lines(&mut self, input: &Input) -> Option<Vec<Vec<PPToken>>> {
+        let mut lines = self.included_file_mut(input)?.lines.clone()?;
+        set_origin_input(&mut lines, input);
+        Some(lines)
+    }
+
+    /// Remember the lines an included file lexed into
+    pub(super) fn set_included_file_lines(&mut self, input: &Input, lines: &[Vec<PPToken>]) {
+        if let Some(file) = self.included_file_mut(input) {
+            file.lines = Some(lines.to_vec());
+        }
+    }
+
+    /// Add a pseudo-file such as `<built-in>`, which defines the predefined
+    /// macros
+    ///
+    /// Unlike included files, it has no `included_from`.
+    pub(super) fn add_pseudo_input(&mut self, name: &str, content: String) -> Rc<Input> {
+        let mut input = Input::new(name.to_owned(), content, None);
+        input.id = self.inputs.len() as u32;
+        self.inputs.push(Rc::new(input));
+        Rc::clone(self.inputs.last().unwrap())
+    }
+
+    fn push_include(
+        &mut self,
+        input: std::result::Result<Input, IncludeError>,
+        included_from: IncludedFrom,
+    ) -> std::result::Result<&Rc<Input>, IncludeError> {
+        let mut input = input?;
+        input.depth = included_from.input.depth + 1;
+        input.included_from = Some(included_from);
+        input.id = self.inputs.len() as u32;
+        self.inputs.push(Rc::new(input));
+        Ok(self.inputs.last().unwrap())
+    }
 
-        if let Some(mut input) = input {
-            input.depth = included_from.input.depth + 1;
-            input.included_from = Some(included_from);
-            input.id = self.inputs.len() as u32;
-            self.inputs.push(Rc::new(input));
-            self.inputs.last() // always Some
-        } else {
-            None
+    /// Paths of every file included by this unit, in the order first included
+    ///
+    /// Each file is listed once, even if it was included several times. Files
+    /// found on disk are listed by their resolved path.
+    pub fn included_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = Vec::new();
+        for input in self.inputs.iter().filter(|i| i.included_from.is_some()) {
+            let file = match &input.path {
+                Some(path) => path.display().to_string(),
+                None => input.name.clone(),
+            };
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        files
+    }
+
+    /// A Makefile rule making this unit's object file depend on its source and
+    /// every included file
+    ///
+    /// With `-MP`, an empty rule follows for each included file, so that `make`
+    /// does not fail once a header is deleted.
+    pub fn dependency_rule(&self) -> String {
+        fn escape(file: &str) -> String {
+            file.replace('$', "$$").replace(' ', "\\ ")
         }
+
+        let source = &self.original_input().name;
+        let target = match &self.tu.session.flags().dependency_target {
+            // like GCC, a target given with `-MT` is used verbatim
+            Some(target) => target.clone(),
+            None => {
+                let object = std::path::Path::new(source).with_extension("o");
+                let object = object.file_name().unwrap_or_default().to_string_lossy();
+                escape(&object)
+            },
+        };
+
+        let included = self.included_files();
+        let mut rule = format!("{}: {}", target, escape(source));
+        for file in &included {
+            rule.push(' ');
+            rule.push_str(&escape(file));
+        }
+        if self.tu.session.flags().dependency_phony {
+            for file in &included {
+                rule.push_str(&format!("\n\n{}:", escape(file)));
+            }
+        }
+        rule
+    }
+
+    /// Write [`dependency_rule()`][TUCtx::dependency_rule] to the `-MF` file
+    /// if one was given, otherwise to stdout
+    pub fn write_dependencies(&self) -> Result<()> {
+        let rule = self.dependency_rule();
+        match &self.tu.session.flags().dependency_file {
+            Some(path) => {
+                std::fs::write(path, format!("{}\n", rule)).map_err(|error| {
+                    ErrorKind::OutputFileError {
+                        filename: path.display().to_string(),
+                        error,
+                    }
+                })?;
+            },
+            None => println!("{}", rule),
+        }
+        Ok(())
     }
 
+    /// Run every pass of the session over this translation unit
+    ///
+    /// Returns whether translation succeeded, meaning no errors were
+    /// reported. Only failures of the compiler itself are returned as `Err`.
     pub fn run(&mut self) -> Result<bool> {
         let session = Rc::clone(&self.tu.session);
         let passes = &session.flags().passes;
+        let time_passes = session.flags().time_passes;
         for pass in passes {
             debug!(
                 "TUCtx::run() tu alias {:?} running pass {:?}",
                 self.tu.input().name,
                 &pass
             );
+            let start = time_passes.then(Instant::now);
             pass.run(self)?;
+            if let Some(start) = start {
+                let elapsed = start.elapsed();
+                self.tu.pass_timings.push((format!("{:?}", pass), elapsed));
+            }
             debug!("TUCtx::run() fatal {}", self.fatal_error);
             if self.fatal_error {
                 info!("TUCtx::run() stopping because of fatal error");
@@ -243,8 +861,12 @@ impl<'a> TUCtx<'a> {
             self.save_state("<final>").unwrap();
         }
 
+        if session.flags().verify {
+            verify::verify_messages(self);
+        }
+
         self.enrich_messages();
-        Ok(self.fatal_error)
+        Ok(!self.tu.had_errors())
     }
 
     fn enrich_messages(&mut self) {
@@ -254,3 +876,352 @@ impl<'a> TUCtx<'a> {
         self.tu.messages = messages;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::front::c::token::{PPTokenKind, TextPosition, TextSpan};
+    use crate::test_util::{session, unit, unit_in, TempDir};
+
+    fn identifier(value: &str) -> PPToken {
+        PPToken {
+            kind: PPTokenKind::Identifier,
+            value: value.into(),
+            origin: TokenOrigin::Source(TextSpan {
+                pos: TextPosition {
+                    input: 0,
+                    absolute: 0,
+                },
+                len: 0,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_state_type_error() {
+        let state = TUState::Text("a".to_owned());
+        let error = state.as_pptokens().unwrap_err();
+        match error.kind() {
+            ErrorKind::TUStateTypeError {
+                current_type,
+                expected_type,
+            } => {
+                assert_eq!(*current_type, "Text");
+                assert_eq!(*expected_type, "PPTokens");
+            },
+            kind => panic!("unexpected error {:?}", kind),
+        }
+        assert_eq!(
+            state.into_pptokens().unwrap_err().to_string(),
+            "mismatched translation unit state; expected `PPTokens`, found `Text`"
+        );
+    }
+
+    #[test]
+    fn test_idents() {
+        let mut tu = unit(
+            &["--pass=phase1..phase4"],
+            "#ident \"one\"\n#if 0\n#ident \"skipped\"\n#endif\n#sccs \"@(#)two\"\n",
+        );
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+        assert_eq!(tuctx.idents(), ["one", "@(#)two"]);
+    }
+
+    #[test]
+    fn test_define_table() {
+        let mut tu = unit(
+            &["--pass=phase1..phase4"],
+            "#define ONE  1 +\t/* x */ 2 \n#define F(a, ...) [a] __VA_ARGS__\n\
+             #define G() \n#define GONE\n#undef GONE\n",
+        );
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+
+        // skip the predefined macros
+        let table = tuctx.define_table();
+        let table: Vec<&str> = table.lines().filter(|l| !l.starts_with('_')).collect();
+        assert_eq!(
+            table.join("\n") + "\n",
+            "F function-like (a, ...) = [a] __VA_ARGS__\n\
+             G function-like () = \n\
+             ONE object-like = 1 + 2\n"
+        );
+    }
+
+    #[test]
+    fn test_define_directives() {
+        let source = "\
+#define ONE 1
+#define EMPTY
+#define F(a, b) ((a) * (b))
+#define V(fmt, ...) printf(fmt, __VA_ARGS__)
+#define N(...) __VA_ARGS__
+#define G() g
+";
+        let session = session(&["--pass=phase1..phase4"]).build();
+        let mut tu = unit_in(&session, source);
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+
+        // skip the predefined macros
+        let directives = tuctx.define_directives();
+        let defined: Vec<&str> = directives
+            .lines()
+            .filter(|l| !l.starts_with("#define _"))
+            .collect();
+        assert_eq!(
+            defined.join("\n") + "\n",
+            "\
+#define EMPTY
+#define F(a, b) ((a) * (b))
+#define G() g
+#define N(...) __VA_ARGS__
+#define ONE 1
+#define V(fmt, ...) printf(fmt, __VA_ARGS__)
+"
+        );
+
+        // reading the output back gives the same definitions
+        let mut tu = unit_in(&session, &directives);
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+        assert_eq!(tuctx.define_directives(), directives);
+    }
+
+    #[test]
+    fn test_expand_macro_once() {
+        let mut tu = unit(
+            &["--pass=phase1..phase4"],
+            "#define ONE TWO + 1\n#define TWO 2\n#define F(a, ...) [a] ONE #a __VA_ARGS__\n",
+        );
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+
+        // not rescanned, so `TWO` remains
+        let output = tuctx.expand_macro_once("ONE", None).unwrap();
+        assert_eq!(PPToken::to_string(&output), "TWO + 1");
+
+        // arguments are not expanded either
+        let args = vec![vec![identifier("TWO")], vec![identifier("x")]];
+        let output = tuctx.expand_macro_once("F", Some(args)).unwrap();
+        assert_eq!(PPToken::to_string(&output), "[TWO] ONE \"TWO\" x");
+
+        let error = |result: Result<Vec<PPToken>>| result.unwrap_err().kind().to_string();
+        assert_eq!(
+            error(tuctx.expand_macro_once("ONE", Some(Vec::new()))),
+            "object-like macro `ONE` takes no arguments"
+        );
+        assert_eq!(
+            error(tuctx.expand_macro_once("F", None)),
+            "function-like macro `F` requires arguments"
+        );
+        assert_eq!(
+            error(tuctx.expand_macro_once("F", Some(vec![Vec::new()]))),
+            "macro `F` expected 2 arguments but received 1"
+        );
+        assert_eq!(
+            error(tuctx.expand_macro_once("UNDEFINED", None)),
+            "`UNDEFINED` is not a macro"
+        );
+    }
+
+    fn pptokens(mut text: &str) -> Vec<PPToken> {
+        let mut tokens = Vec::new();
+        while !text.is_empty() {
+            let (value, kind) = crate::front::c::lexer::lex_one_token(text);
+            let mut token = identifier(value);
+            token.kind = kind;
+            tokens.push(token);
+            text = &text[value.len()..];
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_expand_macro_fully() {
+        let session = session(&["--pass=phase1..phase4"]).build();
+        let source = "#define TWO 2\n#define ADD(a, b) a + b\n#define F(x) ADD(x, TWO) #x\n";
+        let invocation = "F(TWO) ADD(F(1), 3)";
+
+        let mut tu = unit_in(&session, &format!("{}{}\n", source, invocation));
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+        let mut in_context = tuctx.get_state().unwrap().as_pptokens().unwrap().clone();
+        assert_eq!(in_context.pop().unwrap().kind, PPTokenKind::EndOfFile);
+
+        let mut tu = unit_in(&session, source);
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+        let preview = tuctx.expand_macro_fully(pptokens(invocation));
+
+        assert_eq!(
+            PPToken::to_string(&preview),
+            "2 + 2 \"TWO\" 1 + 2 \"1\" + 3"
+        );
+        PPToken::assert_loose_equal(&preview, &in_context);
+    }
+
+    #[test]
+    fn test_dependency_rule() {
+        let session = crate::Session::builder()
+            .parse_cli_args_from_str(&["-M"])
+            .unwrap()
+            .add_extra_file("a.h".to_owned(), "#include <my header.h>\n".to_owned())
+            .add_extra_file("my header.h".to_owned(), "".to_owned())
+            .build();
+        let mut tu = unit_in(
+            &session,
+            "#include <a.h>\n#include <my header.h>\n#include <a.h>\n",
+        );
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+
+        assert_eq!(tuctx.included_files(), vec!["a.h", "my header.h"]);
+        assert_eq!(
+            tuctx.dependency_rule(),
+            "<unit-test>.o: <unit-test> a.h my\\ header.h"
+        );
+    }
+
+    #[test]
+    fn test_dependency_rule_phony() {
+        let session = crate::Session::builder()
+            .parse_cli_args_from_str(&["-M", "-MP", "-MT", "x.o"])
+            .unwrap()
+            .add_extra_file("a.h".to_owned(), "#include <b.h>\n".to_owned())
+            .add_extra_file("b.h".to_owned(), "".to_owned())
+            .build();
+        let mut tu = unit_in(&session, "#include <a.h>\n");
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+
+        assert_eq!(
+            tuctx.dependency_rule(),
+            "x.o: <unit-test> a.h b.h\n\na.h:\n\nb.h:"
+        );
+    }
+
+    #[test]
+    fn test_resolve_provenance_two_levels() {
+        let mut tu = unit(
+            &["--pass=phase1..phase4"],
+            "#define INNER(x) x + 1\n#define OUTER(y) INNER(y)\nOUTER(a)\n",
+        );
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.run().unwrap());
+
+        let tokens = tuctx.get_state().unwrap().as_pptokens().unwrap();
+        let find = |value| tokens.iter().find(|t| t.as_str() == value).unwrap();
+
+        // `a` was passed as an argument through both macros
+        let chain = tuctx.resolve_provenance(&find("a").origin);
+        let steps: Vec<_> = chain
+            .steps
+            .iter()
+            .map(|s| (s.name.as_str(), s.source.clone()))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                (
+                    "INNER",
+                    ProvenanceSource::Argument {
+                        parameter: "x".to_owned(),
+                        index: 0
+                    }
+                ),
+                (
+                    "OUTER",
+                    ProvenanceSource::Argument {
+                        parameter: "y".to_owned(),
+                        index: 0
+                    }
+                ),
+            ]
+        );
+        assert_eq!(chain.span.text(&tuctx), "a");
+
+        // `+` came from the body of `INNER`, which itself came from the body
+        // of `OUTER`
+        let chain = tuctx.resolve_provenance(&find("+").origin);
+        let steps: Vec<_> = chain
+            .steps
+            .iter()
+            .map(|s| (s.name.as_str(), s.source.clone()))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                ("INNER", ProvenanceSource::Body { index: 2 }),
+                ("OUTER", ProvenanceSource::Body { index: 0 }),
+            ]
+        );
+        assert_eq!(chain.span.text(&tuctx), "OUTER");
+    }
+
+    #[test]
+    fn test_included_file_read_once() {
+        thread_local! {
+            static READS: std::cell::Cell<usize> = std::cell::Cell::new(0);
+        }
+        fn counting_read(path: &Path) -> std::result::Result<String, IncludeError> {
+            READS.with(|reads| reads.set(reads.get() + 1));
+            read_include(path)
+        }
+
+        let dir = TempDir::new("read-once");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "#include \"a.h\"\n#include \"a.h\"\n#include \"sub/../a.h\"\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("a.h"), "x\n").unwrap();
+
+        let session = session(&["--pass=phase1..phase4"]).build();
+        let mut tu = crate::tu::CTranslationUnit::builder(&session)
+            .source_file(&dir.join("main.c"))
+            .unwrap()
+            .build();
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        tuctx.read_file = counting_read;
+        assert!(tuctx.run().unwrap());
+
+        let tokens = tuctx.get_state().unwrap().as_pptokens().unwrap();
+        let words: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.is_ident())
+            .map(|t| (t.as_str(), t.origin.as_source().pos.input))
+            .collect();
+        // each inclusion is still its own input
+        assert_eq!(words, [("x", 2), ("x", 3), ("x", 4)]);
+        assert_eq!(READS.with(|reads| reads.get()), 1);
+    }
+
+    #[test]
+    fn test_count_tokens() {
+        let session = session(&["--pass=phase1..phase4", "--pass=count_tokens"])
+            .add_extra_file("a.h".to_owned(), "#define TWICE(x) x x\n".to_owned())
+            .build();
+        let mut tu = unit_in(&session, "#include <a.h>\nTWICE(+) y\n");
+        let mut tuctx = TUCtx::from_tu(&mut tu);
+        assert!(tuctx.token_stats().is_none());
+        assert!(tuctx.run().unwrap());
+
+        let stats = tuctx.token_stats().unwrap();
+        let kinds: Vec<_> = stats.kinds.iter().map(|(&k, &n)| (k, n)).collect();
+        assert_eq!(
+            kinds,
+            [
+                (PPTokenKind::EndOfFile, 1),
+                (PPTokenKind::Whitespace, 3),
+                (PPTokenKind::Identifier, 1),
+                (PPTokenKind::Punctuator, 2),
+            ]
+        );
+        assert_eq!(stats.macro_expansions, 1);
+        assert_eq!(stats.includes, 1);
+    }
+}
@@ -0,0 +1,142 @@
+// Licensed   under  the   Apache  License,   Version  2.0   <LICENSE-APACHE  or
+// http://www.apache.org/licenses/LICENSE-2.0> or  the MIT  license <LICENSE-MIT
+// or http://opensource.org/licenses/MIT>, at your option.  This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Checking messages against expectations written in the source
+//!
+//! When `--verify` is given, comments such as `// expected-error{{too few}}`
+//! declare that a message of that severity, whose headline contains the text
+//! between the braces, is reported on the same line. Matching messages are
+//! discarded. Any expectation left unmatched is reported instead, as is any
+//! message nobody expected.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::core::Severity;
+use crate::front::c::input::Input;
+use crate::front::c::message::{Message, MessageKind};
+use crate::front::c::token::{TextPosition, TextSpan};
+use crate::front::c::tuctx::TUCtx;
+
+lazy_static! {
+    static ref REGEX: Regex = Regex::new(r"expected-(error|warning|note)\{\{(.*?)\}\}").unwrap();
+}
+
+#[derive(Debug)]
+struct Expectation {
+    severity: &'static str,
+    text: String,
+    input: u32,
+    line: u32,
+    span: TextSpan,
+    seen: bool,
+}
+
+/// Find every expectation written within a comment of this input
+fn parse_expectations(input: &Input, output: &mut Vec<Expectation>) {
+    let mut absolute = 0;
+    for (i, line) in input.content.split('\n').enumerate() {
+        for captures in REGEX.captures_iter(line) {
+            let whole = captures.get(0).unwrap();
+            let before = &line[..whole.start()];
+            if !before.contains("//") && !before.contains("/*") {
+                continue;
+            }
+
+            output.push(Expectation {
+                severity: match &captures[1] {
+                    "error" => "error",
+                    "warning" => "warning",
+                    _ => "note",
+                },
+                text: captures[2].to_owned(),
+                input: input.id,
+                line: i as u32 + 1,
+                span: TextSpan {
+                    pos: TextPosition {
+                        input: input.id,
+                        absolute: absolute + before.chars().count() as u32,
+                    },
+                    len: whole.as_str().chars().count() as u32,
+                },
+                seen: false,
+            });
+        }
+        absolute += line.chars().count() as u32 + 1;
+    }
+}
+
+/// The name used in expectations for messages of this severity
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Fatal | Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note | Severity::Info => "note",
+    }
+}
+
+/// Mark the first unseen expectation matching this message
+///
+/// Returns whether one was found.
+fn match_message(tuctx: &TUCtx, expectations: &mut [Expectation], message: &Message) -> bool {
+    let pos = message.origin.macro_root_textspan(tuctx).pos;
+    let (line, _) = tuctx.inputs[pos.input as usize].get_line_column(pos.absolute);
+    let severity = severity_name(message.kind.severity());
+    let headline = message.kind.get_headline();
+
+    let expectation = expectations.iter_mut().find(|e| {
+        !e.seen
+            && e.input == pos.input
+            && e.line == line
+            && e.severity == severity
+            && headline.contains(&e.text)
+    });
+    match expectation {
+        Some(expectation) => {
+            expectation.seen = true;
+            true
+        },
+        None => false,
+    }
+}
+
+/// Compare the messages of a translation unit against its expectations
+///
+/// A message is discarded only if it and all of its children were expected.
+pub fn verify_messages(tuctx: &mut TUCtx) {
+    let mut expectations = Vec::new();
+    for input in &tuctx.inputs {
+        parse_expectations(input, &mut expectations);
+    }
+
+    let messages = std::mem::take(&mut tuctx.tu.messages);
+    let mut unexpected = Vec::new();
+    for message in messages {
+        let mut matched = match_message(tuctx, &mut expectations, &message);
+        for child in message.children.iter().flat_map(|c| c.iter()) {
+            matched &= match_message(tuctx, &mut expectations, child);
+        }
+        if !matched {
+            unexpected.push(message);
+        }
+    }
+
+    tuctx.tu.messages = unexpected;
+    tuctx.fatal_error = tuctx
+        .tu
+        .messages
+        .iter()
+        .any(|m| m.kind.severity() == Severity::Fatal);
+
+    for expectation in expectations.into_iter().filter(|e| !e.seen) {
+        tuctx.emit_message(
+            expectation.span,
+            MessageKind::VerifyExpectationNotSeen {
+                severity: expectation.severity,
+                text: expectation.text,
+            },
+        );
+    }
+}
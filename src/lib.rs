@@ -15,6 +15,9 @@ pub mod util;
 
 pub mod front;
 
+#[cfg(test)]
+mod test_util;
+
 pub use crate::core::{Error, ErrorKind, Result};
 pub use crate::driver::Driver;
 pub use crate::session::Session;
@@ -77,11 +77,15 @@ lazy_static! {
             (s, c)
         }
         [
+            erase("count_tokens", &internal::CountTokens::from_args),
+            erase("dump_defines", &internal::DumpDefines::from_args),
             erase("state_print", &internal::StatePrint::from_args),
             erase("state_print_debug", &internal::StatePrintDebug::from_args),
             erase("state_save", &internal::StateSave::from_args),
             erase("state_write", &internal::StateWrite::from_args),
             erase("state_write_debug", &internal::StateWriteDebug::from_args),
+            erase("state_write_file", &internal::StateWriteFile::from_args),
+            erase("state_read_file", &internal::StateReadFile::from_args),
             erase("state_read_input", &internal::StateReadInput::from_args),
             erase("phase1", &front::Phase1::from_args),
             erase("phase2", &front::Phase2::from_args),
@@ -89,6 +93,11 @@ lazy_static! {
             erase("phase4", &front::Phase4::from_args),
             erase("phase5", &front::Phase5::from_args),
             erase("phase6", &front::Phase6::from_args),
+            erase("split_lines", &front::SplitLines::from_args),
+            erase("join_lines", &front::JoinLines::from_args),
+            erase("stringify", &front::Stringify::from_args),
+            erase("emit_deps", &front::EmitDeps::from_args),
+            erase("emit_defines", &front::EmitDefines::from_args),
         ].iter().map(|(s, c)| (*s, *c)).collect()
     };
 }
@@ -99,13 +108,13 @@ macro_rules! declare_pass {
     {
         $(#[$meta:meta])*
         $alias:ident => pub struct $name:ident {
-            $( pub $field:ident : $type:ty);*
+            $( pub $field:ident : $type:ty ),* $(,)?
         }
     } => {
         $(#[$meta])*
         #[derive(Clone, Debug)]
         pub struct $name {
-            $(pub $field: $type);*
+            $(pub $field: $type),*
         }
         impl $name {
             pub fn from_args(args: &[&str]) -> Result<Box<dyn Pass>> {
@@ -122,7 +131,7 @@ macro_rules! declare_pass {
                     crate::passes::helper::args_get(stringify!($alias),
                     args, _index, stringify!($type))?;
                     _index += 1;
-                );*
+                )*
                 Ok(Box::new($name { $($field),* }))
             }
         }
@@ -131,7 +140,10 @@ macro_rules! declare_pass {
 
 /// Functions used internally by [`declare_pass!()`][macro@declare_pass].
 ///
-/// Use that macro instead of these directly.
+/// Use that macro instead of these directly, except for passes with optional
+/// arguments, which the macro cannot declare. Those check their arguments with
+/// [`args_min()`][helper::args_min], [`args_max()`][helper::args_max], or
+/// [`args_range()`][helper::args_range].
 pub mod helper {
     use super::*;
 
@@ -152,6 +164,49 @@ pub mod helper {
         }
     }
 
+    /// Checks there are between `min` and `max` arguments, inclusive
+    ///
+    /// Returns a [`PassArgsArityRange`][crate::ErrorKind::PassArgsArityRange]
+    /// error otherwise.
+    pub fn args_range(
+        name: impl Into<String>,
+        args: &[&str],
+        min: usize,
+        max: usize,
+    ) -> Result<()> {
+        debug_assert!(min <= max);
+        args_bounds(name, args, min, Some(max))
+    }
+
+    /// Checks there are at least `min` arguments
+    pub fn args_min(name: impl Into<String>, args: &[&str], min: usize) -> Result<()> {
+        args_bounds(name, args, min, None)
+    }
+
+    /// Checks there are at most `max` arguments
+    pub fn args_max(name: impl Into<String>, args: &[&str], max: usize) -> Result<()> {
+        args_bounds(name, args, 0, Some(max))
+    }
+
+    fn args_bounds(
+        name: impl Into<String>,
+        args: &[&str],
+        min: usize,
+        max: Option<usize>,
+    ) -> Result<()> {
+        if args.len() >= min && max.is_none_or(|max| args.len() <= max) {
+            Ok(())
+        } else {
+            Err(ErrorKind::PassArgsArityRange {
+                pass_name: name.into(),
+                min,
+                max,
+                got: args.len(),
+            }
+            .into())
+        }
+    }
+
     /// Parses and returns the corresponding argument
     ///
     /// Returns a [`PassArgsType`][crate::ErrorKind::PassArgsType]
@@ -173,3 +228,63 @@ pub mod helper {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::helper::*;
+    use super::*;
+
+    fn arity_message(result: Result<()>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_args_min() {
+        assert!(args_min("p", &["a"], 1).is_ok());
+        assert!(args_min("p", &["a", "b"], 1).is_ok());
+        assert_eq!(
+            arity_message(args_min("p", &[], 1)),
+            "pass `p` expected at least 1 arguments but received 0"
+        );
+    }
+
+    #[test]
+    fn test_args_max() {
+        assert!(args_max("p", &[], 1).is_ok());
+        assert!(args_max("p", &["a"], 1).is_ok());
+        assert_eq!(
+            arity_message(args_max("p", &["a", "b"], 1)),
+            "pass `p` expected at most 1 arguments but received 2"
+        );
+    }
+
+    #[test]
+    fn test_args_range() {
+        assert!(args_range("p", &["a"], 1, 2).is_ok());
+        assert!(args_range("p", &["a", "b"], 1, 2).is_ok());
+        assert_eq!(
+            arity_message(args_range("p", &[], 1, 2)),
+            "pass `p` expected between 1 and 2 arguments but received 0"
+        );
+        match args_range("p", &["a", "b", "c"], 1, 2).unwrap_err().kind() {
+            ErrorKind::PassArgsArityRange {
+                pass_name,
+                min,
+                max,
+                got,
+            } => {
+                assert_eq!(pass_name, "p");
+                assert_eq!((*min, *max, *got), (1, Some(2), 3));
+            },
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_optional_pass_args() {
+        let construct = PASS_CONSTRUCTORS["state_print"];
+        assert!(construct.construct(&[]).is_ok());
+        assert!(construct.construct(&["saved"]).is_ok());
+        assert!(construct.construct(&["saved", "extra"]).is_err());
+    }
+}
@@ -11,7 +11,8 @@ use crate::core::Result;
 use crate::declare_pass;
 use crate::front::c::lexer::lex;
 use crate::front::c::minor::{concatenate, convert_trigraphs, splice_lines, unescape};
-use crate::front::c::preprocessor::preprocess;
+use crate::front::c::preprocessor::{join_lines, parse_lines, preprocess};
+use crate::front::c::token::{CharToken, PPToken};
 use crate::front::c::tuctx::{TUCtx, TUState};
 use crate::passes::Pass;
 
@@ -22,7 +23,11 @@ declare_pass!(
 impl Pass for Phase1 {
     fn run(&self, tuctx: &mut TUCtx) -> Result<()> {
         let tokens = tuctx.take_state()?.into_chartokens()?;
-        let output = convert_trigraphs(tokens);
+        let output = if tuctx.session().standard().has_trigraphs() {
+            convert_trigraphs(tokens)
+        } else {
+            tokens
+        };
         tuctx.set_state(TUState::CharTokens(output));
 
         Ok(())
@@ -99,3 +104,214 @@ impl Pass for Phase6 {
         Ok(())
     }
 }
+
+declare_pass!(
+    /// Calls [`front::preprocessor::parse_lines`](parse_lines) to group
+    /// [`PPTokens`][TUState::PPTokens] into [`Lines`][TUState::Lines]
+    split_lines => pub struct SplitLines {}
+);
+impl Pass for SplitLines {
+    fn run(&self, tuctx: &mut TUCtx) -> Result<()> {
+        let tu_input = Rc::clone(tuctx.original_input());
+        let tokens = tuctx.take_state()?.into_pptokens()?;
+        let output = parse_lines(tokens, &tu_input);
+        tuctx.set_state(TUState::Lines(output));
+
+        Ok(())
+    }
+}
+
+declare_pass!(
+    /// Calls [`front::preprocessor::join_lines`](join_lines) to turn
+    /// [`Lines`][TUState::Lines] back into [`PPTokens`][TUState::PPTokens]
+    join_lines => pub struct JoinLines {}
+);
+impl Pass for JoinLines {
+    fn run(&self, tuctx: &mut TUCtx) -> Result<()> {
+        let lines = tuctx.take_state()?.into_lines()?;
+        let output = join_lines(lines);
+        tuctx.set_state(TUState::PPTokens(output));
+
+        Ok(())
+    }
+}
+
+declare_pass!(
+    /// Convert tokens back into [`Text`][TUState::Text]
+    ///
+    /// Every token keeps its spelling, including whitespace and comments, so
+    /// the text matches the source until phase 4 rewrites it.
+    stringify => pub struct Stringify {}
+);
+impl Pass for Stringify {
+    fn run(&self, tuctx: &mut TUCtx) -> Result<()> {
+        let output = match tuctx.take_state()? {
+            TUState::CharTokens(tokens) => CharToken::to_string(&tokens),
+            TUState::PPTokens(tokens) => PPToken::to_string(&tokens),
+            TUState::Lines(lines) => PPToken::to_string(&join_lines(lines)),
+            TUState::Text(text) => text,
+        };
+        tuctx.set_state(TUState::Text(output));
+
+        Ok(())
+    }
+}
+
+declare_pass!(
+    /// Print a Makefile rule listing every file included by this unit
+    ///
+    /// See [`TUCtx::write_dependencies()`](TUCtx::write_dependencies).
+    emit_deps => pub struct EmitDeps {}
+);
+impl Pass for EmitDeps {
+    fn run(&self, tuctx: &mut TUCtx) -> Result<()> {
+        tuctx.write_dependencies()
+    }
+}
+
+declare_pass!(
+    /// Print a `#define` directive for each macro defined at the end of
+    /// preprocessing (`-dM`)
+    ///
+    /// See [`TUCtx::define_directives()`](TUCtx::define_directives).
+    emit_defines => pub struct EmitDefines {}
+);
+impl Pass for EmitDefines {
+    fn run(&self, tuctx: &mut TUCtx) -> Result<()> {
+        print!("{}", tuctx.define_directives());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::front::c::tuctx::TUState;
+    use crate::test_util::{unit, unit_in, TempDir};
+
+    fn round_trip(source: &str) -> (TUState, TUState) {
+        let mut tu = unit(
+            &[
+                "--pass=phase1..phase3",
+                "--pass=split_lines",
+                "--pass=state_save(lines)",
+                "--pass=join_lines",
+                "--pass=state_save(tokens)",
+            ],
+            source,
+        );
+        tu.run().unwrap();
+        (
+            tu.saved_states("lines")[0].clone(),
+            tu.saved_states("tokens")[0].clone(),
+        )
+    }
+
+    #[test]
+    fn test_emit_deps_to_file() {
+        let dir = TempDir::new("deps");
+        std::fs::write(dir.join("main.c"), "#include \"a.h\"\n").unwrap();
+        std::fs::write(dir.join("a.h"), "").unwrap();
+        let deps = dir.join("main.d");
+
+        let session = crate::Session::builder()
+            .parse_cli_args_from_str(&[
+                "-M".to_owned(),
+                "-MF".to_owned(),
+                deps.display().to_string(),
+                "-MT".to_owned(),
+                "build/main.o".to_owned(),
+            ])
+            .unwrap()
+            .build();
+        let mut tu = crate::tu::CTranslationUnit::builder(&session)
+            .source_file(&dir.join("main.c"))
+            .unwrap()
+            .build();
+        tu.run().unwrap();
+        assert!(tu.messages().is_empty());
+
+        let rule = std::fs::read_to_string(&deps).unwrap();
+        let (target, prerequisites) = rule.trim_end().split_once(": ").unwrap();
+        assert_eq!(target, "build/main.o");
+        let prerequisites: Vec<_> = prerequisites.split(' ').collect();
+        assert_eq!(prerequisites.len(), 2);
+        assert!(prerequisites[0].ends_with("main.c"));
+        assert_eq!(prerequisites[1], dir.join("a.h").display().to_string());
+    }
+
+    fn saved_tokens(passes: &[&str]) -> String {
+        let mut args: Vec<String> = passes.iter().map(|p| format!("--pass={}", p)).collect();
+        args.push("--pass=state_save(out)".to_owned());
+        let session = crate::Session::builder()
+            .parse_cli_args_from_str(&args)
+            .unwrap()
+            .build();
+        let mut tu = unit_in(&session, "#define A \"a\\n\" \"b\"\nA ??= x\n");
+        tu.run().unwrap();
+        format!("{:?}", tu.saved_states("out")[0])
+    }
+
+    #[test]
+    fn test_pass_shorthands() {
+        let explicit = saved_tokens(&[
+            "state_read_input",
+            "phase1",
+            "phase2",
+            "phase3",
+            "phase4",
+            "phase5",
+            "phase6",
+        ]);
+        assert_eq!(saved_tokens(&["preprocess"]), explicit);
+        assert_eq!(
+            saved_tokens(&["state_read_input", "phase1..phase3", "phase4..phase6"]),
+            explicit
+        );
+        assert_eq!(
+            saved_tokens(&["state_read_input", "phase1..phase1", "phase2..phase3"]),
+            saved_tokens(&["state_read_input", "phase1", "phase2", "phase3"])
+        );
+    }
+
+    #[test]
+    fn test_lines_round_trip() {
+        for source in &["", "a", "#define a 1\n  a b /* c */\n\n", "x\n y"] {
+            let (lines, tokens) = round_trip(source);
+            assert!(lines
+                .as_lines()
+                .unwrap()
+                .iter()
+                .all(|line| line.last().unwrap().is_newline()));
+            assert_eq!(tokens.as_pptokens().unwrap().is_empty(), source.is_empty());
+            assert_eq!(&tokens.to_string(), source);
+        }
+    }
+
+    fn stringify(source: &str, passes: &[&str]) -> String {
+        let mut args: Vec<_> = passes
+            .iter()
+            .map(|pass| format!("--pass={}", pass))
+            .collect();
+        args.push("--pass=stringify".to_owned());
+        args.push("--pass=state_save(text)".to_owned());
+
+        let args: Vec<_> = args.iter().map(String::as_str).collect();
+        let mut tu = unit(&args, source);
+        tu.run().unwrap();
+        tu.saved_states("text")[0].clone().into_text().unwrap()
+    }
+
+    #[test]
+    fn test_stringify() {
+        let source = "#include <stdio.h>\n\nint main(void) {\n\t/* greet */ puts(\"hi\");\r\n    return 0; // done\n}\n";
+        assert_eq!(stringify(source, &["phase1", "phase2"]), source);
+        assert_eq!(stringify(source, &["phase1", "phase2", "phase3"]), source);
+        assert_eq!(
+            stringify(source, &["phase1", "phase2", "phase3", "split_lines"]),
+            source
+        );
+
+        // phases 1 and 2 are not undone
+        assert_eq!(stringify("a??!\\\nb", &["phase1", "phase2"]), "a|b");
+    }
+}
@@ -6,17 +6,41 @@
 //! Passes for manipulating internal compiler state
 
 use crate::declare_pass;
-use crate::front::c::tuctx::{TUCtx, TUState};
-use crate::passes::Pass;
+use crate::front::c::state_file;
+use crate::front::c::tuctx::{SaveFormat, TUCtx, TUState};
+use crate::passes::{helper, Pass};
 use crate::{ErrorKind, Result};
 
-declare_pass!(
-    /// Pretty-print [`TUCtx`][TUCtx]'s primary state to stderr
-    state_print => pub struct StatePrint {}
-);
+/// Pretty-print [`TUCtx`][TUCtx]'s primary state to stderr
+///
+/// Given a name, as in `state_print(name)`, print the state most recently saved
+/// under that name by [`state_save`][ss] instead.
+///
+/// [ss]: crate::passes::internal::StateSave
+#[derive(Clone, Debug)]
+pub struct StatePrint {
+    pub name: Option<String>,
+}
+impl StatePrint {
+    pub fn from_args(args: &[&str]) -> Result<Box<dyn Pass>> {
+        helper::args_max("state_print", args, 1)?;
+        Ok(Box::new(StatePrint {
+            name: args.first().map(|&name| name.to_owned()),
+        }))
+    }
+}
 impl Pass for StatePrint {
     fn run(&self, tuctx: &mut TUCtx) -> Result<()> {
-        let state = tuctx.get_state()?;
+        let state = match &self.name {
+            Some(name) => {
+                tuctx
+                    .saved_state_last(name)
+                    .ok_or_else(|| ErrorKind::TUStateNotSaved {
+                        name: name.to_owned(),
+                    })?
+            },
+            None => tuctx.get_state()?,
+        };
         eprintln!("{}", state);
         Ok(())
     }
@@ -35,18 +59,87 @@ impl Pass for StatePrintDebug {
 }
 
 declare_pass!(
-    /// Save the [`TUCtx`'s][tu] current primary state for later access by
-    /// [`TranslationUnit::saved_states()`][tucs]
+    /// Print every macro defined at the end of preprocessing to stderr
     ///
-    /// [tu]: crate::front::c::tuctx::TUCtx
-    /// [tucs]: crate::front::c::tu::TranslationUnit::saved_states
-    state_save => pub struct StateSave {
-        pub name: String
-    }
+    /// See [`TUCtx::define_table()`](TUCtx::define_table).
+    dump_defines => pub struct DumpDefines {}
 );
+impl Pass for DumpDefines {
+    fn run(&self, tuctx: &mut TUCtx) -> Result<()> {
+        eprint!("{}", tuctx.define_table());
+        Ok(())
+    }
+}
+
+/// Count the tokens of each kind in [`TUCtx`][TUCtx]'s primary state, along
+/// with the macro expansions and file inclusions that produced it
+///
+/// The statistics are kept for [`TUCtx::token_stats()`][ts]. Given `print`, as
+/// in `count_tokens(print)`, they are also printed to stderr.
+///
+/// [ts]: TUCtx::token_stats
+#[derive(Clone, Debug)]
+pub struct CountTokens {
+    pub print: bool,
+}
+impl CountTokens {
+    pub fn from_args(args: &[&str]) -> Result<Box<dyn Pass>> {
+        helper::args_max("count_tokens", args, 1)?;
+        let print = match args.first() {
+            None => false,
+            Some(&"print") => true,
+            Some(&other) => {
+                return Err(ErrorKind::PassArgsType {
+                    pass_name: "count_tokens".to_owned(),
+                    index: 0,
+                    expects: "`print`",
+                    got: other.to_owned(),
+                }
+                .into())
+            },
+        };
+        Ok(Box::new(CountTokens { print }))
+    }
+}
+impl Pass for CountTokens {
+    fn run(&self, tuctx: &mut TUCtx) -> Result<()> {
+        let stats = tuctx.count_tokens()?;
+        if self.print {
+            eprint!("{}", stats);
+        }
+        Ok(())
+    }
+}
+
+/// Save the [`TUCtx`'s][tu] current primary state for later access by
+/// [`TranslationUnit::saved_states()`][tucs]
+///
+/// An optional second argument selects the [format][SaveFormat], one of
+/// `tokens` (the default), `text`, or `json`, as in `state_save(out, json)`.
+///
+/// [tu]: crate::front::c::tuctx::TUCtx
+/// [tucs]: crate::front::c::tu::TranslationUnit::saved_states
+#[derive(Clone, Debug)]
+pub struct StateSave {
+    pub name: String,
+    pub format: SaveFormat,
+}
+impl StateSave {
+    pub fn from_args(args: &[&str]) -> Result<Box<dyn Pass>> {
+        helper::args_range("state_save", args, 1, 2)?;
+        let format = match args.len() {
+            1 => SaveFormat::Tokens,
+            _ => helper::args_get("state_save", args, 1, "`tokens`, `text`, or `json`")?,
+        };
+        Ok(Box::new(StateSave {
+            name: args[0].to_owned(),
+            format,
+        }))
+    }
+}
 impl Pass for StateSave {
     fn run(&self, tuctx: &mut TUCtx) -> Result<()> {
-        tuctx.save_state(&self.name)
+        tuctx.save_state_as(&self.name, self.format)
     }
 }
 
@@ -93,6 +186,49 @@ impl Pass for StateWriteDebug {
     }
 }
 
+declare_pass!(
+    /// Store the state most recently saved by [`state_save`][ss] in a file
+    ///
+    /// See [`state_file`][sf] for the format. The file can be read back with
+    /// [`state_read_file`][srf].
+    ///
+    /// [ss]: crate::passes::internal::StateSave
+    /// [sf]: crate::front::c::state_file
+    /// [srf]: crate::passes::internal::StateReadFile
+    state_write_file => pub struct StateWriteFile {
+        pub name: String,
+        pub filename: String
+    }
+);
+impl Pass for StateWriteFile {
+    fn run(&self, tuctx: &mut TUCtx) -> Result<()> {
+        let state =
+            tuctx
+                .saved_state_last(&self.name)
+                .ok_or_else(|| ErrorKind::TUStateNotSaved {
+                    name: self.name.to_owned(),
+                })?;
+        state_file::write_file(state, &self.filename)
+    }
+}
+
+declare_pass!(
+    /// Replace [`TUCtx`][TUCtx]'s primary state with one stored by
+    /// [`state_write_file`][swf]
+    ///
+    /// [swf]: crate::passes::internal::StateWriteFile
+    state_read_file => pub struct StateReadFile {
+        pub filename: String
+    }
+);
+impl Pass for StateReadFile {
+    fn run(&self, tuctx: &mut TUCtx) -> Result<()> {
+        let state = state_file::read_file(&self.filename)?;
+        tuctx.set_state(state);
+        Ok(())
+    }
+}
+
 declare_pass!(
     /// Reads the specified input for this translation unit
     ///
@@ -5,13 +5,67 @@
 
 //! State common between multiple translation units
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use crate::core::{Flags, Result};
+use crate::core::{CStandard, Flags, PathDisplay, Result};
 use crate::front::c::input::Input;
 
+/// Express `path` relative to `base`, assuming both are absolute
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let mut path_components = path.components().peekable();
+    let mut base_components = base.components().peekable();
+    while path_components.peek().is_some() && path_components.peek() == base_components.peek() {
+        path_components.next();
+        base_components.next();
+    }
+
+    let mut relative = PathBuf::new();
+    for _ in base_components {
+        relative.push("..");
+    }
+    relative.extend(path_components);
+    relative
+}
+
+/// Why an `#include` could not be satisfied
+#[derive(Debug)]
+pub enum IncludeError {
+    /// No search path holds the file
+    NotFound,
+    /// The path found names a directory
+    IsDirectory(PathBuf),
+    /// The file exists but could not be read, such as for lack of permission
+    Io(PathBuf, std::io::Error),
+}
+
+impl IncludeError {
+    /// Keep the more informative of two errors from searching different paths
+    fn or(self, other: IncludeError) -> IncludeError {
+        match self {
+            IncludeError::NotFound => other,
+            error => error,
+        }
+    }
+}
+
+/// Reads a candidate for an included file, given its path
+pub type ReadInclude<'a> = dyn FnMut(&Path) -> std::result::Result<String, IncludeError> + 'a;
+
+/// Read a candidate for an included file from disk
+pub fn read_include(path: &Path) -> std::result::Result<String, IncludeError> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(IncludeError::NotFound),
+        Err(_) if path.is_dir() => Err(IncludeError::IsDirectory(path.to_owned())),
+        Err(e) => Err(IncludeError::Io(path.to_owned(), e)),
+    }
+}
+
+/// The `#if` operators answered by [`Session::has_feature()`]
+pub const FEATURE_QUERIES: &[&str] = &["__has_attribute", "__has_builtin", "__has_feature"];
+
 fn generate_session_clap<'a, 'b>() -> clap::App<'a, 'b> {
     let mut app = clap::App::new("denuocc").about("denuo c compiler");
     for arg in crate::core::generate_clap_args() {
@@ -23,6 +77,7 @@ fn generate_session_clap<'a, 'b>() -> clap::App<'a, 'b> {
 pub struct SessionBuilder {
     flags: Flags,
     extra_files: HashMap<String, String>,
+    features: HashMap<String, HashSet<String>>,
 }
 
 impl SessionBuilder {
@@ -30,6 +85,7 @@ impl SessionBuilder {
         Self {
             flags: Flags::default(),
             extra_files: HashMap::new(),
+            features: HashMap::new(),
         }
     }
 
@@ -42,7 +98,7 @@ impl SessionBuilder {
     ) -> Result<Self> {
         let matches = generate_session_clap()
             .setting(clap::AppSettings::NoBinaryName)
-            .get_matches_from_safe(input)?;
+            .get_matches_from_safe(crate::core::normalize_gcc_args(input))?;
         self.parse_cli_args_from_clap(&matches)
     }
 
@@ -62,10 +118,33 @@ impl SessionBuilder {
         self
     }
 
+    /// Declare support for `name`, so that `query(name)` evaluates to `1`
+    ///
+    /// `query` must be one of [`FEATURE_QUERIES`], e.g. `__has_builtin`.
+    pub fn add_feature(mut self, query: &str, name: String) -> Self {
+        assert!(
+            FEATURE_QUERIES.contains(&query),
+            "unknown feature query `{}`",
+            query
+        );
+        self.features
+            .entry(query.to_owned())
+            .or_default()
+            .insert(name);
+        self
+    }
+
+    /// Select the revision of the C standard to translate
+    pub fn standard(mut self, standard: CStandard) -> Self {
+        self.flags.standard = standard;
+        self
+    }
+
     pub fn build(self) -> Rc<Session> {
         Rc::new(Session {
             flags: self.flags,
             extra_files: self.extra_files,
+            features: self.features,
         })
     }
 }
@@ -74,6 +153,7 @@ impl SessionBuilder {
 #[derive(Clone, Debug)]
 pub struct Session {
     extra_files: HashMap<String, String>,
+    features: HashMap<String, HashSet<String>>,
     flags: Flags,
 }
 
@@ -88,11 +168,63 @@ impl Session {
         &self.extra_files
     }
 
+    /// Whether `name` was declared supported for the `#if` operator `query`
+    ///
+    /// Attribute names may be spelled with surrounding double underscores,
+    /// so `__has_attribute(__packed__)` is the same as
+    /// `__has_attribute(packed)`.
+    pub fn has_feature(&self, query: &str, name: &str) -> bool {
+        let name = match query {
+            "__has_attribute" => name
+                .strip_prefix("__")
+                .and_then(|n| n.strip_suffix("__"))
+                .unwrap_or(name),
+            _ => name,
+        };
+        self.features
+            .get(query)
+            .is_some_and(|names| names.contains(name))
+    }
+
     /// The command line arguments
     pub fn flags(&self) -> &Flags {
         &self.flags
     }
 
+    /// The revision of the C standard being translated
+    pub fn standard(&self) -> CStandard {
+        self.flags.standard
+    }
+
+    /// Whether diagnostics should be colorized
+    ///
+    /// See [`ColorChoice`][ColorChoice] for how this is decided.
+    ///
+    /// [ColorChoice]: crate::core::ColorChoice
+    pub fn use_color(&self) -> bool {
+        self.flags.color.should_colorize()
+    }
+
+    /// The name used to display a file in diagnostics
+    ///
+    /// `name` is how the file was referred to by the user, and `path` is where
+    /// it was found. See [`PathDisplay`][PathDisplay] for the options.
+    ///
+    /// [PathDisplay]: crate::core::PathDisplay
+    pub fn display_name(&self, name: &str, path: &Path) -> String {
+        let canonical = || std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+        match &self.flags.path_display {
+            PathDisplay::Verbatim => name.to_owned(),
+            PathDisplay::Absolute => canonical().to_string_lossy().into_owned(),
+            PathDisplay::Relative(base) => {
+                let base = std::fs::canonicalize(base).unwrap_or_else(|_| base.to_owned());
+                relative_path(&canonical(), &base)
+                    .to_string_lossy()
+                    .into_owned()
+            },
+        }
+    }
+
     /// Search both `<>` and `""` include paths
     ///
     /// `system` specifies whether the #include was wrapped in `<>` brackets. If
@@ -100,6 +232,9 @@ impl Session {
     /// will first attempt to use [`search_for_include_quote()`][sfiq] first,
     /// then fall back to [`search_for_include_system`][sfis].
     ///
+    /// A path that exists but cannot be read does not stop the search. If no
+    /// file is found, the first such path is reported.
+    ///
     /// [sfiq]: Session::search_for_include_quote
     /// [sfis]: Session::search_for_include_system
     pub fn search_for_include(
@@ -107,24 +242,101 @@ impl Session {
         desired_file: &str,
         including_file: Option<&Path>,
         system: bool,
-    ) -> Option<Input> {
-        let mut input = None;
+    ) -> std::result::Result<Input, IncludeError> {
+        self.search_for_include_with(desired_file, including_file, system, &mut read_include)
+    }
+
+    /// Like [`search_for_include()`][sfi], reading each candidate with `read`
+    ///
+    /// [sfi]: Session::search_for_include
+    pub fn search_for_include_with(
+        &self,
+        desired_file: &str,
+        including_file: Option<&Path>,
+        system: bool,
+        read: &mut ReadInclude,
+    ) -> std::result::Result<Input, IncludeError> {
+        let mut error = IncludeError::NotFound;
         if !system {
-            input = self.search_for_include_quote(desired_file, including_file);
-        }
-        if input.is_none() || system {
-            input = self.search_for_include_system(desired_file);
+            match self.search_for_include_quote(desired_file, including_file, read) {
+                Ok(input) => return Ok(input),
+                Err(e) => error = e,
+            }
         }
-        input
+        self.search_for_include_system(desired_file, read)
+            .map_err(|e| error.or(e))
+    }
+
+    /// Search the include paths following the one at index `after`
+    ///
+    /// This implements `#include_next`, which resumes the search after the
+    /// directory in which the including file was found.
+    pub fn search_for_include_next(
+        &self,
+        desired_file: &str,
+        after: usize,
+    ) -> std::result::Result<Input, IncludeError> {
+        self.search_for_include_next_with(desired_file, after, &mut read_include)
+    }
+
+    /// Like [`search_for_include_next()`][sfin], reading each candidate with
+    /// `read`
+    ///
+    /// [sfin]: Session::search_for_include_next
+    pub fn search_for_include_next_with(
+        &self,
+        desired_file: &str,
+        after: usize,
+        read: &mut ReadInclude,
+    ) -> std::result::Result<Input, IncludeError> {
+        self.search_include_paths(desired_file, after + 1, read)
     }
 
     /// Search only the system paths
-    fn search_for_include_system(&self, desired_file: &str) -> Option<Input> {
+    ///
+    /// These are the [extra files][ef] followed by each `-I` directory in
+    /// order.
+    ///
+    /// [ef]: Session::extra_files
+    fn search_for_include_system(
+        &self,
+        desired_file: &str,
+        read: &mut ReadInclude,
+    ) -> std::result::Result<Input, IncludeError> {
         if let Some(content) = self.extra_files.get(desired_file) {
-            return Some(Input::new(desired_file.to_owned(), content.clone(), None));
+            return Ok(Input::new(desired_file.to_owned(), content.clone(), None));
         }
 
-        unimplemented!("searching system paths for #include"); // TODO NYI System #include paths
+        // TODO NYI default system #include paths
+        self.search_include_paths(desired_file, 0, read)
+    }
+
+    /// Search the `-I` directories, beginning with the one at index `start`
+    fn search_include_paths(
+        &self,
+        desired_file: &str,
+        start: usize,
+        read: &mut ReadInclude,
+    ) -> std::result::Result<Input, IncludeError> {
+        let mut error = IncludeError::NotFound;
+        let directories = self.flags.include_paths.iter().enumerate().skip(start);
+        for (index, directory) in directories {
+            // make sure path we store is rooted
+            let path = std::env::current_dir()
+                .unwrap()
+                .join(directory)
+                .join(desired_file);
+            match read(&path) {
+                Ok(content) => {
+                    let name = self.display_name(desired_file, &path);
+                    let mut input = Input::new(name, content, Some(path));
+                    input.include_path_index = Some(index);
+                    return Ok(input);
+                },
+                Err(e) => error = error.or(e),
+            }
+        }
+        Err(error)
     }
 
     /// Search only the non-system paths
@@ -137,17 +349,184 @@ impl Session {
         &self,
         desired_file: &str,
         including_file: Option<&Path>,
-    ) -> Option<Input> {
+        read: &mut ReadInclude,
+    ) -> std::result::Result<Input, IncludeError> {
         let mut path = including_file
+            .and_then(Path::parent)
             .map(PathBuf::from)
             .unwrap_or(std::env::current_dir().unwrap());
         path.push(&desired_file);
 
-        let content = std::fs::read_to_string(&path);
-        if let Ok(content) = content {
-            Some(Input::new(desired_file.to_owned(), content, Some(path)))
-        } else {
-            None
+        let content = read(&path)?;
+        let name = self.display_name(desired_file, &path);
+        Ok(Input::new(name, content, Some(path)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::front::c::token::PPToken;
+    use crate::test_util::{session, TempDir};
+
+    #[test]
+    fn test_relative_path() {
+        let relative = |path, base| relative_path(Path::new(path), Path::new(base));
+        assert_eq!(relative("/a/b/c.h", "/a"), Path::new("b/c.h"));
+        assert_eq!(relative("/a/b/c.h", "/a/d/e"), Path::new("../../b/c.h"));
+        assert_eq!(relative("/a/b", "/a/b"), Path::new(""));
+    }
+
+    #[test]
+    fn test_include_quote_beside_including_file() {
+        let dir = TempDir::new("quote");
+        std::fs::create_dir_all(dir.join("src/sub")).unwrap();
+        std::fs::write(dir.join("src/main.c"), "#include \"sub/inc.h\"\n").unwrap();
+        std::fs::write(dir.join("src/sub/inc.h"), "#include \"inner.h\"\n").unwrap();
+        std::fs::write(dir.join("src/sub/inner.h"), "#undef 3\n").unwrap();
+
+        let session = Session::builder()
+            .parse_cli_args_from_str(&[] as &[&str])
+            .unwrap()
+            .build();
+        let mut tu = crate::tu::CTranslationUnit::builder(&session)
+            .source_file(&dir.join("src/main.c"))
+            .unwrap()
+            .build();
+        tu.run().unwrap();
+        let messages: Vec<_> = tu.messages().iter().map(|m| m.to_string()).collect();
+        assert_eq!(
+            messages,
+            ["inner.h:1:8: expected identifier token; found number token"]
+        );
+    }
+
+    #[test]
+    fn test_display_name_included_file() {
+        let temp = TempDir::new("paths");
+        std::fs::create_dir_all(temp.join("src")).unwrap();
+        std::fs::write(temp.join("src/main.c"), "#include \"inc.h\"\n").unwrap();
+        std::fs::write(temp.join("src/inc.h"), "#undef 3\n").unwrap();
+        let dir = std::fs::canonicalize(&temp).unwrap();
+
+        let message_for = |args: &[String]| {
+            let session = Session::builder()
+                .parse_cli_args_from_str(args)
+                .unwrap()
+                .build();
+            let mut tu = crate::tu::CTranslationUnit::builder(&session)
+                .source_file(&dir.join("src/main.c"))
+                .unwrap()
+                .build();
+            tu.run().unwrap();
+            assert_eq!(tu.messages().len(), 1);
+            tu.messages()[0].to_string()
+        };
+
+        let headline = "expected identifier token; found number token";
+        assert_eq!(message_for(&[]), format!("inc.h:1:8: {}", headline));
+        assert_eq!(
+            message_for(&["--path-display=absolute".to_owned()]),
+            format!("{}:1:8: {}", dir.join("src/inc.h").display(), headline)
+        );
+        assert_eq!(
+            message_for(&[
+                "--path-display=relative".to_owned(),
+                format!("--path-base={}", dir.display()),
+            ]),
+            format!("{}:1:8: {}", Path::new("src/inc.h").display(), headline)
+        );
+    }
+
+    #[test]
+    fn test_include_next() {
+        let dir = TempDir::new("include");
+        std::fs::create_dir_all(dir.join("first")).unwrap();
+        std::fs::create_dir_all(dir.join("second")).unwrap();
+        std::fs::write(dir.join("main.c"), "#include <a.h>\n").unwrap();
+        std::fs::write(dir.join("first/a.h"), "#include_next <a.h>\nfirst\n").unwrap();
+        std::fs::write(dir.join("second/a.h"), "second\n").unwrap();
+
+        let first = format!("-I{}", dir.join("first").display());
+        let second = format!("-I{}", dir.join("second").display());
+        let session = session(&["--pass=phase1..phase4", &first, &second]).build();
+        let mut tu = crate::tu::CTranslationUnit::builder(&session)
+            .source_file(&dir.join("main.c"))
+            .unwrap()
+            .build();
+        tu.run().unwrap();
+        assert!(tu.messages().is_empty());
+
+        let tokens = tu.saved_states("<final>")[0].as_pptokens().unwrap();
+        let words: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.is_ident())
+            .map(PPToken::as_str)
+            .collect();
+        assert_eq!(words, ["second", "first"]);
+    }
+
+    fn include_messages(dir: &Path, search: &Path) -> Vec<String> {
+        let search = format!("-I{}", search.display());
+        let session = session(&["--pass=phase1..phase4", &search]).build();
+        let mut tu = crate::tu::CTranslationUnit::builder(&session)
+            .source_file(&dir.join("main.c"))
+            .unwrap()
+            .build();
+        let _ = tu.run();
+        tu.messages()
+            .iter()
+            .map(|m| m.kind.get_headline())
+            .collect()
+    }
+
+    #[test]
+    fn test_include_directory() {
+        let dir = TempDir::new("include-dir");
+        std::fs::create_dir_all(dir.join("first/a.h")).unwrap();
+        std::fs::create_dir_all(dir.join("second")).unwrap();
+        std::fs::write(dir.join("main.c"), "#include \"sub\"\n").unwrap();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        assert_eq!(
+            include_messages(&dir, &dir.join("first")),
+            ["could not include `sub`: is a directory"]
+        );
+
+        // a directory earlier in the search does not hide a file found later
+        std::fs::write(dir.join("main.c"), "#include <a.h>\n").unwrap();
+        std::fs::write(dir.join("second/a.h"), "\n").unwrap();
+        let session = Session::builder()
+            .parse_cli_args_from_str(&[
+                format!("-I{}", dir.join("first").display()),
+                format!("-I{}", dir.join("second").display()),
+            ])
+            .unwrap()
+            .build();
+        let input = session.search_for_include("a.h", None, true).unwrap();
+        assert_eq!(input.include_path_index, Some(1));
+        match session.search_for_include("sub", Some(&dir.join("main.c")), false) {
+            Err(IncludeError::IsDirectory(path)) => assert_eq!(path, dir.join("sub")),
+            other => panic!("unexpected {:?}", other.map(|i| i.name)),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_include_unreadable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("include-perm");
+        std::fs::write(dir.join("main.c"), "#include \"a.h\"\n").unwrap();
+        std::fs::write(dir.join("a.h"), "\n").unwrap();
+        std::fs::set_permissions(dir.join("a.h"), std::fs::Permissions::from_mode(0o000)).unwrap();
+
+        // privileged users can read the file regardless of its mode
+        if std::fs::read(dir.join("a.h")).is_err() {
+            let messages = include_messages(&dir, &dir);
+            assert_eq!(messages.len(), 1);
+            assert!(messages[0].starts_with("could not include `a.h`: "));
+            assert!(!messages[0].ends_with("file not found"));
         }
     }
 }
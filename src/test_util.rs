@@ -0,0 +1,75 @@
+// Licensed   under  the   Apache  License,   Version  2.0   <LICENSE-APACHE  or
+// http://www.apache.org/licenses/LICENSE-2.0> or  the MIT  license <LICENSE-MIT
+// or http://opensource.org/licenses/MIT>, at your option.  This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Fixtures shared by the unit tests
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::session::{Session, SessionBuilder};
+use crate::tu::CTranslationUnit;
+
+/// Start a session from command line `args`
+///
+/// The input is read by `state_read_input` before any passes listed in
+/// `args`.
+pub(crate) fn session(args: &[&str]) -> SessionBuilder {
+    let mut all = vec!["--pass=state_read_input"];
+    all.extend_from_slice(args);
+    Session::builder().parse_cli_args_from_str(&all).unwrap()
+}
+
+/// Build a translation unit of `source` within `session`
+pub(crate) fn unit_in(session: &Rc<Session>, source: &str) -> CTranslationUnit {
+    CTranslationUnit::builder(session)
+        .source_string("<unit-test>", source)
+        .build()
+}
+
+/// Build a translation unit of `source` in a session with `args`
+///
+/// See [`session()`] for how the input is read.
+pub(crate) fn unit(args: &[&str], source: &str) -> CTranslationUnit {
+    unit_in(&session(args).build(), source)
+}
+
+/// A directory for the files of a test, removed along with its contents when
+/// dropped
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create an empty directory, named after `name` and this process so that
+    /// concurrent tests and test runs do not collide
+    pub(crate) fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("denuocc-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // a failed test may be unwinding, so do not panic again
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -5,5 +5,7 @@
 
 //! Functions and data structures not necessarily specific to compilers
 
+pub mod escape;
 pub mod hashed;
+pub use escape::{c_escape, c_unescape, EscapeError};
 pub use hashed::Hashed;
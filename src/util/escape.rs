@@ -0,0 +1,328 @@
+// Licensed   under  the   Apache  License,   Version  2.0   <LICENSE-APACHE  or
+// http://www.apache.org/licenses/LICENSE-2.0> or  the MIT  license <LICENSE-MIT
+// or http://opensource.org/licenses/MIT>, at your option.  This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Escape sequences of C string literals and character constants
+//!
+//! These functions work on the text between the quotes, without any encoding
+//! prefix. [`c_escape()`][c_escape] and [`c_unescape()`][c_unescape] are
+//! inverses:
+//! ```
+//! use denuocc::util::{c_escape, c_unescape};
+//!
+//! let text = "say \"hi\"\n";
+//! assert_eq!(c_escape(text), r#"say \"hi\"\n"#);
+//! assert_eq!(c_unescape(&c_escape(text)).unwrap(), text);
+//! ```
+use std::convert::TryFrom;
+
+/// Why an escape sequence could not be translated
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EscapeError {
+    /// Nothing follows the backslash, or a numeric escape has no digits
+    Empty,
+    /// A universal character name has too few digits
+    Incomplete {
+        prefix: char,
+        expected: usize,
+        found: usize,
+    },
+    /// A hexadecimal escape has more digits than a code unit holds
+    OutOfRange { prefix: &'static str, value: String },
+    /// A numeric escape names a value that is not a character
+    Invalid { prefix: &'static str, value: String },
+    /// The backslash is followed by a character beginning no escape
+    Unrecognized(char),
+}
+
+impl std::fmt::Display for EscapeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use EscapeError::*;
+        match self {
+            Empty => write!(f, "expected character after escape sequence"),
+            Incomplete {
+                prefix,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected {} digits after `\\{}`; found {}",
+                expected, prefix, found
+            ),
+            OutOfRange { prefix, value } => write!(f, "`\\{}{}` exceeds range", prefix, value),
+            Invalid { prefix, value } => write!(f, "`\\{}{}` cannot be represented", prefix, value),
+            Unrecognized(escape) => write!(f, "`\\{}` is not a valid escape", escape),
+        }
+    }
+}
+
+/// Represents what character came after the backslash in a numeric escape sequence
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DigitEscapePrefix {
+    Universal16,
+    Universal32,
+    Hexadecimal,
+    Octal,
+}
+
+impl DigitEscapePrefix {
+    fn skip(&self) -> bool {
+        use DigitEscapePrefix::*;
+        match *self {
+            Hexadecimal | Universal16 | Universal32 => true,
+            Octal => false,
+        }
+    }
+
+    fn radix(&self) -> u32 {
+        use DigitEscapePrefix::*;
+        match *self {
+            Hexadecimal | Universal16 | Universal32 => 16,
+            Octal => 8,
+        }
+    }
+
+    fn max_len(&self) -> Option<usize> {
+        use DigitEscapePrefix::*;
+        match *self {
+            Hexadecimal => None,
+            Octal => Some(3),
+            Universal16 => Some(4),
+            Universal32 => Some(8),
+        }
+    }
+
+    fn exact_len(&self) -> Option<usize> {
+        use DigitEscapePrefix::*;
+        match *self {
+            Hexadecimal | Octal => None,
+            Universal16 => Some(4),
+            Universal32 => Some(8),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        use DigitEscapePrefix::*;
+        match *self {
+            Hexadecimal => "x",
+            Octal => "",
+            Universal16 => "u",
+            Universal32 => "U",
+        }
+    }
+}
+
+/// Parse a numeric escape digit like `\x1234` or `\040`.
+fn parse_digits(
+    iter: &mut std::iter::Peekable<std::str::Chars>,
+    unit_bytes: usize,
+    prefix: DigitEscapePrefix,
+) -> Result<char, EscapeError> {
+    if prefix.skip() {
+        // throw away the x, u, or U prefix for \x, \u, and \U escapes respectively
+        iter.next();
+    }
+
+    let mut buffer = String::new();
+    while iter.peek().map(|c| c.is_digit(prefix.radix())) == Some(true) {
+        buffer.push(iter.next().unwrap());
+
+        // don't parse more than necessary for universal-character-name or octal-escape-sequence
+        if let Some(max) = prefix.max_len() {
+            if buffer.len() >= max {
+                break;
+            }
+        }
+    }
+
+    if buffer.is_empty() {
+        return Err(EscapeError::Empty);
+    }
+
+    if let Some(expected) = prefix.exact_len().filter(|&desired| buffer.len() < desired) {
+        // detect an incomplete universal-character-name
+        return Err(EscapeError::Incomplete {
+            prefix: if prefix == DigitEscapePrefix::Universal16 {
+                'u'
+            } else {
+                'U'
+            },
+            expected,
+            found: buffer.len(),
+        });
+    } else if prefix == DigitEscapePrefix::Hexadecimal && buffer.len() > unit_bytes * 2 {
+        // detect a hexadecimal-escape-sequence that doesn't fit.
+        // size is measured in bytes; there are 2 hexadecimal digits in a byte
+        return Err(EscapeError::OutOfRange {
+            prefix: prefix.as_str(),
+            value: buffer,
+        });
+    }
+
+    let value = char::try_from(buffer.chars().fold(0u32, |current, next| {
+        current * prefix.radix() + next.to_digit(prefix.radix()).unwrap()
+    }));
+
+    value.map_err(|_| EscapeError::Invalid {
+        prefix: prefix.as_str(),
+        value: buffer,
+    })
+}
+
+/// Translate every escape sequence in `text`, continuing past errors
+///
+/// `unit_bytes` is the size of the code unit of the literal's encoding, which
+/// bounds the digits of a hexadecimal escape. Each erroneous escape is passed
+/// to `on_error` and contributes nothing to the output.
+pub fn c_unescape_with(
+    text: &str,
+    unit_bytes: usize,
+    mut on_error: impl FnMut(EscapeError),
+) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut iter = text.chars().peekable();
+
+    while let Some(c) = iter.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+
+        // just saw a backslash, beginning an escape
+        let prefix = match iter.peek() {
+            Some('x') => Some(DigitEscapePrefix::Hexadecimal), // hexadecimal-escape-sequence
+            Some('u') => Some(DigitEscapePrefix::Universal16), // universal-character-name
+            Some('U') => Some(DigitEscapePrefix::Universal32), // universal-character-name
+            Some(c) if c.is_digit(8) => Some(DigitEscapePrefix::Octal), // octal-escape-sequence
+            _ => None,
+        };
+
+        if let Some(prefix) = prefix {
+            // the escape is made of some sequence of digits
+            match parse_digits(&mut iter, unit_bytes, prefix) {
+                Ok(c) => output.push(c),
+                Err(error) => on_error(error),
+            }
+        } else {
+            match iter.next() {
+                // simple-escape-sequence
+                Some('\\') => output.push('\\'),
+                Some('?') => output.push('?'),
+                Some('\'') => output.push('\''),
+                Some('"') => output.push('"'),
+                Some('a') => output.push('\x07'),
+                Some('b') => output.push('\x08'),
+                Some('f') => output.push('\x0c'),
+                Some('n') => output.push('\x0a'),
+                Some('r') => output.push('\x0d'),
+                Some('t') => output.push('\x09'),
+                Some('v') => output.push('\x0b'),
+
+                Some(c) => on_error(EscapeError::Unrecognized(c)),
+                None => on_error(EscapeError::Empty),
+            }
+        }
+    }
+
+    output
+}
+
+/// Translate the escape sequences of the contents of a plain string literal
+///
+/// Fails on the first erroneous escape sequence.
+pub fn c_unescape(text: &str) -> Result<String, EscapeError> {
+    let mut first_error = None;
+    let output = c_unescape_with(text, 1, |error| {
+        first_error.get_or_insert(error);
+    });
+    match first_error {
+        Some(error) => Err(error),
+        None => Ok(output),
+    }
+}
+
+/// Escape `text` so it may appear between the quotes of a string literal
+///
+/// Backslashes and double quotes are preceded by a backslash, and control
+/// characters are written as escape sequences. Everything else, including
+/// characters outside ASCII, is kept as is.
+pub fn c_escape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => output.push_str("\\\\"),
+            '"' => output.push_str("\\\""),
+            '\x07' => output.push_str("\\a"),
+            '\x08' => output.push_str("\\b"),
+            '\x0c' => output.push_str("\\f"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            '\x0b' => output.push_str("\\v"),
+            // octal escapes stop after three digits, unlike hexadecimal ones,
+            // so whatever follows cannot be mistaken for part of the escape
+            c if c.is_ascii_control() => output.push_str(&format!("\\{:03o}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_c_escape() {
+        assert_eq!(c_escape("plain"), "plain");
+        assert_eq!(c_escape("a\\b\"c'"), "a\\\\b\\\"c'");
+        assert_eq!(c_escape("\n\t\x07\x7f\x01é"), "\\n\\t\\a\\177\\001é");
+    }
+
+    #[test]
+    fn test_c_unescape() {
+        assert_eq!(c_unescape("a\\nb").unwrap(), "a\nb");
+        assert_eq!(c_unescape("\\x41\\101\\0").unwrap(), "AA\0");
+        assert_eq!(c_unescape("\\u00e9\\U0001F600").unwrap(), "é\u{1F600}");
+        assert_eq!(c_unescape("\\\\\\\"\\'\\?").unwrap(), "\\\"'?");
+    }
+
+    #[test]
+    fn test_c_unescape_invalid() {
+        assert_eq!(c_unescape("\\q"), Err(EscapeError::Unrecognized('q')));
+        assert_eq!(c_unescape("abc\\"), Err(EscapeError::Empty));
+        assert_eq!(c_unescape("\\x"), Err(EscapeError::Empty));
+        assert_eq!(
+            c_unescape("\\u00e"),
+            Err(EscapeError::Incomplete {
+                prefix: 'u',
+                expected: 4,
+                found: 3,
+            })
+        );
+        assert_eq!(
+            c_unescape("\\x100"),
+            Err(EscapeError::OutOfRange {
+                prefix: "x",
+                value: "100".to_owned(),
+            })
+        );
+        assert_eq!(
+            c_unescape("\\uD800"),
+            Err(EscapeError::Invalid {
+                prefix: "u",
+                value: "D800".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_c_escape_round_trip() {
+        let text = "tab\there \"quoted\" back\\slash\x1b[0m é";
+        assert_eq!(c_unescape(&c_escape(text)).unwrap(), text);
+        let errors = std::cell::Cell::new(0);
+        c_unescape_with("\\q\\x", 1, |_| errors.set(errors.get() + 1));
+        assert_eq!(errors.get(), 2);
+    }
+}
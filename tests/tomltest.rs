@@ -4,7 +4,7 @@
 // copied, modified, or distributed except according to those terms.
 
 // TODO revisit these unstable feature-attributes
-#![feature(test, rustc_private)]
+#![feature(test)]
 
 extern crate test;
 
@@ -75,6 +75,7 @@ struct Suite {
 struct Case {
     input: Spanned<String>,
     output: Option<String>,
+    args: Option<Vec<String>>,
     messages: Option<Vec<String>>,
     ignored: Option<bool>,
     should_panic: Option<ShouldPanic>,
@@ -90,6 +91,7 @@ impl Case {
         for pass in &suite.passes {
             args.push(format!("--pass={}", pass))
         }
+        args.extend(self.args.iter().flatten().cloned());
 
         let session = Session::builder()
             .parse_cli_args_from_str(&args)
@@ -106,7 +108,7 @@ impl Case {
     }
 
     fn run_input(&self, suite: &Suite) -> TUState {
-        let tu = self.compile_case(self.input.get_mut(), suite);
+        let tu = self.compile_case(self.input.get_ref(), suite);
         let state = tu.saved_states("<final>")[0].clone();
 
         fn recurse_messages(output: &mut Vec<String>, messages: &[Message]) {
@@ -240,13 +242,21 @@ fn read_toml(
                         .clone()
                         .unwrap_or(ShouldPanic::Bool(false))
                         .into(),
-                    allow_fail: false,
+                    ignore_message: None,
+                    source_file: "",
+                    start_line: 0,
+                    start_col: 0,
+                    end_line: 0,
+                    end_col: 0,
+                    compile_fail: false,
+                    no_run: false,
                     test_type: TestType::UnitTest,
                 },
                 testfn: TestFn::DynTestFn({
                     let suite = Arc::clone(&suite);
                     Box::new(move || {
                         case.run(&suite);
+                        Ok(())
                     })
                 }),
             });
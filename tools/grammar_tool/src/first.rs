@@ -6,7 +6,36 @@
 use std::collections::{HashMap, HashSet};
 
 use crate::grammar::Grammar;
-use crate::token::{empty_string_set, string_set_crossproduct, StringSet};
+use crate::token::{
+    empty_string_set, into_sorted_strings, sorted_strings, string_set_crossproduct, StringSet,
+};
+
+/// A grammar whose FIRST sets cannot be computed meaningfully
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FirstError {
+    /// These nonterminals derive no string of terminals, such as `A : A ;`
+    Unproductive(Vec<String>),
+    /// These nonterminals were still changing when the iteration limit was
+    /// reached
+    NoFixpoint(Vec<String>),
+}
+
+impl std::fmt::Display for FirstError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FirstError::Unproductive(nonterminals) => write!(
+                f,
+                "nonterminals derive no string of terminals: {}",
+                nonterminals.join(" ")
+            ),
+            FirstError::NoFixpoint(nonterminals) => write!(
+                f,
+                "FIRST sets did not converge for nonterminals: {}",
+                nonterminals.join(" ")
+            ),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct First<'g> {
@@ -15,11 +44,21 @@ pub struct First<'g> {
 }
 
 impl<'g> First<'g> {
+    /// Compute the FIRST sets, panicking if the grammar is degenerate
     pub fn new(grammar: &'g Grammar, k: usize) -> First<'g> {
-        First {
+        First::try_new(grammar, k).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new(grammar: &'g Grammar, k: usize) -> Result<First<'g>, FirstError> {
+        Ok(First {
             k,
-            sets: FirstBuilder::new(grammar, k).build(),
-        }
+            sets: FirstBuilder::new(grammar, k).build()?,
+        })
+    }
+
+    /// Number of tokens of lookahead
+    pub fn k(&self) -> usize {
+        self.k
     }
 
     pub fn query_token(&self, nonterminal: impl AsRef<str>) -> &StringSet<'g> {
@@ -31,6 +70,22 @@ impl<'g> First<'g> {
             string_set_crossproduct(&acc, self.query_token(x), self.k)
         })
     }
+
+    /// Like [`query_token()`](First::query_token), but in sorted order
+    pub fn query_token_sorted(
+        &self,
+        nonterminal: impl AsRef<str>,
+    ) -> impl Iterator<Item = &Vec<&'g str>> {
+        sorted_strings(self.query_token(nonterminal))
+    }
+
+    /// Like [`query_string()`](First::query_string), but in sorted order
+    pub fn query_string_sorted(
+        &self,
+        string: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> impl Iterator<Item = Vec<&'g str>> {
+        into_sorted_strings(self.query_string(string))
+    }
 }
 
 struct FirstBuilder<'g> {
@@ -48,11 +103,37 @@ impl<'g> FirstBuilder<'g> {
         }
     }
 
-    fn build(mut self) -> HashMap<&'g str, StringSet<'g>> {
+    fn build(mut self) -> Result<HashMap<&'g str, StringSet<'g>>, FirstError> {
         self.populate_terminals();
-        self.populate_nonterminals();
+        self.populate_nonterminals()?;
 
-        self.f
+        let mut unproductive = self
+            .grammar
+            .nonterminals
+            .iter()
+            .filter(|n| self.f[n.as_str()].is_empty())
+            .cloned()
+            .collect::<Vec<_>>();
+        if !unproductive.is_empty() {
+            unproductive.sort();
+            return Err(FirstError::Unproductive(unproductive));
+        }
+
+        Ok(self.f)
+    }
+
+    /// Most iterations needed to reach a fixpoint
+    ///
+    /// Each iteration adds at least one string to some set, and every set
+    /// holds strings of at most `k` terminals.
+    fn iteration_limit(&self) -> usize {
+        let terminals = self.grammar.terminals.len();
+        let strings = (0..=self.k as u32).fold(0usize, |sum, i| {
+            sum.saturating_add(terminals.saturating_pow(i))
+        });
+        strings
+            .saturating_mul(self.grammar.nonterminals.len())
+            .saturating_add(1)
     }
 
     fn populate_terminals(&mut self) {
@@ -64,11 +145,11 @@ impl<'g> FirstBuilder<'g> {
         }
     }
 
-    fn populate_nonterminals(&mut self) {
+    fn populate_nonterminals(&mut self) -> Result<(), FirstError> {
         for nonterminal in &self.grammar.nonterminals {
             self.populate_nonterminal_zero(nonterminal);
         }
-        for _ in 1.. {
+        for _ in 0..self.iteration_limit() {
             let mut changes = Vec::new();
             for nonterminal in &self.grammar.nonterminals {
                 if let Some(change) = self.populate_nonterminal_i(nonterminal) {
@@ -76,11 +157,21 @@ impl<'g> FirstBuilder<'g> {
                 }
             }
             if changes.is_empty() {
-                break;
+                return Ok(());
             } else {
                 self.f.extend(changes);
             }
         }
+
+        let mut changing = self
+            .grammar
+            .nonterminals
+            .iter()
+            .filter(|n| self.populate_nonterminal_i(n).is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+        changing.sort();
+        Err(FirstError::NoFixpoint(changing))
     }
 
     fn is_terminal(&self, token: &'g str) -> bool {
@@ -126,3 +217,52 @@ impl<'g> FirstBuilder<'g> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_query_sorted() {
+        let grammar = Grammar::from_str("%token a b c\n%start S\n%%\nS : c | b S | a ;\n").unwrap();
+        let first = First::new(&grammar, 2);
+
+        let sequences = first.query_token_sorted("S").collect::<Vec<_>>();
+        assert_eq!(
+            sequences,
+            [
+                &vec!["a"],
+                &vec!["b", "a"],
+                &vec!["b", "b"],
+                &vec!["b", "c"],
+                &vec!["c"]
+            ]
+        );
+
+        let sequences = first.query_string_sorted(&["c", "S"]).collect::<Vec<_>>();
+        assert_eq!(sequences, [vec!["c", "a"], vec!["c", "b"], vec!["c", "c"]]);
+    }
+
+    #[test]
+    fn test_unproductive() {
+        let grammar =
+            Grammar::from_str("%token a\n%start S\n%%\nS : a | B ;\nB : B ;\nC : C a ;\n").unwrap();
+        let error = First::try_new(&grammar, 1).unwrap_err();
+        assert_eq!(
+            error,
+            FirstError::Unproductive(vec!["B".to_owned(), "C".to_owned()])
+        );
+        assert_eq!(
+            error.to_string(),
+            "nonterminals derive no string of terminals: B C"
+        );
+
+        // recursion is fine as long as there is some way out
+        let grammar = Grammar::from_str("%token a\n%start S\n%%\nS : S | S a | a ;\n").unwrap();
+        let first = First::try_new(&grammar, 2).unwrap();
+        let sequences = first.query_token_sorted("S").collect::<Vec<_>>();
+        assert_eq!(sequences, [&vec!["a"], &vec!["a", "a"]]);
+    }
+}
@@ -7,8 +7,11 @@ use std::collections::HashMap;
 
 use crate::first::First;
 use crate::grammar::Grammar;
-use crate::token::StringSet;
+use crate::token::{sorted_strings, string_set_crossproduct, StringSet};
 
+/// The strings of up to `k` tokens that may follow each nonterminal
+///
+/// A string shorter than `k` means the input may end after it.
 pub struct Follow<'g> {
     sets: HashMap<&'g str, StringSet<'g>>,
 }
@@ -21,6 +24,11 @@ impl<'g> Follow<'g> {
     pub fn query_token(&self, token: &str) -> &StringSet<'g> {
         &self.sets[token]
     }
+
+    /// Like [`query_token()`](Follow::query_token), but in sorted order
+    pub fn query_token_sorted(&self, token: &str) -> impl Iterator<Item = &Vec<&'g str>> {
+        sorted_strings(self.query_token(token))
+    }
 }
 
 struct Builder<'g> {
@@ -39,51 +47,40 @@ impl<'g> Builder<'g> {
     }
 
     fn build(mut self) -> Follow<'g> {
-        self.build_normal();
-        self.build_sentential_tails(&self.grammar.start);
-
-        Follow { sets: self.sets }
-    }
+        let grammar = self.grammar;
+        for nonterminal in &grammar.nonterminals {
+            self.sets.insert(nonterminal, StringSet::new());
+        }
+        // the input ends after the start symbol
+        self.sets
+            .get_mut(grammar.start.as_str())
+            .unwrap()
+            .insert(Vec::new());
 
-    fn build_normal(&mut self) {
-        for nonterminal in &self.grammar.nonterminals {
-            let mut set = StringSet::new();
-            for production in &self.grammar.productions {
-                for i in 0..production.tokens.len() {
-                    if production.tokens[i] == *nonterminal {
-                        set.extend(self.first.query_string(&production.tokens[i + 1..]));
+        // for every production `A : x B y`, whatever follows `A` may follow
+        // `B` once `y` is exhausted, so FOLLOW(B) contains FIRST(y FOLLOW(A)).
+        // repeat until no set grows
+        loop {
+            let mut changed = false;
+            for production in &grammar.productions {
+                let follows = self.sets[production.name.as_str()].clone();
+                for (i, token) in production.tokens.iter().enumerate() {
+                    if !grammar.nonterminals.contains(token) {
+                        continue;
+                    }
+                    let tail = self.first.query_string(&production.tokens[i + 1..]);
+                    let strings = string_set_crossproduct(&tail, &follows, self.first.k());
+                    let set = self.sets.get_mut(token.as_str()).unwrap();
+                    for string in strings {
+                        changed |= set.insert(string);
                     }
                 }
             }
-            self.sets.insert(nonterminal, set);
-        }
-    }
-
-    fn build_sentential_tails(&mut self, nonterminal: &str) {
-        for production in &self.grammar.production_map[nonterminal] {
-            let tail_nonterminals = production
-                .tokens
-                .iter()
-                .rev()
-                .take_while(|t| self.grammar.nonterminals.contains(*t))
-                .map(|t| t.as_str())
-                .collect::<Vec<_>>();
-            for last in tail_nonterminals {
-                // examine nonterminals working backwards from the end of the
-                // production. halt after encountering the first terminal
-                let unseen = self.sets.get_mut(last).unwrap().insert(Vec::new());
-                if unseen {
-                    // avoid infinitely recursing in a production like `S: a S`
-                    self.build_sentential_tails(last);
-                }
-
-                // if this nonterminal can expand to an empty string, then the
-                // previous nonterminal in the production could also be a
-                // sentential tail
-                if !self.first.query_token(last).contains(&Vec::new()) {
-                    break;
-                }
+            if !changed {
+                break;
             }
         }
+
+        Follow { sets: self.sets }
     }
 }
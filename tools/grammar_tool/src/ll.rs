@@ -0,0 +1,309 @@
+// Licensed   under  the   Apache  License,   Version  2.0   <LICENSE-APACHE  or
+// http://www.apache.org/licenses/LICENSE-2.0> or  the MIT  license <LICENSE-MIT
+// or http://opensource.org/licenses/MIT>, at your option.  This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::collections::HashMap;
+
+use crate::first::First;
+use crate::follow::Follow;
+use crate::grammar::Grammar;
+use crate::token::{sorted_strings, StringSet};
+
+/// A reason a grammar is not (strong) LL(k)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Conflict<'g> {
+    /// Two productions begin with the same lookahead when followed by `suffix`
+    Ll {
+        productions: [usize; 2],
+        lookahead: Vec<Vec<&'g str>>,
+        suffix: Vec<&'g str>,
+    },
+    /// Several productions begin with the same lookahead when followed by
+    /// different strings from the FOLLOW set
+    StrongLl {
+        productions: Vec<usize>,
+        lookahead: Vec<&'g str>,
+    },
+}
+
+/// Whether a grammar is LL(k) and strong LL(k)
+#[derive(Clone, Debug)]
+pub struct Analysis<'g> {
+    pub ll_k: bool,
+    pub strong: bool,
+    pub conflicts: Vec<Conflict<'g>>,
+}
+
+/// Quote a string for JSON
+fn json_string(s: &str) -> String {
+    let mut output = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            c if c.is_control() => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+/// Format a sequence of tokens as a JSON array of strings
+fn json_tokens(tokens: &[&str]) -> String {
+    let tokens = tokens.iter().map(|t| json_string(t)).collect::<Vec<_>>();
+    format!("[{}]", tokens.join(", "))
+}
+
+impl<'g> Conflict<'g> {
+    /// Format this conflict as a JSON object
+    ///
+    /// Every object has a `kind` of `"ll"` or `"strong-ll"`, the ids of the
+    /// conflicting `productions`, and the `lookahead` sequences they share. An
+    /// LL conflict also has the `suffix` following the nonterminal.
+    pub fn to_json(&self) -> String {
+        match self {
+            Conflict::Ll {
+                productions,
+                lookahead,
+                suffix,
+            } => {
+                let lookahead = lookahead.iter().map(|l| json_tokens(l)).collect::<Vec<_>>();
+                format!(
+                    "{{\"kind\": \"ll\", \"productions\": {:?}, \"lookahead\": [{}], \"suffix\": {}}}",
+                    productions,
+                    lookahead.join(", "),
+                    json_tokens(suffix)
+                )
+            },
+            Conflict::StrongLl {
+                productions,
+                lookahead,
+            } => format!(
+                "{{\"kind\": \"strong-ll\", \"productions\": {:?}, \"lookahead\": [{}]}}",
+                productions,
+                json_tokens(lookahead)
+            ),
+        }
+    }
+}
+
+impl<'g> Analysis<'g> {
+    /// Format the analysis as a single JSON object, for checking grammars in
+    /// scripts
+    pub fn to_json(&self, k: usize) -> String {
+        let conflicts = self
+            .conflicts
+            .iter()
+            .map(Conflict::to_json)
+            .collect::<Vec<_>>();
+        format!(
+            "{{\"k\": {}, \"ll\": {}, \"strong\": {}, \"conflicts\": [{}]}}",
+            k,
+            self.ll_k,
+            self.ll_k && self.strong,
+            conflicts.join(", ")
+        )
+    }
+
+    /// Compare the lookahead of every pair of productions of each nonterminal
+    ///
+    /// Strong conflicts are only sought while the grammar is still LL(k), since
+    /// only an LL(k) grammar can be strong.
+    pub fn new(grammar: &'g Grammar, first: &First<'g>, follow: &Follow<'g>) -> Analysis<'g> {
+        let mut analysis = Analysis {
+            ll_k: true,
+            strong: true,
+            conflicts: Vec::new(),
+        };
+
+        for nonterminal in grammar.nonterminals_in_order() {
+            let candidates = &grammar.production_map[nonterminal];
+            if candidates.len() == 1 {
+                // if this nonterminal has only one production, it cannot create an
+                // LL(k) ambiguity
+                continue;
+            }
+
+            for i in 0..candidates.len() {
+                for j in (i + 1)..candidates.len() {
+                    let a = &candidates[i];
+                    let b = &candidates[j];
+                    let mut all_first_a = StringSet::new();
+                    let mut all_first_b = StringSet::new();
+                    let mut sources = HashMap::<Vec<&str>, Vec<usize>>::new();
+
+                    for f in follow.query_token_sorted(nonterminal) {
+                        let mut a_tokens = a.tokens.iter().map(|t| t.as_str()).collect::<Vec<_>>();
+                        let mut b_tokens = b.tokens.iter().map(|t| t.as_str()).collect::<Vec<_>>();
+                        a_tokens.extend(f);
+                        b_tokens.extend(f);
+
+                        let first_b = first.query_string(b_tokens);
+                        let mut lookahead = Vec::new();
+                        for fa in first.query_string_sorted(a_tokens) {
+                            sources.entry(fa.clone()).or_default().push(a.id);
+                            if first_b.contains(&fa) {
+                                lookahead.push(fa.clone());
+                            }
+                            all_first_a.insert(fa);
+                        }
+                        for fb in first_b {
+                            sources.entry(fb.clone()).or_default().push(b.id);
+                            all_first_b.insert(fb);
+                        }
+
+                        if !lookahead.is_empty() {
+                            analysis.ll_k = false;
+                            analysis.conflicts.push(Conflict::Ll {
+                                productions: [a.id, b.id],
+                                lookahead,
+                                suffix: f.clone(),
+                            });
+                        }
+                    }
+
+                    if analysis.ll_k {
+                        for conflict in
+                            sorted_strings(&all_first_a).filter(|s| all_first_b.contains(*s))
+                        {
+                            let mut productions = sources[conflict].clone();
+                            productions.dedup();
+                            debug_assert!(productions.len() >= 2);
+
+                            analysis.strong = false;
+                            analysis.conflicts.push(Conflict::StrongLl {
+                                productions,
+                                lookahead: conflict.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        analysis
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn load_grammar(name: &str) -> Grammar {
+        let path = format!(
+            "{}/grammars/aho_ullman/{}.yacc",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        Grammar::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn analyze(grammar: &Grammar, k: usize) -> (bool, bool, usize) {
+        let first = First::new(grammar, k);
+        let follow = Follow::new(grammar, &first);
+        let analysis = Analysis::new(grammar, &first, &follow);
+        (analysis.ll_k, analysis.strong, analysis.conflicts.len())
+    }
+
+    fn strings<'a>(set: impl Iterator<Item = &'a Vec<&'a str>>) -> Vec<String> {
+        set.map(|s| s.join(" ")).collect()
+    }
+
+    #[test]
+    fn test_first_follow_k2() {
+        let grammar = load_grammar("example_5.3");
+        let first = First::new(&grammar, 2);
+        let follow = Follow::new(&grammar, &first);
+
+        assert_eq!(strings(first.query_token_sorted("S")), ["", "a b"]);
+        assert_eq!(strings(first.query_token_sorted("A")), ["a a", "a b", "b"]);
+        // `A` ends `S`, so whatever follows `S` also follows `A`
+        assert_eq!(strings(follow.query_token_sorted("S")), ["", "a a"]);
+        assert_eq!(strings(follow.query_token_sorted("A")), ["", "a a"]);
+
+        // with one token, both productions of `S` may begin with `a`
+        let first = First::new(&grammar, 1);
+        let follow = Follow::new(&grammar, &first);
+        assert_eq!(strings(first.query_token_sorted("S")), ["", "a"]);
+        assert_eq!(strings(follow.query_token_sorted("S")), ["", "a"]);
+    }
+
+    #[test]
+    fn test_ll2_not_ll1() {
+        let grammar = load_grammar("example_5.3");
+        assert_eq!(analyze(&grammar, 1), (false, true, 1));
+        assert_eq!(analyze(&grammar, 2), (true, true, 0));
+
+        let first = First::new(&grammar, 1);
+        let follow = Follow::new(&grammar, &first);
+        assert_eq!(
+            Analysis::new(&grammar, &first, &follow).conflicts,
+            [Conflict::Ll {
+                productions: [0, 1],
+                lookahead: vec![vec!["a"]],
+                suffix: vec!["a"],
+            }]
+        );
+
+        let grammar = load_grammar("example_5.7");
+        assert!(!analyze(&grammar, 1).0);
+        assert_eq!(analyze(&grammar, 2), (true, true, 0));
+    }
+
+    #[test]
+    fn test_weak_ll2() {
+        let grammar = load_grammar("example_5.8");
+        assert!(!analyze(&grammar, 1).0);
+        assert_eq!(analyze(&grammar, 2), (true, false, 1));
+
+        let first = First::new(&grammar, 2);
+        let follow = Follow::new(&grammar, &first);
+        assert_eq!(
+            Analysis::new(&grammar, &first, &follow).conflicts,
+            [Conflict::StrongLl {
+                productions: vec![2, 3],
+                lookahead: vec!["b", "a"],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let grammar = load_grammar("example_5.8");
+        let first = First::new(&grammar, 1);
+        let follow = Follow::new(&grammar, &first);
+        let json = Analysis::new(&grammar, &first, &follow).to_json(1);
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["k"], 1);
+        assert_eq!(value["ll"], false);
+        assert_eq!(value["strong"], false);
+        assert_eq!(
+            value["conflicts"],
+            serde_json::json!([{
+                "kind": "ll",
+                "productions": [2, 3],
+                "lookahead": [["b"]],
+                "suffix": ["b"],
+            }])
+        );
+
+        let first = First::new(&grammar, 2);
+        let follow = Follow::new(&grammar, &first);
+        let json = Analysis::new(&grammar, &first, &follow).to_json(2);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["ll"], true);
+        assert_eq!(value["strong"], false);
+        assert_eq!(value["conflicts"][0]["kind"], "strong-ll");
+        assert_eq!(
+            value["conflicts"][0]["lookahead"],
+            serde_json::json!([["b", "a"]])
+        );
+
+        assert_eq!(json_string("\"a\\b\n"), r#""\"a\\b\u000a""#);
+    }
+}
@@ -3,6 +3,7 @@
 // or http://opensource.org/licenses/MIT>, at your option.  This file may not be
 // copied, modified, or distributed except according to those terms.
 
+use std::io::Read;
 use std::str::FromStr;
 
 #[macro_use]
@@ -17,11 +18,13 @@ mod first;
 mod follow;
 mod grammar;
 mod input_types;
+mod ll;
 mod token;
 
 use first::First;
 use follow::Follow;
 use grammar::Grammar;
+use ll::{Analysis, Conflict};
 
 static AFTER_HELP: &str = "\
 grammar_tool accepts a very simple grammar format similar to YACC. The input is
@@ -50,7 +53,9 @@ nonterminal. A quoted string in a production is also a terminal.
 ";
 
 fn generate_clap<'a, 'b>() -> clap::App<'a, 'b> {
-    let file = clap::Arg::with_name("FILE").required(true);
+    let file = clap::Arg::with_name("FILE")
+        .help("Grammar to read, or `-` for stdin")
+        .required(true);
     let k = clap::Arg::with_name("k")
         .help("Lookahead constant, or depth")
         .short("k")
@@ -91,6 +96,11 @@ fn generate_clap<'a, 'b>() -> clap::App<'a, 'b> {
                         .short("e")
                         .long("explain")
                         .help("Show details about conflicts"),
+                )
+                .arg(
+                    clap::Arg::with_name("json")
+                        .long("json")
+                        .help("Print the result and any conflicts as JSON"),
                 ),
         )
 }
@@ -107,14 +117,23 @@ fn main() {
         ("", _) => {
             generate_clap().print_help().unwrap();
             println!();
-        }
+        },
         h => panic!("{:?}", h), // clap should've caught unknown subcommands
     }
 }
 
+/// Read the grammar named by `FILE`, where `-` means stdin
 fn get_grammar<'a>(flags: &clap::ArgMatches<'a>) -> Grammar {
-    let input = std::fs::read_to_string(flags.value_of("FILE").unwrap()).unwrap();
-    Grammar::from_str(&input).unwrap()
+    let file = flags.value_of("FILE").unwrap();
+    let (name, input) = if file == "-" {
+        let mut input = String::new();
+        let result = std::io::stdin().read_to_string(&mut input).map(|_| input);
+        ("<stdin>", result)
+    } else {
+        (file, std::fs::read_to_string(file))
+    };
+    let input = input.unwrap_or_else(|e| panic!("cannot read {}: {}", name, e));
+    Grammar::from_str(&input).unwrap_or_else(|_| panic!("cannot parse {}", name))
 }
 
 fn get_k<'a>(flags: &clap::ArgMatches<'a>) -> usize {
@@ -151,12 +170,7 @@ fn first<'a>(flags: &clap::ArgMatches<'a>) {
     let first = First::new(&grammar, k);
 
     for nonterminal in grammar.nonterminals_in_order() {
-        let mut set = first
-            .query_token(nonterminal)
-            .iter()
-            .collect::<Vec<&Vec<_>>>();
-        set.sort();
-        for seq in set {
+        for seq in first.query_token_sorted(nonterminal) {
             println!("{} : {}", nonterminal, seq.join(" "));
         }
     }
@@ -169,12 +183,7 @@ fn follow<'a>(flags: &clap::ArgMatches<'a>) {
     let follow = Follow::new(&grammar, &first);
 
     for nonterminal in grammar.nonterminals_in_order() {
-        let mut set = follow
-            .query_token(nonterminal)
-            .iter()
-            .collect::<Vec<&Vec<_>>>();
-        set.sort();
-        for seq in set {
+        for seq in follow.query_token_sorted(nonterminal) {
             println!("{} : {}", nonterminal, seq.join(" "));
         }
     }
@@ -203,90 +212,48 @@ fn test<'a>(flags: &clap::ArgMatches<'a>) {
     let explain = flags.is_present("explain");
     let first = First::new(&grammar, k);
     let follow = Follow::new(&grammar, &first);
+    let analysis = Analysis::new(&grammar, &first, &follow);
 
-    let mut ll_k = true;
-    let mut strong = true;
-    for nonterminal in grammar.nonterminals_in_order() {
-        let candidates = &grammar.production_map[nonterminal];
-        if candidates.len() == 1 {
-            // if this nonterminal has only one production, it cannot create an
-            // LL(k) ambiguity
-            continue;
-        }
-
-        let follows = follow.query_token(nonterminal);
-        for i in 0..candidates.len() {
-            for j in (i + 1)..candidates.len() {
-                let mut all_first_a = token::StringSet::new();
-                let mut all_first_b = token::StringSet::new();
-                let mut sources = std::collections::HashMap::<Vec<&str>, Vec<usize>>::new();
-
-                for f in follows {
-                    let a = &candidates[i];
-                    let b = &candidates[j];
-
-                    let mut a_tokens = a.tokens.iter().map(|t| t.as_str()).collect::<Vec<_>>();
-                    let mut b_tokens = b.tokens.iter().map(|t| t.as_str()).collect::<Vec<_>>();
-                    a_tokens.extend(f);
-                    b_tokens.extend(f);
-
-                    let first_a = first.query_string(a_tokens);
-                    let first_b = first.query_string(b_tokens);
-
-                    for fa in &first_a {
-                        sources.entry(fa.clone()).or_insert(Vec::new()).push(a.id);
-                        all_first_a.insert(fa.clone());
-                    }
-                    for fb in &first_b {
-                        sources.entry(fb.clone()).or_insert(Vec::new()).push(b.id);
-                        all_first_b.insert(fb.clone());
-                    }
+    if flags.is_present("json") {
+        println!("{}", analysis.to_json(k));
+        return;
+    }
 
-                    let conflicts = first_a.intersection(&first_b).collect::<Vec<_>>();
-                    if !conflicts.is_empty() {
-                        ll_k = false;
-                        if explain {
-                            println!(
-                                "productions {:?} cause LL-conflicts: {:?}",
-                                [a.id, b.id],
-                                conflicts
-                            );
-                            println!(
-                                "  production {}   {} : {};",
-                                a.id,
-                                &a.name,
-                                a.tokens.join(" ")
-                            );
-                            println!(
-                                "  production {}   {} : {};",
-                                b.id,
-                                &b.name,
-                                b.tokens.join(" ")
-                            );
-                            println!("  conflicting suffix: {:?}", &f);
-                        }
-                    }
-                }
-                // strong conflicts
-                if ll_k {
-                    // only an LL(k) grammar can be strong
-                    for conflict in all_first_a.intersection(&all_first_b) {
-                        debug_assert!(sources[conflict].len() >= 2);
-                        strong = false;
-
-                        if explain {
-                            println!(
-                                "productions {:?} cause strong-LL-conflict: {:?}",
-                                &sources[conflict], conflict
-                            )
-                        }
+    if explain {
+        for conflict in &analysis.conflicts {
+            match conflict {
+                Conflict::Ll {
+                    productions,
+                    lookahead,
+                    suffix,
+                } => {
+                    println!(
+                        "productions {:?} cause LL-conflicts: {:?}",
+                        productions, lookahead
+                    );
+                    for &id in productions {
+                        let production = &grammar.productions[id];
+                        println!(
+                            "  production {}   {} : {};",
+                            production.id,
+                            &production.name,
+                            production.tokens.join(" ")
+                        );
                     }
-                }
+                    println!("  conflicting suffix: {:?}", suffix);
+                },
+                Conflict::StrongLl {
+                    productions,
+                    lookahead,
+                } => println!(
+                    "productions {:?} cause strong-LL-conflict: {:?}",
+                    productions, lookahead
+                ),
             }
         }
     }
 
-    match (ll_k, strong) {
+    match (analysis.ll_k, analysis.strong) {
         (true, true) => println!("grammar is strong LL({})", k),
         (true, false) => println!("grammar is weak LL({})", k),
         (false, _) => println!("grammar is not LL({})", k),
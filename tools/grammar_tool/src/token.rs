@@ -7,6 +7,20 @@ use std::collections::HashSet;
 
 pub type StringSet<'g> = HashSet<Vec<&'g str>>;
 
+/// Iterate over the strings of a set in sorted order
+pub fn sorted_strings<'s, 'g>(set: &'s StringSet<'g>) -> impl Iterator<Item = &'s Vec<&'g str>> {
+    let mut strings = set.iter().collect::<Vec<_>>();
+    strings.sort();
+    strings.into_iter()
+}
+
+/// Consume a set, yielding its strings in sorted order
+pub fn into_sorted_strings(set: StringSet<'_>) -> impl Iterator<Item = Vec<&'_ str>> {
+    let mut strings = set.into_iter().collect::<Vec<_>>();
+    strings.sort();
+    strings.into_iter()
+}
+
 pub fn empty_string_set() -> StringSet<'static> {
     let mut set = StringSet::new();
     set.insert(Vec::new());
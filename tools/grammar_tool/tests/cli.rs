@@ -0,0 +1,39 @@
+// Licensed   under  the   Apache  License,   Version  2.0   <LICENSE-APACHE  or
+// http://www.apache.org/licenses/LICENSE-2.0> or  the MIT  license <LICENSE-MIT
+// or http://opensource.org/licenses/MIT>, at your option.  This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Run the tool with `args`, writing `stdin` to it, and return its stdout
+fn run(args: &[&str], stdin: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_grammar_tool"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_grammar_from_stdin() {
+    let grammar = "%token a b\n%start S\n%%\nS : a S | b ;\n";
+    assert_eq!(run(&["test", "-"], grammar), "grammar is strong LL(1)\n");
+    assert_eq!(run(&["first", "-"], grammar), "S : a\nS : b\n");
+
+    let file = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/grammars/aho_ullman/example_5.7.yacc"
+    );
+    assert_eq!(run(&["test", file], ""), "grammar is not LL(1)\n");
+}